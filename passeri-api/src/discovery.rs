@@ -0,0 +1,302 @@
+use crate::DiscoveryError;
+use log::{debug, warn};
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// DNS-SD service type under which the senders are advertised
+pub const SERVICE_TYPE: &str = "_passeri._tcp.local.";
+
+/// Default time spent looking for senders by [Discovery::browse]
+pub const DEFAULT_BROWSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+const TXT_MIDI_PORT: &str = "midi_port";
+const TXT_TRANSPORT: &str = "transport";
+const TXT_PROTOCOL: &str = "proto";
+
+//
+//	DISCOVERY
+//
+
+/// Handle on a mDNS responder used to advertise senders and to browse the ones available on the network
+///
+/// Cloning a [Discovery] gives a new handle on the same responder, which is shut down with its last handle.
+#[derive(Clone)]
+pub struct Discovery {
+    inner: Arc<Responder>,
+}
+
+struct Responder {
+    daemon: ServiceDaemon,
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        if let Err(err) = self.daemon.shutdown() {
+            debug!("unable to shutdown the mDNS responder: {}", err);
+        }
+    }
+}
+
+impl Discovery {
+    /// Start a mDNS responder on every network interface
+    pub fn new() -> Result<Self, DiscoveryError> {
+        Ok(Discovery {
+            inner: Arc::new(Responder {
+                daemon: ServiceDaemon::new()?,
+            }),
+        })
+    }
+
+    /// Start a mDNS responder on the IPv4 loopback interface only, to find bridges running on the same host
+    pub fn loopback() -> Result<Self, DiscoveryError> {
+        let daemon = ServiceDaemon::new()?;
+        daemon.disable_interface(IfKind::All)?;
+        daemon.enable_interface(IfKind::LoopbackV4)?;
+        Ok(Discovery {
+            inner: Arc::new(Responder { daemon }),
+        })
+    }
+
+    /// Advertise a sender until the returned [Advertisement] is dropped
+    ///
+    /// When `addr` is unspecified (e.g. `0.0.0.0`), the addresses of every network interface are advertised.
+    ///
+    /// # Arguments
+    /// * `name` - DNS-SD instance name, used by the receivers to pick the sender
+    /// * `transport` - name of the [net_thread](crate::net::sender::Thread) implementation (e.g. `tcp`)
+    /// * `addr` - address on which the sender is listening
+    /// * `midi_port` - name of the bridged MIDI port
+    pub fn advertise(
+        &self,
+        name: &str,
+        transport: &str,
+        addr: SocketAddr,
+        midi_port: &str,
+    ) -> Result<Advertisement, DiscoveryError> {
+        let protocol = crate::net::PROTOCOL_VERSION.to_string();
+        let properties = [
+            (TXT_MIDI_PORT, midi_port),
+            (TXT_TRANSPORT, transport),
+            (TXT_PROTOCOL, protocol.as_str()),
+        ];
+        let host = format!("{}.local.", hostname());
+
+        let info = if addr.ip().is_unspecified() {
+            ServiceInfo::new(SERVICE_TYPE, name, &host, (), addr.port(), &properties[..])?
+                .enable_addr_auto()
+        } else {
+            ServiceInfo::new(
+                SERVICE_TYPE,
+                name,
+                &host,
+                addr.ip(),
+                addr.port(),
+                &properties[..],
+            )?
+        };
+        let fullname = info.get_fullname().to_string();
+
+        self.inner.daemon.register(info)?;
+        debug!("advertising {} on {}", fullname, addr);
+        Ok(Advertisement {
+            responder: self.inner.clone(),
+            fullname,
+        })
+    }
+
+    /// Look for the senders advertised on the network during `timeout`
+    pub fn browse(&self, timeout: Duration) -> Result<Vec<SenderService>, DiscoveryError> {
+        let mut found = HashMap::new();
+        self.browse_until(timeout, |services| {
+            found = services.clone();
+            false
+        })?;
+
+        let mut services: Vec<SenderService> = found.into_values().collect();
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(services)
+    }
+
+    /// Look for the sender advertised under `name`, returning as soon as it is found
+    pub fn find(&self, name: &str, timeout: Duration) -> Result<SenderService, DiscoveryError> {
+        let mut service = None;
+        self.browse_until(timeout, |services| {
+            service = services.get(name).cloned();
+            service.is_some()
+        })?;
+        service.ok_or_else(|| DiscoveryError::NotFound(name.to_string()))
+    }
+
+    /// Browse the network until `timeout` or until `done` returns `true`, `done` being called with
+    /// the services found so far (indexed by name) each time they change
+    fn browse_until(
+        &self,
+        timeout: Duration,
+        mut done: impl FnMut(&HashMap<String, SenderService>) -> bool,
+    ) -> Result<(), DiscoveryError> {
+        let events = self.inner.daemon.browse(SERVICE_TYPE)?;
+        let deadline = Instant::now() + timeout;
+        let mut services = HashMap::new();
+
+        while let Ok(event) = events.recv_deadline(deadline) {
+            match event {
+                ServiceEvent::ServiceResolved(info) => match SenderService::from_info(&info) {
+                    Some(service) => {
+                        services.insert(service.name.clone(), service);
+                    }
+                    None => warn!("ignoring invalid service {}", info.get_fullname()),
+                },
+                ServiceEvent::ServiceRemoved(_, fullname) => {
+                    services.remove(instance_name(&fullname));
+                }
+                _ => continue,
+            }
+            if done(&services) {
+                break;
+            }
+        }
+        done(&services);
+
+        if let Err(err) = self.inner.daemon.stop_browse(SERVICE_TYPE) {
+            debug!("unable to stop browsing: {}", err);
+        }
+        Ok(())
+    }
+}
+
+/// Sender advertised over DNS-SD, withdrawn when dropped
+pub struct Advertisement {
+    responder: Arc<Responder>,
+    fullname: String,
+}
+
+impl Advertisement {
+    /// DNS-SD full name of the advertised service
+    pub fn fullname(&self) -> &str {
+        &self.fullname
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        if let Err(err) = self.responder.daemon.unregister(&self.fullname) {
+            debug!("unable to unregister {}: {}", self.fullname, err);
+        }
+    }
+}
+
+//
+//	SENDER SERVICE
+//
+
+/// Sender found on the network by a [Discovery]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SenderService {
+    /// instance name under which the sender is advertised
+    pub name: String,
+    /// host name of the machine running the sender
+    pub host: String,
+    /// addresses on which the sender can be reached
+    pub addrs: Vec<SocketAddr>,
+    /// name of the bridged MIDI port
+    pub midi_port: String,
+    /// name of the [net_thread](crate::net::sender::Thread) implementation
+    pub transport: String,
+    /// version of the **Passeri** protocol spoken by the sender
+    pub protocol_version: u32,
+}
+
+impl SenderService {
+    fn from_info(info: &ServiceInfo) -> Option<Self> {
+        let mut addrs: Vec<SocketAddr> = info
+            .get_addresses()
+            .iter()
+            .map(|ip| SocketAddr::new(*ip, info.get_port()))
+            .collect();
+        addrs.sort();
+
+        Some(SenderService {
+            name: instance_name(info.get_fullname()).to_string(),
+            host: info.get_hostname().to_string(),
+            addrs,
+            midi_port: info.get_property_val_str(TXT_MIDI_PORT)?.to_string(),
+            transport: info.get_property_val_str(TXT_TRANSPORT)?.to_string(),
+            protocol_version: info.get_property_val_str(TXT_PROTOCOL)?.parse().ok()?,
+        })
+    }
+
+    /// First advertised address, IPv4 ones being preferred
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addrs
+            .iter()
+            .find(|addr| addr.is_ipv4())
+            .or(self.addrs.first())
+            .copied()
+    }
+}
+
+/// Extract the instance name from a DNS-SD full name (`<instance>._passeri._tcp.local.`)
+fn instance_name(fullname: &str) -> &str {
+    fullname
+        .strip_suffix(SERVICE_TYPE)
+        .and_then(|name| name.strip_suffix('.'))
+        .unwrap_or(fullname)
+}
+
+/// Name of the local host, reduced to a valid DNS label
+fn hostname() -> String {
+    let name = std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .unwrap_or_default();
+    let label: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    match label.trim_matches('-') {
+        "" => "passeri".into(),
+        label => label.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_name() {
+        assert_eq!(instance_name("Studio A._passeri._tcp.local."), "Studio A");
+        assert_eq!(instance_name("other"), "other");
+    }
+
+    #[test]
+    fn test_advertise_and_browse() {
+        let responder = Discovery::loopback().unwrap();
+        let addr: SocketAddr = "127.0.0.1:45123".parse().unwrap();
+        let advertisement = responder
+            .advertise("passeri test", "tcp", addr, "USB Keyboard")
+            .unwrap();
+        assert_eq!(
+            advertisement.fullname(),
+            "passeri test._passeri._tcp.local."
+        );
+
+        let browser = Discovery::loopback().unwrap();
+        let service = browser
+            .find("passeri test", Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(service.addr(), Some(addr));
+        assert_eq!(service.midi_port, "USB Keyboard");
+        assert_eq!(service.transport, "tcp");
+        assert_eq!(service.protocol_version, crate::net::PROTOCOL_VERSION);
+
+        assert!(matches!(
+            browser.find("missing", Duration::from_millis(500)),
+            Err(DiscoveryError::NotFound(_))
+        ));
+    }
+}
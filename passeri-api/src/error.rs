@@ -0,0 +1,222 @@
+use crate::midi::MidiPort;
+use std::io;
+use thiserror::Error;
+
+/// Every error that can be returned by **Passeri**
+#[derive(Error, Debug)]
+pub enum Error {
+    /// error related to local MIDI ports
+    #[error(transparent)]
+    Midi(#[from] MidiError),
+
+    /// error related to the network layer implemented by the [net_thread](crate::net::sender::Thread)
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+
+    /// error in the dialog with the [net_thread](crate::net::sender::Thread) or the distant peer
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+
+    /// error while advertising or browsing senders
+    #[error(transparent)]
+    Discovery(#[from] DiscoveryError),
+
+    /// the [net_thread](crate::net::sender::Thread) is not running anymore
+    #[error("the net_thread is not running anymore")]
+    NetThreadStopped,
+
+    /// waiting for a client was cancelled by a [CancelHandle](crate::net::sender::CancelHandle)
+    #[error("waiting for a client was cancelled")]
+    Cancelled,
+
+    /// no sender at this index in a [MergedReceiver](crate::net::merge::MergedReceiver)
+    #[error("no merged sender {0}")]
+    UnknownSource(usize),
+
+    /// a [MergedReceiver](crate::net::merge::MergedReceiver) needs at least one sender
+    #[error("no sender to merge")]
+    NoSource,
+}
+
+/// Errors related to local MIDI ports
+#[derive(Error, Debug)]
+pub enum MidiError {
+    /// unable to create a MIDI client
+    #[error("unable to create the MIDI client \"{name}\"")]
+    Init {
+        /// name of the MIDI client
+        name: String,
+        /// error returned by [midir]
+        #[source]
+        source: midir::InitError,
+    },
+
+    /// no port is matching the [PortSelector](crate::midi::PortSelector)
+    #[error("no MIDI port matches `{selector}`, available ports are:{}", list_ports(.candidates))]
+    PortNotFound {
+        /// selector used to look for the port
+        selector: String,
+        /// every available port
+        candidates: Vec<MidiPort>,
+    },
+
+    /// several ports are matching the [PortSelector](crate::midi::PortSelector)
+    #[error("several MIDI ports match `{selector}`, use a more specific selector (e.g. `id:<id>`):{}", list_ports(.candidates))]
+    AmbiguousPort {
+        /// selector used to look for the port
+        selector: String,
+        /// every matching port
+        candidates: Vec<MidiPort>,
+    },
+
+    /// the [PortSelector](crate::midi::PortSelector) pattern is not a valid regular expression
+    #[error("invalid MIDI port pattern `{pattern}`")]
+    InvalidSelector {
+        /// the invalid pattern
+        pattern: String,
+        /// error returned by [regex]
+        #[source]
+        source: regex::Error,
+    },
+
+    /// unable to connect to a MIDI port
+    #[error("unable to connect to the MIDI port {port} ({reason})")]
+    Connect {
+        /// description of the port
+        port: String,
+        /// reason given by [midir]
+        reason: String,
+    },
+
+    /// unable to send a message to a MIDI port
+    #[error("unable to send to the MIDI port")]
+    Send(#[from] midir::SendError),
+
+    /// MIDI channels are numbered from 0 to 15
+    #[error("invalid MIDI channel {0}, expecting 0 to 15")]
+    InvalidChannel(u8),
+
+    /// a multiplexed bridge needs between 1 and [MAX_CABLES](crate::net::cable::MAX_CABLES) ports
+    #[error("unable to multiplex {0} MIDI ports, expecting 1 to {max}", max = crate::net::cable::MAX_CABLES)]
+    CableCount(usize),
+
+    /// unknown class of messages in a [SnapshotConfig](crate::midi::SnapshotConfig)
+    #[error("unknown snapshot class `{0}`, expecting programs, controllers, pitch_bend, pressure, rpn, all or none")]
+    UnknownSnapshotClass(String),
+}
+
+fn list_ports(ports: &[MidiPort]) -> String {
+    if ports.is_empty() {
+        return " none".into();
+    }
+    ports.iter().map(|port| format!("\n\t{}", port)).collect()
+}
+
+/// Errors related to the network layer implemented by the [net_thread](crate::net::sender::Thread)
+#[derive(Error, Debug)]
+pub enum TransportError {
+    /// unable to bind to the local address
+    #[error("unable to bind to {addr}")]
+    Bind {
+        /// local address
+        addr: String,
+        /// underlying error
+        #[source]
+        source: io::Error,
+    },
+
+    /// unable to connect to the distant address
+    #[error("unable to connect to {addr}")]
+    Connect {
+        /// distant address
+        addr: String,
+        /// underlying error
+        #[source]
+        source: io::Error,
+    },
+
+    /// unable to accept an incomming connection
+    #[error("unable to accept a new client")]
+    Accept(#[source] io::Error),
+
+    /// unable to configure the connection
+    #[error("unable to configure the connection")]
+    Configure(#[source] io::Error),
+
+    /// unable to establish a secure session with the distant peer
+    #[error("TLS handshake failed")]
+    Handshake(#[source] io::Error),
+
+    /// unable to wait for the network events
+    #[error("unable to poll the connections")]
+    Poll(#[source] io::Error),
+
+    /// unable to read from the connection
+    #[error("unable to read from the connection")]
+    Read(#[source] io::Error),
+
+    /// unable to write to the connection
+    #[error("unable to write to the connection")]
+    Write(#[source] io::Error),
+
+    /// nothing was received from the distant peer, not even a heartbeat
+    #[error("no heartbeat from the distant peer for {0:?}")]
+    HeartbeatTimeout(std::time::Duration),
+}
+
+/// Errors in the dialog with the [net_thread](crate::net::sender::Thread) or the distant peer
+#[derive(Error, Debug)]
+pub enum ProtocolError {
+    /// the [net_thread](crate::net::sender::Thread) answered with an unexpected response
+    #[error("invalid response from net_thread: {0}")]
+    UnexpectedResponse(String),
+
+    /// the client to stream to has not been accepted by the [net_thread](crate::net::sender::Thread)
+    #[error("client {0} not found")]
+    ClientNotFound(String),
+
+    /// the distant peer sent a malformed or unexpected handshake message
+    #[error("invalid handshake: {0}")]
+    InvalidMessage(String),
+
+    /// the sender rejected the receiver
+    #[error("rejected by the sender: {0}")]
+    Rejected(String),
+
+    /// the relay refused to pair the peer
+    #[error("refused by the relay: {0}")]
+    RelayRefused(String),
+
+    /// the distant peer speaks a protocol version out of the supported range
+    #[error("incompatible protocol version {distant} (local version {local})")]
+    IncompatibleVersion {
+        /// version spoken by this peer
+        local: u32,
+        /// version spoken by the distant peer
+        distant: u32,
+    },
+
+    /// a feature required by one of the peers is not supported by the other
+    #[error("missing required features: {0}")]
+    MissingFeatures(crate::net::lobby::Features),
+
+    /// a feature name is not known
+    #[error("unknown feature `{0}`")]
+    UnknownFeature(String),
+
+    /// the system random number generator failed
+    #[error("unable to generate a random {0}")]
+    Random(&'static str),
+}
+
+/// Errors related to the advertisement and discovery of senders
+#[derive(Error, Debug)]
+pub enum DiscoveryError {
+    /// error returned by the mDNS responder
+    #[error("mDNS responder error")]
+    Responder(#[from] mdns_sd::Error),
+
+    /// no sender is advertised under the given name
+    #[error("no sender named \"{0}\" found on the network")]
+    NotFound(String),
+}
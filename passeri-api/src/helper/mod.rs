@@ -1,38 +1,249 @@
 use crate::{
-    midi,
-    net::{self, Result},
+    midi::{self, PortSelector},
+    net::{self, lobby::Credentials, Result},
 };
 
-/// Helper function use to create a new [Sender](net::Sender) bridge
+//
+//	SENDER BUILDER
+//
+
+/// Builder of a [Sender](net::Sender) bridge, streaming MIDI input ports with the given [net_thread][net::sender::Thread]
+///
+/// The messages of the `n`th port are sent on the [cable](net::cable) `n`, and the MIDI input connections are
+/// automatically re-opened when their port is plugged back (see [midi::InputConnection::auto_reconnect]).
+///
+/// ```no_run
+/// # fn example<T: passeri_api::net::sender::Thread>(addr: T::Addr) -> passeri_api::net::Result<()> {
+/// use passeri_api::{midi::PortSelector, SenderBuilder};
+///
+/// let sender = SenderBuilder::<T>::new(&[PortSelector::Index(0)], "PASSERI_SENDER").listen(addr)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SenderBuilder<NetThread: net::sender::Thread> {
+    ports: Vec<PortSelector>,
+    name: String,
+    config: NetThread::Config,
+}
+
+impl<NetThread: net::sender::Thread> SenderBuilder<NetThread> {
+    /// Stream the MIDI input ports matching `ports` (you can list them with a [midi::get_availables_midi_in_port]
+    /// function call), `name` being used to create their [InputConnection][midi::InputConnection]s
+    pub fn new(ports: &[PortSelector], name: &str) -> Self {
+        SenderBuilder {
+            ports: ports.to_vec(),
+            name: name.to_string(),
+            config: NetThread::Config::default(),
+        }
+    }
+
+    /// Set the options of the [net_thread][net::sender::Thread] implementation (e.g.: TLS settings)
+    pub fn with_config(mut self, config: NetThread::Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Open the MIDI input ports and listen for receivers on `addr`
+    pub fn listen(self, addr: NetThread::Addr) -> Result<net::Sender<NetThread>> {
+        let (mut conns, rx) = midi::new_cable_receivers(&self.ports, &self.name)?;
+        let watcher = midi::PortWatcher::shared();
+        for conn in conns.iter_mut() {
+            conn.auto_reconnect(&watcher);
+        }
+        net::Sender::<NetThread>::new(conns, rx, addr, self.config)
+    }
+}
+
+//
+//	RECEIVER BUILDER
+//
+
+/// Builder of a [Receiver](net::Receiver) bridge, or of a [MergedReceiver](net::merge::MergedReceiver) merging
+/// several senders, playing on MIDI output ports with the given [net_thread][net::receiver::Thread]
+///
+/// The messages of the [cable](net::cable) `n` are sent to the `n`th port, and the MIDI output connections are
+/// automatically re-opened when their port is plugged back (see [midi::OutputConnection::auto_reconnect]).
+///
+/// ```no_run
+/// # fn example<T: passeri_api::net::receiver::Thread>(addr: T::Addr) -> passeri_api::net::Result<()> {
+/// use passeri_api::{midi::PortSelector, net::lobby::Credentials, ReceiverBuilder};
 ///
-/// # Arguments
-/// * `midi_port_index` - Index of a MIDI input port (you can get it from a [midi::get_availables_midi_port] function call)
-/// * `midi_port_name` - Name used to create the [MidiInputConnection][midir::MidiInputConnection]
-/// * `binding_addr` - Address used by the given [net_thread][net::sender::Thread] implementation to listen on
+/// let receiver = ReceiverBuilder::<T>::new(&[PortSelector::Index(0)], "PASSERI_RECV")
+///     .with_credentials(Credentials::new("studio").with_secret("s3cr3t"))
+///     .connect(addr)?;
+/// receiver.receive()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ReceiverBuilder<NetThread: net::receiver::Thread> {
+    ports: Vec<PortSelector>,
+    name: String,
+    config: NetThread::Config,
+    credentials: Credentials,
+}
+
+impl<NetThread: net::receiver::Thread> ReceiverBuilder<NetThread> {
+    /// Play on the MIDI output ports matching `ports` (you can list them with a [midi::get_availables_midi_out_port]
+    /// function call), `name` being used to create their [OutputConnection][midi::OutputConnection]s and presented
+    /// to the senders unless [ReceiverBuilder::with_credentials] is called
+    pub fn new(ports: &[PortSelector], name: &str) -> Self {
+        ReceiverBuilder {
+            ports: ports.to_vec(),
+            name: name.to_string(),
+            config: NetThread::Config::default(),
+            credentials: Credentials::new(name),
+        }
+    }
+
+    /// Set the options of the [net_thread][net::receiver::Thread] implementation (e.g.: TLS settings)
+    pub fn with_config(mut self, config: NetThread::Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set the name and pre-shared key or pairing code presented to the senders
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    fn open(&self) -> Result<Vec<midi::OutputConnection>> {
+        let mut conns = midi::new_cable_senders(&self.ports, &self.name)?;
+        let watcher = midi::PortWatcher::shared();
+        for conn in conns.iter_mut() {
+            conn.auto_reconnect(&watcher);
+        }
+        Ok(conns)
+    }
+
+    /// Open the MIDI output ports and connect to the sender at `addr`, returning once it accepted the receiver
+    pub fn connect(self, addr: NetThread::Addr) -> Result<net::Receiver> {
+        let conns = self.open()?;
+        net::Receiver::new::<NetThread>(conns, addr, self.config, self.credentials)
+    }
+
+    /// Open the MIDI output ports and connect to every sender of `sources` (address, channel offset and initial state),
+    /// merging their streams into the same ports
+    pub fn merge(
+        self,
+        sources: Vec<net::merge::Source<NetThread::Addr>>,
+    ) -> Result<net::merge::MergedReceiver>
+    where
+        NetThread::Config: Clone,
+    {
+        let conns = self.open()?;
+        net::merge::MergedReceiver::new::<NetThread>(conns, sources, self.config, self.credentials)
+    }
+}
+
+//
+//	DEPRECATED HELPERS
+//
+
+/// Helper function use to create a new [Sender](net::Sender) bridge streaming the MIDI input port matching `midi_port`
+#[deprecated(note = "use SenderBuilder")]
 pub fn new_sender<NetThread: net::sender::Thread>(
-    midi_port_index: usize,
+    midi_port: &PortSelector,
+    midi_port_name: &str,
+    binding_addr: NetThread::Addr,
+) -> Result<net::Sender<NetThread>> {
+    SenderBuilder::new(std::slice::from_ref(midi_port), midi_port_name).listen(binding_addr)
+}
+
+/// Same as [new_sender()], with the given options of the [net_thread][net::sender::Thread] implementation
+#[deprecated(note = "use SenderBuilder::with_config")]
+pub fn new_sender_with_config<NetThread: net::sender::Thread>(
+    midi_port: &PortSelector,
     midi_port_name: &str,
     binding_addr: NetThread::Addr,
+    config: NetThread::Config,
 ) -> Result<net::Sender<NetThread>> {
-    let (conn, rx) = midi::new_receiver(midi_port_index, midi_port_name)?;
-    let net = net::Sender::<NetThread>::new(conn, rx, binding_addr)?;
+    SenderBuilder::new(std::slice::from_ref(midi_port), midi_port_name)
+        .with_config(config)
+        .listen(binding_addr)
+}
 
-    Ok(net)
+/// Same as [new_sender_with_config()], the messages of the `n`th port being sent on the [cable](net::cable) `n`
+#[deprecated(note = "use SenderBuilder::with_config")]
+pub fn new_multi_sender_with_config<NetThread: net::sender::Thread>(
+    midi_ports: &[PortSelector],
+    midi_port_name: &str,
+    binding_addr: NetThread::Addr,
+    config: NetThread::Config,
+) -> Result<net::Sender<NetThread>> {
+    SenderBuilder::new(midi_ports, midi_port_name)
+        .with_config(config)
+        .listen(binding_addr)
 }
 
-/// Helper function use to create a new [Receiver](net::Receiver) bridge
-///
-/// # Arguments
-/// * `midi_port_index` - Index of a MIDI output port (you can get it from a [midi::get_availables_midi_port] function call)
-/// * `midi_port_name` - Name used to create the [MidiOutputConnection][midir::MidiOutputConnection]
-/// * `sender_addr` - Address used by the given [net_thread][net::receiver::Thread] implementation to connect to
+/// Helper function use to create a new [Receiver](net::Receiver) bridge playing on the MIDI output port matching `midi_port`
+#[deprecated(note = "use ReceiverBuilder")]
 pub fn new_receiver<NetThread: net::receiver::Thread>(
-    midi_port_index: usize,
+    midi_port: &PortSelector,
     midi_port_name: &str,
     sender_addr: NetThread::Addr,
 ) -> Result<net::Receiver> {
-    let conn = midi::new_sender(midi_port_index, midi_port_name)?;
-    let net = net::Receiver::new::<NetThread>(conn, sender_addr)?;
+    ReceiverBuilder::<NetThread>::new(std::slice::from_ref(midi_port), midi_port_name)
+        .connect(sender_addr)
+}
 
-    Ok(net)
+/// Same as [new_receiver()], with the given options of the [net_thread][net::receiver::Thread] implementation
+#[deprecated(note = "use ReceiverBuilder::with_config")]
+pub fn new_receiver_with_config<NetThread: net::receiver::Thread>(
+    midi_port: &PortSelector,
+    midi_port_name: &str,
+    sender_addr: NetThread::Addr,
+    config: NetThread::Config,
+) -> Result<net::Receiver> {
+    ReceiverBuilder::<NetThread>::new(std::slice::from_ref(midi_port), midi_port_name)
+        .with_config(config)
+        .connect(sender_addr)
+}
+
+/// Same as [new_receiver_with_config()], presenting the given [Credentials] to the sender
+#[deprecated(note = "use ReceiverBuilder::with_credentials")]
+pub fn new_receiver_with_credentials<NetThread: net::receiver::Thread>(
+    midi_port: &PortSelector,
+    midi_port_name: &str,
+    sender_addr: NetThread::Addr,
+    config: NetThread::Config,
+    credentials: Credentials,
+) -> Result<net::Receiver> {
+    ReceiverBuilder::<NetThread>::new(std::slice::from_ref(midi_port), midi_port_name)
+        .with_config(config)
+        .with_credentials(credentials)
+        .connect(sender_addr)
+}
+
+/// Same as [new_receiver_with_credentials()], the messages of the [cable](net::cable) `n` being sent to the `n`th port
+#[deprecated(note = "use ReceiverBuilder::with_credentials")]
+pub fn new_multi_receiver_with_credentials<NetThread: net::receiver::Thread>(
+    midi_ports: &[PortSelector],
+    midi_port_name: &str,
+    sender_addr: NetThread::Addr,
+    config: NetThread::Config,
+    credentials: Credentials,
+) -> Result<net::Receiver> {
+    ReceiverBuilder::<NetThread>::new(midi_ports, midi_port_name)
+        .with_config(config)
+        .with_credentials(credentials)
+        .connect(sender_addr)
+}
+
+/// Helper function use to create a new [MergedReceiver](net::merge::MergedReceiver) bridge, connected to several senders
+#[deprecated(note = "use ReceiverBuilder::merge")]
+pub fn new_merged_receiver_with_credentials<NetThread: net::receiver::Thread>(
+    midi_ports: &[PortSelector],
+    midi_port_name: &str,
+    sources: Vec<net::merge::Source<NetThread::Addr>>,
+    config: NetThread::Config,
+    credentials: Credentials,
+) -> Result<net::merge::MergedReceiver>
+where
+    NetThread::Config: Clone,
+{
+    ReceiverBuilder::<NetThread>::new(midi_ports, midi_port_name)
+        .with_config(config)
+        .with_credentials(credentials)
+        .merge(sources)
 }
@@ -1,8 +1,16 @@
 #![warn(missing_docs)]
 #![doc = include_str!("../../README.md")]
+mod error;
+pub use error::{DiscoveryError, Error, MidiError, ProtocolError, TransportError};
 mod helper;
 pub use helper::*;
 
+/// advertisement and discovery of senders over DNS-SD (mDNS)
+pub mod discovery;
+/// state machine and events describing the life of a bridge
+pub mod lifecycle;
+/// counters and latency histograms describing the activity of a bridge
+pub mod metrics;
 /// provides interfaces between OS MIDI ports and **Passeri**, it is fully relying on [midir]
 pub mod midi;
 /// defines the necessary behaviour to implement midi over network messenger
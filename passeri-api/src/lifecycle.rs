@@ -0,0 +1,254 @@
+use log::debug;
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+
+/// Function called on every [Event] of a bridge
+type Callback = Arc<dyn Fn(&Event) + Send + Sync>;
+
+//
+//	EVENTS
+//
+
+/// Change in the life of a bridge, notified to the subscribers of its [Lifecycle]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// the sender is waiting for receivers on the given address
+    Listening(String),
+    /// a distant peer is connected: a receiver admitted by the sender, or the sender that accepted the receiver
+    ClientConnected(String),
+    /// MIDI messages are flowing between the peers
+    Streaming,
+    /// the distant peer closed the connection
+    ClientLeft,
+    /// the MIDI port has been unplugged, the bridge waits for it to come back
+    Reconnecting(String),
+    /// the MIDI port has been plugged back
+    Reconnected(String),
+    /// the bridge failed with the given error
+    Error(String),
+    /// the [net_thread](crate::net::sender::Thread) ended for the given reason
+    Stopped(String),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Listening(addr) => write!(f, "listening on {}", addr),
+            Event::ClientConnected(client) => write!(f, "connected to {}", client),
+            Event::Streaming => write!(f, "streaming"),
+            Event::ClientLeft => write!(f, "the distant peer left"),
+            Event::Reconnecting(port) => write!(f, "MIDI port {} unplugged, reconnecting", port),
+            Event::Reconnected(port) => write!(f, "MIDI port {} reconnected", port),
+            Event::Error(err) => write!(f, "error: {}", err),
+            Event::Stopped(reason) => write!(f, "stopped: {}", reason),
+        }
+    }
+}
+
+//
+//	STATE MACHINE
+//
+
+/// State of a bridge, driven by its [Event]s
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BridgeState {
+    /// created, no distant peer
+    #[default]
+    Idle,
+    /// waiting for receivers (sender only)
+    Listening,
+    /// a distant peer is connected, the stream is not started yet
+    Connected,
+    /// MIDI messages are flowing
+    Streaming,
+    /// the MIDI port is unplugged
+    Reconnecting,
+    /// the bridge failed, it is about to stop
+    Failed,
+    /// the [net_thread](crate::net::sender::Thread) ended
+    Stopped,
+}
+
+impl BridgeState {
+    /// State following `event` for a connected MIDI port, `None` if the event is not expected in this state
+    fn session(self, event: &Event) -> Option<BridgeState> {
+        use BridgeState::*;
+        match (self, event) {
+            (Idle | Listening | Connected, Event::Listening(_)) => Some(Listening),
+            (Idle | Listening | Connected, Event::ClientConnected(_)) => Some(Connected),
+            (Connected, Event::Streaming) => Some(Streaming),
+            (Connected | Streaming, Event::ClientLeft) => Some(Idle),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Machine {
+    state: BridgeState,
+    /// state to go back to once the MIDI port is reconnected
+    resume: BridgeState,
+    subscribers: Vec<mpsc::Sender<Event>>,
+    callbacks: Vec<Callback>,
+}
+
+impl Machine {
+    /// Apply `event`, returning `false` if it is not expected in the current state
+    fn apply(&mut self, event: &Event) -> bool {
+        use BridgeState::*;
+        let next = match (self.state, event) {
+            (Stopped, _) => None,
+            (_, Event::Stopped(_)) => Some(Stopped),
+            (Failed, _) => None,
+            (_, Event::Error(_)) => Some(Failed),
+            (Reconnecting, Event::Reconnected(_)) => Some(self.resume),
+            (Reconnecting, event) => {
+                // the session goes on while the MIDI port is unplugged
+                return match self.resume.session(event) {
+                    Some(resume) => {
+                        self.resume = resume;
+                        true
+                    }
+                    None => false,
+                };
+            }
+            (state, Event::Reconnecting(_)) => {
+                self.resume = state;
+                Some(Reconnecting)
+            }
+            (state, event) => state.session(event),
+        };
+        match next {
+            Some(next) => {
+                self.state = next;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+//
+//	LIFECYCLE
+//
+
+/// State machine of a bridge, shared between the bridge, its MIDI connection and its [net_thread](crate::net::sender::Thread)
+///
+/// Every accepted [Event] is sent to the channels returned by [Lifecycle::subscribe] and given to the callbacks
+/// registered with [Lifecycle::on_event], events not expected in the current state being ignored.
+///
+/// Cloning a [Lifecycle] gives a new handle on the same state machine.
+#[derive(Clone, Default)]
+pub struct Lifecycle {
+    inner: Arc<Mutex<Machine>>,
+}
+
+impl fmt::Debug for Lifecycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lifecycle")
+            .field("state", &self.state())
+            .finish()
+    }
+}
+
+impl Lifecycle {
+    /// Create a new state machine, in the [BridgeState::Idle] state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn machine(&self) -> MutexGuard<'_, Machine> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Current state of the bridge
+    pub fn state(&self) -> BridgeState {
+        self.machine().state
+    }
+
+    /// Return a channel receiving every following [Event]
+    pub fn subscribe(&self) -> mpsc::Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.machine().subscribers.push(tx);
+        rx
+    }
+
+    /// Call `callback` on every following [Event], from the thread emitting it
+    pub fn on_event(&self, callback: impl Fn(&Event) + Send + Sync + 'static) {
+        self.machine().callbacks.push(Arc::new(callback));
+    }
+
+    /// Move the state machine according to `event` and notify the subscribers,
+    /// returning `false` if the event is not expected in the current state
+    pub fn emit(&self, event: Event) -> bool {
+        let callbacks = {
+            let mut machine = self.machine();
+            if !machine.apply(&event) {
+                debug!("ignoring {:?} in state {:?}", event, machine.state);
+                return false;
+            }
+            machine
+                .subscribers
+                .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+            machine.callbacks.clone()
+        };
+        // called without the lock, so callbacks can query the state
+        for callback in callbacks {
+            callback(&event);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        let lifecycle = Lifecycle::new();
+        let events = lifecycle.subscribe();
+
+        assert!(!lifecycle.emit(Event::Streaming));
+        assert!(lifecycle.emit(Event::Listening("0.0.0.0:8080".into())));
+        assert!(lifecycle.emit(Event::ClientConnected("studio".into())));
+
+        // the session goes on while the MIDI port is unplugged
+        assert!(lifecycle.emit(Event::Reconnecting("USB Keyboard".into())));
+        assert_eq!(lifecycle.state(), BridgeState::Reconnecting);
+        assert!(lifecycle.emit(Event::Streaming));
+        assert_eq!(lifecycle.state(), BridgeState::Reconnecting);
+        assert!(lifecycle.emit(Event::Reconnected("USB Keyboard".into())));
+        assert_eq!(lifecycle.state(), BridgeState::Streaming);
+
+        assert!(lifecycle.emit(Event::ClientLeft));
+        assert_eq!(lifecycle.state(), BridgeState::Idle);
+        assert!(lifecycle.emit(Event::Error("boom".into())));
+        assert!(!lifecycle.emit(Event::Listening("0.0.0.0:8080".into())));
+        assert!(lifecycle.emit(Event::Stopped("boom".into())));
+        assert!(!lifecycle.emit(Event::Stopped("twice".into())));
+        assert_eq!(lifecycle.state(), BridgeState::Stopped);
+
+        assert_eq!(events.try_iter().count(), 8);
+    }
+
+    #[test]
+    fn test_callback() {
+        let lifecycle = Lifecycle::new();
+        let handle = lifecycle.clone();
+        let (tx, rx) = mpsc::channel();
+        lifecycle.on_event(move |event| {
+            // the state is already updated when callbacks are called
+            tx.send((event.clone(), handle.state())).unwrap();
+        });
+
+        lifecycle.emit(Event::ClientConnected("sender".into()));
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            (
+                Event::ClientConnected("sender".into()),
+                BridgeState::Connected
+            )
+        );
+    }
+}
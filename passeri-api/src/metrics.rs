@@ -0,0 +1,296 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Upper bounds (in microseconds) of the [LatencyHistogram] buckets, the last bucket being unbounded
+const LATENCY_BUCKETS_US: [u64; 12] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 1_000_000,
+];
+
+//
+//	METRICS
+//
+
+/// Counters of a running bridge, shared between the bridge, its MIDI connection and its [net_thread](crate::net::sender::Thread)
+///
+/// Cloning a [Metrics] gives a new handle on the same counters, a [Stats] snapshot can be taken at any time.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug)]
+struct Counters {
+    started: Instant,
+    messages: AtomicU64,
+    bytes: AtomicU64,
+    sysex: AtomicU64,
+    dropped: AtomicU64,
+    reconnects: AtomicU64,
+    errors: AtomicU64,
+    latency: Mutex<LatencyHistogram>,
+    rtt: Mutex<LatencyHistogram>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            inner: Arc::new(Counters {
+                started: Instant::now(),
+                messages: AtomicU64::new(0),
+                bytes: AtomicU64::new(0),
+                sysex: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+                reconnects: AtomicU64::new(0),
+                errors: AtomicU64::new(0),
+                latency: Mutex::new(LatencyHistogram::default()),
+                rtt: Mutex::new(LatencyHistogram::default()),
+            }),
+        }
+    }
+}
+
+impl Metrics {
+    /// Create a new set of counters
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a MIDI message forwarded by the bridge
+    pub fn record_message(&self, message: &[u8]) {
+        self.inner.messages.fetch_add(1, Ordering::Relaxed);
+        self.inner
+            .bytes
+            .fetch_add(message.len() as u64, Ordering::Relaxed);
+        if message.first() == Some(&0xF0) {
+            self.inner.sysex.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a MIDI message that could not be forwarded
+    pub fn record_drop(&self) {
+        self.inner.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a re-opened connection (MIDI port or network)
+    pub fn record_reconnect(&self) {
+        self.inner.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an error encountered by the bridge
+    pub fn record_error(&self) {
+        self.inner.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Add a latency measure to the histogram
+    pub fn record_latency(&self, latency: Duration) {
+        self.inner
+            .latency
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .record(latency);
+    }
+
+    /// Add a network round trip time measure, taken by the [Heartbeat](crate::net::heartbeat::Heartbeat)
+    pub fn record_rtt(&self, rtt: Duration) {
+        self.inner
+            .rtt
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .record(rtt);
+    }
+
+    /// Take a snapshot of the counters
+    pub fn snapshot(&self) -> Stats {
+        let latency = self
+            .inner
+            .latency
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+        let rtt = self
+            .inner
+            .rtt
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+
+        Stats {
+            uptime: self.inner.started.elapsed(),
+            messages: self.inner.messages.load(Ordering::Relaxed),
+            bytes: self.inner.bytes.load(Ordering::Relaxed),
+            sysex: self.inner.sysex.load(Ordering::Relaxed),
+            dropped: self.inner.dropped.load(Ordering::Relaxed),
+            reconnects: self.inner.reconnects.load(Ordering::Relaxed),
+            errors: self.inner.errors.load(Ordering::Relaxed),
+            latency,
+            rtt,
+        }
+    }
+}
+
+//
+//	STATS SNAPSHOT
+//
+
+/// Snapshot of the [Metrics] of a bridge
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// time elapsed since the bridge creation
+    pub uptime: Duration,
+    /// number of forwarded MIDI messages
+    pub messages: u64,
+    /// number of forwarded MIDI bytes
+    pub bytes: u64,
+    /// number of forwarded SysEx messages
+    pub sysex: u64,
+    /// number of MIDI messages that could not be forwarded
+    pub dropped: u64,
+    /// number of re-opened connections
+    pub reconnects: u64,
+    /// number of encountered errors
+    pub errors: u64,
+    /// one-way network latency, estimated by the heartbeat as half of each round trip time
+    pub latency: LatencyHistogram,
+    /// network round trip time measured by the heartbeat
+    pub rtt: LatencyHistogram,
+}
+
+//
+//	LATENCY HISTOGRAM
+//
+
+/// Histogram of latency measures, with logarithmic buckets from 100µs to 1s
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; LATENCY_BUCKETS_US.len() + 1],
+    count: u64,
+    sum: Duration,
+    min: Option<Duration>,
+    max: Option<Duration>,
+}
+
+impl LatencyHistogram {
+    /// Add a latency measure
+    pub fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros();
+        let bucket = LATENCY_BUCKETS_US
+            .iter()
+            .position(|bound| micros <= *bound as u128)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += latency;
+        self.min = Some(self.min.map_or(latency, |min| min.min(latency)));
+        self.max = Some(self.max.map_or(latency, |max| max.max(latency)));
+    }
+
+    /// Number of measures
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Smallest measure
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    /// Largest measure
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    /// Average of the measures
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count {
+            0 => None,
+            count => Some(Duration::from_nanos(
+                (self.sum.as_nanos() / count as u128) as u64,
+            )),
+        }
+    }
+
+    /// Upper bound of the bucket containing the `p`-th percentile (`p` between 0 and 100),
+    /// the largest measure being returned for the unbounded bucket
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((p.clamp(0., 100.) / 100.) * self.count as f64)
+            .ceil()
+            .max(1.) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return match LATENCY_BUCKETS_US.get(bucket) {
+                    Some(bound) => Some(Duration::from_micros(*bound).min(self.max?)),
+                    None => self.max,
+                };
+            }
+        }
+        self.max
+    }
+
+    /// Iterate over the buckets as `(upper bound, number of measures)`, the last bucket having no upper bound
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        self.buckets.iter().enumerate().map(|(bucket, count)| {
+            (
+                LATENCY_BUCKETS_US
+                    .get(bucket)
+                    .map(|bound| Duration::from_micros(*bound)),
+                *count,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        let metrics = Metrics::new();
+        let handle = metrics.clone();
+
+        handle.record_message(&[0x90, 0x3C, 0x40]);
+        handle.record_message(&[0xF0, 0x43, 0x10, 0xF7]);
+        handle.record_drop();
+        handle.record_reconnect();
+        handle.record_error();
+
+        let stats = metrics.snapshot();
+        assert_eq!(stats.messages, 2);
+        assert_eq!(stats.bytes, 7);
+        assert_eq!(stats.sysex, 1);
+        assert_eq!(stats.dropped, 1);
+        assert_eq!(stats.reconnects, 1);
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.latency.count(), 0);
+        assert_eq!(stats.rtt.count(), 0);
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile(50.), None);
+
+        for micros in [80, 90, 400, 700, 3_000, 2_000_000] {
+            histogram.record(Duration::from_micros(micros));
+        }
+
+        assert_eq!(histogram.count(), 6);
+        assert_eq!(histogram.min(), Some(Duration::from_micros(80)));
+        assert_eq!(histogram.max(), Some(Duration::from_secs(2)));
+        assert_eq!(histogram.percentile(0.), Some(Duration::from_micros(100)));
+        assert_eq!(histogram.percentile(50.), Some(Duration::from_micros(500)));
+        assert_eq!(histogram.percentile(100.), Some(Duration::from_secs(2)));
+        assert_eq!(histogram.buckets().map(|(_, count)| count).sum::<u64>(), 6);
+    }
+}
@@ -0,0 +1,222 @@
+use std::time::{Duration, Instant};
+
+//
+//	CLOCK REGENERATION
+//
+
+/// Timing Clock, sent 24 times per quarter note
+pub const CLOCK: u8 = 0xF8;
+/// Song Position Pointer, Start, Continue and Stop, after which the incoming clock is followed from scratch
+const TRANSPORT: [u8; 4] = [0xF2, 0xFA, 0xFB, 0xFC];
+/// Silence after which the incoming clock is considered stopped, its tempo being estimated again from the next tick
+const MAX_GAP: Duration = Duration::from_secs(1);
+/// Shortest interval between two ticks (24 ticks per quarter note at 1000 BPM)
+const MIN_PERIOD: f64 = 0.0025;
+
+/// Options of the [ClockRegenerator]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockConfig {
+    /// weight of the past ticks in the tempo and phase estimation, from 0 (following each incoming tick)
+    /// to close to 1 (steadiest, but slow to follow tempo changes)
+    pub smoothing: f64,
+    /// network latency compensated for, the generated ticks being sent this much ahead of the incoming ones
+    pub latency: Duration,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            smoothing: 0.9,
+            latency: Duration::ZERO,
+        }
+    }
+}
+
+/// Replace an incoming MIDI Clock suffering from network jitter by a locally generated, smoothed one
+///
+/// The incoming ticks are absorbed by [ClockRegenerator::receive], which estimates their tempo and phase with
+/// an alpha-beta filter, and the ticks generated in their place are returned by [ClockRegenerator::poll] once due.
+/// As many ticks are generated as received, plus the ones anticipated to compensate for the latency,
+/// so the song position is kept: if the incoming clock stops, the generated one stops too. The transport messages are let through and resynchronise the generated clock.
+#[derive(Debug, Clone)]
+pub struct ClockRegenerator {
+    config: ClockConfig,
+    /// weight of the phase error in the phase and period corrections
+    alpha: f64,
+    beta: f64,
+    /// reference of the times, in seconds
+    origin: Instant,
+    /// smoothed arrival time of the last incoming tick and its number since the last resynchronisation
+    last: Option<(f64, u64)>,
+    /// estimated interval between two ticks, in seconds
+    period: Option<f64>,
+    /// ticks generated since the last resynchronisation
+    sent: u64,
+}
+
+impl ClockRegenerator {
+    /// Create a new regenerator, waiting for the first incoming tick
+    pub fn new(config: ClockConfig) -> Self {
+        let alpha = (1.0 - config.smoothing).clamp(0.01, 1.0);
+        ClockRegenerator {
+            config,
+            alpha,
+            // critically damped
+            beta: alpha * alpha / (2.0 - alpha),
+            origin: Instant::now(),
+            last: None,
+            period: None,
+            sent: 0,
+        }
+    }
+
+    /// Estimated tempo of the incoming clock, in beats per minute
+    pub fn bpm(&self) -> Option<f64> {
+        self.period.map(|period| 60.0 / (24.0 * period))
+    }
+
+    /// Process a message received at `now`, returning `false` if it is a tick to absorb rather than forward
+    pub fn receive(&mut self, msg: &[u8], now: Instant) -> bool {
+        match msg.first() {
+            Some(&CLOCK) => {
+                self.tick(self.secs(now));
+                false
+            }
+            Some(status) if TRANSPORT.contains(status) => {
+                self.last = None;
+                self.sent = 0;
+                true
+            }
+            _ => true,
+        }
+    }
+
+    /// Time at which the next tick is due, if any
+    pub fn next_tick(&self) -> Option<Instant> {
+        let (last, count) = self.last?;
+        let period = self.period.unwrap_or_default();
+        // ticks are generated ahead of the incoming ones only to compensate for the latency
+        let lead = match self.period {
+            Some(period) => (self.config.latency.as_secs_f64() / period).ceil() as u64,
+            None => 0,
+        };
+        if self.sent > count + lead {
+            return None;
+        }
+        let at =
+            last + (self.sent as f64 - count as f64) * period - self.config.latency.as_secs_f64();
+        Some(self.origin + Duration::from_secs_f64(at.max(0.0)))
+    }
+
+    /// Return the ticks due at `now`
+    pub fn poll(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let mut ticks = vec![];
+        while self.next_tick().is_some_and(|at| at <= now) {
+            self.sent += 1;
+            ticks.push(vec![CLOCK]);
+        }
+        ticks
+    }
+
+    fn secs(&self, time: Instant) -> f64 {
+        time.saturating_duration_since(self.origin).as_secs_f64()
+    }
+
+    /// Update the phase and period estimations with a tick received at `now`
+    fn tick(&mut self, now: f64) {
+        let Some((last, count)) = self.last else {
+            self.last = Some((now, 0));
+            return;
+        };
+        let elapsed = now - last;
+        self.last = Some(match self.period {
+            // the tempo of a clock resuming after a silence is estimated from its next tick
+            _ if elapsed > MAX_GAP.as_secs_f64() => (now, count + 1),
+            None => {
+                self.period = Some(elapsed.max(MIN_PERIOD));
+                (now, count + 1)
+            }
+            Some(period) => {
+                let predicted = last + period;
+                let error = now - predicted;
+                self.period = Some((period + self.beta * error).max(MIN_PERIOD));
+                (predicted + self.alpha * error, count + 1)
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ticks at 125 BPM (20ms apart), arriving up to 4ms late
+    fn jittery(start: Instant, count: u64) -> Vec<Instant> {
+        (0..count)
+            .map(|tick| start + Duration::from_millis(tick * 20 + (tick * 7) % 5))
+            .collect()
+    }
+
+    #[test]
+    fn test_regenerate() {
+        let start = Instant::now();
+        let mut clock = ClockRegenerator::new(ClockConfig::default());
+        let mut generated = vec![];
+        for at in jittery(start, 200) {
+            assert!(!clock.receive(&[CLOCK], at));
+            for _ in clock.poll(at) {
+                generated.push(at);
+            }
+            // nothing is generated ahead without latency to compensate
+            assert!(clock.next_tick().is_none_or(|next| next > at));
+        }
+        assert!((clock.bpm().unwrap() - 125.0).abs() < 2.0);
+        let at = start + Duration::from_secs(5);
+        assert_eq!(generated.len() + clock.poll(at).len(), 200);
+        assert!(clock.poll(at + Duration::from_secs(1)).is_empty());
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let start = Instant::now();
+        let mut clock = ClockRegenerator::new(ClockConfig::default());
+        let mut scheduled = vec![];
+        for at in jittery(start, 100) {
+            clock.receive(&[CLOCK], at);
+            while let Some(next) = clock.next_tick().filter(|next| *next <= at) {
+                scheduled.push(next);
+                clock.poll(next);
+            }
+        }
+        // the generated ticks are evenly spaced despite the jitter, once the tempo is found
+        for ticks in scheduled[50..].windows(2) {
+            let interval = ticks[1] - ticks[0];
+            assert!(interval > Duration::from_millis(19) && interval < Duration::from_millis(21));
+        }
+    }
+
+    #[test]
+    fn test_latency() {
+        let start = Instant::now();
+        let mut clock = ClockRegenerator::new(ClockConfig {
+            smoothing: 0.0,
+            latency: Duration::from_millis(30),
+        });
+        for tick in 0..10 {
+            clock.receive(&[CLOCK], start + Duration::from_millis(tick * 20));
+        }
+        let now = start + Duration::from_millis(180);
+        // two ticks ahead of the last incoming one, compensating 30ms at 20ms per tick
+        assert_eq!(clock.poll(now + Duration::from_millis(30)).len(), 12);
+        assert_eq!(clock.next_tick(), None);
+
+        // transport messages are forwarded and restart the count
+        assert!(clock.receive(&[0xFA], now));
+        assert!(clock.receive(&[0x90, 0x40, 0x7F], now));
+        assert_eq!(clock.next_tick(), None);
+        clock.receive(&[CLOCK], now);
+        // the first tick is 30ms late already, the next one 10ms late
+        assert_eq!(clock.poll(now).len(), 2);
+        assert_eq!(clock.next_tick(), Some(now + Duration::from_millis(10)));
+    }
+}
@@ -0,0 +1,430 @@
+use super::{MergeStream, MidiPort, PortDirection, PortEvent, PortWatcher};
+use crate::lifecycle::{Event, Lifecycle};
+use crate::net::Notifier;
+use crate::{metrics::Metrics, MidiError};
+use log::{info, trace, warn};
+use midir::{MidiInputConnection, MidiOutputConnection};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::RecvTimeoutError,
+    Arc, Mutex, MutexGuard,
+};
+
+/// Connection shared between its owner and the thread re-opening it
+type Shared<C> = Arc<Mutex<Option<C>>>;
+
+/// Function opening a new connection to the given port
+type Reopen<C> = Box<dyn FnMut(&MidiPort) -> Result<C, MidiError> + Send>;
+
+fn lock<C>(conn: &Shared<C>) -> MutexGuard<'_, Option<C>> {
+    conn.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+//
+//	INPUT CONNECTION
+//
+
+/// Origin of the messages forwarded by an [InputConnection]
+enum Source {
+    /// MIDI input port opened with [midir], kept open as long as the connection
+    Port(#[allow(dead_code)] MidiInputConnection<()>),
+    /// messages sent by the caller to the channel of the bridge, see [InputConnection::channel]
+    #[cfg(any(test, feature = "testing"))]
+    Channel,
+}
+
+/// Connection to a MIDI input port, forwarding every received message to the [Receiver](std::sync::mpsc::Receiver)
+/// returned by [new_receiver](super::new_receiver)
+///
+/// With [InputConnection::auto_reconnect], the connection is closed when its port disappears and re-opened
+/// as soon as a matching port is plugged back, the messages keep flowing through the same channel.
+pub struct InputConnection {
+    port: MidiPort,
+    conn: Shared<Source>,
+    reopen: Option<Reopen<Source>>,
+    supervisor: Option<Supervisor>,
+    metrics: Metrics,
+    lifecycle: Lifecycle,
+    notifier: Notifier,
+}
+
+impl InputConnection {
+    pub(super) fn new(
+        port: MidiPort,
+        conn: MidiInputConnection<()>,
+        mut reopen: impl FnMut(&MidiPort) -> Result<MidiInputConnection<()>, MidiError> + Send + 'static,
+        notifier: Notifier,
+        metrics: Metrics,
+        lifecycle: Lifecycle,
+    ) -> Self {
+        InputConnection {
+            port,
+            conn: Arc::new(Mutex::new(Some(Source::Port(conn)))),
+            reopen: Some(Box::new(move |port| reopen(port).map(Source::Port))),
+            supervisor: None,
+            metrics,
+            lifecycle,
+            notifier,
+        }
+    }
+
+    /// Return a connection standing for `port`, without opening any MIDI port
+    ///
+    /// The messages of the port are the ones the caller sends to the channel given along with it to
+    /// [Sender::new](crate::net::Sender::new), waking the sender with its [InputConnection::notifier].
+    /// It never gets unplugged, [InputConnection::auto_reconnect] having no effect on it. Useful to run a sender
+    /// without any MIDI backend in tests, hence only available with the `testing` feature.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn channel(port: MidiPort) -> Self {
+        InputConnection {
+            port,
+            conn: Arc::new(Mutex::new(Some(Source::Channel))),
+            reopen: None,
+            supervisor: None,
+            metrics: Metrics::new(),
+            lifecycle: Lifecycle::new(),
+            notifier: Notifier::new(),
+        }
+    }
+
+    /// Follow the MIDI input ports seen by `watcher` (usually [PortWatcher::shared]), closing the connection when
+    /// its port is unplugged and re-opening it when a port with the same id or name appears
+    pub fn auto_reconnect(&mut self, watcher: &PortWatcher) {
+        if let Some(reopen) = self.reopen.take() {
+            self.supervisor = Some(Supervisor::spawn(
+                PortDirection::Input,
+                self.port.clone(),
+                watcher.clone(),
+                Arc::clone(&self.conn),
+                reopen,
+                self.metrics.clone(),
+                self.lifecycle.clone(),
+            ));
+        }
+    }
+
+    /// Return `true` if the connection to the MIDI port is currently open
+    pub fn is_connected(&self) -> bool {
+        lock(&self.conn).is_some()
+    }
+
+    /// Return the MIDI port this connection was opened on
+    pub fn port(&self) -> &MidiPort {
+        &self.port
+    }
+
+    /// Return the [Metrics] in which reconnections are counted
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Return the [Lifecycle] notified of the port unplugs and reconnections
+    pub fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    /// Return the [Notifier] called after each message forwarded to the channel
+    pub fn notifier(&self) -> &Notifier {
+        &self.notifier
+    }
+}
+
+//
+//	OUTPUT CONNECTION
+//
+
+/// Destination of the messages sent through an [OutputConnection]
+enum Sink {
+    /// MIDI output port opened with [midir]
+    Port(MidiOutputConnection),
+    /// channel standing for a MIDI port, see [OutputConnection::channel]
+    #[cfg(any(test, feature = "testing"))]
+    Channel(std::sync::mpsc::Sender<Vec<u8>>),
+}
+
+impl Sink {
+    fn send(&mut self, message: &[u8]) -> Result<(), MidiError> {
+        match self {
+            Sink::Port(conn) => Ok(conn.send(message)?),
+            #[cfg(any(test, feature = "testing"))]
+            Sink::Channel(tx) => {
+                if tx.send(message.to_vec()).is_err() {
+                    trace!("channel closed, drop {:?}", message);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Connection to a MIDI output port
+///
+/// With [OutputConnection::auto_reconnect], the connection is closed when its port disappears and re-opened
+/// as soon as a matching port is plugged back. Messages sent in the meantime are dropped (and counted in its [Metrics]).
+pub struct OutputConnection {
+    port: MidiPort,
+    conn: Shared<Sink>,
+    reopen: Option<Reopen<Sink>>,
+    supervisor: Option<Supervisor>,
+    metrics: Metrics,
+    lifecycle: Lifecycle,
+    /// stream of the messages sent through a connection returned by [OutputConnection::merge]
+    merge: Option<Mutex<MergeStream>>,
+}
+
+impl OutputConnection {
+    pub(super) fn new(
+        port: MidiPort,
+        conn: MidiOutputConnection,
+        mut reopen: impl FnMut(&MidiPort) -> Result<MidiOutputConnection, MidiError> + Send + 'static,
+        metrics: Metrics,
+        lifecycle: Lifecycle,
+    ) -> Self {
+        OutputConnection {
+            port,
+            conn: Arc::new(Mutex::new(Some(Sink::Port(conn)))),
+            reopen: Some(Box::new(move |port| reopen(port).map(Sink::Port))),
+            supervisor: None,
+            metrics,
+            lifecycle,
+            merge: None,
+        }
+    }
+
+    /// Return a connection standing for `port`, sending the messages to the returned channel instead of a MIDI port
+    ///
+    /// It never gets unplugged, [OutputConnection::auto_reconnect] having no effect on it. Useful to record the
+    /// messages a receiver outputs in tests, hence only available with the `testing` feature.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn channel(port: MidiPort) -> (Self, std::sync::mpsc::Receiver<Vec<u8>>) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let conn = OutputConnection {
+            port,
+            conn: Arc::new(Mutex::new(Some(Sink::Channel(tx)))),
+            reopen: None,
+            supervisor: None,
+            metrics: Metrics::new(),
+            lifecycle: Lifecycle::new(),
+            merge: None,
+        };
+        (conn, rx)
+    }
+
+    /// Return a connection sending to the same port, for one of the streams merged into it
+    ///
+    /// The messages sent through it are cut into complete messages by a [MergeStream] shifting their channel
+    /// by `channel_offset`, and each of them is sent at once, so the messages of the merged streams never interleave.
+    /// It shares the [Metrics] of this connection, and follows its reconnections, but has its own [Lifecycle]
+    /// and leaves the port open when dropped.
+    pub fn merge(&self, channel_offset: u8) -> OutputConnection {
+        OutputConnection {
+            port: self.port.clone(),
+            conn: Arc::clone(&self.conn),
+            reopen: None,
+            supervisor: None,
+            metrics: self.metrics.clone(),
+            lifecycle: Lifecycle::new(),
+            merge: Some(Mutex::new(MergeStream::new(channel_offset))),
+        }
+    }
+
+    /// Follow the MIDI output ports seen by `watcher` (usually [PortWatcher::shared]), closing the connection when
+    /// its port is unplugged and re-opening it when a port with the same id or name appears
+    pub fn auto_reconnect(&mut self, watcher: &PortWatcher) {
+        if let Some(reopen) = self.reopen.take() {
+            self.supervisor = Some(Supervisor::spawn(
+                PortDirection::Output,
+                self.port.clone(),
+                watcher.clone(),
+                Arc::clone(&self.conn),
+                reopen,
+                self.metrics.clone(),
+                self.lifecycle.clone(),
+            ));
+        }
+    }
+
+    /// Send a MIDI message to the port, the message is dropped if the port is currently unplugged
+    pub fn send(&self, message: &[u8]) -> Result<(), MidiError> {
+        let Some(merge) = self.merge.as_ref() else {
+            return self.send_message(message);
+        };
+        let messages = merge
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(message);
+        messages
+            .iter()
+            .try_for_each(|message| self.send_message(message))
+    }
+
+    fn send_message(&self, message: &[u8]) -> Result<(), MidiError> {
+        match lock(&self.conn).as_mut() {
+            Some(conn) => conn.send(message),
+            None => {
+                trace!("MIDI port unplugged, drop {:?}", message);
+                self.metrics.record_drop();
+                Ok(())
+            }
+        }
+    }
+
+    /// Return `true` if the connection to the MIDI port is currently open
+    pub fn is_connected(&self) -> bool {
+        lock(&self.conn).is_some()
+    }
+
+    /// Return the MIDI port this connection was opened on
+    pub fn port(&self) -> &MidiPort {
+        &self.port
+    }
+
+    /// Return the [Metrics] in which reconnections are counted
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Return the [Lifecycle] notified of the port unplugs and reconnections
+    pub fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+}
+
+//
+//	RECONNECTION SUPERVISOR
+//
+
+/// Background thread following the [PortEvent]s of a [PortWatcher] to close and re-open a connection,
+/// keeping the watcher running while it lives
+struct Supervisor {
+    stop: Arc<AtomicBool>,
+}
+
+impl Supervisor {
+    fn spawn<C: Send + 'static>(
+        direction: PortDirection,
+        mut port: MidiPort,
+        watcher: PortWatcher,
+        conn: Shared<C>,
+        mut reopen: impl FnMut(&MidiPort) -> Result<C, MidiError> + Send + 'static,
+        metrics: Metrics,
+        lifecycle: Lifecycle,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+
+        // subscribed right away, so no event is missed while the thread starts
+        let events = watcher.subscribe();
+        std::thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                match events.recv_timeout(watcher.interval()) {
+                    Ok(PortEvent::Removed(dir, removed)) if dir == direction => {
+                        if removed.id == port.id && lock(&conn).take().is_some() {
+                            warn!("MIDI port {} unplugged, waiting for it", port);
+                            lifecycle.emit(Event::Reconnecting(port.to_string()));
+                        }
+                    }
+                    Ok(PortEvent::Added(dir, added)) if dir == direction => {
+                        if lock(&conn).is_some() || (added.id != port.id && added.name != port.name)
+                        {
+                            continue;
+                        }
+                        match reopen(&added) {
+                            Ok(_) if thread_stop.load(Ordering::Relaxed) => break,
+                            Ok(new_conn) => {
+                                info!("MIDI port {} plugged back, reconnected", added);
+                                *lock(&conn) = Some(new_conn);
+                                metrics.record_reconnect();
+                                lifecycle.emit(Event::Reconnected(added.to_string()));
+                                port = added;
+                            }
+                            Err(err) => warn!("unable to reconnect to {} ({})", added, err),
+                        }
+                    }
+                    Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+
+        Supervisor { stop }
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// the supervisor thread is not joined, so the port is closed right away instead of when the thread notices it has to stop
+impl Drop for InputConnection {
+    fn drop(&mut self) {
+        self.supervisor.take();
+        lock(&self.conn).take();
+    }
+}
+
+impl Drop for OutputConnection {
+    fn drop(&mut self) {
+        self.supervisor.take();
+        // the merged connections leave the port to its owner
+        if self.merge.is_none() {
+            lock(&self.conn).take();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reconnect() {
+        let synth = MidiPort {
+            index: 0,
+            id: "24:0".into(),
+            name: "Synth".into(),
+        };
+        let lists = Arc::new(Mutex::new(vec![synth.clone()]));
+        let watched = lists.clone();
+        let watcher = PortWatcher::with_lists(Duration::from_millis(10), move || {
+            Ok((vec![], watched.lock().unwrap().clone()))
+        });
+
+        // every connection to the port goes through the same channel
+        let (tx, rx) = std::sync::mpsc::channel();
+        let reopened = tx.clone();
+        let mut conn = OutputConnection {
+            port: synth.clone(),
+            conn: Arc::new(Mutex::new(Some(Sink::Channel(tx)))),
+            reopen: Some(Box::new(move |_| Ok(Sink::Channel(reopened.clone())))),
+            supervisor: None,
+            metrics: Metrics::new(),
+            lifecycle: Lifecycle::new(),
+            merge: None,
+        };
+        let events = conn.lifecycle().subscribe();
+        conn.auto_reconnect(&watcher);
+
+        let timeout = Duration::from_secs(1);
+        lists.lock().unwrap().clear();
+        assert_eq!(
+            events.recv_timeout(timeout).unwrap(),
+            Event::Reconnecting(synth.to_string())
+        );
+        assert!(!conn.is_connected());
+        conn.send(&[0x90, 0x40, 0x7F]).unwrap();
+        assert_eq!(conn.metrics().snapshot().dropped, 1);
+
+        lists.lock().unwrap().push(synth.clone());
+        assert_eq!(
+            events.recv_timeout(timeout).unwrap(),
+            Event::Reconnected(synth.to_string())
+        );
+        conn.send(&[0x80, 0x40, 0]).unwrap();
+        assert_eq!(rx.try_recv().unwrap(), [0x80, 0x40, 0]);
+        assert_eq!(conn.metrics().snapshot().reconnects, 1);
+    }
+}
@@ -0,0 +1,285 @@
+use crate::MidiError;
+
+//
+//	CHANNEL FILTER
+//
+
+/// Number of MIDI channels, numbered from 0 to 15
+pub const CHANNELS: u8 = 16;
+
+/// Controller of the sustain pedal, keeping the released notes sounding while down
+const SUSTAIN: u8 = 64;
+/// Channel mode messages sent by [panic_messages]: All Sound Off, All Notes Off and Reset All Controllers
+const PANIC_CONTROLLERS: [u8; 3] = [120, 123, 121];
+
+/// Return the messages silencing every channel of a synth: All Sound Off, All Notes Off and Reset All Controllers,
+/// followed by a Note Off for each of the 128 notes when `note_offs` is set, for synths ignoring the channel mode messages
+pub fn panic_messages(note_offs: bool) -> Vec<Vec<u8>> {
+    let mut messages = vec![];
+    for channel in 0..CHANNELS {
+        messages.extend(
+            PANIC_CONTROLLERS
+                .iter()
+                .map(|controller| vec![0xB0 | channel, *controller, 0]),
+        );
+        if note_offs {
+            messages.extend((0..128).map(|note| vec![0x80 | channel, note, 0]));
+        }
+    }
+    messages
+}
+
+/// Request changing the [ChannelFilter] of a bridge, applied by [ChannelFilter::apply]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// stop (`true`) or restart (`false`) forwarding every message
+    Pause(bool),
+    /// stop (`true`) or restart (`false`) forwarding the messages of the given channel
+    Mute(u8, bool),
+    /// add (`true`) or remove (`false`) the given channel from the soloed ones
+    Solo(u8, bool),
+    /// send the [panic_messages], with a Note Off for every note when set
+    Panic(bool),
+}
+
+/// Pause, mute and solo state of a bridge, deciding which MIDI messages are forwarded
+///
+/// It keeps track of the notes it forwarded, so every note held on a channel it stops forwarding
+/// (all of them when pausing) is released by the Note Off returned by the setters, along with the sustain pedal.
+/// Only channel messages are muted, system messages being forwarded unless the filter is paused.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChannelFilter {
+    paused: bool,
+    muted: u16,
+    soloed: u16,
+    /// notes forwarded and not released yet, one bit per note and per channel
+    held: [u128; CHANNELS as usize],
+    /// channels whose sustain pedal is down
+    sustained: u16,
+}
+
+impl ChannelFilter {
+    /// Create a new filter forwarding every message
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return `true` if no message is forwarded
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Return `true` if the messages of `channel` are forwarded
+    pub fn is_open(&self, channel: u8) -> bool {
+        let bit = 1 << (channel & 0x0F);
+        !self.paused && self.muted & bit == 0 && (self.soloed == 0 || self.soloed & bit != 0)
+    }
+
+    /// Stop or restart forwarding every message, returning the messages releasing the held notes
+    pub fn set_paused(&mut self, paused: bool) -> Vec<Vec<u8>> {
+        self.paused = paused;
+        self.release()
+    }
+
+    /// Stop or restart forwarding the messages of `channel`, returning the messages releasing its held notes
+    pub fn set_muted(&mut self, channel: u8, muted: bool) -> Vec<Vec<u8>> {
+        set_bit(&mut self.muted, channel, muted);
+        self.release()
+    }
+
+    /// Add or remove `channel` from the soloed ones, the other channels not being forwarded while one is soloed.
+    /// Returns the messages releasing the notes held on the channels not forwarded anymore.
+    pub fn set_soloed(&mut self, channel: u8, soloed: bool) -> Vec<Vec<u8>> {
+        set_bit(&mut self.soloed, channel, soloed);
+        self.release()
+    }
+
+    /// Forget the held notes and return the [panic_messages], to be sent whatever the pause, mute and solo state
+    pub fn panic(&mut self, note_offs: bool) -> Vec<Vec<u8>> {
+        self.held = Default::default();
+        self.sustained = 0;
+        panic_messages(note_offs)
+    }
+
+    /// Apply a [Control], returning the messages to send: the releases of the held notes or the [panic_messages]
+    pub fn apply(&mut self, control: Control) -> Vec<Vec<u8>> {
+        match control {
+            Control::Pause(paused) => self.set_paused(paused),
+            Control::Mute(channel, muted) => self.set_muted(channel, muted),
+            Control::Solo(channel, soloed) => self.set_soloed(channel, soloed),
+            Control::Panic(note_offs) => self.panic(note_offs),
+        }
+    }
+
+    /// Return `true` if `msg` has to be forwarded, keeping track of the held notes
+    pub fn forward(&mut self, msg: &[u8]) -> bool {
+        let Some(&status) = msg.first() else {
+            return false;
+        };
+        if !(0x80..0xF0).contains(&status) {
+            return !self.paused;
+        }
+        let channel = status & 0x0F;
+        if !self.is_open(channel) {
+            return false;
+        }
+
+        let held = &mut self.held[channel as usize];
+        match (status & 0xF0, msg.get(1), msg.get(2)) {
+            (0x90, Some(&note), Some(&velocity)) if velocity > 0 => *held |= 1 << (note & 0x7F),
+            (0x80 | 0x90, Some(&note), _) => *held &= !(1 << (note & 0x7F)),
+            (0xB0, Some(&SUSTAIN), Some(&value)) => {
+                set_bit(&mut self.sustained, channel, value >= 64)
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Forget the held notes and return the Note Offs releasing them, along with the sustain pedals,
+    /// whatever the pause, mute and solo state. Used when the Note Offs of the forwarded notes will never come.
+    pub fn release_all(&mut self) -> Vec<Vec<u8>> {
+        self.release_channels(0..CHANNELS)
+    }
+
+    /// Release the notes and the sustain pedal of the channels not forwarded anymore
+    fn release(&mut self) -> Vec<Vec<u8>> {
+        let closed: Vec<u8> = (0..CHANNELS)
+            .filter(|channel| !self.is_open(*channel))
+            .collect();
+        self.release_channels(closed)
+    }
+
+    fn release_channels(&mut self, channels: impl IntoIterator<Item = u8>) -> Vec<Vec<u8>> {
+        let mut messages = vec![];
+        for channel in channels {
+            let held = std::mem::take(&mut self.held[channel as usize]);
+            messages.extend(
+                (0..128u8)
+                    .filter(|note| held & (1 << note) != 0)
+                    .map(|note| vec![0x80 | channel, note, 0]),
+            );
+            if self.sustained & (1 << channel) != 0 {
+                set_bit(&mut self.sustained, channel, false);
+                messages.push(vec![0xB0 | channel, SUSTAIN, 0]);
+            }
+        }
+        messages
+    }
+}
+
+/// Return `channel` if it is a valid MIDI channel
+pub(crate) fn check_channel(channel: u8) -> Result<u8, MidiError> {
+    if channel < CHANNELS {
+        Ok(channel)
+    } else {
+        Err(MidiError::InvalidChannel(channel))
+    }
+}
+
+fn set_bit(mask: &mut u16, channel: u8, set: bool) {
+    let bit = 1 << (channel & 0x0F);
+    if set {
+        *mask |= bit;
+    } else {
+        *mask &= !bit;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pause() {
+        let mut filter = ChannelFilter::new();
+        assert!(filter.forward(&[0x90, 0x3C, 0x40]));
+        assert!(filter.forward(&[0x91, 0x40, 0x40]));
+        assert!(filter.forward(&[0x91, 0x43, 0x40]));
+        assert!(filter.forward(&[0x91, 0x43, 0x00]));
+        assert!(filter.forward(&[0xB1, SUSTAIN, 0x7F]));
+
+        // the notes still held are released
+        assert_eq!(
+            filter.set_paused(true),
+            vec![
+                vec![0x80, 0x3C, 0],
+                vec![0x81, 0x40, 0],
+                vec![0xB1, SUSTAIN, 0]
+            ]
+        );
+        assert!(!filter.forward(&[0x90, 0x3C, 0x40]));
+        assert!(!filter.forward(&[0xF8]));
+
+        assert!(filter.set_paused(false).is_empty());
+        assert!(filter.forward(&[0xF8]));
+        assert!(filter.set_paused(true).is_empty());
+    }
+
+    #[test]
+    fn test_mute_solo() {
+        let mut filter = ChannelFilter::new();
+        assert!(filter.forward(&[0x90, 0x3C, 0x40]));
+        assert!(filter.forward(&[0x99, 0x24, 0x40]));
+
+        assert_eq!(filter.set_muted(9, true), vec![vec![0x89, 0x24, 0]]);
+        assert!(!filter.forward(&[0x99, 0x24, 0x40]));
+        // system messages are not muted
+        assert!(filter.forward(&[0xF8]));
+
+        // soloing a channel mutes the other ones
+        assert_eq!(filter.set_soloed(1, true), vec![vec![0x80, 0x3C, 0]]);
+        assert!(filter.forward(&[0x91, 0x3C, 0x40]));
+        assert!(!filter.forward(&[0x92, 0x3C, 0x40]));
+        assert!(filter.set_soloed(1, false).is_empty());
+        assert!(filter.forward(&[0x92, 0x3C, 0x40]));
+        assert!(!filter.is_open(9));
+
+        assert_eq!(
+            filter.apply(Control::Solo(9, true)),
+            vec![vec![0x81, 0x3C, 0], vec![0x82, 0x3C, 0]]
+        );
+        assert!(filter.apply(Control::Mute(9, false)).is_empty());
+        assert!(filter.forward(&[0x99, 0x24, 0x40]));
+    }
+
+    #[test]
+    fn test_panic() {
+        let messages = panic_messages(false);
+        assert_eq!(messages.len(), 16 * 3);
+        assert_eq!(messages[0], vec![0xB0, 120, 0]);
+        assert_eq!(messages[47], vec![0xBF, 121, 0]);
+
+        let messages = panic_messages(true);
+        assert_eq!(messages.len(), 16 * (3 + 128));
+        assert_eq!(messages[3], vec![0x80, 0, 0]);
+        assert_eq!(messages.last(), Some(&vec![0x8F, 127, 0]));
+
+        // the notes released by the panic are not released again
+        let mut filter = ChannelFilter::new();
+        assert!(filter.forward(&[0x90, 0x3C, 0x40]));
+        assert_eq!(filter.panic(false).len(), 16 * 3);
+        assert!(filter.set_paused(true).is_empty());
+    }
+
+    #[test]
+    fn test_release_all() {
+        let mut filter = ChannelFilter::new();
+        assert!(filter.forward(&[0x90, 0x3C, 0x40]));
+        assert!(filter.forward(&[0xB0, SUSTAIN, 0x7F]));
+        assert!(filter.forward(&[0x95, 0x40, 0x40]));
+        assert!(filter.set_muted(9, true).is_empty());
+
+        // the open channels are released too
+        assert_eq!(
+            filter.release_all(),
+            vec![
+                vec![0x80, 0x3C, 0],
+                vec![0xB0, SUSTAIN, 0],
+                vec![0x85, 0x40, 0]
+            ]
+        );
+        assert!(filter.release_all().is_empty());
+        assert!(!filter.is_open(9));
+    }
+}
@@ -0,0 +1,91 @@
+use super::{MidiParser, CHANNELS};
+
+//
+//	MERGE STREAM
+//
+
+/// Cut one of the streams merged into a MIDI out port into complete, self-contained messages
+///
+/// The stream is cut by a [MidiParser], holding fragments until the message they belong to is complete and
+/// expanding running status, so the messages of the other streams can be sent in between without corrupting
+/// a SysEx or changing the status applied to the following data bytes. Channel messages are shifted by the
+/// channel offset, wrapping from 15 to 0.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeStream {
+    channel_offset: u8,
+    parser: MidiParser,
+}
+
+impl MergeStream {
+    /// Create a new stream, its channel messages being shifted by `channel_offset` channels
+    pub fn new(channel_offset: u8) -> Self {
+        MergeStream {
+            channel_offset: channel_offset % CHANNELS,
+            ..Self::default()
+        }
+    }
+
+    /// Return the messages completed by `bytes`, shifted to their channel
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut messages = self.parser.parse(bytes);
+        for msg in messages.iter_mut() {
+            if (0x80..0xF0).contains(&msg[0]) {
+                msg[0] = msg[0] & 0xF0 | (msg[0] + self.channel_offset) & 0x0F;
+            }
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fragments() {
+        let mut stream = MergeStream::new(0);
+        assert!(stream.push(&[0xF0, 0x43, 0x10]).is_empty());
+        // real-time messages are not held
+        assert_eq!(stream.push(&[0xF8, 0x3E]), vec![vec![0xF8]]);
+        assert_eq!(
+            stream.push(&[0x12, 0xF7, 0x90, 0x3C]),
+            vec![vec![0xF0, 0x43, 0x10, 0x3E, 0x12, 0xF7]]
+        );
+        assert_eq!(stream.push(&[0x40]), vec![vec![0x90, 0x3C, 0x40]]);
+
+        // an unfinished message interrupted by another one is dropped
+        assert_eq!(
+            stream.push(&[0xF0, 0x43, 0xC0, 0x05]),
+            vec![vec![0xC0, 0x05]]
+        );
+        assert!(stream.push(&[0xF7]).is_empty());
+        assert_eq!(stream.push(&[0xF6]), vec![vec![0xF6]]);
+    }
+
+    #[test]
+    fn test_running_status() {
+        let mut stream = MergeStream::new(0);
+        assert_eq!(
+            stream.push(&[0x90, 0x3C, 0x40, 0x3E, 0x40, 0x40]),
+            vec![vec![0x90, 0x3C, 0x40], vec![0x90, 0x3E, 0x40]]
+        );
+        assert_eq!(stream.push(&[0x40]), vec![vec![0x90, 0x40, 0x40]]);
+        // system common messages cancel the running status
+        assert_eq!(stream.push(&[0xF3, 0x01]), vec![vec![0xF3, 0x01]]);
+        assert!(stream.push(&[0x3C, 0x40]).is_empty());
+    }
+
+    #[test]
+    fn test_channel_offset() {
+        let mut stream = MergeStream::new(4);
+        assert_eq!(
+            stream.push(&[0x90, 0x3C, 0x40, 0xCD, 0x05, 0x06, 0xF2, 0x00, 0x00]),
+            vec![
+                vec![0x94, 0x3C, 0x40],
+                vec![0xC1, 0x05],
+                vec![0xC1, 0x06],
+                vec![0xF2, 0x00, 0x00]
+            ]
+        );
+    }
+}
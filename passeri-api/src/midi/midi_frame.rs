@@ -1,41 +1,96 @@
+use log::trace;
+
 //
 //	MIDI PARSER
 //
 
-/// Struct used to parse Midi Message from incomming network messages
-/// It keep track of the current unfinished message
-#[derive(Debug, PartialEq, Eq)]
+/// Start and end of the System Exclusive messages
+const SYSEX: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+
+/// Number of data bytes following `status`, `None` for System Exclusive, ended by [SYSEX_END]
+fn data_len(status: u8) -> Option<usize> {
+    match status {
+        SYSEX => None,
+        0xC0..=0xDF | 0xF1 | 0xF3 => Some(1),
+        0x80..=0xEF | 0xF2 => Some(2),
+        _ => Some(0),
+    }
+}
+
+/// Struct used to parse Midi Message from incomming network messages or serial links
+///
+/// A message is returned as soon as its last byte arrives, the fragments being held until then, and running
+/// status is expanded so every message returned is self-contained. Real-time messages are let through as soon
+/// as they arrive, even in the middle of another one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct MidiParser {
-    buffer: Option<Vec<u8>>,
+    /// bytes of the message being received
+    pending: Vec<u8>,
+    /// status of the last channel message, applying to the data bytes received without status
+    running: Option<u8>,
 }
 
 impl MidiParser {
     /// Create a new MidiParser
     pub fn new() -> Self {
-        MidiParser { buffer: None }
+        Self::default()
     }
 
-    /// Parse all possible midi message in the given slice
+    /// Parse all the midi messages completed by the given slice
     pub fn parse(&mut self, src: &[u8]) -> Vec<Vec<u8>> {
-        let mut res: Vec<Vec<u8>> = vec![];
-
-        for elem in src {
-            if elem & 0x80 != 0 && *elem != 0xf7 {
-                if let Some(buff) = self.buffer.take() {
-                    res.push(buff);
+        let mut messages = vec![];
+        for &byte in src {
+            match byte {
+                // real-time messages may be sent in the middle of any other one
+                0xF8.. => messages.push(vec![byte]),
+                SYSEX_END if self.pending.first() == Some(&SYSEX) => {
+                    self.pending.push(byte);
+                    messages.push(std::mem::take(&mut self.pending));
+                }
+                SYSEX_END => trace!("drop a stray end of SysEx"),
+                0x80.. => {
+                    self.start(byte);
+                    // system common messages cancel the running status
+                    self.running = (byte < SYSEX).then_some(byte);
+                    messages.extend(self.complete());
+                }
+                _ => {
+                    if self.pending.is_empty() {
+                        let Some(running) = self.running else {
+                            trace!("drop the data byte {:#04x} without status", byte);
+                            continue;
+                        };
+                        self.pending.push(running);
+                    }
+                    self.pending.push(byte);
+                    messages.extend(self.complete());
                 }
-            }
-            match self.buffer.as_mut() {
-                Some(buf) => buf.push(*elem),
-                None => self.buffer = Some(vec![*elem]),
             }
         }
-        res
+        messages
     }
 
     /// Return the cached unfinished midi message
     pub fn flush(&mut self) -> Option<Vec<u8>> {
-        self.buffer.take()
+        (!self.pending.is_empty()).then(|| std::mem::take(&mut self.pending))
+    }
+
+    /// Start a new message with `status`, dropping the unfinished one
+    fn start(&mut self, status: u8) {
+        if !self.pending.is_empty() {
+            trace!("drop the unfinished message {:?}", self.pending);
+        }
+        self.pending = vec![status];
+    }
+
+    /// Return the pending message once complete
+    fn complete(&mut self) -> Option<Vec<u8>> {
+        let len = data_len(*self.pending.first()?)?;
+        if self.pending.len() <= len {
+            return None;
+        }
+        Some(std::mem::take(&mut self.pending))
     }
 }
 
@@ -43,44 +98,67 @@ impl MidiParser {
 mod tests {
     use super::*;
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
+    #[test]
+    fn test_midi_messages() {
+        let midi_messages: Vec<u8> = vec![
+            0x90, 0x3C, 0x40, // Note On, Middle C, Velocity 64
+            0x80, 0x3C, 0x40, // Note Off, Middle C, Velocity 64
+            0xB0, 0x07, 0x7F, // Control Change, Volume, Max
+            0xF0, // SysEx start
+            0x43, // Manufacturer ID (Yamaha)
+            0x10, // Device ID
+            0x3E, // Model ID
+            0x12, // Command ID
+            0x00, 0x7F, 0x00, // Parameters
+            0xF7, // SysEx end
+        ];
 
-        #[test]
-        fn test_midi_messages() {
-            let midi_messages: Vec<u8> = vec![
-                0x90, 0x3C, 0x40, // Note On, Middle C, Velocity 64
-                0x80, 0x3C, 0x40, // Note Off, Middle C, Velocity 64
-                0xB0, 0x07, 0x7F, // Control Change, Volume, Max
-                0xF0, // SysEx start
-                0x43, // Manufacturer ID (Yamaha)
-                0x10, // Device ID
-                0x3E, // Model ID
-                0x12, // Command ID
-                0x00, 0x7F, 0x00, // Parameters
-                0xF7, // SysEx end
-            ];
+        let expected: Vec<Vec<u8>> = vec![
+            vec![0x90, 0x3C, 0x40],
+            vec![0x80, 0x3C, 0x40],
+            vec![0xB0, 0x07, 0x7F],
+            vec![0xF0, 0x43, 0x10, 0x3E, 0x12, 0x00, 0x7F, 0x00, 0xF7],
+        ];
 
-            let expected: Vec<Vec<u8>> = vec![
-                vec![0x90, 0x3C, 0x40],
-                vec![0x80, 0x3C, 0x40],
-                vec![0xB0, 0x07, 0x7F],
-                vec![0xF0, 0x43, 0x10, 0x3E, 0x12, 0x00, 0x7F, 0x00, 0xF7],
-            ];
-
-            for chunk_size in 1..midi_messages.len() {
-                let mut midi_parser = MidiParser::new();
-                let mut out: Vec<Vec<u8>> = vec![];
-                for msg in midi_messages.chunks(chunk_size) {
-                    out.append(&mut midi_parser.parse(msg));
-                }
-                if let Some(res) = midi_parser.flush() {
-                    out.push(res);
-                }
-                assert_eq!(out, expected);
-                println!("{out:x?}");
+        for chunk_size in 1..midi_messages.len() {
+            let mut midi_parser = MidiParser::new();
+            let mut out: Vec<Vec<u8>> = vec![];
+            for msg in midi_messages.chunks(chunk_size) {
+                out.append(&mut midi_parser.parse(msg));
             }
+            if let Some(res) = midi_parser.flush() {
+                out.push(res);
+            }
+            assert_eq!(out, expected);
+            println!("{out:x?}");
         }
     }
+
+    #[test]
+    fn test_running_status() {
+        let mut parser = MidiParser::new();
+        assert_eq!(
+            parser.parse(&[0x90, 0x3C, 0x40, 0x3E, 0x40, 0x40]),
+            vec![vec![0x90, 0x3C, 0x40], vec![0x90, 0x3E, 0x40]]
+        );
+        // nor real-time messages, even the undefined ones
+        assert_eq!(
+            parser.parse(&[0x40, 0xF9, 0x3C, 0x00]),
+            vec![vec![0x90, 0x40, 0x40], vec![0xF9], vec![0x90, 0x3C, 0x00]]
+        );
+        // messages are returned without waiting for the next status
+        assert_eq!(parser.parse(&[0xC0, 0x05]), vec![vec![0xC0, 0x05]]);
+        // system common messages do
+        assert_eq!(parser.parse(&[0xF3, 0x01]), vec![vec![0xF3, 0x01]]);
+        assert!(parser.parse(&[0x3C, 0x40]).is_empty());
+    }
+
+    #[test]
+    fn test_flush() {
+        let mut parser = MidiParser::new();
+        // a real-time message does not interrupt a SysEx
+        assert_eq!(parser.parse(&[0xF0, 0x43, 0xF9, 0x10]), vec![vec![0xF9]]);
+        assert_eq!(parser.flush(), Some(vec![0xF0, 0x43, 0x10]));
+        assert_eq!(parser.flush(), None);
+    }
 }
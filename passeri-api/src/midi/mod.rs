@@ -1,106 +1,297 @@
-use std::{
-    process::exit,
-    sync::mpsc::{channel, Receiver},
-};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
 
+use crate::lifecycle::Lifecycle;
+use crate::metrics::Metrics;
+use crate::net::cable::{CablePayload, MAX_CABLES};
+use crate::net::Notifier;
+use crate::MidiError;
 use log::{info, trace};
-use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use midir::{
+    Ignore, MidiInput, MidiInputConnection, MidiInputPort, MidiOutput, MidiOutputConnection,
+    MidiOutputPort,
+};
 
+mod clock;
+pub use clock::{ClockConfig, ClockRegenerator, CLOCK};
+mod filter;
+pub(crate) use filter::check_channel;
+pub use filter::{panic_messages, ChannelFilter, Control, CHANNELS};
+mod merge;
+pub use merge::MergeStream;
 mod midi_frame;
 pub use midi_frame::MidiParser;
+mod port_selector;
+pub use port_selector::{MidiPort, PortSelector};
+mod port_watcher;
+pub use port_watcher::{PortDirection, PortEvent, PortWatcher, DEFAULT_WATCH_INTERVAL};
+mod connection;
+pub use connection::{InputConnection, OutputConnection};
+mod output;
+pub use output::Output;
+mod state;
+pub use state::{MidiState, SnapshotConfig};
 
 const LOOKUP_PORT_NAME: &str = "PASSERI_LOOKUP";
 // const LISTEN_PORT_NAME: &str = "PASSERI_LISTENER";
 // const EMITTER_PORT_NAME: &str = "PASSERI_EMITTER";
 
 /// Returns a vector of all MIDI input ports that [midir] can connect to
-pub fn get_availables_midi_in_port() -> Result<Vec<(usize, String)>, String> {
-    match MidiInput::new(LOOKUP_PORT_NAME) {
-        Ok(lookup_port) => {
-            let in_ports = lookup_port.ports();
-            Ok(in_ports
-                .into_iter()
-                .map(|port| lookup_port.port_name(&port).unwrap())
-                .enumerate()
-                .collect::<Vec<(usize, String)>>())
-        }
-        Err(_) => Err("unable to lookup available in ports".into()),
-    }
+pub fn get_availables_midi_in_port() -> Result<Vec<MidiPort>, MidiError> {
+    let lookup_port = new_midi_input(LOOKUP_PORT_NAME)?;
+    Ok(describe_in_ports(&lookup_port, &lookup_port.ports()))
 }
 
-/// Returns a vector of all MIDI input ports that [midir] can connect to
-pub fn get_availables_midi_out_port() -> Result<Vec<(usize, String)>, String> {
-    match MidiOutput::new(LOOKUP_PORT_NAME) {
-        Ok(lookup_port) => {
-            let out_ports = lookup_port.ports();
-            Ok(out_ports
-                .into_iter()
-                .map(|port| lookup_port.port_name(&port).unwrap())
-                .enumerate()
-                .collect::<Vec<(usize, String)>>())
-        }
-        Err(_) => Err("unable to lookup available out ports".into()),
-    }
+/// Returns a vector of all MIDI output ports that [midir] can connect to
+pub fn get_availables_midi_out_port() -> Result<Vec<MidiPort>, MidiError> {
+    let lookup_port = new_midi_output(LOOKUP_PORT_NAME)?;
+    Ok(describe_out_ports(&lookup_port, &lookup_port.ports()))
+}
+
+fn new_midi_input(name: &str) -> Result<MidiInput, MidiError> {
+    MidiInput::new(name).map_err(|source| MidiError::Init {
+        name: name.into(),
+        source,
+    })
+}
+
+fn new_midi_output(name: &str) -> Result<MidiOutput, MidiError> {
+    MidiOutput::new(name).map_err(|source| MidiError::Init {
+        name: name.into(),
+        source,
+    })
+}
+
+fn describe_in_ports(midi_in: &MidiInput, ports: &[MidiInputPort]) -> Vec<MidiPort> {
+    ports
+        .iter()
+        .enumerate()
+        .map(|(index, port)| MidiPort {
+            index,
+            id: port.id(),
+            name: midi_in.port_name(port).unwrap_or_default(),
+        })
+        .collect()
+}
+
+fn describe_out_ports(midi_out: &MidiOutput, ports: &[MidiOutputPort]) -> Vec<MidiPort> {
+    ports
+        .iter()
+        .enumerate()
+        .map(|(index, port)| MidiPort {
+            index,
+            id: port.id(),
+            name: midi_out.port_name(port).unwrap_or_default(),
+        })
+        .collect()
 }
 
 /// Tuple decribing incomming MIDI message: first part is timestamp, second one is [MidiFrame]
 pub type MidiPayload = (u64, Vec<u8>);
 
-/// Create a new [MidiOutputConnection] instance, which can be called to send MIDI message to the provided MIDI port
+/// Function forwarding the messages of an input port to its tunnel, returning `false` once the tunnel is closed
+type Forward = Arc<dyn Fn(MidiPayload) -> bool + Send + Sync>;
+
+/// Create a new [OutputConnection] instance, which can be called to send MIDI message to the provided MIDI port
 ///
 /// Under the hood, [midir] spawn a background listening thread which is waiting for any incomming call to the returned instance.
 ///
 /// # Arguments
-/// * `midi_port_index` - Index of a MIDI output port (you can get it from a [get_availables_midi_port] function call)
+/// * `port` - [PortSelector] matching exactly one MIDI output port (you can list them with a [get_availables_midi_out_port] function call)
+/// * `midi_port_name` - Name of the MIDI client created to connect to the port
 pub fn new_sender(
-    midi_port_index: usize,
+    port: &PortSelector,
+    midi_port_name: &str,
+) -> Result<OutputConnection, MidiError> {
+    open_output(port, midi_port_name, Metrics::new(), Lifecycle::new())
+}
+
+/// Create an [OutputConnection] for each of the given ports, the port of the cable `n` of a multiplexed stream
+/// (see [cable](crate::net::cable)) being the `n`th one. The connections share their [Metrics] and [Lifecycle].
+///
+/// # Arguments
+/// * `ports` - [PortSelector]s matching exactly one MIDI output port each, at most [MAX_CABLES]
+/// * `midi_port_name` - Name of the MIDI clients created to connect to the ports
+pub fn new_cable_senders(
+    ports: &[PortSelector],
+    midi_port_name: &str,
+) -> Result<Vec<OutputConnection>, MidiError> {
+    check_cables(ports)?;
+    let (metrics, lifecycle) = (Metrics::new(), Lifecycle::new());
+    ports
+        .iter()
+        .map(|port| open_output(port, midi_port_name, metrics.clone(), lifecycle.clone()))
+        .collect()
+}
+
+fn open_output(
+    port: &PortSelector,
     midi_port_name: &str,
-) -> Result<MidiOutputConnection, String> {
-    let midi_out = MidiOutput::new(midi_port_name).expect("unable to create the lookup port");
+    metrics: Metrics,
+    lifecycle: Lifecycle,
+) -> Result<OutputConnection, MidiError> {
+    let midi_out = new_midi_output(midi_port_name)?;
     info!("MIDI-OUT port is set up to: {}", midi_port_name);
 
-    if let Some(port) = midi_out.ports().get(midi_port_index) {
-        info!("midi_thread is running for {}", midi_port_name);
-        if let Ok(conn) = midi_out.connect(port, "midir-read-input") {
-            return Ok(conn);
-        }
-        return Err("unable to connect to the port".into());
-    }
-    Err("couldnt find the port".into())
+    let ports = midi_out.ports();
+    let candidates = describe_out_ports(&midi_out, &ports);
+    let selected = port.select(&candidates)?.clone();
+    info!(
+        "midi_thread is running for {} on {}",
+        midi_port_name, selected
+    );
+    let conn = connect_output(midi_out, &selected)?;
+
+    let client_name = midi_port_name.to_string();
+    Ok(OutputConnection::new(
+        selected,
+        conn,
+        move |port| {
+            let midi_out = new_midi_output(&client_name)?;
+            connect_output(midi_out, port)
+        },
+        metrics,
+        lifecycle,
+    ))
 }
 
-/// Create a new [MidiInputConnection] instance, which will forward any received MIDI message to the returned [Receiver] end tunnel
+/// Create a new [InputConnection] instance, which will forward any received MIDI message to the returned [Receiver] end tunnel
 ///
 /// # Arguments
-/// * `midi_port_index` - Index of a MIDI output port (you can get it from a [get_availables_midi_port] function call)
+/// * `port` - [PortSelector] matching exactly one MIDI input port (you can list them with a [get_availables_midi_in_port] function call)
+/// * `midi_port_name` - Name of the MIDI client created to connect to the port
 pub fn new_receiver(
-    midi_port_index: usize,
+    port: &PortSelector,
     midi_port_name: &str,
-) -> Result<(MidiInputConnection<()>, Receiver<MidiPayload>), String> {
-    let mut midi_in = MidiInput::new(midi_port_name).expect("unable to create the lookup port");
-    midi_in.ignore(Ignore::None);
+) -> Result<(InputConnection, Receiver<MidiPayload>), MidiError> {
+    let (tx, rx) = channel::<MidiPayload>();
+    let forward: Forward = Arc::new(move |payload| tx.send(payload).is_ok());
+    let conn = open_input(
+        port,
+        midi_port_name,
+        forward,
+        Notifier::new(),
+        Metrics::new(),
+        Lifecycle::new(),
+    )?;
+    Ok((conn, rx))
+}
+
+/// Create an [InputConnection] for each of the given ports, forwarding the received MIDI messages to the returned
+/// [Receiver] end tunnel along with the cable of their port, its index in `ports` (see [cable](crate::net::cable)).
+/// The connections share their [Metrics], [Lifecycle] and [Notifier].
+///
+/// # Arguments
+/// * `ports` - [PortSelector]s matching exactly one MIDI input port each, at most [MAX_CABLES]
+/// * `midi_port_name` - Name of the MIDI clients created to connect to the ports
+pub fn new_cable_receivers(
+    ports: &[PortSelector],
+    midi_port_name: &str,
+) -> Result<(Vec<InputConnection>, Receiver<CablePayload>), MidiError> {
+    check_cables(ports)?;
+    let (tx, rx) = channel::<CablePayload>();
+    let notifier = Notifier::new();
+    let (metrics, lifecycle) = (Metrics::new(), Lifecycle::new());
+    let conns = (0..).zip(ports).map(|(cable, port)| {
+        let tx = tx.clone();
+        let forward: Forward = Arc::new(move |payload| tx.send((cable, payload)).is_ok());
+        open_input(
+            port,
+            midi_port_name,
+            forward,
+            notifier.clone(),
+            metrics.clone(),
+            lifecycle.clone(),
+        )
+    });
+    Ok((conns.collect::<Result<_, _>>()?, rx))
+}
+
+fn check_cables(ports: &[PortSelector]) -> Result<(), MidiError> {
+    if ports.is_empty() || ports.len() > MAX_CABLES {
+        return Err(MidiError::CableCount(ports.len()));
+    }
+    Ok(())
+}
+
+fn open_input(
+    port: &PortSelector,
+    midi_port_name: &str,
+    forward: Forward,
+    notifier: Notifier,
+    metrics: Metrics,
+    lifecycle: Lifecycle,
+) -> Result<InputConnection, MidiError> {
+    let midi_in = new_midi_input(midi_port_name)?;
     info!("MIDI-IN port is set up to: {}", midi_port_name);
 
-    match midi_in.ports().get(midi_port_index) {
-        Some(port) => {
-            info!("midi_thread is running for {}", midi_port_name);
-            let (tx, rx) = channel::<MidiPayload>();
-
-            match midi_in.connect(
-                port,
-                "midir-read-input",
-                move |stamp: u64, msg: &[u8], _| {
-                    trace!("msg: {:?}", msg);
-                    if let Err(_) = tx.send((stamp, msg.into())) {
-                        exit(1);
-                    }
-                },
-                (),
-            ) {
-                Ok(conn) => Ok((conn, rx)),
-                Err(_) => Err("unable to connect to the port".into()),
-            }
-        }
-        None => Err("couldnt find the port".into()),
+    let ports = midi_in.ports();
+    let candidates = describe_in_ports(&midi_in, &ports);
+    let selected = port.select(&candidates)?.clone();
+    info!(
+        "midi_thread is running for {} on {}",
+        midi_port_name, selected
+    );
+    let conn = connect_input(midi_in, &selected, forward.clone(), notifier.clone())?;
+
+    let client_name = midi_port_name.to_string();
+    let reopen_notifier = notifier.clone();
+    Ok(InputConnection::new(
+        selected,
+        conn,
+        move |port| {
+            let midi_in = new_midi_input(&client_name)?;
+            connect_input(midi_in, port, forward.clone(), reopen_notifier.clone())
+        },
+        notifier,
+        metrics,
+        lifecycle,
+    ))
+}
+
+fn connect_output(
+    midi_out: MidiOutput,
+    port: &MidiPort,
+) -> Result<MidiOutputConnection, MidiError> {
+    let midi_port = midi_out
+        .find_port_by_id(port.id.clone())
+        .ok_or_else(|| connect_error(port, "port not found"))?;
+    midi_out
+        .connect(&midi_port, "midir-read-input")
+        .map_err(|err| connect_error(port, err))
+}
+
+fn connect_input(
+    mut midi_in: MidiInput,
+    port: &MidiPort,
+    forward: Forward,
+    notifier: Notifier,
+) -> Result<MidiInputConnection<()>, MidiError> {
+    midi_in.ignore(Ignore::None);
+    let midi_port = midi_in
+        .find_port_by_id(port.id.clone())
+        .ok_or_else(|| connect_error(port, "port not found"))?;
+    midi_in
+        .connect(
+            &midi_port,
+            "midir-read-input",
+            move |stamp: u64, msg: &[u8], _| {
+                trace!("msg: {:?}", msg);
+                if forward((stamp, msg.into())) {
+                    notifier.notify();
+                } else {
+                    // the bridge has been dropped and the connection is about to be closed
+                    trace!("MIDI tunnel closed, drop {:?}", msg);
+                }
+            },
+            (),
+        )
+        .map_err(|err| connect_error(port, err))
+}
+
+fn connect_error(port: &MidiPort, reason: impl ToString) -> MidiError {
+    MidiError::Connect {
+        port: port.to_string(),
+        reason: reason.to_string(),
     }
 }
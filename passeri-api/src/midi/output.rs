@@ -0,0 +1,115 @@
+use super::{ChannelFilter, ClockConfig, ClockRegenerator, Control, OutputConnection};
+use crate::metrics::Metrics;
+use crate::MidiError;
+use log::{debug, trace};
+use std::time::Instant;
+
+//
+//	OUTPUT
+//
+
+/// MIDI out port a receiver [net_thread](crate::net::receiver::Thread) sends the messages of a cable to
+///
+/// The messages go through the [ChannelFilter] of the port, and through a [ClockRegenerator] when the incoming
+/// MIDI Clock is replaced by a generated one. The notes held when the port gets unplugged are released once it is
+/// plugged back, see [Output::watch_port].
+pub struct Output {
+    conn: OutputConnection,
+    /// pause, mute and solo state of the stream, tracking the notes held on the port
+    filter: ChannelFilter,
+    /// generator of the MIDI Clock sent in place of the incoming one
+    clock: Option<ClockRegenerator>,
+    /// messages releasing the notes held when the port was unplugged, sent once it is plugged back
+    unplugged: Option<Vec<Vec<u8>>>,
+}
+
+impl Output {
+    /// Send the messages received to `conn`, regenerating the MIDI Clock with `clock` if set
+    pub fn new(conn: OutputConnection, clock: Option<ClockConfig>) -> Self {
+        Output {
+            conn,
+            filter: ChannelFilter::new(),
+            clock: clock.map(ClockRegenerator::new),
+            unplugged: None,
+        }
+    }
+
+    /// Connection to the MIDI out port
+    pub fn conn(&self) -> &OutputConnection {
+        &self.conn
+    }
+
+    /// Send a message received from the sender, unless filtered out or absorbed by the clock regeneration
+    pub fn forward(&mut self, msg: &[u8], metrics: &Metrics) -> Result<(), MidiError> {
+        if !self.filter.forward(msg) {
+            trace!("filtered {:?}", msg);
+            return Ok(());
+        }
+        if let Some(clock) = self.clock.as_mut() {
+            if !clock.receive(msg, Instant::now()) {
+                return Ok(());
+            }
+        }
+        self.conn.send(msg)?;
+        metrics.record_message(msg);
+        Ok(())
+    }
+
+    /// Send the generated clock ticks due
+    pub fn tick(&mut self, metrics: &Metrics) -> Result<(), MidiError> {
+        let Some(clock) = self.clock.as_mut() else {
+            return Ok(());
+        };
+        for msg in clock.poll(Instant::now()) {
+            self.conn.send(&msg)?;
+            metrics.record_message(&msg);
+        }
+        Ok(())
+    }
+
+    /// Time of the next generated clock tick, if any
+    pub fn next_tick(&self) -> Option<Instant> {
+        self.clock.as_ref()?.next_tick()
+    }
+
+    /// Apply a [Control] to the filter, sending the messages it returns
+    pub fn control(&mut self, control: Control) -> Result<(), MidiError> {
+        for msg in self.filter.apply(control) {
+            trace!("release {:?}", msg);
+            self.conn.send(&msg)?;
+        }
+        Ok(())
+    }
+
+    /// Release the notes held when the port got unplugged once it is plugged back,
+    /// their Note Offs having been dropped in the meantime
+    pub fn watch_port(&mut self) -> Result<(), MidiError> {
+        match (self.unplugged.take(), self.conn.is_connected()) {
+            (None, false) => self.unplugged = Some(self.filter.release_all()),
+            (Some(release), true) => self.release(release)?,
+            (unplugged, _) => self.unplugged = unplugged,
+        }
+        Ok(())
+    }
+
+    /// Release every note still held, whose Note Off will never arrive (e.g. the stream ended)
+    pub fn release_all(&mut self) -> Result<(), MidiError> {
+        let release = self.filter.release_all();
+        self.release(release)
+    }
+
+    /// Send the Note Offs and sustain pedal releases of the stuck notes
+    fn release(&mut self, release: Vec<Vec<u8>>) -> Result<(), MidiError> {
+        if !release.is_empty() {
+            debug!(
+                "release {} stuck notes and pedals on {}",
+                release.len(),
+                self.conn.port()
+            );
+        }
+        for msg in release {
+            self.conn.send(&msg)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use crate::MidiError;
+use regex::Regex;
+use std::{fmt, str::FromStr};
+
+//
+//	MIDI PORT DESCRIPTION
+//
+
+/// Description of a MIDI port that [midir] can connect to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MidiPort {
+    /// position of the port in the current port list (it changes whenever a device is plugged or unplugged)
+    pub index: usize,
+    /// backend identifier of the port, stable as long as the device stays plugged
+    pub id: String,
+    /// human readable name of the port
+    pub name: String,
+}
+
+impl fmt::Display for MidiPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: \"{}\" (id: {})", self.index, self.name, self.id)
+    }
+}
+
+//
+//	PORT SELECTOR
+//
+
+/// Describe how to pick a MIDI port among the ones [midir] can connect to
+///
+/// It can be parsed from a string with the following syntax:
+/// * `3` - [PortSelector::Index]
+/// * `id:<id>` - [PortSelector::Id]
+/// * `name:<name>` - [PortSelector::Name]
+/// * `re:<pattern>` - [PortSelector::Regex]
+/// * anything else - [PortSelector::Contains]
+#[derive(Debug, Clone)]
+pub enum PortSelector {
+    /// port at the given index (indices change whenever a device is plugged or unplugged)
+    Index(usize),
+    /// port with the given stable identifier (see [MidiPort::id])
+    Id(String),
+    /// port whose name is exactly the given one
+    Name(String),
+    /// port whose name contains the given string
+    Contains(String),
+    /// port whose name matches the given regular expression
+    Regex(Regex),
+}
+
+impl PortSelector {
+    /// Return `true` if the given port is matched by this selector
+    pub fn matches(&self, port: &MidiPort) -> bool {
+        match self {
+            PortSelector::Index(index) => port.index == *index,
+            PortSelector::Id(id) => port.id == *id,
+            PortSelector::Name(name) => port.name == *name,
+            PortSelector::Contains(pattern) => port.name.contains(pattern.as_str()),
+            PortSelector::Regex(regex) => regex.is_match(&port.name),
+        }
+    }
+
+    /// Pick the only port of `ports` matched by this selector
+    ///
+    /// The returned error lists the candidates when no port or several ports match.
+    pub fn select<'a>(&self, ports: &'a [MidiPort]) -> Result<&'a MidiPort, MidiError> {
+        let matching: Vec<&MidiPort> = ports.iter().filter(|port| self.matches(port)).collect();
+
+        match matching.as_slice() {
+            [port] => Ok(port),
+            [] => Err(MidiError::PortNotFound {
+                selector: self.to_string(),
+                candidates: ports.to_vec(),
+            }),
+            _ => Err(MidiError::AmbiguousPort {
+                selector: self.to_string(),
+                candidates: matching.into_iter().cloned().collect(),
+            }),
+        }
+    }
+}
+
+impl From<usize> for PortSelector {
+    fn from(index: usize) -> Self {
+        PortSelector::Index(index)
+    }
+}
+
+impl FromStr for PortSelector {
+    type Err = MidiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(index) = s.parse::<usize>() {
+            return Ok(PortSelector::Index(index));
+        }
+        if let Some(id) = s.strip_prefix("id:") {
+            return Ok(PortSelector::Id(id.into()));
+        }
+        if let Some(name) = s.strip_prefix("name:") {
+            return Ok(PortSelector::Name(name.into()));
+        }
+        if let Some(pattern) = s.strip_prefix("re:") {
+            return Regex::new(pattern)
+                .map(PortSelector::Regex)
+                .map_err(|source| MidiError::InvalidSelector {
+                    pattern: pattern.into(),
+                    source,
+                });
+        }
+        Ok(PortSelector::Contains(s.into()))
+    }
+}
+
+impl fmt::Display for PortSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortSelector::Index(index) => write!(f, "{}", index),
+            PortSelector::Id(id) => write!(f, "id:{}", id),
+            PortSelector::Name(name) => write!(f, "name:{}", name),
+            PortSelector::Contains(pattern) => write!(f, "{}", pattern),
+            PortSelector::Regex(regex) => write!(f, "re:{}", regex),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports() -> Vec<MidiPort> {
+        [
+            "Midi Through Port-0",
+            "USB Keyboard MIDI 1",
+            "USB Keyboard MIDI 2",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(index, name)| MidiPort {
+            index,
+            id: format!("{}:0", 14 + index * 10),
+            name: name.to_string(),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_select() {
+        let ports = ports();
+        let select = |s: &str| {
+            s.parse::<PortSelector>()
+                .unwrap()
+                .select(&ports)
+                .map(|port| port.index)
+                .map_err(|err| err.to_string())
+        };
+
+        assert_eq!(select("1"), Ok(1));
+        assert_eq!(select("id:34:0"), Ok(2));
+        assert_eq!(select("name:Midi Through Port-0"), Ok(0));
+        assert_eq!(select("Through"), Ok(0));
+        assert_eq!(select("re:MIDI 2$"), Ok(2));
+
+        let err = select("Keyboard").unwrap_err();
+        assert!(err.contains("several"));
+        assert!(err.contains("USB Keyboard MIDI 1") && err.contains("USB Keyboard MIDI 2"));
+
+        let err = select("name:Synth").unwrap_err();
+        assert!(err.contains("no MIDI port"));
+        assert!(err.contains("Midi Through Port-0"));
+    }
+
+    #[test]
+    fn test_parse_roundtrip() {
+        for s in ["4", "id:20:0", "name:Synth", "re:^USB", "Keyboard"] {
+            assert_eq!(s.parse::<PortSelector>().unwrap().to_string(), s);
+        }
+        assert!("re:(".parse::<PortSelector>().is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use super::{get_availables_midi_in_port, get_availables_midi_out_port, MidiPort};
+use crate::MidiError;
+use log::{debug, warn};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex, MutexGuard, Weak,
+    },
+    time::Duration,
+};
+
+/// Interval at which the port lists are polled by the [PortWatcher::shared] watcher
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Direction of a MIDI port, seen from **Passeri**
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortDirection {
+    /// port from which MIDI messages can be read
+    Input,
+    /// port to which MIDI messages can be written
+    Output,
+}
+
+/// Change in the MIDI port list notified by a [PortWatcher]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortEvent {
+    /// a new port is available
+    Added(PortDirection, MidiPort),
+    /// a port is not available anymore
+    Removed(PortDirection, MidiPort),
+}
+
+/// MIDI input and output port lists, as polled by a [PortWatcher]
+type PortLists = (Vec<MidiPort>, Vec<MidiPort>);
+
+fn available_ports() -> Result<PortLists, MidiError> {
+    Ok((
+        get_availables_midi_in_port()?,
+        get_availables_midi_out_port()?,
+    ))
+}
+
+/// Watcher started by [PortWatcher::shared], while a handle to it is alive
+static SHARED: Mutex<Weak<Watching>> = Mutex::new(Weak::new());
+
+/// State of a polling thread, stopped once the last [PortWatcher] handle is dropped
+struct Watching {
+    interval: Duration,
+    stop: Arc<AtomicBool>,
+    subscribers: Arc<Mutex<Vec<Sender<PortEvent>>>>,
+}
+
+impl Drop for Watching {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Background thread polling the MIDI port lists ([get_availables_midi_in_port] and [get_availables_midi_out_port])
+/// and notifying every [PortEvent] to its subscribers.
+///
+/// The handles are cheap to clone, so a single thread serves every connection of the process (see
+/// [PortWatcher::shared]). The thread stops by itself at its next poll once the last handle is dropped.
+#[derive(Clone)]
+pub struct PortWatcher {
+    inner: Arc<Watching>,
+}
+
+impl PortWatcher {
+    /// Start watching the port lists, polling them every `interval`
+    pub fn new(interval: Duration) -> PortWatcher {
+        Self::with_lists(interval, available_ports)
+    }
+
+    /// Return the watcher shared by the whole process, polling the port lists every [DEFAULT_WATCH_INTERVAL],
+    /// started if none is running
+    pub fn shared() -> PortWatcher {
+        let mut shared = SHARED.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(inner) = shared.upgrade() {
+            return PortWatcher { inner };
+        }
+        let watcher = PortWatcher::new(DEFAULT_WATCH_INTERVAL);
+        *shared = Arc::downgrade(&watcher.inner);
+        watcher
+    }
+
+    /// Start watching the port lists returned by `list`, polling them every `interval`
+    pub(crate) fn with_lists(
+        interval: Duration,
+        mut list: impl FnMut() -> Result<PortLists, MidiError> + Send + 'static,
+    ) -> PortWatcher {
+        let stop = Arc::new(AtomicBool::new(false));
+        let subscribers: Arc<Mutex<Vec<Sender<PortEvent>>>> = Arc::default();
+        let thread_stop = Arc::clone(&stop);
+        let thread_subscribers = Arc::clone(&subscribers);
+
+        // listed right away, so the changes following the creation are all notified
+        let (mut in_ports, mut out_ports) = list().unwrap_or_default();
+        std::thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                std::thread::sleep(interval);
+                match list() {
+                    Ok((new_in_ports, new_out_ports)) => {
+                        let events: Vec<PortEvent> =
+                            diff_ports(PortDirection::Input, &in_ports, &new_in_ports)
+                                .chain(diff_ports(
+                                    PortDirection::Output,
+                                    &out_ports,
+                                    &new_out_ports,
+                                ))
+                                .collect();
+                        notify(&thread_subscribers, &events);
+                        in_ports = new_in_ports;
+                        out_ports = new_out_ports;
+                    }
+                    Err(err) => warn!("port watcher: {}", err),
+                }
+            }
+            debug!("port watcher stopped");
+        });
+
+        PortWatcher {
+            inner: Arc::new(Watching {
+                interval,
+                stop,
+                subscribers,
+            }),
+        }
+    }
+
+    /// Return a channel notified of the following [PortEvent]s, the ports already available not being notified
+    pub fn subscribe(&self) -> Receiver<PortEvent> {
+        let (tx, rx) = channel();
+        lock(&self.inner.subscribers).push(tx);
+        rx
+    }
+
+    /// Interval at which the port lists are polled
+    pub fn interval(&self) -> Duration {
+        self.inner.interval
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Send `events` to every subscriber, forgetting the ones that dropped their channel
+fn notify(subscribers: &Mutex<Vec<Sender<PortEvent>>>, events: &[PortEvent]) {
+    for event in events {
+        debug!("port watcher: {:?}", event);
+    }
+    lock(subscribers).retain(|tx| events.iter().all(|event| tx.send(event.clone()).is_ok()));
+}
+
+/// Compute the [PortEvent]s between two snapshots of a port list, ports being identified by their [MidiPort::id]
+fn diff_ports<'a>(
+    direction: PortDirection,
+    previous: &'a [MidiPort],
+    current: &'a [MidiPort],
+) -> impl Iterator<Item = PortEvent> + 'a {
+    let removed = previous
+        .iter()
+        .filter(|port| !current.iter().any(|other| other.id == port.id))
+        .map(move |port| PortEvent::Removed(direction, port.clone()));
+    let added = current
+        .iter()
+        .filter(|port| !previous.iter().any(|other| other.id == port.id))
+        .map(move |port| PortEvent::Added(direction, port.clone()));
+    removed.chain(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(index: usize, id: &str, name: &str) -> MidiPort {
+        MidiPort {
+            index,
+            id: id.into(),
+            name: name.into(),
+        }
+    }
+
+    #[test]
+    fn test_diff_ports() {
+        let previous = vec![port(0, "14:0", "Midi Through"), port(1, "24:0", "Keyboard")];
+        let current = vec![port(0, "14:0", "Midi Through"), port(1, "28:0", "Keyboard")];
+
+        let events: Vec<PortEvent> =
+            diff_ports(PortDirection::Input, &previous, &current).collect();
+        assert_eq!(
+            events,
+            vec![
+                PortEvent::Removed(PortDirection::Input, port(1, "24:0", "Keyboard")),
+                PortEvent::Added(PortDirection::Input, port(1, "28:0", "Keyboard")),
+            ]
+        );
+        assert_eq!(
+            diff_ports(PortDirection::Output, &current, &current).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_watch() {
+        let synth = port(0, "24:0", "Synth");
+        let lists = Arc::new(Mutex::new((vec![], vec![synth.clone()])));
+        let watched = lists.clone();
+        let watcher =
+            PortWatcher::with_lists(
+                Duration::from_millis(10),
+                move || Ok(lock(&watched).clone()),
+            );
+        // every subscriber of the same watcher is notified
+        let events = [watcher.subscribe(), watcher.clone().subscribe()];
+
+        let timeout = Duration::from_secs(1);
+        lock(&lists).1.clear();
+        for events in &events {
+            assert_eq!(
+                events.recv_timeout(timeout).unwrap(),
+                PortEvent::Removed(PortDirection::Output, synth.clone())
+            );
+        }
+        lock(&lists).1.push(synth.clone());
+        for events in &events {
+            assert_eq!(
+                events.recv_timeout(timeout).unwrap(),
+                PortEvent::Added(PortDirection::Output, synth.clone())
+            );
+        }
+
+        assert!(Arc::ptr_eq(
+            &PortWatcher::shared().inner,
+            &PortWatcher::shared().inner
+        ));
+    }
+}
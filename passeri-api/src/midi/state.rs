@@ -0,0 +1,332 @@
+use super::CHANNELS;
+use crate::MidiError;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+//
+//	SNAPSHOT CONFIG
+//
+
+/// Bank Select controllers, MSB and LSB, sent with the programs
+const BANK_SELECT: [u8; 2] = [0, 32];
+/// Data Entry controllers, MSB and LSB, setting the value of the selected parameter
+const DATA_ENTRY: [u8; 2] = [6, 38];
+/// Registered Parameter Number controllers, LSB and MSB
+const RPN: [u8; 2] = [100, 101];
+/// Reset All Controllers, the other channel mode messages (120 to 127) not being state
+const RESET_CONTROLLERS: u8 = 121;
+/// Value of both RPN controllers deselecting any parameter
+const RPN_NULL: u8 = 127;
+
+/// Classes of messages sent by [MidiState::snapshot]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotConfig {
+    /// Bank Select and Program Change
+    pub programs: bool,
+    /// Control Change, except the bank, RPN and channel mode controllers
+    pub controllers: bool,
+    /// Pitch Bend
+    pub pitch_bend: bool,
+    /// Channel Pressure
+    pub pressure: bool,
+    /// Registered Parameters (pitch bend range, tuning...) set through Data Entry
+    pub rpn: bool,
+}
+
+impl SnapshotConfig {
+    /// Every class of messages
+    pub const ALL: SnapshotConfig = SnapshotConfig {
+        programs: true,
+        controllers: true,
+        pitch_bend: true,
+        pressure: true,
+        rpn: true,
+    };
+
+    /// No message at all, disabling the snapshot
+    pub const NONE: SnapshotConfig = SnapshotConfig {
+        programs: false,
+        controllers: false,
+        pitch_bend: false,
+        pressure: false,
+        rpn: false,
+    };
+
+    fn flags(&self) -> [(&'static str, bool); 5] {
+        [
+            ("programs", self.programs),
+            ("controllers", self.controllers),
+            ("pitch_bend", self.pitch_bend),
+            ("pressure", self.pressure),
+            ("rpn", self.rpn),
+        ]
+    }
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig::ALL
+    }
+}
+
+impl FromStr for SnapshotConfig {
+    type Err = MidiError;
+
+    /// Parse a comma separated list of classes (`programs`, `controllers`, `pitch_bend`, `pressure`, `rpn`), `all` or `none`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = SnapshotConfig::NONE;
+        for name in s.split(',').map(str::trim) {
+            match name {
+                "none" => {}
+                "all" => config = SnapshotConfig::ALL,
+                "programs" => config.programs = true,
+                "controllers" => config.controllers = true,
+                "pitch_bend" => config.pitch_bend = true,
+                "pressure" => config.pressure = true,
+                "rpn" => config.rpn = true,
+                _ => return Err(MidiError::UnknownSnapshotClass(name.to_string())),
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for SnapshotConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = self
+            .flags()
+            .into_iter()
+            .filter_map(|(name, set)| set.then_some(name))
+            .collect();
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join(","))
+        }
+    }
+}
+
+//
+//	MIDI STATE
+//
+
+/// Controller and program state of a channel
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ChannelState {
+    /// Bank Select MSB and LSB
+    bank: [Option<u8>; 2],
+    program: Option<u8>,
+    controllers: BTreeMap<u8, u8>,
+    pitch_bend: Option<u16>,
+    pressure: Option<u8>,
+    /// RPN MSB and LSB selected by the last parameter number controllers, `None` once an NRPN is selected
+    selected: Option<[Option<u8>; 2]>,
+    /// Data Entry MSB and LSB of each Registered Parameter
+    rpn: BTreeMap<u16, [Option<u8>; 2]>,
+}
+
+impl ChannelState {
+    fn control(&mut self, controller: u8, value: u8) {
+        match controller {
+            // Bank Select
+            0 | 32 => self.bank[(controller == 32) as usize] = Some(value),
+            // Data Entry
+            6 | 38 => {
+                if let Some(number) = self.selected_rpn() {
+                    self.rpn.entry(number).or_default()[(controller == 38) as usize] = Some(value);
+                }
+            }
+            // Registered Parameter Number
+            100 | 101 => {
+                self.selected.get_or_insert([None, None])[(controller == 100) as usize] =
+                    Some(value)
+            }
+            // Non-Registered Parameter Number
+            98 | 99 => self.selected = None,
+            RESET_CONTROLLERS => {
+                self.controllers.clear();
+                self.pitch_bend = None;
+                self.pressure = None;
+                self.selected = None;
+            }
+            // Data Increment and Decrement, channel mode messages
+            96 | 97 | 120..=127 => {}
+            _ => {
+                self.controllers.insert(controller, value);
+            }
+        }
+    }
+
+    /// Number of the selected Registered Parameter, if any
+    fn selected_rpn(&self) -> Option<u16> {
+        match self.selected? {
+            [Some(RPN_NULL), Some(RPN_NULL)] => None,
+            [Some(msb), Some(lsb)] => Some((msb as u16) << 7 | lsb as u16),
+            _ => None,
+        }
+    }
+
+    fn snapshot(&self, channel: u8, config: &SnapshotConfig, messages: &mut Vec<Vec<u8>>) {
+        let cc = |controller: u8, value: u8| vec![0xB0 | channel, controller, value];
+        if config.programs {
+            for (controller, value) in BANK_SELECT.into_iter().zip(self.bank) {
+                messages.extend(value.map(|value| cc(controller, value)));
+            }
+            messages.extend(self.program.map(|program| vec![0xC0 | channel, program]));
+        }
+        if config.controllers {
+            messages.extend(
+                self.controllers
+                    .iter()
+                    .map(|(&controller, &value)| cc(controller, value)),
+            );
+        }
+        if config.rpn && !self.rpn.is_empty() {
+            for (number, entry) in &self.rpn {
+                messages.push(cc(RPN[1], (number >> 7) as u8));
+                messages.push(cc(RPN[0], (number & 0x7F) as u8));
+                for (controller, value) in DATA_ENTRY.into_iter().zip(*entry) {
+                    messages.extend(value.map(|value| cc(controller, value)));
+                }
+            }
+            // leave no parameter selected, so stray Data Entry messages change nothing
+            messages.push(cc(RPN[1], RPN_NULL));
+            messages.push(cc(RPN[0], RPN_NULL));
+        }
+        if config.pitch_bend {
+            messages.extend(
+                self.pitch_bend
+                    .map(|bend| vec![0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8]),
+            );
+        }
+        if config.pressure {
+            messages.extend(self.pressure.map(|pressure| vec![0xD0 | channel, pressure]));
+        }
+    }
+}
+
+/// Live model of the programs, controllers, pitch bend, pressure and Registered Parameters of every channel,
+/// updated with each message played, from which a receiver joining mid-performance can be brought up to date
+///
+/// Notes are not part of the state, a late receiver only hearing the ones played after it joined.
+/// Data Increment and Decrement messages are not tracked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MidiState {
+    channels: [ChannelState; CHANNELS as usize],
+}
+
+impl MidiState {
+    /// Create a new state, with no message played yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the state with a MIDI message
+    pub fn update(&mut self, msg: &[u8]) {
+        let Some(&status) = msg.first() else {
+            return;
+        };
+        if status == 0xFF {
+            // System Reset
+            *self = Self::new();
+            return;
+        }
+        let state = &mut self.channels[(status & 0x0F) as usize];
+        match (status & 0xF0, msg.get(1), msg.get(2)) {
+            (0xB0, Some(&controller), Some(&value)) => state.control(controller, value),
+            (0xC0, Some(&program), _) => state.program = Some(program),
+            (0xD0, Some(&pressure), _) => state.pressure = Some(pressure),
+            (0xE0, Some(&lsb), Some(&msb)) => {
+                state.pitch_bend = Some((msb as u16) << 7 | lsb as u16)
+            }
+            _ => {}
+        }
+    }
+
+    /// Return the messages bringing a synth to the current state, limited to the classes enabled in `config`
+    pub fn snapshot(&self, config: &SnapshotConfig) -> Vec<Vec<u8>> {
+        let mut messages = vec![];
+        for (channel, state) in self.channels.iter().enumerate() {
+            state.snapshot(channel as u8, config, &mut messages);
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let mut state = MidiState::new();
+        for msg in [
+            vec![0x90, 0x3C, 0x40],
+            vec![0xB1, 0, 1],
+            vec![0xC1, 5],
+            vec![0xB1, 7, 100],
+            vec![0xB1, 7, 90],
+            vec![0xB1, 123, 0],
+            // pitch bend range of 12 semitones
+            vec![0xB1, 101, 0],
+            vec![0xB1, 100, 0],
+            vec![0xB1, 6, 12],
+            vec![0xB1, 101, RPN_NULL],
+            vec![0xB1, 100, RPN_NULL],
+            vec![0xB1, 6, 2],
+            vec![0xE1, 0x00, 0x50],
+            vec![0xD2, 0x30],
+        ] {
+            state.update(&msg);
+        }
+
+        assert_eq!(
+            state.snapshot(&SnapshotConfig::ALL),
+            vec![
+                vec![0xB1, 0, 1],
+                vec![0xC1, 5],
+                vec![0xB1, 7, 90],
+                vec![0xB1, 101, 0],
+                vec![0xB1, 100, 0],
+                vec![0xB1, 6, 12],
+                vec![0xB1, 101, RPN_NULL],
+                vec![0xB1, 100, RPN_NULL],
+                vec![0xE1, 0x00, 0x50],
+                vec![0xD2, 0x30],
+            ]
+        );
+        let config = "programs,pressure".parse().unwrap();
+        assert_eq!(
+            state.snapshot(&config),
+            vec![vec![0xB1, 0, 1], vec![0xC1, 5], vec![0xD2, 0x30]]
+        );
+        assert!(state.snapshot(&SnapshotConfig::NONE).is_empty());
+
+        // Reset All Controllers keeps the program and the parameters of its channel
+        state.update(&[0xB1, RESET_CONTROLLERS, 0]);
+        assert_eq!(
+            state.snapshot(&"programs,controllers,pitch_bend".parse().unwrap()),
+            vec![vec![0xB1, 0, 1], vec![0xC1, 5]]
+        );
+        state.update(&[0xFF]);
+        assert_eq!(state, MidiState::new());
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            "all".parse::<SnapshotConfig>().unwrap(),
+            SnapshotConfig::ALL
+        );
+        assert_eq!(
+            "none".parse::<SnapshotConfig>().unwrap(),
+            SnapshotConfig::NONE
+        );
+        assert_eq!(
+            SnapshotConfig::ALL.to_string(),
+            "programs,controllers,pitch_bend,pressure,rpn"
+        );
+        assert!("notes".parse::<SnapshotConfig>().is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use crate::midi::MidiPayload;
+
+/// Status byte of the frames selecting the cable of the following messages, one of the
+/// [RESERVED_STATUS](super::RESERVED_STATUS)
+pub const CABLE_STATUS: u8 = 0xF9;
+/// Number of MIDI ports a connection can multiplex
+pub const MAX_CABLES: usize = 16;
+
+/// Identifier of a MIDI port multiplexed over a connection, from 0 to [MAX_CABLES] excluded.
+/// A bridge of a single port only uses the cable 0.
+pub type Cable = u8;
+
+/// MIDI message received on a multiplexed port: the cable of the port, then the timestamp and the message
+pub type CablePayload = (Cable, MidiPayload);
+
+/// Cable of the messages flowing over a connection once the `cables` [Feature](crate::net::lobby::Features) is agreed
+///
+/// The stream starts on the cable 0. Before a message of another cable, the sender writes a 2 bytes frame,
+/// [CABLE_STATUS] followed by the cable, which holds for the following messages until the next frame.
+/// Frames are only written between two complete messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CableSwitch {
+    current: Cable,
+    /// a frame was cut right after its status byte, the next byte received being its cable
+    switching: bool,
+}
+
+impl CableSwitch {
+    /// Create a new switch, on the cable 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the frame to write before a message of `cable`, if it is not the current one
+    pub fn select(&mut self, cable: Cable) -> Option<[u8; 2]> {
+        if cable == self.current {
+            return None;
+        }
+        self.current = cable;
+        Some([CABLE_STATUS, cable])
+    }
+
+    /// Strip the frames out of bytes received from the stream, returning the bytes of each cable in order
+    /// (a frame split between two reads is completed by the next call)
+    pub fn strip<'a>(&mut self, src: &'a [u8]) -> Vec<(Cable, &'a [u8])> {
+        let mut runs = vec![];
+        let mut start = 0;
+        for (at, &byte) in src.iter().enumerate() {
+            if self.switching {
+                self.switching = false;
+                self.current = byte;
+                start = at + 1;
+            } else if byte == CABLE_STATUS {
+                if start < at {
+                    runs.push((self.current, &src[start..at]));
+                }
+                self.switching = true;
+                start = at + 1;
+            }
+        }
+        if start < src.len() {
+            runs.push((self.current, &src[start..]));
+        }
+        runs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::MidiParser;
+
+    #[test]
+    fn test_switch() {
+        let mut sender = CableSwitch::new();
+        let mut stream = vec![];
+        for (cable, msg) in [
+            (0, [0x90, 0x40, 0x7F]),
+            (2, [0x91, 0x40, 0x7F]),
+            (2, [0x81, 0x40, 0]),
+        ] {
+            stream.extend(sender.select(cable).into_iter().flatten());
+            stream.extend(msg);
+        }
+        assert_eq!(stream[3..5], [CABLE_STATUS, 2]);
+
+        // whatever the reads the stream is cut into
+        for len in 1..stream.len() {
+            let mut parser = MidiParser::new();
+            let mut receiver = CableSwitch::new();
+            let mut routed = vec![];
+            for chunk in stream.chunks(len) {
+                for (cable, run) in receiver.strip(chunk) {
+                    routed.extend(parser.parse(run).into_iter().map(|msg| (cable, msg)));
+                }
+            }
+            assert_eq!(
+                routed,
+                vec![
+                    (0, vec![0x90, 0x40, 0x7F]),
+                    (2, vec![0x91, 0x40, 0x7F]),
+                    (2, vec![0x81, 0x40, 0])
+                ]
+            );
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use crate::metrics::Metrics;
+use crate::TransportError;
+use std::time::{Duration, Instant};
+
+/// Status byte starting every heartbeat frame, one of the [RESERVED_STATUS](super::RESERVED_STATUS)
+pub const HEARTBEAT_STATUS: u8 = 0xFD;
+/// Size of a heartbeat frame: status byte, kind, 28 bits sequence number split in 4 data bytes
+const FRAME_LEN: usize = 6;
+/// Sequence numbers wrap around after 28 bits, to fit in MIDI data bytes
+const SEQ_MASK: u32 = 0x0FFF_FFFF;
+
+/// Interval at which the heartbeat frames are sent, and delay after which a silent peer is considered gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    /// interval between two pings
+    pub interval: Duration,
+    /// time without hearing anything from the distant peer before failing with [TransportError::HeartbeatTimeout]
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Heartbeat frame interleaved with the MIDI messages, between two complete messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Beat {
+    /// request for a [Beat::Pong] with the same sequence number
+    Ping(u32),
+    /// answer to a [Beat::Ping]
+    Pong(u32),
+}
+
+impl Beat {
+    /// Encode the frame as bytes to write on the connection
+    pub fn encode(&self) -> [u8; FRAME_LEN] {
+        let (kind, seq) = match *self {
+            Beat::Ping(seq) => (1, seq),
+            Beat::Pong(seq) => (2, seq),
+        };
+        [
+            HEARTBEAT_STATUS,
+            kind,
+            (seq >> 21) as u8 & 0x7F,
+            (seq >> 14) as u8 & 0x7F,
+            (seq >> 7) as u8 & 0x7F,
+            seq as u8 & 0x7F,
+        ]
+    }
+
+    fn decode(frame: &[u8]) -> Option<Self> {
+        let seq = frame[2..]
+            .iter()
+            .fold(0, |seq, byte| (seq << 7) | u32::from(*byte));
+        match frame[1] {
+            1 => Some(Beat::Ping(seq)),
+            2 => Some(Beat::Pong(seq)),
+            _ => None,
+        }
+    }
+}
+
+/// Heartbeat of one side of a connection, both sides pinging each other and answering the pings of the other
+///
+/// The **net_thread** owning it has to:
+/// - give every byte received from the distant peer to [Heartbeat::receive], forwarding the returned MIDI bytes
+///   and writing the returned answers
+/// - call [Heartbeat::poll] at least a few times per `interval`, writing the returned ping
+///
+/// Round trip times are recorded in the [Metrics] of the bridge.
+#[derive(Debug)]
+pub struct Heartbeat {
+    config: HeartbeatConfig,
+    metrics: Metrics,
+    seq: u32,
+    /// last ping sent, waiting for its pong
+    pending: Option<(u32, Instant)>,
+    last_ping: Instant,
+    last_seen: Instant,
+    last_rtt: Option<Duration>,
+    /// heartbeat frame split between two reads
+    frame: Vec<u8>,
+}
+
+impl Heartbeat {
+    /// Start the heartbeat of a newly established connection
+    pub fn new(config: HeartbeatConfig, metrics: Metrics) -> Self {
+        let now = Instant::now();
+        Heartbeat {
+            config,
+            metrics,
+            seq: 0,
+            pending: None,
+            last_ping: now,
+            last_seen: now,
+            last_rtt: None,
+            frame: Vec::with_capacity(FRAME_LEN),
+        }
+    }
+
+    /// Split the heartbeat frames out of bytes received from the distant peer,
+    /// returning the remaining MIDI bytes and the answers to write back
+    pub fn receive(&mut self, src: &[u8]) -> (Vec<u8>, Vec<Beat>) {
+        self.last_seen = Instant::now();
+        let mut midi = Vec::with_capacity(src.len());
+        let mut answers = vec![];
+
+        for &byte in src {
+            if !self.frame.is_empty() && byte & 0x80 == 0 {
+                self.frame.push(byte);
+                if self.frame.len() == FRAME_LEN {
+                    match Beat::decode(&self.frame) {
+                        Some(Beat::Ping(seq)) => answers.push(Beat::Pong(seq)),
+                        Some(Beat::Pong(seq)) => self.pong(seq),
+                        None => {}
+                    }
+                    self.frame.clear();
+                }
+                continue;
+            }
+            // a status byte interrupts a truncated frame
+            self.frame.clear();
+            if byte == HEARTBEAT_STATUS {
+                self.frame.push(byte);
+            } else {
+                midi.push(byte);
+            }
+        }
+        (midi, answers)
+    }
+
+    fn pong(&mut self, seq: u32) {
+        match self.pending {
+            Some((pending, sent)) if pending == seq => {
+                let rtt = sent.elapsed();
+                self.pending = None;
+                self.last_rtt = Some(rtt);
+                self.metrics.record_rtt(rtt);
+                self.metrics.record_latency(rtt / 2);
+            }
+            _ => {}
+        }
+    }
+
+    /// Check the distant peer is still alive, returning the ping to write when one is due
+    pub fn poll(&mut self) -> Result<Option<Beat>, TransportError> {
+        if self.last_seen.elapsed() > self.config.timeout {
+            return Err(TransportError::HeartbeatTimeout(self.config.timeout));
+        }
+        if self.last_ping.elapsed() < self.config.interval {
+            return Ok(None);
+        }
+        self.seq = (self.seq + 1) & SEQ_MASK;
+        self.last_ping = Instant::now();
+        self.pending = Some((self.seq, self.last_ping));
+        Ok(Some(Beat::Ping(self.seq)))
+    }
+
+    /// Round trip time measured by the last answered ping
+    pub fn rtt(&self) -> Option<Duration> {
+        self.last_rtt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receive() {
+        let mut heartbeat = Heartbeat::new(HeartbeatConfig::default(), Metrics::new());
+        let ping = Beat::Ping(0x0ABC_DEF1).encode();
+        assert!(ping[1..].iter().all(|byte| byte & 0x80 == 0));
+
+        // frames are split out of the MIDI stream, even across two reads
+        let mut stream = vec![0x90, 0x40, 0x7F];
+        stream.extend_from_slice(&ping);
+        stream.extend_from_slice(&[0x80, 0x40, 0x00]);
+        let (midi, answers) = heartbeat.receive(&stream[..5]);
+        assert_eq!(midi, [0x90, 0x40, 0x7F]);
+        assert!(answers.is_empty());
+        let (midi, answers) = heartbeat.receive(&stream[5..]);
+        assert_eq!(midi, [0x80, 0x40, 0x00]);
+        assert_eq!(answers, [Beat::Pong(0x0ABC_DEF1)]);
+
+        // a truncated frame does not swallow the following message
+        let (midi, _) = heartbeat.receive(&[HEARTBEAT_STATUS, 1, 0x90, 0x40, 0x7F]);
+        assert_eq!(midi, [0x90, 0x40, 0x7F]);
+    }
+
+    #[test]
+    fn test_poll() {
+        let metrics = Metrics::new();
+        let config = HeartbeatConfig {
+            interval: Duration::from_millis(10),
+            timeout: Duration::from_millis(50),
+        };
+        let mut heartbeat = Heartbeat::new(config, metrics.clone());
+        assert_eq!(heartbeat.poll().unwrap(), None);
+
+        std::thread::sleep(config.interval);
+        let Some(Beat::Ping(seq)) = heartbeat.poll().unwrap() else {
+            panic!("expecting a ping");
+        };
+        heartbeat.receive(&Beat::Pong(seq).encode());
+        assert!(heartbeat.rtt().is_some());
+        assert_eq!(metrics.snapshot().rtt.count(), 1);
+        assert_eq!(metrics.snapshot().latency.count(), 1);
+
+        std::thread::sleep(config.timeout * 2);
+        assert!(matches!(
+            heartbeat.poll(),
+            Err(TransportError::HeartbeatTimeout(_))
+        ));
+    }
+}
@@ -0,0 +1,954 @@
+use crate::net::{Result, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use crate::{ProtocolError, TransportError};
+use ring::{hmac, rand::SecureRandom};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Bytes starting every handshake message
+const MAGIC: &[u8; 4] = b"PSRI";
+/// Size of the nonce sent by the sender to challenge the receivers credential
+pub const NONCE_LEN: usize = 16;
+/// Number of characters of the pairing codes generated by [Lobby::enable_pairing], 80 random bits making the code
+/// impractical to guess even offline from a proof obtained by a rogue sender
+const PAIRING_CODE_LEN: usize = 16;
+/// Characters of the pairing codes (Crockford base32, without the letters mistaken for digits)
+const PAIRING_CODE_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+/// Time a pairing code stays valid once generated
+pub const PAIRING_CODE_TTL: Duration = Duration::from_secs(5 * 60);
+/// Failed authentications allowed from a host while a pairing code is valid, beyond which it may not use the code
+const MAX_PAIRING_ATTEMPTS: u32 = 3;
+/// Failed authentications allowed from every host while a pairing code is valid, beyond which the code is revoked
+const MAX_PAIRING_FAILURES: u32 = 10;
+
+//
+//	FEATURES
+//
+
+/// Optional capabilities of a peer, the ones used by a session being supported by both peers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features {
+    /// MIDI messages are sent along with their timestamp
+    pub timestamps: bool,
+    /// System Exclusive messages are forwarded
+    pub sysex: bool,
+    /// the MIDI stream is compressed
+    pub compression: bool,
+    /// MIDI messages flow both ways
+    pub duplex: bool,
+    /// several MIDI ports are multiplexed over the connection, see [cable](crate::net::cable)
+    pub cables: bool,
+}
+
+impl Features {
+    /// No optional capability
+    pub const NONE: Features = Features {
+        timestamps: false,
+        sysex: false,
+        compression: false,
+        duplex: false,
+        cables: false,
+    };
+
+    fn flags(&self) -> [(&'static str, bool); 5] {
+        [
+            ("timestamps", self.timestamps),
+            ("sysex", self.sysex),
+            ("compression", self.compression),
+            ("duplex", self.duplex),
+            ("cables", self.cables),
+        ]
+    }
+
+    fn combine(self, other: Self, f: impl Fn(bool, bool) -> bool) -> Self {
+        Features {
+            timestamps: f(self.timestamps, other.timestamps),
+            sysex: f(self.sysex, other.sysex),
+            compression: f(self.compression, other.compression),
+            duplex: f(self.duplex, other.duplex),
+            cables: f(self.cables, other.cables),
+        }
+    }
+
+    /// Features found in both sets
+    pub fn intersection(self, other: Self) -> Self {
+        self.combine(other, |a, b| a && b)
+    }
+
+    /// Features found in either set
+    pub fn union(self, other: Self) -> Self {
+        self.combine(other, |a, b| a || b)
+    }
+
+    /// Features of `self` missing from `other`
+    pub fn difference(self, other: Self) -> Self {
+        self.combine(other, |a, b| a && !b)
+    }
+
+    /// Return `true` if no feature is set
+    pub fn is_empty(&self) -> bool {
+        *self == Features::NONE
+    }
+
+    /// Set the feature called `name`, returning `false` if unknown
+    fn set(&mut self, name: &str) -> bool {
+        match name {
+            "timestamps" => self.timestamps = true,
+            "sysex" => self.sysex = true,
+            "compression" => self.compression = true,
+            "duplex" => self.duplex = true,
+            "cables" => self.cables = true,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Parse a comma separated list of feature names, unknown names being ignored for forward compatibility
+    fn decode(list: &str) -> Self {
+        let mut features = Features::NONE;
+        for name in list.split(',') {
+            features.set(name.trim());
+        }
+        features
+    }
+}
+
+impl FromStr for Features {
+    type Err = ProtocolError;
+
+    /// Parse a comma separated list of feature names (`timestamps`, `sysex`, `compression`, `duplex`, `cables`) or `none`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut features = Features::NONE;
+        for name in s.split(',').map(str::trim) {
+            if name != "none" && !features.set(name) {
+                return Err(ProtocolError::UnknownFeature(name.to_string()));
+            }
+        }
+        Ok(features)
+    }
+}
+
+impl Display for Features {
+    /// Comma separated list of feature names, `none` if empty
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        let names: Vec<&str> = self
+            .flags()
+            .into_iter()
+            .filter_map(|(name, set)| set.then_some(name))
+            .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+//
+//	HANDSHAKE MESSAGES
+//
+
+/// Messages exchanged between a sender and a receiver before the MIDI stream starts
+///
+/// 1. the sender introduces itself with a [Hello] holding a random nonce
+/// 2. the receiver checks the protocol version and features are compatible, then answers with its own [Hello],
+///    proving it knows the credential expected by the sender by signing the nonce
+/// 3. once the host approved or rejected the receiver, the sender answers with [Message::Accept] or [Message::Reject]
+///
+/// Either peer can end the handshake with a [Message::Reject], e.g. when the other one is incompatible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// introduction of a peer
+    Hello(Hello),
+    /// the receiver is accepted with the agreed features, the MIDI stream follows
+    Accept(Features),
+    /// the session is refused, with the reason given by the peer
+    Reject(String),
+}
+
+impl Message {
+    fn kind(&self) -> u8 {
+        match self {
+            Message::Hello(_) => 1,
+            Message::Accept(_) => 2,
+            Message::Reject(_) => 3,
+        }
+    }
+
+    /// Write the message to a stream (`PSRI`, type byte, big endian `u16` payload length, payload)
+    pub fn write_to(&self, stream: &mut impl Write) -> Result<()> {
+        let payload = match self {
+            Message::Hello(hello) => hello.encode().into_bytes(),
+            Message::Accept(features) => features.to_string().into_bytes(),
+            Message::Reject(reason) => reason.as_bytes().to_vec(),
+        };
+        write_frame(stream, MAGIC, self.kind(), &payload)
+    }
+
+    /// Read a message from a stream, consuming exactly its bytes
+    pub fn read_from(stream: &mut impl Read) -> Result<Self> {
+        let (kind, payload) = read_frame(stream, MAGIC)?;
+        let message = match kind {
+            1 => Message::Hello(Hello::decode(&payload)?),
+            2 => Message::Accept(Features::decode(&payload)),
+            3 => Message::Reject(payload),
+            kind => {
+                return Err(
+                    ProtocolError::InvalidMessage(format!("unknown message type {}", kind)).into(),
+                )
+            }
+        };
+        Ok(message)
+    }
+
+    /// Read a message, failing if it is not the expected one
+    pub fn expect<T>(
+        stream: &mut impl Read,
+        what: &str,
+        f: impl FnOnce(Self) -> Option<T>,
+    ) -> Result<T> {
+        let message = Self::read_from(stream)?;
+        let description = format!("{:?}", message);
+        f(message).ok_or_else(|| {
+            ProtocolError::InvalidMessage(format!("expecting {}, got {}", what, description)).into()
+        })
+    }
+}
+
+/// Write a frame to a stream (`magic`, type byte, big endian `u16` payload length, payload)
+pub(crate) fn write_frame(
+    stream: &mut impl Write,
+    magic: &[u8; 4],
+    kind: u8,
+    payload: &[u8],
+) -> Result<()> {
+    let len = u16::try_from(payload.len())
+        .map_err(|_| ProtocolError::InvalidMessage("handshake message too long".into()))?;
+
+    let mut frame = Vec::with_capacity(7 + payload.len());
+    frame.extend_from_slice(magic);
+    frame.push(kind);
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame).map_err(TransportError::Write)?;
+    stream.flush().map_err(TransportError::Write)?;
+    Ok(())
+}
+
+/// Read a frame written by [write_frame], returning its type and payload
+pub(crate) fn read_frame(stream: &mut impl Read, magic: &[u8; 4]) -> Result<(u8, String)> {
+    let mut header = [0; 7];
+    stream
+        .read_exact(&mut header)
+        .map_err(TransportError::Read)?;
+    if &header[..4] != magic {
+        return Err(ProtocolError::InvalidMessage(
+            "the distant peer is not speaking the Passeri protocol".into(),
+        )
+        .into());
+    }
+    let mut payload = vec![0; u16::from_be_bytes([header[5], header[6]]) as usize];
+    stream
+        .read_exact(&mut payload)
+        .map_err(TransportError::Read)?;
+    let payload = String::from_utf8(payload)
+        .map_err(|_| ProtocolError::InvalidMessage("message is not valid UTF-8".into()))?;
+    Ok((header[4], payload))
+}
+
+/// Introduction of a peer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hello {
+    /// version of the **Passeri** protocol spoken by the peer
+    pub version: u32,
+    /// name of the peer, shown to the host approving it
+    pub name: String,
+    /// name of the MIDI port bridged by the peer
+    pub midi_port: String,
+    /// features supported by the peer
+    pub features: Features,
+    /// features the peer can not work without
+    pub requires: Features,
+    /// random nonce the receiver has to sign with its credential (sender only)
+    pub nonce: Option<[u8; NONCE_LEN]>,
+    /// HMAC-SHA256 of the sender nonce keyed by the receiver credential (receiver only)
+    pub proof: Option<Vec<u8>>,
+}
+
+impl Hello {
+    /// Answer the [Hello] of a sender with the given [Credentials], returning the answer and the agreed features
+    ///
+    /// # Arguments
+    /// * `credentials` - name, credential and required features of the receiver
+    /// * `midi_port` - name of the MIDI port bridged by the receiver
+    /// * `features` - features supported by the receiver [net_thread](crate::net::receiver::Thread)
+    /// * `sender` - [Hello] received from the sender
+    pub fn answer(
+        credentials: &Credentials,
+        midi_port: &str,
+        features: Features,
+        sender: &Hello,
+    ) -> std::result::Result<(Self, Features), ProtocolError> {
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            name: credentials.name.clone(),
+            midi_port: midi_port.to_string(),
+            features,
+            requires: credentials.requires,
+            nonce: None,
+            proof: credentials
+                .secret
+                .as_deref()
+                .zip(sender.nonce)
+                .map(|(secret, nonce)| sign(secret, &nonce)),
+        };
+        let agreed = hello.negotiate(sender)?;
+        Ok((hello, agreed))
+    }
+
+    /// Check the distant peer is compatible, returning the features supported by both peers
+    pub fn negotiate(&self, distant: &Hello) -> std::result::Result<Features, ProtocolError> {
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&distant.version) {
+            return Err(ProtocolError::IncompatibleVersion {
+                local: self.version,
+                distant: distant.version,
+            });
+        }
+
+        let agreed = self.features.intersection(distant.features);
+        let missing = self.requires.union(distant.requires).difference(agreed);
+        if !missing.is_empty() {
+            return Err(ProtocolError::MissingFeatures(missing));
+        }
+        Ok(agreed)
+    }
+
+    /// Encode as `key=value` lines, unknown keys being ignored by the distant peer
+    fn encode(&self) -> String {
+        let mut fields = BTreeMap::new();
+        fields.insert("version", self.version.to_string());
+        fields.insert("name", self.name.replace('\n', " "));
+        fields.insert("midi_port", self.midi_port.replace('\n', " "));
+        fields.insert("features", self.features.to_string());
+        fields.insert("requires", self.requires.to_string());
+        if let Some(nonce) = &self.nonce {
+            fields.insert("nonce", encode_hex(nonce));
+        }
+        if let Some(proof) = &self.proof {
+            fields.insert("proof", encode_hex(proof));
+        }
+        fields
+            .into_iter()
+            .map(|(key, value)| format!("{}={}\n", key, value))
+            .collect()
+    }
+
+    fn decode(payload: &str) -> Result<Self> {
+        let invalid = |what: String| ProtocolError::InvalidMessage(what);
+        let mut hello = Hello::default();
+        for line in payload.lines().filter(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("invalid hello field `{}`", line)))?;
+            match key {
+                "version" => {
+                    hello.version = value
+                        .parse()
+                        .map_err(|_| invalid(format!("invalid version `{}`", value)))?
+                }
+                "name" => hello.name = value.to_string(),
+                "midi_port" => hello.midi_port = value.to_string(),
+                "features" => hello.features = Features::decode(value),
+                "requires" => hello.requires = Features::decode(value),
+                "nonce" => {
+                    hello.nonce = Some(
+                        decode_hex(value)?
+                            .try_into()
+                            .map_err(|_| invalid("invalid nonce length".into()))?,
+                    )
+                }
+                "proof" => hello.proof = Some(decode_hex(value)?),
+                _ => continue,
+            }
+        }
+        Ok(hello)
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    let invalid = || ProtocolError::InvalidMessage(format!("invalid hexadecimal `{}`", hex));
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid().into());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| invalid().into())
+        })
+        .collect()
+}
+
+fn sign(secret: &str, nonce: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::sign(&key, nonce).as_ref().to_vec()
+}
+
+fn verify(secret: &str, nonce: &[u8], proof: &[u8]) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::verify(&key, nonce, proof).is_ok()
+}
+
+//
+//	RECEIVER SIDE
+//
+
+/// Name, optional credential and required features presented by a receiver to the sender
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    /// name of the receiver, shown to the host approving it
+    pub name: String,
+    /// pre-shared key or pairing code expected by the sender
+    pub secret: Option<String>,
+    /// features the receiver can not work without
+    pub requires: Features,
+}
+
+impl Credentials {
+    /// Credentials without secret, enough for senders not requiring authentication
+    pub fn new(name: &str) -> Self {
+        Credentials {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the pre-shared key or pairing code expected by the sender
+    pub fn with_secret(mut self, secret: &str) -> Self {
+        self.secret = Some(secret.to_string());
+        self
+    }
+
+    /// Set the features the sender has to support
+    pub fn with_requirements(mut self, requires: Features) -> Self {
+        self.requires = requires;
+        self
+    }
+}
+
+//
+//	SENDER SIDE
+//
+
+/// How a pending client authenticated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Auth {
+    /// the sender does not require any credential
+    None,
+    /// the client proved it knows the pre-shared key
+    PreSharedKey,
+    /// the client proved it knows the current pairing code
+    PairingCode,
+}
+
+/// Receiver that passed the [Lobby] checks, waiting for the host to accept or reject it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo<Addr> {
+    /// address of the receiver, to give to [Sender::send](crate::net::Sender::send) or [Sender::reject](crate::net::Sender::reject)
+    pub addr: Addr,
+    /// name presented by the receiver
+    pub name: String,
+    /// name of the MIDI port bridged by the receiver
+    pub midi_port: String,
+    /// version of the **Passeri** protocol spoken by the receiver
+    pub version: u32,
+    /// features agreed for the session
+    pub features: Features,
+    /// how the receiver authenticated
+    pub auth: Auth,
+}
+
+impl<Addr: Display> Display for ClientInfo<Addr> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\" ({})", self.name, self.addr)
+    }
+}
+
+/// Admission rules of a sender, shared between the [Sender instance](crate::net::Sender) and its [net_thread](crate::net::sender::Thread)
+///
+/// Rules are matched against the host part of the receiver address, or the identity it authenticated with
+/// (e.g. the fingerprint of its certificate over mutual TLS). The name a receiver presents is not authenticated,
+/// so it is never matched:
+/// - receivers matching the deny list are rejected
+/// - when the allow list is not empty, receivers not matching it are rejected
+/// - when a pre-shared key or a pairing code is set, receivers must prove they know one of them
+/// - receivers speaking an incompatible protocol version, or missing a required feature, are rejected
+///
+/// Cloning a [Lobby] gives a new handle on the same rules, which can be changed at any time.
+#[derive(Debug, Clone, Default)]
+pub struct Lobby {
+    inner: Arc<Mutex<Rules>>,
+}
+
+#[derive(Debug, Default)]
+struct Rules {
+    name: String,
+    midi_port: String,
+    requires: Features,
+    key: Option<String>,
+    pairing: Option<Pairing>,
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+/// Pairing code valid for a single receiver, until it expires or too many wrong credentials were presented
+///
+/// Once spent, expired or revoked, receivers still have to present a credential until the pairing is disabled.
+#[derive(Debug)]
+struct Pairing {
+    code: String,
+    expires: Instant,
+    /// wrong credentials presented by each host since the code was generated
+    attempts: HashMap<String, u32>,
+    /// the code admitted a receiver or was revoked
+    spent: bool,
+}
+
+impl Pairing {
+    fn is_valid(&self) -> bool {
+        !self.spent && Instant::now() < self.expires
+    }
+
+    /// Return `true` if `host` may still authenticate with the code
+    fn admits(&self, host: &str) -> bool {
+        self.is_valid()
+            && self.attempts.get(host).copied().unwrap_or_default() < MAX_PAIRING_ATTEMPTS
+    }
+
+    /// Count a wrong credential presented by `host`, revoking the code once too many were presented
+    fn fail(&mut self, host: &str) {
+        *self.attempts.entry(host.to_string()).or_default() += 1;
+        if self.attempts.values().sum::<u32>() >= MAX_PAIRING_FAILURES {
+            self.spent = true;
+        }
+    }
+}
+
+impl Lobby {
+    /// Create a lobby admitting every receiver
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn rules(&self) -> std::sync::MutexGuard<'_, Rules> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Set the name and the MIDI port presented to the receivers
+    pub fn set_identity(&self, name: &str, midi_port: &str) {
+        let mut rules = self.rules();
+        rules.name = name.to_string();
+        rules.midi_port = midi_port.to_string();
+    }
+
+    /// Set the features receivers have to support
+    pub fn require(&self, requires: Features) {
+        self.rules().requires = requires;
+    }
+
+    /// Require receivers to know `key` (or the pairing code), `None` removing the requirement
+    pub fn set_key(&self, key: Option<&str>) {
+        self.rules().key = key.map(str::to_string);
+    }
+
+    /// Generate a new pairing code, to be communicated to the receiver out of band
+    ///
+    /// The code admits a single receiver within [PAIRING_CODE_TTL]. It is revoked sooner when too many wrong
+    /// credentials are presented, and a host presenting a few wrong ones may not use it anymore.
+    pub fn enable_pairing(&self) -> std::result::Result<String, ProtocolError> {
+        let mut bytes = [0; PAIRING_CODE_LEN];
+        ring::rand::SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| ProtocolError::Random("pairing code"))?;
+        let code: String = bytes
+            .iter()
+            .map(|byte| PAIRING_CODE_ALPHABET[*byte as usize % PAIRING_CODE_ALPHABET.len()] as char)
+            .collect();
+        self.rules().pairing = Some(Pairing {
+            code: code.clone(),
+            expires: Instant::now() + PAIRING_CODE_TTL,
+            attempts: HashMap::new(),
+            spent: false,
+        });
+        Ok(code)
+    }
+
+    /// Stop accepting pairing codes, receivers no longer needing a credential unless a key is set
+    pub fn disable_pairing(&self) {
+        self.rules().pairing = None;
+    }
+
+    /// Current pairing code, if enabled and neither used, expired nor revoked
+    pub fn pairing_code(&self) -> Option<String> {
+        let rules = self.rules();
+        let pairing = rules.pairing.as_ref()?;
+        pairing.is_valid().then(|| pairing.code.clone())
+    }
+
+    /// Add a receiver host or authenticated identity to the allow list
+    pub fn allow(&self, entry: &str) {
+        let mut rules = self.rules();
+        rules.deny.retain(|e| e != entry);
+        if !rules.allow.iter().any(|e| e == entry) {
+            rules.allow.push(entry.to_string());
+        }
+    }
+
+    /// Add a receiver host or authenticated identity to the deny list
+    pub fn deny(&self, entry: &str) {
+        let mut rules = self.rules();
+        rules.allow.retain(|e| e != entry);
+        if !rules.deny.iter().any(|e| e == entry) {
+            rules.deny.push(entry.to_string());
+        }
+    }
+
+    /// Remove a receiver host or authenticated identity from both lists
+    pub fn forget(&self, entry: &str) {
+        let mut rules = self.rules();
+        rules.allow.retain(|e| e != entry);
+        rules.deny.retain(|e| e != entry);
+    }
+
+    /// Build the [Hello] introducing the sender to a new receiver, with a fresh nonce
+    ///
+    /// # Arguments
+    /// * `features` - features supported by the sender [net_thread](crate::net::sender::Thread)
+    pub fn hello(&self, features: Features) -> std::result::Result<Hello, ProtocolError> {
+        let mut nonce = [0; NONCE_LEN];
+        ring::rand::SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| ProtocolError::Random("nonce"))?;
+        let rules = self.rules();
+        Ok(Hello {
+            version: PROTOCOL_VERSION,
+            name: rules.name.clone(),
+            midi_port: rules.midi_port.clone(),
+            features,
+            requires: rules.requires,
+            nonce: Some(nonce),
+            proof: None,
+        })
+    }
+
+    /// Check a receiver against the rules, returning the reason of its rejection if any
+    ///
+    /// # Arguments
+    /// * `addr` - address of the receiver
+    /// * `identity` - identity the receiver authenticated with at the transport level, if any
+    ///   (e.g. the fingerprint of its TLS certificate)
+    /// * `local` - [Hello] sent to the receiver by [Lobby::hello]
+    /// * `distant` - answer of the receiver
+    pub fn admit<Addr: Display>(
+        &self,
+        addr: Addr,
+        identity: Option<&str>,
+        local: &Hello,
+        distant: Hello,
+    ) -> std::result::Result<ClientInfo<Addr>, String> {
+        let features = local.negotiate(&distant).map_err(|err| err.to_string())?;
+        let mut rules = self.rules();
+        let addr_str = addr.to_string();
+        let host = host(&addr_str);
+        let matches = |entry: &String| entry == host || Some(entry.as_str()) == identity;
+
+        if rules.deny.iter().any(matches) {
+            return Err("denied".into());
+        }
+        if !rules.allow.is_empty() && !rules.allow.iter().any(matches) {
+            return Err("not allowed".into());
+        }
+
+        let auth = if rules.key.is_none() && rules.pairing.is_none() {
+            Auth::None
+        } else {
+            let nonce = local.nonce.unwrap_or_default();
+            let proof = distant.proof.as_deref().ok_or("credential required")?;
+            let paired = |pairing: &&mut Pairing| {
+                pairing.admits(host) && verify(&pairing.code, &nonce, proof)
+            };
+            if rules
+                .key
+                .as_deref()
+                .is_some_and(|key| verify(key, &nonce, proof))
+            {
+                Auth::PreSharedKey
+            } else if let Some(pairing) = rules.pairing.as_mut().filter(paired) {
+                pairing.spent = true;
+                Auth::PairingCode
+            } else {
+                if let Some(pairing) = rules.pairing.as_mut().filter(|pairing| pairing.is_valid()) {
+                    pairing.fail(host);
+                }
+                return Err("invalid credential".into());
+            }
+        };
+
+        Ok(ClientInfo {
+            addr,
+            name: distant.name,
+            midi_port: distant.midi_port,
+            version: distant.version,
+            features,
+            auth,
+        })
+    }
+}
+
+/// Host part of an address formatted as `host:port` or `[ipv6]:port`
+fn host(addr: &str) -> &str {
+    match addr.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => {
+            host.trim_start_matches('[').trim_end_matches(']')
+        }
+        _ => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYSEX: Features = Features {
+        sysex: true,
+        ..Features::NONE
+    };
+
+    #[test]
+    fn test_message_roundtrip() {
+        let sender = Lobby::new().hello(SYSEX).unwrap();
+        let (receiver, _) = Hello::answer(
+            &Credentials::new("studio").with_secret("1234"),
+            "USB Synth",
+            SYSEX,
+            &sender,
+        )
+        .unwrap();
+        let messages = [
+            Message::Hello(sender),
+            Message::Hello(receiver),
+            Message::Accept(SYSEX),
+            Message::Reject("denied".into()),
+        ];
+
+        let mut buf = vec![];
+        for message in &messages {
+            message.write_to(&mut buf).unwrap();
+        }
+        buf.extend_from_slice(&[0x90, 0x40, 0x7F]);
+
+        let mut stream = &buf[..];
+        for message in &messages {
+            assert_eq!(&Message::read_from(&mut stream).unwrap(), message);
+        }
+        // the MIDI stream following the handshake is left untouched
+        assert_eq!(stream, &[0x90, 0x40, 0x7F]);
+
+        assert!(Message::read_from(&mut &[0x90, 0x40, 0x7F, 0, 0, 0, 0][..]).is_err());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let all = Features {
+            timestamps: true,
+            sysex: true,
+            compression: true,
+            duplex: true,
+            cables: true,
+        };
+        assert_eq!(
+            all.to_string(),
+            "timestamps,sysex,compression,duplex,cables"
+        );
+        assert_eq!(Features::decode(&all.to_string()), all);
+        assert_eq!(Features::decode("sysex,teleport"), SYSEX);
+        assert_eq!(Features::NONE.to_string(), "none");
+        assert_eq!("sysex".parse::<Features>().unwrap(), SYSEX);
+        assert_eq!("none".parse::<Features>().unwrap(), Features::NONE);
+        assert!("sysex,teleport".parse::<Features>().is_err());
+
+        let local = Lobby::new().hello(all).unwrap();
+        let distant = Hello {
+            version: PROTOCOL_VERSION,
+            features: SYSEX,
+            ..Default::default()
+        };
+        assert_eq!(local.negotiate(&distant).unwrap(), SYSEX);
+
+        let requiring = Hello {
+            requires: Features {
+                duplex: true,
+                ..Features::NONE
+            },
+            ..distant.clone()
+        };
+        assert!(matches!(
+            local.negotiate(&requiring),
+            Err(ProtocolError::MissingFeatures(missing)) if missing.duplex && !missing.sysex
+        ));
+
+        let outdated = Hello {
+            version: MIN_PROTOCOL_VERSION - 1,
+            ..distant
+        };
+        assert!(matches!(
+            local.negotiate(&outdated),
+            Err(ProtocolError::IncompatibleVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn test_admit() {
+        let lobby = Lobby::new();
+        let local = lobby.hello(SYSEX).unwrap();
+        let addr = "192.168.1.10:5000";
+        let hello = |name: &str, secret: Option<&str>| {
+            let mut credentials = Credentials::new(name);
+            credentials.secret = secret.map(str::to_string);
+            Hello::answer(&credentials, "USB Synth", SYSEX, &local)
+                .unwrap()
+                .0
+        };
+
+        assert_eq!(
+            lobby
+                .admit(addr, None, &local, hello("a", None))
+                .unwrap()
+                .auth,
+            Auth::None
+        );
+
+        lobby.set_key(Some("secret"));
+        let code = lobby.enable_pairing().unwrap();
+        assert_eq!(code.len(), PAIRING_CODE_LEN);
+        assert!(lobby.admit(addr, None, &local, hello("a", None)).is_err());
+        assert!(lobby
+            .admit(addr, None, &local, hello("a", Some("wrong")))
+            .is_err());
+        assert_eq!(
+            lobby
+                .admit(addr, None, &local, hello("a", Some("secret")))
+                .unwrap()
+                .auth,
+            Auth::PreSharedKey
+        );
+        assert_eq!(
+            lobby
+                .admit(addr, None, &local, hello("a", Some(&code)))
+                .unwrap(),
+            ClientInfo {
+                addr,
+                name: "a".into(),
+                midi_port: "USB Synth".into(),
+                version: PROTOCOL_VERSION,
+                features: SYSEX,
+                auth: Auth::PairingCode
+            }
+        );
+        // the pairing code admits a single receiver
+        assert!(lobby
+            .admit(addr, None, &local, hello("b", Some(&code)))
+            .is_err());
+        assert_eq!(lobby.pairing_code(), None);
+        // a proof is bound to its nonce
+        assert!(lobby
+            .admit(
+                addr,
+                None,
+                &lobby.hello(SYSEX).unwrap(),
+                hello("a", Some("secret"))
+            )
+            .is_err());
+
+        lobby.deny("192.168.1.10");
+        assert!(lobby
+            .admit(addr, None, &local, hello("a", Some("secret")))
+            .is_err());
+        lobby.forget("192.168.1.10");
+        // the names presented by the receivers are not authenticated, only their identity is
+        lobby.allow("b");
+        assert!(lobby
+            .admit(addr, None, &local, hello("b", Some("secret")))
+            .is_err());
+        assert!(lobby
+            .admit(addr, Some("a"), &local, hello("b", Some("secret")))
+            .is_err());
+        assert!(lobby
+            .admit(addr, Some("b"), &local, hello("a", Some("secret")))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_pairing() {
+        let lobby = Lobby::new();
+        let local = lobby.hello(SYSEX).unwrap();
+        let hello = |secret: &str| {
+            let credentials = Credentials::new("stage").with_secret(secret);
+            Hello::answer(&credentials, "USB Synth", SYSEX, &local)
+                .unwrap()
+                .0
+        };
+        let code = lobby.enable_pairing().unwrap();
+        assert_ne!(lobby.enable_pairing().unwrap(), code);
+        let code = lobby.pairing_code().unwrap();
+        assert!(code.bytes().all(|c| PAIRING_CODE_ALPHABET.contains(&c)));
+
+        // a host guessing the code may not use it anymore, the others still can
+        for _ in 0..MAX_PAIRING_ATTEMPTS {
+            assert!(lobby
+                .admit("192.168.1.10:5000", None, &local, hello("000000"))
+                .is_err());
+        }
+        assert!(lobby
+            .admit("192.168.1.10:5000", None, &local, hello(&code))
+            .is_err());
+        assert!(lobby
+            .admit("192.168.1.11:5000", None, &local, hello(&code))
+            .is_ok());
+
+        // too many wrong credentials revoke the code
+        let code = lobby.enable_pairing().unwrap();
+        for host in 0..MAX_PAIRING_FAILURES {
+            let addr = format!("10.0.0.{}:5000", host);
+            assert!(lobby.admit(addr, None, &local, hello("000000")).is_err());
+        }
+        assert_eq!(lobby.pairing_code(), None);
+        assert!(lobby
+            .admit("192.168.1.11:5000", None, &local, hello(&code))
+            .is_err());
+
+        // and so does time
+        let code = lobby.enable_pairing().unwrap();
+        if let Some(pairing) = lobby.rules().pairing.as_mut() {
+            pairing.expires = Instant::now();
+        }
+        assert_eq!(lobby.pairing_code(), None);
+        assert!(lobby
+            .admit("192.168.1.11:5000", None, &local, hello(&code))
+            .is_err());
+    }
+
+    #[test]
+    fn test_host() {
+        assert_eq!(host("192.168.1.10:5000"), "192.168.1.10");
+        assert_eq!(host("[::1]:5000"), "::1");
+        assert_eq!(host("studio"), "studio");
+    }
+}
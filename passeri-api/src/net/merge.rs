@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use crate::lifecycle::Event;
+use crate::metrics::Stats;
+use crate::midi::{check_channel, OutputConnection};
+use crate::net::lobby::Credentials;
+use crate::net::receiver::{Receiver, Thread, ThreadReturn};
+pub use crate::net::Result;
+use crate::{Error, MidiError};
+use log::info;
+
+/// Sender merged by a [MergedReceiver]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source<A> {
+    /// address of the sender
+    pub addr: A,
+    /// number of channels the messages of the sender are shifted by, wrapping from 15 to 0
+    pub channel_offset: u8,
+    /// when unset, the messages of the sender are not forwarded until [MergedReceiver::set_enabled] is called
+    pub enabled: bool,
+}
+
+impl<A> Source<A> {
+    /// Create a new source, enabled and forwarded on its own channels
+    pub fn new(addr: A) -> Self {
+        Source {
+            addr,
+            channel_offset: 0,
+            enabled: true,
+        }
+    }
+}
+
+/// Receiver connected to several senders, merging their streams into the same MIDI out ports
+///
+/// Each sender is received by its own [Receiver] (see [MergedReceiver::sources]), sending to
+/// [merged](OutputConnection::merge) connections of the ports, so the messages of the senders never interleave
+/// inside a SysEx or a running status sequence. The bridge counters are shared by every sender.
+pub struct MergedReceiver {
+    sources: Vec<Receiver>,
+    /// connections owning the MIDI out ports, kept open while the senders are received
+    outputs: Vec<OutputConnection>,
+    /// callbacks given to [MergedReceiver::on_event], also called on the events of the senders added later
+    callbacks: Vec<EventCallback>,
+}
+
+type EventCallback = Arc<dyn Fn(&Event) + Send + Sync>;
+
+impl MergedReceiver {
+    /// Connect to every sender of `sources` (it is recommended to use the
+    /// [ReceiverBuilder::merge()](crate::ReceiverBuilder::merge) function),
+    /// the messages of the cable `n` being sent to the `n`th connection of `midi_tx`
+    pub fn new<T: Thread>(
+        midi_tx: Vec<OutputConnection>,
+        sources: Vec<Source<T::Addr>>,
+        config: T::Config,
+        credentials: Credentials,
+    ) -> Result<Self>
+    where
+        T::Config: Clone,
+    {
+        if sources.is_empty() {
+            return Err(Error::NoSource);
+        }
+        let mut receiver = MergedReceiver::with_outputs(midi_tx)?;
+        for source in sources {
+            receiver.add_source::<T>(source, config.clone(), credentials.clone())?;
+        }
+        Ok(receiver)
+    }
+
+    /// Create a new receiver merging no sender yet into the MIDI out ports of `midi_tx`,
+    /// the senders being added with [MergedReceiver::add_source]
+    pub fn with_outputs(midi_tx: Vec<OutputConnection>) -> Result<Self> {
+        if midi_tx.is_empty() {
+            return Err(MidiError::CableCount(0).into());
+        }
+        Ok(MergedReceiver {
+            sources: vec![],
+            outputs: midi_tx,
+            callbacks: vec![],
+        })
+    }
+
+    /// Connect to one more sender, returning its index. Its stream is forwarded once [Receiver::receive]
+    /// is called on its [source](MergedReceiver::source).
+    pub fn add_source<T: Thread>(
+        &mut self,
+        source: Source<T::Addr>,
+        config: T::Config,
+        credentials: Credentials,
+    ) -> Result<usize> {
+        let channel_offset = check_channel(source.channel_offset)?;
+        let merged = self
+            .outputs
+            .iter()
+            .map(|conn| conn.merge(channel_offset))
+            .collect();
+        let receiver = Receiver::new::<T>(merged, source.addr, config, credentials)?;
+        if !source.enabled {
+            receiver.pause()?;
+        }
+        for callback in self.callbacks.iter() {
+            let callback = callback.clone();
+            receiver.on_event(move |event| callback(event));
+        }
+        info!(
+            "merging {} shifted by {} channels",
+            receiver.info(),
+            channel_offset
+        );
+        self.sources.push(receiver);
+        Ok(self.sources.len() - 1)
+    }
+
+    /// Disconnect from the sender at `index`, the following senders moving down by one index.
+    /// The returned [Receiver] is stopped when dropped.
+    pub fn remove_source(&mut self, index: usize) -> Result<Receiver> {
+        if index >= self.sources.len() {
+            return Err(Error::UnknownSource(index));
+        }
+        let receiver = self.sources.remove(index);
+        info!("stopped merging {}", receiver.info());
+        Ok(receiver)
+    }
+
+    /// Start forwarding the streams of every sender
+    pub fn receive(&self) -> Result<()> {
+        self.sources.iter().try_for_each(Receiver::receive)
+    }
+
+    /// Restart (`true`) or stop (`false`) forwarding the messages of the sender at `index`,
+    /// its held notes being released on the MIDI out ports
+    pub fn set_enabled(&self, index: usize, enabled: bool) -> Result<()> {
+        let source = self.source(index)?;
+        if enabled {
+            source.resume()
+        } else {
+            source.pause()
+        }
+    }
+
+    /// Return the [Receiver] of the sender at `index`, in the order of the sources, to pause, mute or solo it
+    pub fn source(&self, index: usize) -> Result<&Receiver> {
+        self.sources.get(index).ok_or(Error::UnknownSource(index))
+    }
+
+    /// Return the [Receiver] of every sender, in the order of the sources
+    pub fn sources(&self) -> &[Receiver] {
+        &self.sources
+    }
+
+    /// Silence a stuck synth on the MIDI out ports, see [Receiver::panic]
+    pub fn panic(&self, note_offs: bool) -> Result<()> {
+        // every receiver shares the ports, any of them still running can send the messages
+        let mut ret = Err(Error::NetThreadStopped);
+        for source in self.sources.iter() {
+            ret = source.panic(note_offs);
+            if ret.is_ok() {
+                break;
+            }
+        }
+        ret
+    }
+
+    /// Close the connection to every sender, which is also done when the [MergedReceiver] is dropped
+    pub fn stop(&self) -> Result<()> {
+        let mut ret = Ok(());
+        for source in self.sources.iter().filter(|source| !source.is_finished()) {
+            ret = ret.and(source.stop());
+        }
+        ret
+    }
+
+    /// Wait for the receiver of every sender to end and return their [ThreadReturn]s
+    pub fn join(&mut self) -> Result<Vec<ThreadReturn>> {
+        self.sources.iter_mut().map(Receiver::join).collect()
+    }
+
+    /// Return `true` once the receiver of every sender has ended, or if no sender is merged
+    pub fn is_finished(&self) -> bool {
+        self.sources.iter().all(Receiver::is_finished)
+    }
+
+    /// Return a string describing the address of the receiver of every sender
+    pub fn info(&self) -> String {
+        self.sources
+            .iter()
+            .map(Receiver::info)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Return a snapshot of the counters shared by every sender
+    pub fn stats(&self) -> Stats {
+        self.outputs[0].metrics().snapshot()
+    }
+
+    /// Call `callback` on the following [Event]s of the MIDI out ports and of the receiver of every sender,
+    /// including the ones added later, from the thread emitting them
+    pub fn on_event(&mut self, callback: impl Fn(&Event) + Send + Sync + 'static) {
+        let callback: EventCallback = Arc::new(callback);
+        for output in self.outputs.iter() {
+            let callback = callback.clone();
+            output.lifecycle().on_event(move |event| callback(event));
+        }
+        for source in self.sources.iter() {
+            let callback = callback.clone();
+            source.on_event(move |event| callback(event));
+        }
+        self.callbacks.push(callback);
+    }
+}
@@ -1,9 +1,36 @@
-use std::error::Error;
+/// Version of the **Passeri** protocol, advertised with the senders and exchanged in the [lobby::Hello]
+pub const PROTOCOL_VERSION: u32 = 3;
+/// Oldest version of the **Passeri** protocol a peer can talk to
+pub const MIN_PROTOCOL_VERSION: u32 = 3;
+
+/// Status bytes starting the frames interleaved with the MIDI messages of a connection: [cable::CABLE_STATUS] and
+/// [heartbeat::HEARTBEAT_STATUS]
+///
+/// Both are System Real-Time bytes left undefined by the MIDI specification, yet a device may still emit them. They are
+/// reserved by the protocol: the senders do not forward the local messages starting with one of them, counting them as
+/// dropped in their [Metrics](crate::metrics::Metrics), so the receivers never mistake a MIDI message for a frame.
+pub const RESERVED_STATUS: [u8; 2] = [cable::CABLE_STATUS, heartbeat::HEARTBEAT_STATUS];
+
 #[doc(hidden)]
-pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
+pub type Result<T> = std::result::Result<T, crate::Error>;
+/// Frames multiplexing several MIDI ports over a connection
+pub mod cable;
+/// Keepalive frames detecting vanished peers and measuring the round trip time
+pub mod heartbeat;
+/// Handshake letting senders identify, authenticate and approve receivers
+pub mod lobby;
+/// Receivers merging the streams of several senders into the same MIDI out ports
+pub mod merge;
+/// Wake-up of the net_thread event loops when a request or a MIDI message is sent to them
+pub mod notifier;
+pub use notifier::Notifier;
 /// Define a set of enums and thread trait to work with [Receiver] bridge
 pub mod receiver;
+/// Handshake with the relays pairing senders and receivers unable to reach each other, e.g. behind NAT
+pub mod relay;
 pub use receiver::Receiver;
+/// Named sessions in which several senders and receivers meet through a relay
+pub mod room;
 /// Define a set of enums and thread trait to work with [Sender] bridge
 pub mod sender;
 pub use sender::Sender;
@@ -0,0 +1,65 @@
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// Function waking up the event loop of a net_thread, see [sender::Thread::waker](crate::net::sender::Thread::waker)
+pub type Wake = Arc<dyn Fn() + Send + Sync>;
+
+/// Handle waking up the event loop of a [net_thread](crate::net::sender::Thread) each time a [Request](crate::net::sender::Request)
+/// or a MIDI message is sent to it, the channels it reads from not being pollable
+///
+/// It is shared between the bridge, its MIDI input and its net_thread, which sets the [Wake] function once its event loop is ready.
+/// Cloning a [Notifier] gives a new handle on the same function.
+#[derive(Clone, Default)]
+pub struct Notifier {
+    wake: Arc<RwLock<Option<Wake>>>,
+}
+
+impl fmt::Debug for Notifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let set = self.wake.read().is_ok_and(|wake| wake.is_some());
+        f.debug_struct("Notifier").field("set", &set).finish()
+    }
+}
+
+impl Notifier {
+    /// Create a new notifier, doing nothing until [Notifier::set] is called
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the function called by [Notifier::notify], replacing the previous one
+    pub fn set(&self, wake: Wake) {
+        *self.wake.write().unwrap_or_else(|err| err.into_inner()) = Some(wake);
+    }
+
+    /// Wake up the event loop of the net_thread
+    pub fn notify(&self) {
+        let wake = self.wake.read().unwrap_or_else(|err| err.into_inner());
+        if let Some(wake) = wake.as_ref() {
+            wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_notify() {
+        let notifier = Notifier::new();
+        let handle = notifier.clone();
+        // nothing to wake up yet
+        handle.notify();
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let wakes = count.clone();
+        notifier.set(Arc::new(move || {
+            wakes.fetch_add(1, Ordering::Relaxed);
+        }));
+        handle.notify();
+        handle.notify();
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+    }
+}
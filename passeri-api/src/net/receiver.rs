@@ -1,14 +1,25 @@
 use std::{fmt::Debug, sync::mpsc, thread::JoinHandle};
 
+use crate::lifecycle::{BridgeState, Event, Lifecycle};
+use crate::metrics::{Metrics, Stats};
+use crate::midi::{check_channel, Control, OutputConnection};
+use crate::net::lobby::{Credentials, Features};
+use crate::net::notifier::{Notifier, Wake};
 pub use crate::net::Result;
+use crate::{Error, MidiError, ProtocolError, TransportError};
 use log::{info, trace};
-use midir::MidiOutputConnection;
 
 /// Set of requests send by the [Receiver instance](Receiver) to the [net_thread](Thread).
-/// It have to be able to process all these requests to be compliant with this [Receiver instance](Receiver).
+/// It have to be able to process all these requests to be compliant with this [Receiver instance](Receiver),
+/// including while receiving.
 pub enum Request {
     /// start receiving from the distant sender
     Receive, // send invitation to specified address:port
+    /// change the [ChannelFilter](crate::midi::ChannelFilter) of the stream, sending the messages it returns to the MIDI out port
+    /// (the releases of the notes held on the channels not forwarded anymore, or the panic messages), answering with [Response::Applied]
+    Control(Control),
+    /// close the connection and end the [net_thread](Thread), answering with [Response::Stopped]
+    Stop,
 }
 
 /// Set of responses that can return the [net_thread](Thread) to the [Receiver instance](Receiver) after receiving [Request].
@@ -16,6 +27,10 @@ pub enum Request {
 pub enum Response {
     /// notify that [net_thread](Thread) start to receive from distant sender
     StartReceiving,
+    /// the [net_thread](Thread) is about to end
+    Stopped,
+    /// the [Request::Control] request has been applied
+    Applied,
 }
 
 /// Oneshot tunnel letting the [net_thread](Thread) return [Response] to the [Receiver instance](Receiver)
@@ -40,33 +55,43 @@ pub enum ThreadReturn {
     #[error("unable to send response to tunnel")]
     Send(#[from] oneshot::SendError<Response>),
 
-    /// unable to write to TcpStream
-    #[error("unable to write to TcpStream")]
-    Write(#[from] std::io::Error),
+    /// error in the network layer
+    #[error(transparent)]
+    Transport(#[from] TransportError),
 
-    /// unable to read from TcpStream
-    #[error("unable to read from TcpStream")]
-    Read(std::io::Error),
+    /// error with the local MIDI port
+    #[error(transparent)]
+    Midi(#[from] MidiError),
 
-    /// unable to read from MIDI
-    #[error("unable to read from MIDI")]
-    MidiRecvError,
+    /// error in the dialog with the distant peer
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
 
-    /// unable to send to MIDI
-    #[error("unable to send to MIDI")]
-    MidiSendError(midir::SendError),
-
-    /// unable to join the TCP Thread
-    #[error("Join Error")]
+    /// the net_thread panicked
+    #[error("unable to join the net_thread")]
     JoinError,
 
     /// Distant Sender disconnect
-    #[error("Receive End")]
+    #[error("the distant Sender closed the connection")]
     ReceiveEnd,
 
-    /// Distant Receiver disconnect
-    #[error("Send End")]
+    /// the MIDI input port tunnel is closed
+    #[error("the MIDI input tunnel is closed")]
     SendEnd,
+
+    /// the net_thread stopped processing requests
+    #[error("the net_thread stopped")]
+    Stopped,
+}
+
+impl ThreadReturn {
+    /// Return `true` if the [net_thread](Thread) ended because of an error
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            ThreadReturn::Transport(_) | ThreadReturn::Midi(_) | ThreadReturn::Protocol(_)
+        )
+    }
 }
 
 //
@@ -75,34 +100,60 @@ pub enum ThreadReturn {
 
 /// Minimum set of function that have to implement a [net_thread](Thread)
 ///
-/// It is recommended to implement it as a background thread waiting for any incomming data from the distant Sender,
-/// then forwarding it to the MIDI out port by a `send()` call to the provided `MidiOutputConnection` instance.
+/// It is run in a background thread as an event loop multiplexing the [Request]s from `messenger_rx` and the data
+/// from the distant Sender, forwarded to the MIDI out port by a `send()` call to the provided `OutputConnection` instance.
+/// The channel is not pollable: the function returned by [Thread::waker] is called after each request sent to it.
 pub trait Thread {
     /// Type used by the chosen Network Layer to describe addresses (e.g.: `SocketAddr` for TCP)
     type Addr: 'static + Send;
 
+    /// [Features] supported by the Network Layer, announced to the sender in the [Hello](crate::net::lobby::Hello)
+    const FEATURES: Features;
+
+    /// Options of the Network Layer (e.g.: TLS settings), the default value being used by the [ReceiverBuilder](crate::ReceiverBuilder)
+    type Config: 'static + Send + Default;
+
     /// create a new Receiver instance
     ///
     /// # Arguments
     /// * `addr` - the distant Sender address to which the newly created **ReceiverThread** have to listen for
-    /// * `midi_tx` - the [MidiOutputConnection] instance used to forward the receiving call to the local MIDI out port
+    /// * `config` - options of the Network Layer
+    /// * `midi_tx` - the [OutputConnection] instances used to forward the receiving call to the local MIDI out ports,
+    ///   the messages of the [cable](crate::net::cable) `n` going to the `n`th one
     /// * `messenger_rx` - [Receiver](mpsc::Receiver) from which the **ReceiverThread** will get [Request] from the main thread
+    /// * `metrics` - [Metrics] of the bridge, in which the **ReceiverThread** have to count the messages it forwards to `midi_tx`
+    /// * `credentials` - [Credentials] presented to the distant Sender (see [lobby::Message](crate::net::lobby::Message)),
+    ///   implementation have to return once the Sender accepted them, or a [ProtocolError::Rejected] error,
+    ///   and fail with the [ProtocolError] returned by [Hello::answer](crate::net::lobby::Hello::answer) when the Sender is incompatible
     fn new(
         addr: Self::Addr,
-        midi_tx: MidiOutputConnection,
+        config: Self::Config,
+        midi_tx: Vec<OutputConnection>,
         messenger_rx: mpsc::Receiver<PasseriReq>,
-    ) -> std::result::Result<Self, String>
+        metrics: Metrics,
+        credentials: Credentials,
+    ) -> std::result::Result<Self, Error>
     where
         Self: Sized;
 
-    /// implementation have to block reading on the `messenger_rx` [Receiver](mpsc::Receiver), processing each incomming [Request]
+    /// implementation have to run the event loop until `messenger_rx` is disconnected or a [Request::Stop] is received,
+    /// sleeping until the connection is ready or it is woken up by [Thread::waker]. On each wake-up, it have to:
+    /// - process every incomming [Request] from `messenger_rx`
+    /// - once a [Request::Receive] answered with [Response::StartReceiving], forward the incomming [crate::midi::MidiFrame]
+    ///   from the distant sender to the local midi_thread using the `midi_tx` [OutputConnection] of their cable,
+    ///   dropping the messages of the cables without port
+    /// - keep the connection alive from the start, before the first [Request::Receive], holding the messages
+    ///   received until then
+    /// - filter the forwarded messages with a [ChannelFilter](crate::midi::ChannelFilter) driven by [Request::Control],
+    ///   sending the messages it returns to `midi_tx`
+    ///
+    /// Whenever it returns, and when the MIDI out port is plugged back, it have to send the messages of
+    /// [ChannelFilter::release_all](crate::midi::ChannelFilter::release_all) to `midi_tx`, so no note is left stuck.
+    /// It have to fail with [ThreadReturn::ReceiveEnd] once the distant sender closed the connection.
     fn run(&mut self) -> std::result::Result<(), ThreadReturn>;
 
-    /// implementation have to start forwarding incomming [crate::midi::MidiFrame] from the distant sender to the local midi_thread using `midi_tx` [MidiOutputConnection].
-    /// It have to notify the main thread that the receiving stream is starting by a [Response::StartReceiving] [Response] and then looping over this way:
-    /// 	- blocking on reading the incomming [crate::midi::MidiFrame] from the distant sender
-    /// 	- forwarding received message to the local midi_thread using `midi_tx` [MidiOutputConnection]
-    fn receive(&mut self, responder: Responder) -> std::result::Result<(), ThreadReturn>;
+    /// return the function waking up the event loop of [Thread::run], called by the [Receiver instance](Receiver) after each [Request]
+    fn waker(&self) -> Wake;
 
     /// String describing the distant Sender address
     fn info(&self) -> String;
@@ -116,68 +167,194 @@ pub trait Thread {
 pub struct Receiver {
     net_thread: Option<JoinHandle<ThreadReturn>>,
     tx: mpsc::Sender<PasseriReq>,
+    notifier: Notifier,
     addr: String,
+    metrics: Metrics,
+    lifecycle: Lifecycle,
 }
 
 impl Receiver {
-    /// Create a new [Receiver instance](Receiver) (it is recommended to use the [new_receiver()][crate::new_receiver] function)
-    pub fn new<T: Thread>(midi_tx: MidiOutputConnection, addr: T::Addr) -> Result<Self> {
+    /// Create a new [Receiver instance](Receiver) (it is recommended to use the [ReceiverBuilder][crate::ReceiverBuilder]),
+    /// the messages of the cable `n` being sent to the `n`th connection of `midi_tx`
+    pub fn new<T: Thread>(
+        midi_tx: Vec<OutputConnection>,
+        addr: T::Addr,
+        config: T::Config,
+        credentials: Credentials,
+    ) -> Result<Self> {
         let (tx, rx) = mpsc::channel::<PasseriReq>();
-        let (init_tx, init_rx) = oneshot::channel::<std::result::Result<String, String>>();
-
-        let net_thread = Some(std::thread::spawn(|| {
-            let mut socket = match T::new(addr, midi_tx, rx) {
+        let (init_tx, init_rx) = oneshot::channel::<Result<String>>();
+        let first = midi_tx.first().ok_or(MidiError::CableCount(0))?;
+        let metrics = first.metrics().clone();
+        let thread_metrics = metrics.clone();
+        let lifecycle = first.lifecycle().clone();
+        let thread_lifecycle = lifecycle.clone();
+        let notifier = Notifier::new();
+        let thread_notifier = notifier.clone();
+
+        let net_thread = Some(std::thread::spawn(move || {
+            let mut socket = match T::new(
+                addr,
+                config,
+                midi_tx,
+                rx,
+                thread_metrics.clone(),
+                credentials,
+            ) {
                 Ok(res) => {
-                    init_tx.send(Ok(res.info())).unwrap();
+                    thread_notifier.set(res.waker());
+                    let _ = init_tx.send(Ok(res.info()));
                     res
                 }
                 Err(err) => {
-                    init_tx.send(Err(err)).unwrap();
+                    let _ = init_tx.send(Err(err));
                     return ThreadReturn::InitError;
                 }
             };
 
             info!("receiver created on {}", socket.info());
 
-            socket.run().unwrap_err()
+            let ret = socket.run().err().unwrap_or(ThreadReturn::Stopped);
+            if matches!(ret, ThreadReturn::ReceiveEnd) {
+                thread_lifecycle.emit(Event::ClientLeft);
+            }
+            if ret.is_error() {
+                thread_metrics.record_error();
+                thread_lifecycle.emit(Event::Error(ret.to_string()));
+            }
+            thread_lifecycle.emit(Event::Stopped(ret.to_string()));
+            ret
         }));
 
-        let addr = init_rx.recv()??;
+        let addr = init_rx.recv().map_err(|_| Error::NetThreadStopped)??;
+        lifecycle.emit(Event::ClientConnected(addr.clone()));
 
         Ok(Receiver {
             net_thread,
             tx,
+            notifier,
             addr,
+            metrics,
+            lifecycle,
         })
     }
 
     /// Start forwarding network stream from [net_thread](Thread) to output MIDI port
     pub fn receive(&self) -> Result<()> {
-        let (response_sender, response_receiver) = oneshot::channel();
-        self.tx.send((Request::Receive, response_sender))?;
-
-        match response_receiver.recv()? {
+        match self.request(Request::Receive)? {
             Response::StartReceiving => {
                 trace!("received ListenStream");
+                self.lifecycle.emit(Event::Streaming);
                 Ok(())
             }
+            response => Err(ProtocolError::UnexpectedResponse(format!("{:?}", response)).into()),
+        }
+    }
+
+    /// Stop forwarding MIDI messages without closing the connection, the notes held being released on the MIDI out port
+    pub fn pause(&self) -> Result<()> {
+        self.control(Control::Pause(true))
+    }
+
+    /// Restart forwarding MIDI messages after a [Receiver::pause]
+    pub fn resume(&self) -> Result<()> {
+        self.control(Control::Pause(false))
+    }
+
+    /// Stop (`true`) or restart (`false`) forwarding the messages of MIDI `channel` (0 to 15), its held notes being released on the MIDI out port
+    pub fn mute(&self, channel: u8, muted: bool) -> Result<()> {
+        self.control(Control::Mute(check_channel(channel)?, muted))
+    }
+
+    /// Add (`true`) or remove (`false`) MIDI `channel` (0 to 15) from the soloed ones, the other channels not being forwarded while one is soloed
+    pub fn solo(&self, channel: u8, soloed: bool) -> Result<()> {
+        self.control(Control::Solo(check_channel(channel)?, soloed))
+    }
+
+    /// Silence a stuck synth on the MIDI out port: All Sound Off, All Notes Off and Reset All Controllers are sent on every channel,
+    /// followed by a Note Off for each note when `note_offs` is set
+    pub fn panic(&self, note_offs: bool) -> Result<()> {
+        self.control(Control::Panic(note_offs))
+    }
+
+    fn control(&self, control: Control) -> Result<()> {
+        match self.request(Request::Control(control))? {
+            Response::Applied => Ok(()),
+            response => Err(ProtocolError::UnexpectedResponse(format!("{:?}", response)).into()),
+        }
+    }
+
+    /// Close the connection and end the [net_thread](Thread), which is also done when the [Receiver instance](Receiver) is dropped
+    pub fn stop(&self) -> Result<()> {
+        match self.request(Request::Stop)? {
+            Response::Stopped => Ok(()),
+            response => Err(ProtocolError::UnexpectedResponse(format!("{:?}", response)).into()),
         }
     }
 
+    /// Wait for the [net_thread](Thread) to end and return its [ThreadReturn]
     pub fn join(&mut self) -> Result<ThreadReturn> {
         Ok(self
             .net_thread
             .take()
-            .unwrap()
+            .ok_or(Error::NetThreadStopped)?
             .join()
             .unwrap_or(ThreadReturn::JoinError))
     }
 
-    pub fn is_finished(&mut self) -> bool {
-        self.net_thread.as_ref().unwrap().is_finished()
+    /// Return `true` if the [net_thread](Thread) has ended
+    pub fn is_finished(&self) -> bool {
+        match self.net_thread.as_ref() {
+            Some(net_thread) => net_thread.is_finished(),
+            None => true,
+        }
     }
 
+    /// Return a string describing the address of the [net_thread](Thread)
     pub fn info(&self) -> String {
         self.addr.clone()
     }
+
+    /// Return a snapshot of the bridge counters
+    pub fn stats(&self) -> Stats {
+        self.metrics.snapshot()
+    }
+
+    /// Return the current state of the bridge
+    pub fn state(&self) -> BridgeState {
+        self.lifecycle.state()
+    }
+
+    /// Return a channel receiving the following [Event]s of the bridge
+    pub fn subscribe(&self) -> mpsc::Receiver<Event> {
+        self.lifecycle.subscribe()
+    }
+
+    /// Call `callback` on the following [Event]s of the bridge, from the thread emitting them
+    pub fn on_event(&self, callback: impl Fn(&Event) + Send + Sync + 'static) {
+        self.lifecycle.on_event(callback)
+    }
+
+    fn request(&self, request: Request) -> Result<Response> {
+        self.post(request)?
+            .recv()
+            .map_err(|_| Error::NetThreadStopped)
+    }
+
+    /// Send a request to the [net_thread](Thread) without waiting for its response
+    fn post(&self, request: Request) -> Result<oneshot::Receiver<Response>> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.tx
+            .send((request, response_sender))
+            .map_err(|_| Error::NetThreadStopped)?;
+        self.notifier.notify();
+        Ok(response_receiver)
+    }
+}
+
+impl Drop for Receiver {
+    /// Ask the [net_thread](Thread) to stop, without waiting for it
+    fn drop(&mut self) {
+        let _ = self.post(Request::Stop);
+    }
 }
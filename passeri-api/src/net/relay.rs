@@ -0,0 +1,226 @@
+use crate::net::lobby::{read_frame, write_frame};
+use crate::net::room::Member;
+use crate::net::Result;
+use crate::ProtocolError;
+use std::fmt::{self, Display};
+use std::io::{Read, Write};
+
+/// Bytes starting every relay message
+const MAGIC: &[u8; 4] = b"PSRY";
+/// Size of the header of a relay message, before its payload
+pub const HEADER_LEN: usize = 7;
+
+//
+//	TICKET
+//
+
+/// Room of a relay a peer connects to, and the token guarding it
+///
+/// The token is part of the room: peers only meet the ones presenting the same name and token,
+/// so no peer can claim a room or lock the others out of it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ticket {
+    /// name of the room, shared by the sender and its receivers
+    pub room: String,
+    /// token guarding the room, `None` letting anyone who knows its name join it
+    pub token: Option<String>,
+}
+
+impl Ticket {
+    /// Create a new ticket for `room`, without token
+    pub fn new(room: &str) -> Self {
+        Ticket {
+            room: room.to_string(),
+            token: None,
+        }
+    }
+
+    /// Set the token guarding the room
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    fn encode(&self) -> Result<String> {
+        if self.room.is_empty() || self.room.contains('\n') {
+            return Err(ProtocolError::InvalidMessage(format!(
+                "invalid room name {:?}",
+                self.room
+            ))
+            .into());
+        }
+        Ok(match &self.token {
+            Some(token) => format!("{}\n{}", self.room, token),
+            None => self.room.clone(),
+        })
+    }
+
+    fn decode(payload: &str) -> Self {
+        match payload.split_once('\n') {
+            Some((room, token)) => Ticket::new(room).with_token(token),
+            None => Ticket::new(payload),
+        }
+    }
+}
+
+impl Display for Ticket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "room \"{}\"", self.room)
+    }
+}
+
+//
+//	RELAY MESSAGES
+//
+
+/// Messages exchanged between a peer and a relay, before the relay forwards the bytes of the paired peers
+///
+/// 1. the sender connects to the relay with [RelayMessage::Host], and waits for a receiver on this connection
+/// 2. the receiver connects with [RelayMessage::Join], and is paired with one of the waiting connections of the room
+/// 3. the relay sends [RelayMessage::Paired] to both peers, then forwards everything one peer sends to the other,
+///    starting with the [lobby](crate::net::lobby) handshake. The sender opens a new connection for the next receiver.
+///
+/// The relay answers with [RelayMessage::Refused] instead when the token is wrong, no sender is waiting in the room
+/// or the peer is over its rate limit.
+///
+/// A peer can also enter the [room](crate::net::room) as a named member with [RelayMessage::Enter], on a connection
+/// of its own. The relay answers with the [RelayMessage::Members] already in the room, then announces on this
+/// connection the members joining ([RelayMessage::Joined]) and leaving ([RelayMessage::Left]) until it is closed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayMessage {
+    /// a sender waits for a receiver of the room
+    Host(Ticket),
+    /// a receiver asks to be paired with a sender of the room
+    Join(Ticket),
+    /// the peers are paired, with the address of the other peer as seen by the relay
+    Paired(String),
+    /// the relay refused the connection, for the given reason
+    Refused(String),
+    /// a member enters the room, to be announced to the other members
+    Enter(Ticket, Member),
+    /// the other members of the room, in the order they entered it
+    Members(Vec<Member>),
+    /// a member entered the room
+    Joined(Member),
+    /// the member with the given name left the room
+    Left(String),
+}
+
+impl RelayMessage {
+    fn kind(&self) -> u8 {
+        match self {
+            RelayMessage::Host(_) => 1,
+            RelayMessage::Join(_) => 2,
+            RelayMessage::Paired(_) => 3,
+            RelayMessage::Refused(_) => 4,
+            RelayMessage::Enter(..) => 5,
+            RelayMessage::Members(_) => 6,
+            RelayMessage::Joined(_) => 7,
+            RelayMessage::Left(_) => 8,
+        }
+    }
+
+    /// Write the message to a stream (`PSRY`, type byte, big endian `u16` payload length, payload)
+    pub fn write_to(&self, stream: &mut impl Write) -> Result<()> {
+        let payload = match self {
+            RelayMessage::Host(ticket) | RelayMessage::Join(ticket) => ticket.encode()?,
+            RelayMessage::Paired(addr) => addr.clone(),
+            RelayMessage::Refused(reason) | RelayMessage::Left(reason) => reason.clone(),
+            // the member line first, the ticket spanning one or two lines
+            RelayMessage::Enter(ticket, member) => {
+                format!("{}\n{}", member.encode()?, ticket.encode()?)
+            }
+            RelayMessage::Members(members) => members
+                .iter()
+                .map(Member::encode)
+                .collect::<Result<Vec<_>>>()?
+                .join("\n"),
+            RelayMessage::Joined(member) => member.encode()?,
+        };
+        write_frame(stream, MAGIC, self.kind(), payload.as_bytes())
+    }
+
+    /// Read a message from a stream, consuming exactly its bytes
+    pub fn read_from(stream: &mut impl Read) -> Result<Self> {
+        let (kind, payload) = read_frame(stream, MAGIC)?;
+        let message = match kind {
+            1 => RelayMessage::Host(Ticket::decode(&payload)),
+            2 => RelayMessage::Join(Ticket::decode(&payload)),
+            3 => RelayMessage::Paired(payload),
+            4 => RelayMessage::Refused(payload),
+            5 => match payload.split_once('\n') {
+                Some((member, ticket)) => {
+                    RelayMessage::Enter(Ticket::decode(ticket), Member::decode(member)?)
+                }
+                None => {
+                    return Err(ProtocolError::InvalidMessage(format!(
+                        "invalid room entry {:?}",
+                        payload
+                    ))
+                    .into())
+                }
+            },
+            6 => RelayMessage::Members(payload.lines().map(Member::decode).collect::<Result<_>>()?),
+            7 => RelayMessage::Joined(Member::decode(&payload)?),
+            8 => RelayMessage::Left(payload),
+            kind => {
+                return Err(ProtocolError::InvalidMessage(format!(
+                    "unknown relay message type {}",
+                    kind
+                ))
+                .into())
+            }
+        };
+        Ok(message)
+    }
+
+    /// Size of the message starting with `header`, for the readers not able to block until it is complete
+    pub fn frame_len(header: &[u8; HEADER_LEN]) -> usize {
+        HEADER_LEN + u16::from_be_bytes([header[5], header[6]]) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_roundtrip() {
+        let messages = [
+            RelayMessage::Host(Ticket::new("jam").with_token("s3cr3t")),
+            RelayMessage::Join(Ticket::new("jam")),
+            RelayMessage::Paired("203.0.113.7:51234".into()),
+            RelayMessage::Refused("invalid token".into()),
+            RelayMessage::Enter(
+                Ticket::new("jam").with_token("s3cr3t"),
+                Member::sender("alice", None),
+            ),
+            RelayMessage::Enter(Ticket::new("jam"), Member::receiver("bob")),
+            RelayMessage::Members(vec![]),
+            RelayMessage::Members(vec![
+                Member::sender("alice", Some("192.0.2.1:8080")),
+                Member::receiver("bob"),
+            ]),
+            RelayMessage::Joined(Member::receiver("carol")),
+            RelayMessage::Left("carol".into()),
+        ];
+        let mut buf = vec![];
+        for message in &messages {
+            message.write_to(&mut buf).unwrap();
+        }
+        let header: [u8; HEADER_LEN] = buf[..HEADER_LEN].try_into().unwrap();
+        assert_eq!(
+            RelayMessage::frame_len(&header),
+            HEADER_LEN + "jam\ns3cr3t".len()
+        );
+
+        let mut stream = buf.as_slice();
+        for message in messages {
+            assert_eq!(RelayMessage::read_from(&mut stream).unwrap(), message);
+        }
+        assert!(stream.is_empty());
+        assert!(RelayMessage::Host(Ticket::new(""))
+            .write_to(&mut vec![])
+            .is_err());
+    }
+}
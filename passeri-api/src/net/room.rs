@@ -0,0 +1,291 @@
+use crate::net::relay::{RelayMessage, Ticket};
+use crate::net::Result;
+use crate::ProtocolError;
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+//
+//	MEMBERS
+//
+
+/// Part a member plays in a room
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// streams its MIDI ports to the receivers subscribed to it
+    Sender,
+    /// plays the streams of the senders it subscribed to
+    Receiver,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Sender => write!(f, "sender"),
+            Role::Receiver => write!(f, "receiver"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sender" => Ok(Role::Sender),
+            "receiver" => Ok(Role::Receiver),
+            _ => Err(ProtocolError::InvalidMessage(format!(
+                "unknown role {:?}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Participant of a room, announced to the other members while it stays in it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Member {
+    /// name of the member, unique in the room
+    pub name: String,
+    /// part played by the member
+    pub role: Role,
+    /// address a sender listens on for its receivers, `None` if it waits for them at the relay
+    /// (see [Member::ticket]). The relay refuses the addresses outside the host of the member connection.
+    pub addr: Option<String>,
+}
+
+impl Member {
+    /// Create a new sender member, listening on `addr` or waiting for its receivers at the relay
+    pub fn sender(name: &str, addr: Option<&str>) -> Self {
+        Member {
+            name: name.to_string(),
+            role: Role::Sender,
+            addr: addr.map(str::to_string),
+        }
+    }
+
+    /// Create a new receiver member
+    pub fn receiver(name: &str) -> Self {
+        Member {
+            name: name.to_string(),
+            role: Role::Receiver,
+            addr: None,
+        }
+    }
+
+    /// Return `true` if the member streams its MIDI ports
+    pub fn is_sender(&self) -> bool {
+        self.role == Role::Sender
+    }
+
+    /// Relay room in which a sender of `room` waits for its receivers, guarded by the token of `room`
+    pub fn ticket(&self, room: &Ticket) -> Ticket {
+        Ticket {
+            room: format!("{}/{}", room.room, self.name),
+            token: room.token.clone(),
+        }
+    }
+
+    /// Encode the member on a line (`role`, `name` and `addr` separated by tabs)
+    pub(crate) fn encode(&self) -> Result<String> {
+        if self.name.is_empty() || self.name.contains(['\t', '\n', '/']) {
+            return Err(ProtocolError::InvalidMessage(format!(
+                "invalid member name {:?}",
+                self.name
+            ))
+            .into());
+        }
+        Ok(format!(
+            "{}\t{}\t{}",
+            self.role,
+            self.name,
+            self.addr.as_deref().unwrap_or_default()
+        ))
+    }
+
+    pub(crate) fn decode(line: &str) -> Result<Self> {
+        let mut fields = line.splitn(3, '\t');
+        let (Some(role), Some(name), Some(addr)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(ProtocolError::InvalidMessage(format!("invalid member {:?}", line)).into());
+        };
+        Ok(Member {
+            name: name.to_string(),
+            role: role.parse()?,
+            addr: (!addr.is_empty()).then(|| addr.to_string()),
+        })
+    }
+}
+
+impl Display for Member {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} \"{}\"", self.role, self.name)
+    }
+}
+
+//
+//	SUBSCRIPTION
+//
+
+/// Senders of a room a receiver plays the streams of
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Subscription {
+    /// every sender, including the ones joining later
+    #[default]
+    All,
+    /// only the senders with these names
+    Only(BTreeSet<String>),
+}
+
+impl Subscription {
+    /// Subscribe to the senders with the given names only
+    pub fn only<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        Subscription::Only(names.into_iter().map(str::to_string).collect())
+    }
+
+    /// Return `true` if `member` is a sender covered by the subscription
+    pub fn includes(&self, member: &Member) -> bool {
+        member.is_sender()
+            && match self {
+                Subscription::All => true,
+                Subscription::Only(names) => names.contains(&member.name),
+            }
+    }
+
+    /// Add the sender `name` to the subscription
+    pub fn add(&mut self, name: &str) {
+        if let Subscription::Only(names) = self {
+            names.insert(name.to_string());
+        }
+    }
+
+    /// Remove the sender `name` from the subscription, turning [Subscription::All] into every other sender of `members`
+    pub fn remove(&mut self, name: &str, members: &[Member]) {
+        if let Subscription::All = self {
+            *self = Subscription::Only(
+                members
+                    .iter()
+                    .filter(|member| member.is_sender())
+                    .map(|member| member.name.clone())
+                    .collect(),
+            );
+        }
+        if let Subscription::Only(names) = self {
+            names.remove(name);
+        }
+    }
+}
+
+//
+//	ROSTER
+//
+
+/// Change in the membership of a room, announced to every other member
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomEvent {
+    /// a member entered the room
+    Joined(Member),
+    /// a member left the room, or lost its connection to the relay
+    Left(Member),
+}
+
+/// Members of a room as seen by one of them, kept up to date with the announcements of the relay
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Roster {
+    members: Vec<Member>,
+}
+
+impl Roster {
+    /// Create a new roster with the members present when entering the room
+    pub fn new(members: Vec<Member>) -> Self {
+        Roster { members }
+    }
+
+    /// Return the other members of the room, in the order they entered it
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    /// Return the member named `name`, if in the room
+    pub fn get(&self, name: &str) -> Option<&Member> {
+        self.members.iter().find(|member| member.name == name)
+    }
+
+    /// Apply an announcement of the relay, returning the [RoomEvent] it stands for if it changed the roster
+    pub fn apply(&mut self, message: RelayMessage) -> Option<RoomEvent> {
+        match message {
+            RelayMessage::Joined(member) => {
+                self.members.retain(|known| known.name != member.name);
+                self.members.push(member.clone());
+                Some(RoomEvent::Joined(member))
+            }
+            RelayMessage::Left(name) => {
+                let index = self.members.iter().position(|member| member.name == name)?;
+                Some(RoomEvent::Left(self.members.remove(index)))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_member() {
+        let members = [
+            Member::sender("alice", None),
+            Member::sender("bob", Some("192.0.2.1:8080")),
+            Member::receiver("carol"),
+        ];
+        for member in members {
+            assert_eq!(Member::decode(&member.encode().unwrap()).unwrap(), member);
+        }
+        assert!(Member::receiver("").encode().is_err());
+        assert!(Member::receiver("a/b").encode().is_err());
+        assert!(Member::decode("host\tdave\t").is_err());
+
+        let room = Ticket::new("jam").with_token("s3cr3t");
+        assert_eq!(
+            Member::sender("alice", None).ticket(&room),
+            Ticket::new("jam/alice").with_token("s3cr3t")
+        );
+    }
+
+    #[test]
+    fn test_subscription() {
+        let [alice, bob, carol] = [
+            Member::sender("alice", None),
+            Member::sender("bob", None),
+            Member::receiver("carol"),
+        ];
+        let mut subscription = Subscription::default();
+        assert!(subscription.includes(&alice) && subscription.includes(&bob));
+        assert!(!subscription.includes(&carol));
+
+        subscription.remove("alice", &[alice.clone(), bob.clone(), carol.clone()]);
+        assert_eq!(subscription, Subscription::only(["bob"]));
+        subscription.add("alice");
+        assert!(subscription.includes(&alice));
+        assert!(!Subscription::only(["carol"]).includes(&carol));
+    }
+
+    #[test]
+    fn test_roster() {
+        let alice = Member::sender("alice", None);
+        let mut roster = Roster::new(vec![alice.clone()]);
+        let bob = Member::receiver("bob");
+        assert_eq!(
+            roster.apply(RelayMessage::Joined(bob.clone())),
+            Some(RoomEvent::Joined(bob.clone()))
+        );
+        assert_eq!(roster.get("bob"), Some(&bob));
+        assert_eq!(
+            roster.apply(RelayMessage::Left("alice".into())),
+            Some(RoomEvent::Left(alice))
+        );
+        assert_eq!(roster.apply(RelayMessage::Left("alice".into())), None);
+        assert_eq!(roster.members(), [bob]);
+    }
+}
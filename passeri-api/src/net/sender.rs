@@ -1,30 +1,61 @@
-use crate::midi::MidiPayload;
+use crate::discovery::{Advertisement, Discovery};
+use crate::lifecycle::{BridgeState, Event, Lifecycle};
+use crate::metrics::{Metrics, Stats};
+use crate::midi::{check_channel, Control, InputConnection};
+use crate::net::cable::CablePayload;
+use crate::net::lobby::{ClientInfo, Features, Lobby};
+use crate::net::notifier::{Notifier, Wake};
 pub use crate::net::Result;
-use log::{debug, error, info, trace};
-use midir::MidiInputConnection;
+use crate::{Error, MidiError, ProtocolError, TransportError};
+use log::{debug, info};
 use std::{
     fmt::Debug,
-    sync::mpsc::{self},
+    net::SocketAddr,
+    sync::{
+        mpsc::{self},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
     thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
 /// Set of requests send by the [Sender instance](Sender) to the [net_thread](Thread).
-/// It have to be able to process all these requests to be compliant with this [Sender instance](Sender).
+/// It have to be able to process all these requests to be compliant with this [Sender instance](Sender),
+/// including while streaming.
 pub enum Request<Addr> {
-    /// start listening on the provided address for potential receiver client
+    /// start listening on the provided address for potential receiver client, answering once one passed the [Lobby] checks.
+    /// The room stays open until then, other requests being processed in the meantime.
     OpenRoom,
-    /// start to stream [crate::midi::MidiFrame] to the given address (obtained by the `OpenRoom` request)
+    /// stop listening for receiver clients, the pending [Request::OpenRoom] being answered with [Response::RoomClosed]
+    CloseRoom,
+    /// start to stream [crate::midi::MidiFrame] to the given address (obtained by the `OpenRoom` request),
+    /// along with the receivers already streamed to
     AcceptClient(Addr),
+    /// notify the given client (obtained by the `OpenRoom` request) that it is rejected for the given reason, then close its connection
+    RejectClient(Addr, String),
+    /// change the [ChannelFilter](crate::midi::ChannelFilter) of the stream, sending the messages it returns to the receivers
+    /// (the releases of the notes held on the channels not forwarded anymore, or the panic messages), answering with [Response::Applied]
+    Control(Control),
+    /// close every connection and end the [net_thread](Thread), answering with [Response::Stopped]
+    Stop,
 }
 
 /// Set of responses that can return the [net_thread](Thread) to the [Sender instance](Sender) after receiving [Request].
 #[derive(Debug)]
 pub enum Response<Addr> {
-    /// received a new potential receiver client
-    NewClient(Addr),
+    /// received a new potential receiver client, which passed the [Lobby] checks
+    NewClient(ClientInfo<Addr>),
     /// notify that sender thread start to stream incomming MIDI to receiver client
     StartStream,
-    /// response that have to be return in case of a [Request::AcceptClient] request before a [Request::OpenRoom] one
+    /// the client has been notified of its rejection and disconnected
+    ClientRejected,
+    /// the room has been closed before any receiver client passed the [Lobby] checks
+    RoomClosed,
+    /// the [net_thread](Thread) is about to end
+    Stopped,
+    /// the [Request::Control] request has been applied
+    Applied,
+    /// response that have to be return in case of a [Request::AcceptClient] or [Request::RejectClient] request before a [Request::OpenRoom] one
     ClientNotFound,
 }
 
@@ -47,39 +78,49 @@ pub enum ThreadReturn<Addr> {
     #[error("unable to send response to tunnel")]
     Send(#[from] oneshot::SendError<Response<Addr>>),
 
-    /// unable to write to TcpStream
-    #[error("unable to write to TcpStream")]
-    Write(#[from] std::io::Error),
+    /// error in the network layer
+    #[error(transparent)]
+    Transport(#[from] TransportError),
 
-    /// unable to read from TcpStream
-    #[error("unable to read from TcpStream")]
-    Read(std::io::Error),
+    /// error with the local MIDI port
+    #[error(transparent)]
+    Midi(#[from] MidiError),
 
-    /// unable to read from MIDI
-    #[error("unable to read from MIDI")]
-    MidiRecvError,
+    /// error in the dialog with the distant peer
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
 
-    /// unable to send to MIDI
-    #[error("unable to send to MIDI")]
-    MidiSendError(midir::SendError),
-
-    /// unable to join the TCP Thread
-    #[error("Join Error")]
+    /// the net_thread panicked
+    #[error("unable to join the net_thread")]
     JoinError,
 
     /// Distant Sender disconnect
-    #[error("Receive End")]
+    #[error("the distant Sender closed the connection")]
     ReceiveEnd,
 
-    /// Distant Receiver disconnect
-    #[error("Send End")]
+    /// the MIDI input port tunnel is closed
+    #[error("the MIDI input tunnel is closed")]
     SendEnd,
 
+    /// the net_thread stopped processing requests
+    #[error("the net_thread stopped")]
+    Stopped,
+
     /// The Receiver leave the passeri connection
     #[error("The Receiver leave the passeri connection")]
     RecvLeave,
 }
 
+impl<Addr> ThreadReturn<Addr> {
+    /// Return `true` if the [net_thread](Thread) ended because of an error
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            ThreadReturn::Transport(_) | ThreadReturn::Midi(_) | ThreadReturn::Protocol(_)
+        )
+    }
+}
+
 //
 //	SenderThread definition
 //
@@ -89,39 +130,63 @@ pub type PasseriReq<Addr> = (Request<Addr>, Responder<Addr>);
 
 /// Minimum set of function that have to implement a [net_thread](Thread)
 ///
-/// It is recommended to implement it as a background thread waiting for any incomming MIDI message from the `midi_rx` [mpsc::Receiver],
-/// then sending it over network to the connected receiver client.
+/// It is run in a background thread as an event loop multiplexing the [Request]s from `messenger_rx`, the MIDI messages
+/// from `midi_rx` and its connections, so requests keep being processed while streaming.
+/// The channels are not pollable: the function returned by [Thread::waker] is called after each message sent to them.
 pub trait Thread {
     /// Type used by the chosen Network Layer to describe addresses (e.g.: `SocketAddr` for TCP)
     type Addr: 'static + Send + Debug + std::fmt::Display + Clone;
 
+    /// Name of the Network Layer (e.g.: `tcp`), advertised with the sender
+    const TRANSPORT: &'static str;
+
+    /// [Features] supported by the Network Layer, announced to the receivers in the [Hello](crate::net::lobby::Hello)
+    const FEATURES: Features;
+
+    /// Options of the Network Layer (e.g.: TLS settings), the default value being used by the [SenderBuilder](crate::SenderBuilder)
+    type Config: 'static + Send + Default;
+
     /// create a new Sender instance
     ///
     /// # Arguments
-    /// * `addr` -		the address on which the Network Layer have to bind to
-    /// * `midi_rx` -	[Receiver](mpsc::Receiver) from which the **SenderThread** will get timestamp and
-    /// 				[MidiFrame](crate::midi::MidiFrame) received by the midi thread
+    /// * `addr` - the address on which the Network Layer have to bind to
+    /// * `config` - options of the Network Layer
+    /// * `midi_rx` - [Receiver](mpsc::Receiver) from which the **SenderThread** will get the cable of the MIDI port,
+    ///   timestamp and [MidiFrame](crate::midi::MidiFrame) received by the midi thread
     /// * `messenger_rx` - [Receiver](mpsc::Receiver) from which the **SenderThread** will get [Request] from the main thread
+    /// * `metrics` - [Metrics] of the bridge, in which the **SenderThread** have to count the messages it sends over network
+    /// * `lobby` - [Lobby] rules every connecting receiver have to pass (see [lobby::Message](crate::net::lobby::Message)) before being returned by [Request::OpenRoom]
+    /// * `lifecycle` - [Lifecycle] of the bridge, in which the **SenderThread** have to emit [Event::ClientLeft] when the receiver leaves
     fn new(
         addr: Self::Addr,
-        midi_rx: mpsc::Receiver<MidiPayload>,
+        config: Self::Config,
+        midi_rx: mpsc::Receiver<CablePayload>,
         messenger_rx: mpsc::Receiver<PasseriReq<Self::Addr>>,
-    ) -> std::result::Result<Self, String>
+        metrics: Metrics,
+        lobby: Lobby,
+        lifecycle: Lifecycle,
+    ) -> std::result::Result<Self, Error>
     where
         Self: Sized;
 
-    /// implementation have to block reading on the `messenger_rx` [Receiver](mpsc::Receiver), processing each incomming [Request]
+    /// implementation have to run the event loop until `messenger_rx` is disconnected or a [Request::Stop] is received,
+    /// sleeping until its connections are ready or it is woken up by [Thread::waker]. On each wake-up, it have to:
+    /// - process every incomming [Request] from `messenger_rx`, accepting receiver clients while a room is open
+    /// - forward every MIDI message from `midi_rx` to the accepted receiver clients, dropping them when there is none,
+    ///   and fail with [ThreadReturn::SendEnd] once `midi_rx` is disconnected. Messages of a cable other than 0 are
+    ///   only forwarded to the receivers which agreed on the `cables` [Features], preceded by a [cable](crate::net::cable) frame.
+    ///   Messages starting with a [RESERVED_STATUS](crate::net::RESERVED_STATUS) byte are never forwarded and
+    ///   counted with [Metrics::record_drop]
+    /// - filter the forwarded messages with a [ChannelFilter](crate::midi::ChannelFilter) driven by [Request::Control],
+    ///   sending the messages it returns to the receiver clients
+    ///
+    /// Handshakes must not stall the loop. Once the last receiver left, it have to emit [Event::ClientLeft] and go on,
+    /// so the next receiver can be accepted.
     fn run(&mut self) -> std::result::Result<(), ThreadReturn<Self::Addr>>;
 
-    /// implementation have to start forwarding local MIDI message to connected receiver client.
-    /// It have to notify the main thread that the stream is starting by a [Response::StartStream] [Response] and then looping over this way:
-    /// 	- blocking on reading `midi_rx` [Receiver](mpsc::Receiver)
-    /// 	- forwarding received message to the receiver client
-    fn send(
-        &mut self,
-        distant: Self::Addr,
-        responder: Responder<Self::Addr>,
-    ) -> std::result::Result<(), ThreadReturn<Self::Addr>>;
+    /// return the function waking up the event loop of [Thread::run], called by the [Sender instance](Sender)
+    /// after each [Request] and by the MIDI input after each message
+    fn waker(&self) -> Wake;
 
     /// return a informationnal string on the address on which is bound the sender thread
     fn info(&self) -> Self::Addr;
@@ -133,84 +198,460 @@ pub trait Thread {
 
 /// [Sender instance](Sender) used to bridge local MIDI messages to distant receiver over network (implemented by [net_thread](Thread))
 pub struct Sender<T: Thread> {
-    _midi_thread: MidiInputConnection<()>,
+    _midi_threads: Vec<InputConnection>,
+    /// names of the MIDI ports, joined
+    midi_port: String,
     net_thread: Option<JoinHandle<ThreadReturn<T::Addr>>>,
     tx: mpsc::Sender<PasseriReq<T::Addr>>,
+    notifier: Notifier,
     addr: T::Addr,
+    metrics: Metrics,
+    lifecycle: Lifecycle,
+    lobby: Lobby,
+    pending: Mutex<Vec<ClientInfo<T::Addr>>>,
+    room: Arc<Lounge<T::Addr>>,
+}
+
+/// State of the room of a [Sender instance](Sender)
+enum Room<Addr> {
+    /// no [Request::OpenRoom] is pending
+    Closed,
+    /// a [Request::OpenRoom] is pending, no one waiting for its response
+    Open(oneshot::Receiver<Response<Addr>>),
+    /// a [Sender::wait_for_client] call is waiting for the response, outside of the lock,
+    /// `cancelled` once a [CancelHandle] closed the room for it
+    Waiting { cancelled: bool },
+}
+
+/// Room of a [Sender instance](Sender), shared with its [CancelHandle]s
+struct Lounge<Addr> {
+    state: Mutex<Room<Addr>>,
+    /// notified when a wait of a client gives the room back
+    freed: Condvar,
+}
+
+impl<Addr> Lounge<Addr> {
+    fn lock(&self) -> MutexGuard<'_, Room<Addr>> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Handle cancelling the [Sender::wait_for_client] calls of a [Sender instance](Sender) from another thread
+pub struct CancelHandle<Addr> {
+    tx: mpsc::Sender<PasseriReq<Addr>>,
+    notifier: Notifier,
+    room: Arc<Lounge<Addr>>,
+}
+
+impl<Addr> Clone for CancelHandle<Addr> {
+    fn clone(&self) -> Self {
+        CancelHandle {
+            tx: self.tx.clone(),
+            notifier: self.notifier.clone(),
+            room: Arc::clone(&self.room),
+        }
+    }
+}
+
+impl<Addr> CancelHandle<Addr> {
+    /// Close the room, the pending wait of a client failing with [Error::Cancelled]
+    /// (no-op if no wait is in progress, a room left open by a timed out wait staying open)
+    pub fn cancel(&self) -> Result<()> {
+        let (responder, response) = oneshot::channel();
+        {
+            let mut room = self.room.lock();
+            let Room::Waiting { cancelled } = &mut *room else {
+                return Ok(());
+            };
+            *cancelled = true;
+            // sent under the lock, so the wait cannot give the room back in the meantime
+            self.tx
+                .send((Request::CloseRoom, responder))
+                .map_err(|_| Error::NetThreadStopped)?;
+        }
+        self.notifier.notify();
+        response.recv().map_err(|_| Error::NetThreadStopped)?;
+        Ok(())
+    }
 }
+
 impl<T: Thread> Sender<T> {
-    /// Create a new [Sender instance](Sender) (it is recommended to use the [new_sender()][crate::new_sender] function)
+    /// Create a new [Sender instance](Sender) (it is recommended to use the [SenderBuilder][crate::SenderBuilder]),
+    /// the cable of each message received from `midi_rx` being the index of its port in `_midi_threads`
     pub fn new(
-        _midi_thread: MidiInputConnection<()>,
-        midi_rx: mpsc::Receiver<MidiPayload>,
+        _midi_threads: Vec<InputConnection>,
+        midi_rx: mpsc::Receiver<CablePayload>,
         addr: T::Addr,
+        config: T::Config,
     ) -> Result<Self> {
+        let first = _midi_threads.first().ok_or(MidiError::CableCount(0))?;
         let (tx, rx) = mpsc::channel::<PasseriReq<T::Addr>>();
-        let (init_tx, init_rx) = oneshot::channel::<std::result::Result<T::Addr, String>>();
+        let (init_tx, init_rx) = oneshot::channel::<Result<T::Addr>>();
+        let metrics = first.metrics().clone();
+        let thread_metrics = metrics.clone();
+        let lifecycle = first.lifecycle().clone();
+        let thread_lifecycle = lifecycle.clone();
+        let notifier = first.notifier().clone();
+        let thread_notifier = notifier.clone();
+        let lobby = Lobby::new();
+        let midi_port = _midi_threads
+            .iter()
+            .map(|conn| conn.port().name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        lobby.set_identity(&midi_port, &midi_port);
+        let thread_lobby = lobby.clone();
 
         let net_thread = Some(std::thread::spawn(move || {
-            let mut socket = match T::new(addr, midi_rx, rx) {
+            let mut socket = match T::new(
+                addr,
+                config,
+                midi_rx,
+                rx,
+                thread_metrics.clone(),
+                thread_lobby,
+                thread_lifecycle.clone(),
+            ) {
                 Ok(res) => {
-                    init_tx.send(Ok(res.info())).unwrap();
+                    thread_notifier.set(res.waker());
+                    let _ = init_tx.send(Ok(res.info()));
                     res
                 }
                 Err(err) => {
-                    init_tx.send(Err(err)).unwrap();
+                    let _ = init_tx.send(Err(err));
                     return ThreadReturn::InitError;
                 }
             };
 
             info!("sender created on {}", socket.info());
 
-            socket.run().unwrap_err()
+            let ret = socket.run().err().unwrap_or(ThreadReturn::Stopped);
+            if matches!(ret, ThreadReturn::RecvLeave) {
+                thread_lifecycle.emit(Event::ClientLeft);
+            }
+            if ret.is_error() {
+                thread_metrics.record_error();
+                thread_lifecycle.emit(Event::Error(ret.to_string()));
+            }
+            thread_lifecycle.emit(Event::Stopped(ret.to_string()));
+            ret
         }));
 
-        let addr = init_rx.recv()??;
+        let addr = init_rx.recv().map_err(|_| Error::NetThreadStopped)??;
 
         Ok(Sender {
-            _midi_thread,
+            _midi_threads,
+            midi_port,
             net_thread,
             tx,
+            notifier,
             addr,
+            metrics,
+            lifecycle,
+            lobby,
+            pending: Mutex::new(Vec::new()),
+            room: Arc::new(Lounge {
+                state: Mutex::new(Room::Closed),
+                freed: Condvar::new(),
+            }),
         })
     }
 
-    /// listen for possible distant receiver client
-    pub fn wait_for_client(&self) -> Result<T::Addr> {
-        let (response_sender, response_receiver) = oneshot::channel();
-        self.tx.send((Request::OpenRoom, response_sender))?;
+    /// listen for possible distant receiver client, returning the first one passing the [Lobby] checks.
+    /// It stays pending until accepted by [Sender::send] or rejected by [Sender::reject].
+    /// Fails with [Error::Cancelled] if interrupted by a [CancelHandle].
+    pub fn wait_for_client(&self) -> Result<ClientInfo<T::Addr>> {
+        self.wait(None)?.ok_or(Error::Cancelled)
+    }
+
+    /// Same as [Sender::wait_for_client], returning `None` if no client showed up before `timeout`.
+    /// The room stays open, a client arriving later being returned by the next call.
+    pub fn wait_for_client_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<ClientInfo<T::Addr>>> {
+        self.wait(Some(timeout))
+    }
 
-        match response_receiver.recv()? {
-            Response::NewClient(addr) => Ok(addr),
-            _ => Err("invalid response from net_thread".into()),
+    /// Same as [Sender::wait_for_client_timeout], without blocking
+    pub fn try_wait_for_client(&self) -> Result<Option<ClientInfo<T::Addr>>> {
+        self.wait(Some(Duration::ZERO))
+    }
+
+    /// Return a [CancelHandle] interrupting the waits of a client from another thread
+    pub fn cancel_handle(&self) -> CancelHandle<T::Addr> {
+        CancelHandle {
+            tx: self.tx.clone(),
+            notifier: self.notifier.clone(),
+            room: Arc::clone(&self.room),
+        }
+    }
+
+    fn wait(&self, timeout: Option<Duration>) -> Result<Option<ClientInfo<T::Addr>>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let response_receiver = match self.take_room(deadline)? {
+            Some(response_receiver) => response_receiver,
+            None => return Ok(None),
+        };
+        // the lock is released while waiting, so the other calls are not blocked behind it
+        let response = match deadline {
+            None => response_receiver
+                .recv()
+                .map_err(|_| Error::NetThreadStopped),
+            Some(deadline) => {
+                match response_receiver
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                {
+                    Ok(response) => Ok(response),
+                    Err(oneshot::RecvTimeoutError::Timeout) => {
+                        let cancelled =
+                            matches!(*self.room.lock(), Room::Waiting { cancelled: true });
+                        if !cancelled {
+                            self.give_room(Room::Open(response_receiver));
+                            return Ok(None);
+                        }
+                        // the room is being closed, its answer is on its way
+                        response_receiver
+                            .recv()
+                            .map_err(|_| Error::NetThreadStopped)
+                    }
+                    Err(oneshot::RecvTimeoutError::Disconnected) => Err(Error::NetThreadStopped),
+                }
+            }
+        };
+        self.give_room(Room::Closed);
+        let response = response?;
+
+        match response {
+            Response::NewClient(client) => {
+                self.pending_mut().push(client.clone());
+                self.lifecycle
+                    .emit(Event::ClientConnected(client.to_string()));
+                Ok(Some(client))
+            }
+            Response::RoomClosed => Err(Error::Cancelled),
+            response => Err(ProtocolError::UnexpectedResponse(format!("{:?}", response)).into()),
         }
     }
 
     /// Start forwarding local MIDI messages to distant receiver over network
     pub fn send(&self, client: T::Addr) -> Result<()> {
-        let (response_sender, response_receiver) = oneshot::channel();
-        self.tx
-            .send((Request::AcceptClient(client), response_sender))?;
-
-        match response_receiver.recv()? {
+        self.forget_pending(&client);
+        match self.request(Request::AcceptClient(client.clone()))? {
             Response::StartStream => {
                 debug!("received StartStream");
+                self.lifecycle.emit(Event::Streaming);
                 Ok(())
             }
-            _ => Err("invalid response from net_thread".into()),
+            Response::ClientNotFound => {
+                Err(ProtocolError::ClientNotFound(client.to_string()).into())
+            }
+            response => Err(ProtocolError::UnexpectedResponse(format!("{:?}", response)).into()),
+        }
+    }
+
+    /// Notify a pending client that it is rejected for `reason`, then disconnect it
+    pub fn reject(&self, client: T::Addr, reason: &str) -> Result<()> {
+        self.forget_pending(&client);
+        match self.request(Request::RejectClient(client.clone(), reason.to_string()))? {
+            Response::ClientRejected => Ok(()),
+            Response::ClientNotFound => {
+                Err(ProtocolError::ClientNotFound(client.to_string()).into())
+            }
+            response => Err(ProtocolError::UnexpectedResponse(format!("{:?}", response)).into()),
+        }
+    }
+
+    /// Close every connection and end the [net_thread](Thread), which is also done when the [Sender instance](Sender) is dropped
+    pub fn stop(&self) -> Result<()> {
+        match self.request(Request::Stop)? {
+            Response::Stopped => Ok(()),
+            response => Err(ProtocolError::UnexpectedResponse(format!("{:?}", response)).into()),
+        }
+    }
+
+    /// Stop forwarding MIDI messages without closing the connections, the notes held being released on the receivers
+    pub fn pause(&self) -> Result<()> {
+        self.control(Control::Pause(true))
+    }
+
+    /// Restart forwarding MIDI messages after a [Sender::pause]
+    pub fn resume(&self) -> Result<()> {
+        self.control(Control::Pause(false))
+    }
+
+    /// Stop (`true`) or restart (`false`) forwarding the messages of MIDI `channel` (0 to 15), its held notes being released on the receivers
+    pub fn mute(&self, channel: u8, muted: bool) -> Result<()> {
+        self.control(Control::Mute(check_channel(channel)?, muted))
+    }
+
+    /// Add (`true`) or remove (`false`) MIDI `channel` (0 to 15) from the soloed ones, the other channels not being forwarded while one is soloed
+    pub fn solo(&self, channel: u8, soloed: bool) -> Result<()> {
+        self.control(Control::Solo(check_channel(channel)?, soloed))
+    }
+
+    /// Silence a stuck synth on the receivers: All Sound Off, All Notes Off and Reset All Controllers are sent on every channel,
+    /// followed by a Note Off for each note when `note_offs` is set
+    pub fn panic(&self, note_offs: bool) -> Result<()> {
+        self.control(Control::Panic(note_offs))
+    }
+
+    fn control(&self, control: Control) -> Result<()> {
+        match self.request(Request::Control(control))? {
+            Response::Applied => Ok(()),
+            response => Err(ProtocolError::UnexpectedResponse(format!("{:?}", response)).into()),
+        }
+    }
+
+    /// Return the clients returned by [Sender::wait_for_client] that are neither accepted nor rejected yet
+    pub fn pending_clients(&self) -> Vec<ClientInfo<T::Addr>> {
+        self.pending_mut().clone()
+    }
+
+    /// Return the admission rules of the sender, which can be changed at any time
+    pub fn lobby(&self) -> &Lobby {
+        &self.lobby
+    }
+
+    /// Take the pending [Request::OpenRoom], opening the room if needed. Returns `None` if another call
+    /// is still waiting for a client at `deadline`
+    fn take_room(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<Option<oneshot::Receiver<Response<T::Addr>>>> {
+        let mut room = self.room.lock();
+        loop {
+            match std::mem::replace(&mut *room, Room::Waiting { cancelled: false }) {
+                Room::Open(response_receiver) => return Ok(Some(response_receiver)),
+                Room::Closed => {
+                    let response_receiver = match self.post(Request::OpenRoom) {
+                        Ok(response_receiver) => response_receiver,
+                        Err(err) => {
+                            *room = Room::Closed;
+                            return Err(err);
+                        }
+                    };
+                    self.lifecycle.emit(Event::Listening(self.addr.to_string()));
+                    return Ok(Some(response_receiver));
+                }
+                Room::Waiting { cancelled } => *room = Room::Waiting { cancelled },
+            }
+            room = match deadline {
+                None => self
+                    .room
+                    .freed
+                    .wait(room)
+                    .unwrap_or_else(|err| err.into_inner()),
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        return Ok(None);
+                    }
+                    self.room
+                        .freed
+                        .wait_timeout(room, timeout)
+                        .unwrap_or_else(|err| err.into_inner())
+                        .0
+                }
+            };
         }
     }
 
+    /// Give the room back once a wait of a client is over, waking up the other calls waiting for it
+    fn give_room(&self, state: Room<T::Addr>) {
+        *self.room.lock() = state;
+        self.room.freed.notify_all();
+    }
+
+    fn pending_mut(&self) -> std::sync::MutexGuard<'_, Vec<ClientInfo<T::Addr>>> {
+        self.pending.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn forget_pending(&self, client: &T::Addr) {
+        let client = client.to_string();
+        self.pending_mut()
+            .retain(|pending| pending.addr.to_string() != client);
+    }
+
+    /// Wait for the [net_thread](Thread) to end and return its [ThreadReturn]
     pub fn join(&mut self) -> Result<ThreadReturn<T::Addr>> {
         Ok(self
             .net_thread
             .take()
-            .unwrap()
+            .ok_or(Error::NetThreadStopped)?
             .join()
             .unwrap_or(ThreadReturn::JoinError))
     }
 
+    /// Return `true` if the [net_thread](Thread) has ended
+    pub fn is_finished(&self) -> bool {
+        match self.net_thread.as_ref() {
+            Some(net_thread) => net_thread.is_finished(),
+            None => true,
+        }
+    }
+
+    /// Return the address on which the [net_thread](Thread) is bound
     pub fn info(&self) -> T::Addr {
         self.addr.clone()
     }
+
+    /// Return a snapshot of the bridge counters
+    pub fn stats(&self) -> Stats {
+        self.metrics.snapshot()
+    }
+
+    /// Return the current state of the bridge
+    pub fn state(&self) -> BridgeState {
+        self.lifecycle.state()
+    }
+
+    /// Return a channel receiving the following [Event]s of the bridge
+    pub fn subscribe(&self) -> mpsc::Receiver<Event> {
+        self.lifecycle.subscribe()
+    }
+
+    /// Call `callback` on the following [Event]s of the bridge, from the thread emitting them
+    pub fn on_event(&self, callback: impl Fn(&Event) + Send + Sync + 'static) {
+        self.lifecycle.on_event(callback)
+    }
+
+    fn request(&self, request: Request<T::Addr>) -> Result<Response<T::Addr>> {
+        self.post(request)?
+            .recv()
+            .map_err(|_| Error::NetThreadStopped)
+    }
+
+    /// Send a request to the [net_thread](Thread) without waiting for its response
+    fn post(&self, request: Request<T::Addr>) -> Result<oneshot::Receiver<Response<T::Addr>>> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.tx
+            .send((request, response_sender))
+            .map_err(|_| Error::NetThreadStopped)?;
+        self.notifier.notify();
+        Ok(response_receiver)
+    }
+}
+
+impl<T: Thread> Drop for Sender<T> {
+    /// Ask the [net_thread](Thread) to stop, without waiting for it
+    fn drop(&mut self) {
+        let _ = self.post(Request::Stop);
+    }
+}
+
+impl<T: Thread> Sender<T>
+where
+    T::Addr: Into<SocketAddr>,
+{
+    /// Advertise the sender over DNS-SD under `name` so receivers can find it with [Discovery::find],
+    /// until the returned [Advertisement] is dropped
+    pub fn advertise(&self, discovery: &Discovery, name: &str) -> Result<Advertisement> {
+        Ok(discovery.advertise(
+            name,
+            T::TRANSPORT,
+            self.addr.clone().into(),
+            &self.midi_port,
+        )?)
+    }
 }
@@ -0,0 +1,606 @@
+use crate::output::{port_json, Output};
+use crate::{BridgeArgs, LobbyArgs};
+use passeri_api::discovery::{Discovery, DEFAULT_BROWSE_TIMEOUT};
+use passeri_api::lifecycle::Event;
+use passeri_api::metrics::{LatencyHistogram, Stats};
+use passeri_api::midi::{self, PortSelector};
+use passeri_api::net::lobby::{ClientInfo, Credentials, Features};
+use passeri_api::net::merge::Source;
+use passeri_api::net::relay::Ticket;
+use passeri_api::net::room::{Member, RoomEvent, Subscription};
+use passeri_api::net::{receiver, sender};
+use passeri_api::{ReceiverBuilder, SenderBuilder};
+use passeri_tcp::room::{Participant, RoomReceiver};
+use passeri_tcp::ReceiverConfig;
+use serde_json::json;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Interval at which a running bridge is checked
+const WATCH_ITV: Duration = Duration::from_millis(100);
+
+/// Non-commercial SysEx header used by ping messages (`F0 7D 'P' 'S'`)
+const PING_HEADER: [u8; 4] = [0xF0, 0x7D, 0x50, 0x53];
+
+pub fn list_ports(output: &Output) -> Result<()> {
+    let inputs = midi::get_availables_midi_in_port()?;
+    let outputs = midi::get_availables_midi_out_port()?;
+
+    let mut text = String::from("inputs:");
+    for port in &inputs {
+        text.push_str(&format!("\n\t{}", port));
+    }
+    text.push_str("\noutputs:");
+    for port in &outputs {
+        text.push_str(&format!("\n\t{}", port));
+    }
+
+    output.emit(
+        text,
+        json!({
+            "inputs": inputs.iter().map(port_json).collect::<Vec<_>>(),
+            "outputs": outputs.iter().map(port_json).collect::<Vec<_>>(),
+        }),
+    );
+    Ok(())
+}
+
+pub fn send<T>(
+    output: &Output,
+    addr: &str,
+    advertise: Option<&str>,
+    lobby: &LobbyArgs,
+    config: T::Config,
+    args: &BridgeArgs,
+) -> Result<()>
+where
+    T: sender::Thread,
+    T::Addr: FromStr + Into<SocketAddr>,
+    <T::Addr as FromStr>::Err: Error + 'static,
+{
+    let mut sender = open_sender::<T>(output, T::Addr::from_str(addr)?, lobby, config, args)?;
+
+    let _advertisement = match advertise {
+        Some(name) => {
+            let advertisement = sender.advertise(&Discovery::new()?, name)?;
+            output.event("advertised", advertisement.fullname());
+            Some(advertisement)
+        }
+        None => None,
+    };
+
+    if let (Some(room), Some(name)) = (args.relay(), args.member.as_deref()) {
+        let participant = enter_room(
+            output,
+            sender.info().into(),
+            &room,
+            Member::sender(name, None),
+        )?;
+        // every receiver of the room is streamed to at once
+        let mut last_stats = Instant::now();
+        while !sender.is_finished() && participant.is_present() {
+            if let Some(client) = sender.wait_for_client_timeout(WATCH_ITV)? {
+                output.event("pending", &client);
+                if lobby.approve && !approve(&client)? {
+                    sender.reject(client.addr.clone(), "rejected by the host")?;
+                    output.event("rejected", &client);
+                    continue;
+                }
+                output.event("connected", &client);
+                sender.send(client.addr.clone())?;
+            }
+            if let Some(interval) = args.stats {
+                if last_stats.elapsed() >= Duration::from_secs(interval) {
+                    output.stats(&sender.stats());
+                    last_stats = Instant::now();
+                }
+            }
+        }
+        sender.stop()?;
+        output.event("stopped", sender.join()?);
+        return Ok(());
+    }
+
+    stream(output, &mut sender, lobby, args)
+}
+
+/// Bridge the MIDI input ports to the serial device at `device`, streamed to once approved
+pub fn send_serial(
+    output: &Output,
+    device: &str,
+    lobby: &LobbyArgs,
+    config: passeri_serial::SerialConfig,
+    args: &BridgeArgs,
+) -> Result<()> {
+    let mut sender =
+        open_sender::<passeri_serial::Sender>(output, device.to_string(), lobby, config, args)?;
+    stream(output, &mut sender, lobby, args)
+}
+
+/// Create a sender bridging the MIDI input ports of `args`, its lobby set up with `lobby`
+fn open_sender<T: sender::Thread>(
+    output: &Output,
+    addr: T::Addr,
+    lobby: &LobbyArgs,
+    config: T::Config,
+    args: &BridgeArgs,
+) -> Result<sender::Sender<T>> {
+    let sender = SenderBuilder::<T>::new(&args.port, &args.name)
+        .with_config(config)
+        .listen(addr)?;
+    output.event("listening", sender.info());
+    sender.on_event(port_events(*output));
+
+    sender.lobby().set_key(lobby.key.as_deref());
+    sender.lobby().require(lobby.require);
+    if lobby.pairing {
+        output.event("pairing", sender.lobby().enable_pairing()?);
+    }
+    lobby
+        .allow
+        .iter()
+        .for_each(|entry| sender.lobby().allow(entry));
+    lobby
+        .deny
+        .iter()
+        .for_each(|entry| sender.lobby().deny(entry));
+    Ok(sender)
+}
+
+/// Stream to one receiver at a time until the sender stops, going back to waiting for a receiver each time one leaves
+fn stream<T: sender::Thread>(
+    output: &Output,
+    sender: &mut sender::Sender<T>,
+    lobby: &LobbyArgs,
+    args: &BridgeArgs,
+) -> Result<()> {
+    let events = sender.subscribe();
+    while !sender.is_finished() {
+        let client = loop {
+            let client = sender.wait_for_client()?;
+            output.event("pending", &client);
+            if !lobby.approve || approve(&client)? {
+                break client;
+            }
+            sender.reject(client.addr.clone(), "rejected by the host")?;
+            output.event("rejected", &client);
+        };
+        output.event("connected", &client);
+        sender.send(client.addr.clone())?;
+        output.event("streaming", sender.info());
+
+        watch(
+            output,
+            args.stats,
+            || sender.is_finished() || events.try_iter().any(|event| event == Event::ClientLeft),
+            || sender.stats(),
+        );
+        if !sender.is_finished() {
+            output.event("left", &client);
+        }
+    }
+    output.event("stopped", sender.join()?);
+    Ok(())
+}
+
+pub fn receive<T>(
+    output: &Output,
+    addrs: &[String],
+    channel_offsets: &[u8],
+    key: Option<&str>,
+    require: Features,
+    config: T::Config,
+    args: &BridgeArgs,
+) -> Result<()>
+where
+    T: receiver::Thread,
+    T::Addr: FromStr + From<SocketAddr>,
+    T::Config: Clone,
+{
+    let mut credentials = Credentials::new(&args.name).with_requirements(require);
+    credentials.secret = key.map(str::to_string);
+    if let [addr] = addrs {
+        if channel_offsets.iter().all(|offset| *offset == 0) {
+            return receive_one::<T>(output, resolve(output, addr)?, config, credentials, args);
+        }
+    }
+    if channel_offsets.len() > addrs.len() {
+        return Err("more channel offsets than senders".into());
+    }
+
+    let mut sources = vec![];
+    for (index, addr) in addrs.iter().enumerate() {
+        let mut source = Source::new(resolve::<T::Addr>(output, addr)?);
+        source.channel_offset = channel_offsets.get(index).copied().unwrap_or_default();
+        sources.push(source);
+    }
+    let mut receiver = ReceiverBuilder::<T>::new(&args.port, &args.name)
+        .with_config(config)
+        .with_credentials(credentials)
+        .merge(sources)?;
+    output.event("connected", receiver.info());
+    receiver.on_event(port_events(*output));
+
+    receiver.receive()?;
+    output.event("streaming", receiver.info());
+
+    watch(
+        output,
+        args.stats,
+        || receiver.is_finished(),
+        || receiver.stats(),
+    );
+    for ret in receiver.join()? {
+        output.event("stopped", ret);
+    }
+    Ok(())
+}
+
+/// Enter the relay room of a receiver, merging the streams of the senders of `subscription` as they join it
+pub fn receive_room(
+    output: &Output,
+    addrs: &[String],
+    subscription: Subscription,
+    key: Option<&str>,
+    require: Features,
+    config: ReceiverConfig,
+    args: &BridgeArgs,
+) -> Result<()> {
+    let [addr] = addrs else {
+        return Err("a room is entered through a single relay".into());
+    };
+    let (Some(room), Some(name)) = (args.relay(), args.member.as_deref()) else {
+        return Err("no relay room to enter".into());
+    };
+    let mut credentials = Credentials::new(&args.name).with_requirements(require);
+    credentials.secret = key.map(str::to_string);
+    let mut conns = midi::new_cable_senders(&args.port, &args.name)?;
+    for conn in conns.iter_mut() {
+        conn.auto_reconnect(&midi::PortWatcher::shared());
+    }
+
+    let receiver = RoomReceiver::enter(
+        conns,
+        SocketAddr::from_str(addr)?,
+        &room,
+        name,
+        subscription,
+        config,
+        credentials,
+    )?;
+    print_members(output, &room, receiver.participant());
+    receiver.on_event(room_events(*output));
+    receiver.with_merged(|merged| merged.on_event(port_events(*output)));
+    output.event("streaming", receiver.senders().join(", "));
+
+    watch(
+        output,
+        args.stats,
+        || !receiver.participant().is_present(),
+        || receiver.with_merged(|merged| merged.stats()),
+    );
+    output.event("stopped", format!("lost the relay of {}", room));
+    Ok(())
+}
+
+/// Enter `room` at `relay` as `member`, printing the members joining and leaving it
+fn enter_room(
+    output: &Output,
+    relay: SocketAddr,
+    room: &Ticket,
+    member: Member,
+) -> Result<Participant> {
+    let participant = Participant::enter(relay, room, member, room_events(*output))?;
+    print_members(output, room, &participant);
+    Ok(participant)
+}
+
+fn print_members(output: &Output, room: &Ticket, participant: &Participant) {
+    output.event("entered", format!("{} as {}", room, participant.member()));
+    for member in participant.members() {
+        output.event("member", member);
+    }
+}
+
+/// Print the members joining and leaving the room of a bridge
+fn room_events(output: Output) -> impl Fn(&RoomEvent) + Send + 'static {
+    move |event| match event {
+        RoomEvent::Joined(member) => output.event("joined", member),
+        RoomEvent::Left(member) => output.event("departed", member),
+    }
+}
+
+/// Bridge the serial device at `device` to the MIDI output port, the device being read as soon as it is opened
+pub fn receive_serial(
+    output: &Output,
+    device: &str,
+    config: passeri_serial::SerialConfig,
+    args: &BridgeArgs,
+) -> Result<()> {
+    let credentials = Credentials::new(&args.name);
+    receive_one::<passeri_serial::Receiver>(output, device.to_string(), config, credentials, args)
+}
+
+/// Return the address of a sender, browsing the local network if `addr` is the name under which it is advertised
+fn resolve<A>(output: &Output, addr: &str) -> Result<A>
+where
+    A: FromStr + From<SocketAddr>,
+{
+    if let Ok(addr) = A::from_str(addr) {
+        return Ok(addr);
+    }
+    let service = Discovery::new()?.find(addr, DEFAULT_BROWSE_TIMEOUT)?;
+    let found = service.addr().ok_or("the sender advertised no address")?;
+    output.event("found", format!("{} at {}", service.name, found));
+    Ok(found.into())
+}
+
+fn receive_one<T>(
+    output: &Output,
+    addr: T::Addr,
+    config: T::Config,
+    credentials: Credentials,
+    args: &BridgeArgs,
+) -> Result<()>
+where
+    T: receiver::Thread,
+{
+    let mut receiver = ReceiverBuilder::<T>::new(&args.port, &args.name)
+        .with_config(config)
+        .with_credentials(credentials)
+        .connect(addr)?;
+    output.event("connected", receiver.info());
+    receiver.on_event(port_events(*output));
+
+    receiver.receive()?;
+    output.event("streaming", receiver.info());
+
+    watch(
+        output,
+        args.stats,
+        || receiver.is_finished(),
+        || receiver.stats(),
+    );
+    output.event("stopped", receiver.join()?);
+    Ok(())
+}
+
+/// Print the unplugs and reconnections of the MIDI port of a bridge, the other events being printed by the commands
+fn port_events(output: Output) -> impl Fn(&Event) + Send + Sync + 'static {
+    move |event| match event {
+        Event::Reconnecting(port) => output.event("unplugged", port),
+        Event::Reconnected(port) => output.event("replugged", port),
+        _ => {}
+    }
+}
+
+/// Ask on the terminal whether `client` should be accepted
+fn approve<Addr: std::fmt::Display>(client: &ClientInfo<Addr>) -> Result<bool> {
+    eprint!(
+        "accept {} bridging \"{}\" ({:?}, features {})? [y/N] ",
+        client, client.midi_port, client.auth, client.features
+    );
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Block until `is_finished` returns `true`, printing the `stats` every `interval` seconds
+fn watch(
+    output: &Output,
+    interval: Option<u64>,
+    is_finished: impl Fn() -> bool,
+    stats: impl Fn() -> Stats,
+) {
+    let mut last_stats = Instant::now();
+    while !is_finished() {
+        std::thread::sleep(WATCH_ITV);
+        if let Some(interval) = interval {
+            if last_stats.elapsed() >= Duration::from_secs(interval) {
+                output.stats(&stats());
+                last_stats = Instant::now();
+            }
+        }
+    }
+}
+
+pub fn discover(output: &Output, timeout: u64) -> Result<()> {
+    let services = Discovery::new()?.browse(Duration::from_millis(timeout))?;
+    for service in services {
+        let addrs: Vec<String> = service.addrs.iter().map(|addr| addr.to_string()).collect();
+        output.emit(
+            format!(
+                "\"{}\" {} ({}, MIDI port \"{}\", protocol v{})",
+                service.name,
+                addrs.join(", "),
+                service.transport,
+                service.midi_port,
+                service.protocol_version
+            ),
+            json!({
+                "event": "sender",
+                "name": service.name,
+                "host": service.host,
+                "addrs": addrs,
+                "transport": service.transport,
+                "midi_port": service.midi_port,
+                "protocol_version": service.protocol_version,
+            }),
+        );
+    }
+    Ok(())
+}
+
+pub fn monitor(output: &Output, port: &PortSelector) -> Result<()> {
+    let (conn, rx) = midi::new_receiver(port, "passeri-monitor")?;
+    output.event("monitoring", conn.port());
+
+    for (stamp, message) in rx {
+        let bytes: Vec<String> = message.iter().map(|byte| format!("{:02X}", byte)).collect();
+        output.emit(
+            format!(
+                "{:>12} {:<24} {}",
+                stamp,
+                bytes.join(" "),
+                describe(&message)
+            ),
+            json!({
+                "event": "message",
+                "timestamp": stamp,
+                "bytes": message,
+                "description": describe(&message),
+            }),
+        );
+    }
+    Ok(())
+}
+
+pub fn ping(
+    output: &Output,
+    out: &PortSelector,
+    input: &PortSelector,
+    count: u32,
+    interval: u64,
+    timeout: u64,
+) -> Result<()> {
+    let out_conn = midi::new_sender(out, "passeri-ping")?;
+    let (in_conn, rx) = midi::new_receiver(input, "passeri-ping")?;
+    output.event(
+        "ping",
+        format!("from {} to {}", out_conn.port(), in_conn.port()),
+    );
+
+    let mut histogram = LatencyHistogram::default();
+    for seq in 0..count {
+        let message = ping_message(seq);
+        let sent = Instant::now();
+        let deadline = sent + Duration::from_millis(timeout);
+        out_conn.send(&message)?;
+
+        loop {
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((_, received)) if received == message => {
+                    let rtt = sent.elapsed();
+                    histogram.record(rtt);
+                    output.emit(
+                        format!("seq={} rtt={:.3}ms", seq, rtt.as_secs_f64() * 1000.),
+                        json!({ "event": "pong", "seq": seq, "rtt_us": rtt.as_micros() as u64 }),
+                    );
+                    break;
+                }
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => {
+                    output.emit(
+                        format!("seq={} timeout", seq),
+                        json!({ "event": "timeout", "seq": seq }),
+                    );
+                    break;
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err("MIDI input closed".into()),
+            }
+        }
+        if seq + 1 < count {
+            std::thread::sleep(Duration::from_millis(interval));
+        }
+    }
+
+    let millis = |d: Option<Duration>| d.map_or(0., |d| d.as_secs_f64() * 1000.);
+    let received = histogram.count();
+    output.emit(
+        format!(
+            "{} sent, {} received, {:.1}% lost, rtt min/avg/max = {:.3}/{:.3}/{:.3} ms",
+            count,
+            received,
+            (count as u64 - received) as f64 * 100. / count.max(1) as f64,
+            millis(histogram.min()),
+            millis(histogram.mean()),
+            millis(histogram.max()),
+        ),
+        json!({
+            "event": "summary",
+            "sent": count,
+            "received": received,
+            "min_us": histogram.min().map(|d| d.as_micros() as u64),
+            "mean_us": histogram.mean().map(|d| d.as_micros() as u64),
+            "max_us": histogram.max().map(|d| d.as_micros() as u64),
+        }),
+    );
+    Ok(())
+}
+
+/// Build the SysEx message of the ping number `seq`, splitting it in 7-bit data bytes
+fn ping_message(seq: u32) -> Vec<u8> {
+    let mut message = PING_HEADER.to_vec();
+    message.extend((0..5).rev().map(|i| ((seq >> (7 * i)) & 0x7F) as u8));
+    message.push(0xF7);
+    message
+}
+
+/// Human readable description of a MIDI message
+fn describe(message: &[u8]) -> String {
+    let channel = |status: u8| (status & 0x0F) + 1;
+    match message {
+        [status, note, 0] if status & 0xF0 == 0x90 => {
+            format!("ch{} note off {}", channel(*status), note)
+        }
+        [status, note, velocity] if status & 0xF0 == 0x90 => {
+            format!("ch{} note on {} vel {}", channel(*status), note, velocity)
+        }
+        [status, note, velocity] if status & 0xF0 == 0x80 => {
+            format!("ch{} note off {} vel {}", channel(*status), note, velocity)
+        }
+        [status, note, pressure] if status & 0xF0 == 0xA0 => {
+            format!("ch{} aftertouch {} {}", channel(*status), note, pressure)
+        }
+        [status, controller, value] if status & 0xF0 == 0xB0 => {
+            format!("ch{} control {} = {}", channel(*status), controller, value)
+        }
+        [status, program] if status & 0xF0 == 0xC0 => {
+            format!("ch{} program {}", channel(*status), program)
+        }
+        [status, pressure] if status & 0xF0 == 0xD0 => {
+            format!("ch{} pressure {}", channel(*status), pressure)
+        }
+        [status, lsb, msb] if status & 0xF0 == 0xE0 => {
+            let bend = ((*msb as i32) << 7 | *lsb as i32) - 8192;
+            format!("ch{} pitch bend {}", channel(*status), bend)
+        }
+        [0xF0, ..] => format!("sysex {} bytes", message.len()),
+        [0xF8] => "clock".into(),
+        [0xFA] => "start".into(),
+        [0xFB] => "continue".into(),
+        [0xFC] => "stop".into(),
+        [0xFE] => "active sensing".into(),
+        [0xFF] => "reset".into(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ping_message() {
+        assert_eq!(
+            ping_message(0x12345),
+            vec![0xF0, 0x7D, 0x50, 0x53, 0x00, 0x00, 0x04, 0x46, 0x45, 0xF7]
+        );
+        assert!(ping_message(u32::MAX)[1..9]
+            .iter()
+            .all(|byte| byte & 0x80 == 0));
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe(&[0x91, 60, 100]), "ch2 note on 60 vel 100");
+        assert_eq!(describe(&[0x90, 60, 0]), "ch1 note off 60");
+        assert_eq!(describe(&[0xE0, 0x00, 0x40]), "ch1 pitch bend 0");
+        assert_eq!(describe(&[0xF0, 0x7D, 0xF7]), "sysex 3 bytes");
+    }
+}
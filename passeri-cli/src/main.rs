@@ -0,0 +1,416 @@
+//! `passeri` command-line tool, bridging local MIDI ports over network from a terminal
+use clap::{Parser, Subcommand, ValueEnum};
+use log::error;
+use passeri_api::midi::{ClockConfig, PortSelector, SnapshotConfig};
+use passeri_api::net::heartbeat::HeartbeatConfig;
+use passeri_api::net::lobby::Features;
+use passeri_api::net::relay::Ticket;
+use passeri_api::net::room::{Member, Subscription};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+mod commands;
+mod output;
+mod tls;
+
+use output::Output;
+
+#[derive(Parser)]
+#[command(
+    name = "passeri",
+    version,
+    about = "MIDI Sender/Receiver bridge over network"
+)]
+struct Cli {
+    /// verbosity of the logs, written on stderr
+    #[arg(long, global = true, value_enum, default_value_t = LogLevel::Warn)]
+    log_level: LogLevel,
+
+    /// print machine-readable JSON lines instead of human readable text
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// list the MIDI input and output ports
+    ListPorts,
+
+    /// bridge a local MIDI input port to a distant receiver
+    Send {
+        /// address to listen on for a receiver, or the address of the relay with `--relay`
+        addr: String,
+        /// advertise the sender over DNS-SD under the given name
+        #[arg(long, value_name = "NAME")]
+        advertise: Option<String>,
+        /// state sent to each receiver joining (comma separated: programs, controllers, pitch_bend, pressure, rpn, all or none)
+        #[arg(long, value_name = "CLASSES", default_value = "all")]
+        snapshot: SnapshotConfig,
+        #[command(flatten)]
+        lobby: LobbyArgs,
+        #[command(flatten)]
+        tls: tls::ServerArgs,
+        #[command(flatten)]
+        bridge: BridgeArgs,
+    },
+
+    /// bridge a distant sender to a local MIDI output port, or merge several senders into it
+    Receive {
+        /// addresses of the senders to connect to, or the names under which they are advertised
+        /// (the address of the relay with `--relay`)
+        #[arg(required = true, value_name = "ADDR")]
+        addrs: Vec<String>,
+        /// number of channels the messages of each sender are shifted by, in the order of the senders
+        #[arg(long, value_name = "CHANNELS", value_parser = clap::value_parser!(u8).range(0..16), conflicts_with = "member")]
+        channel_offset: Vec<u8>,
+        /// only receive the senders of the relay room with these names, instead of every one of them
+        #[arg(long, value_name = "NAME", requires = "member")]
+        subscribe: Vec<String>,
+        /// pre-shared key or pairing code expected by the sender
+        #[arg(long, value_name = "SECRET")]
+        key: Option<String>,
+        /// refuse senders not supporting these features (comma separated)
+        #[arg(long, value_name = "FEATURES", default_value = "none")]
+        require: Features,
+        #[command(flatten)]
+        clock: ClockArgs,
+        #[command(flatten)]
+        tls: tls::ClientArgs,
+        #[command(flatten)]
+        bridge: BridgeArgs,
+    },
+
+    /// generate a self-signed certificate usable with `--cert`, printing its fingerprint
+    GenCert {
+        /// PEM file to write the certificate and its private key to
+        out: PathBuf,
+        /// DNS names or IP addresses the certificate is valid for
+        #[arg(long = "name", default_value = "localhost")]
+        names: Vec<String>,
+    },
+
+    /// list the senders advertised on the local network
+    Discover {
+        /// time spent looking for senders, in milliseconds
+        #[arg(short, long, default_value_t = 3000)]
+        timeout: u64,
+    },
+
+    /// print every message received on a MIDI input port
+    Monitor {
+        /// MIDI input port (index, `id:<id>`, `name:<name>`, `re:<pattern>` or part of its name)
+        #[arg(short, long, default_value = "0")]
+        port: PortSelector,
+    },
+
+    /// measure the round trip time of MIDI messages sent on an output port and received back on an input port
+    Ping {
+        /// MIDI output port on which the pings are sent
+        #[arg(long)]
+        out: PortSelector,
+        /// MIDI input port on which the pings are expected back
+        #[arg(long = "in")]
+        input: PortSelector,
+        /// number of pings to send
+        #[arg(short, long, default_value_t = 10)]
+        count: u32,
+        /// interval between pings, in milliseconds
+        #[arg(short, long, default_value_t = 500)]
+        interval: u64,
+        /// time to wait for each ping to come back, in milliseconds
+        #[arg(short, long, default_value_t = 1000)]
+        timeout: u64,
+    },
+}
+
+/// Admission rules of the `send` command
+#[derive(clap::Args)]
+struct LobbyArgs {
+    /// require receivers to know this pre-shared key
+    #[arg(long, value_name = "SECRET")]
+    key: Option<String>,
+
+    /// generate and print a pairing code admitting a single receiver within 5 minutes (alternative to `--key`)
+    #[arg(long)]
+    pairing: bool,
+
+    /// only accept receivers from this IP address, or presenting a client certificate with this fingerprint
+    #[arg(long, value_name = "HOST|FINGERPRINT")]
+    allow: Vec<String>,
+
+    /// reject receivers from this IP address, or presenting a client certificate with this fingerprint
+    #[arg(long, value_name = "HOST|FINGERPRINT")]
+    deny: Vec<String>,
+
+    /// ask on the terminal before accepting each receiver
+    #[arg(long)]
+    approve: bool,
+
+    /// reject receivers not supporting these features (comma separated)
+    #[arg(long, value_name = "FEATURES", default_value = "none")]
+    require: Features,
+}
+
+/// MIDI Clock regeneration of the `receive` command
+#[derive(clap::Args)]
+struct ClockArgs {
+    /// replace the incoming MIDI Clock, suffering from network jitter, by a smoothed one generated locally
+    #[arg(long)]
+    clock: bool,
+
+    /// weight of the past ticks in the tempo estimation, from 0 (following each tick) to close to 1 (steadiest)
+    #[arg(long, default_value_t = 0.9, requires = "clock")]
+    clock_smoothing: f64,
+
+    /// network latency compensated for by sending the generated ticks ahead, in milliseconds
+    #[arg(long, default_value_t = 0, requires = "clock")]
+    clock_latency: u64,
+}
+
+impl ClockArgs {
+    fn config(&self) -> Option<ClockConfig> {
+        self.clock.then(|| ClockConfig {
+            smoothing: self.clock_smoothing,
+            latency: Duration::from_millis(self.clock_latency),
+        })
+    }
+}
+
+#[derive(clap::Args)]
+struct BridgeArgs {
+    /// MIDI port (index, `id:<id>`, `name:<name>`, `re:<pattern>` or part of its name),
+    /// repeated to multiplex several ports over the connection
+    #[arg(short, long, default_value = "0")]
+    port: Vec<PortSelector>,
+
+    /// name of the MIDI client created by the bridge
+    #[arg(short, long, default_value = "passeri")]
+    name: String,
+
+    /// network layer used by the bridge
+    #[arg(short, long, value_enum, default_value_t = Transport::Tcp)]
+    transport: Transport,
+
+    /// print the bridge statistics every given number of seconds
+    #[arg(long)]
+    stats: Option<u64>,
+
+    /// interval between two heartbeats exchanged with the distant peer, in milliseconds
+    #[arg(long, default_value_t = 1000)]
+    heartbeat: u64,
+
+    /// time without hearing from the distant peer before dropping the connection, in milliseconds
+    #[arg(long, default_value_t = 5000)]
+    heartbeat_timeout: u64,
+
+    /// meet the distant peer in this room of a `passeri-relay`, the address being the one of the relay
+    #[arg(long, value_name = "ROOM")]
+    relay: Option<String>,
+
+    /// token guarding the relay room, peers only meeting the ones with the same token
+    #[arg(long, value_name = "TOKEN", requires = "relay")]
+    relay_token: Option<String>,
+
+    /// enter the relay room as a member with this name, announced to the other members:
+    /// senders stream to every receiver of the room, receivers merge the streams of its senders
+    #[arg(long, value_name = "NAME", requires = "relay")]
+    member: Option<String>,
+
+    /// speed of the serial link with `--transport serial`, in bits per second
+    #[arg(long, default_value_t = passeri_serial::DIN_MIDI_BAUD_RATE)]
+    baud_rate: u32,
+}
+
+impl LobbyArgs {
+    /// Fail on the admission rules a serial device, neither authenticated nor addressed, cannot be checked against
+    fn serial(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.key.is_some() || self.pairing {
+            return Err("a serial device cannot be authenticated with --key or --pairing".into());
+        }
+        if !self.allow.is_empty() || !self.deny.is_empty() {
+            return Err("a serial device cannot be filtered with --allow or --deny".into());
+        }
+        Ok(())
+    }
+}
+
+impl BridgeArgs {
+    fn heartbeat(&self) -> HeartbeatConfig {
+        HeartbeatConfig {
+            interval: Duration::from_millis(self.heartbeat),
+            timeout: Duration::from_millis(self.heartbeat_timeout),
+        }
+    }
+
+    fn serial(&self) -> Result<passeri_serial::SerialConfig, Box<dyn std::error::Error>> {
+        if self.relay.is_some() {
+            return Err("a serial link cannot go through a relay".into());
+        }
+        Ok(passeri_serial::SerialConfig {
+            baud_rate: self.baud_rate,
+        })
+    }
+
+    fn relay(&self) -> Option<Ticket> {
+        let ticket = Ticket::new(self.relay.as_deref()?);
+        Some(match self.relay_token.as_deref() {
+            Some(token) => ticket.with_token(token),
+            None => ticket,
+        })
+    }
+
+    /// Relay room a sender waits for its receivers in, its own one when it is a member of the room
+    fn sender_relay(&self) -> Option<Ticket> {
+        let room = self.relay()?;
+        Some(match self.member.as_deref() {
+            Some(name) => Member::sender(name, None).ticket(&room),
+            None => room,
+        })
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Transport {
+    /// plain TCP stream (passeri-tcp)
+    Tcp,
+    /// serial link to a DIN-MIDI interface or a microcontroller, the address being the path of the device
+    /// (passeri-serial)
+    Serial,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => log::LevelFilter::Off,
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    env_logger::builder()
+        .filter_level(cli.log_level.into())
+        .init();
+    let output = Output::new(cli.json);
+
+    let result = match cli.command {
+        Command::ListPorts => commands::list_ports(&output),
+        Command::Send {
+            addr,
+            advertise,
+            snapshot,
+            lobby,
+            tls,
+            bridge,
+        } => match bridge.transport {
+            Transport::Tcp => tls::sender_config(&output, &tls).and_then(|mut config| {
+                config.heartbeat = bridge.heartbeat();
+                config.snapshot = snapshot;
+                config.relay = bridge.sender_relay();
+                commands::send::<passeri_tcp::Sender>(
+                    &output,
+                    &addr,
+                    advertise.as_deref(),
+                    &lobby,
+                    config,
+                    &bridge,
+                )
+            }),
+            Transport::Serial if advertise.is_some() => {
+                Err("a serial device cannot be advertised".into())
+            }
+            Transport::Serial => lobby
+                .serial()
+                .and_then(|()| bridge.serial())
+                .and_then(|config| commands::send_serial(&output, &addr, &lobby, config, &bridge)),
+        },
+        Command::Receive {
+            addrs,
+            channel_offset,
+            subscribe,
+            key,
+            require,
+            clock,
+            tls,
+            bridge,
+        } => match bridge.transport {
+            Transport::Tcp => tls::receiver_config(&tls).and_then(|mut config| {
+                config.heartbeat = bridge.heartbeat();
+                config.clock = clock.config();
+                config.relay = bridge.relay();
+                if bridge.member.is_some() {
+                    let subscription = if subscribe.is_empty() {
+                        Subscription::All
+                    } else {
+                        Subscription::only(subscribe.iter().map(String::as_str))
+                    };
+                    return commands::receive_room(
+                        &output,
+                        &addrs,
+                        subscription,
+                        key.as_deref(),
+                        require,
+                        config,
+                        &bridge,
+                    );
+                }
+                commands::receive::<passeri_tcp::Receiver>(
+                    &output,
+                    &addrs,
+                    &channel_offset,
+                    key.as_deref(),
+                    require,
+                    config,
+                    &bridge,
+                )
+            }),
+            Transport::Serial if key.is_some() => {
+                Err("a serial device cannot be authenticated with --key".into())
+            }
+            Transport::Serial => match addrs.as_slice() {
+                [device] => bridge
+                    .serial()
+                    .and_then(|config| commands::receive_serial(&output, device, config, &bridge)),
+                _ => Err("a serial receiver reads a single device".into()),
+            },
+        },
+        Command::GenCert { out, names } => tls::gen_cert(&output, &out, &names),
+        Command::Discover { timeout } => commands::discover(&output, timeout),
+        Command::Monitor { port } => commands::monitor(&output, &port),
+        Command::Ping {
+            out,
+            input,
+            count,
+            interval,
+            timeout,
+        } => commands::ping(&output, &out, &input, count, interval, timeout),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{}", err);
+            output.event("error", &err);
+            ExitCode::FAILURE
+        }
+    }
+}
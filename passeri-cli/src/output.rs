@@ -0,0 +1,82 @@
+use passeri_api::metrics::Stats;
+use passeri_api::midi::MidiPort;
+use serde_json::{json, Value};
+use std::fmt::Display;
+
+/// Print the events of a command, either as human readable lines or as JSON lines
+#[derive(Clone, Copy)]
+pub struct Output {
+    json: bool,
+}
+
+impl Output {
+    pub fn new(json: bool) -> Self {
+        Output { json }
+    }
+
+    /// Print `text` in human readable mode, `value` in JSON mode
+    pub fn emit(&self, text: impl Display, value: Value) {
+        if self.json {
+            println!("{}", value);
+        } else {
+            println!("{}", text);
+        }
+    }
+
+    /// Print a lifecycle event of a bridge
+    pub fn event(&self, event: &str, detail: impl Display) {
+        self.emit(
+            format!("{:<10} {}", event, detail),
+            json!({ "event": event, "detail": detail.to_string() }),
+        );
+    }
+
+    /// Print a snapshot of the bridge counters
+    pub fn stats(&self, stats: &Stats) {
+        let latency = &stats.latency;
+        let rtt = &stats.rtt;
+        let mut text = format!(
+            "{:<10} {} messages, {} bytes, {} sysex, {} dropped, {} reconnects, {} errors",
+            "stats",
+            stats.messages,
+            stats.bytes,
+            stats.sysex,
+            stats.dropped,
+            stats.reconnects,
+            stats.errors
+        );
+        if let Some(mean) = rtt.mean() {
+            text.push_str(&format!(", rtt {:.3} ms", mean.as_secs_f64() * 1000.));
+        }
+        self.emit(
+            text,
+            json!({
+                "event": "stats",
+                "uptime_ms": stats.uptime.as_millis() as u64,
+                "messages": stats.messages,
+                "bytes": stats.bytes,
+                "sysex": stats.sysex,
+                "dropped": stats.dropped,
+                "reconnects": stats.reconnects,
+                "errors": stats.errors,
+                "latency": {
+                    "count": latency.count(),
+                    "min_us": latency.min().map(|d| d.as_micros() as u64),
+                    "mean_us": latency.mean().map(|d| d.as_micros() as u64),
+                    "p99_us": latency.percentile(99.).map(|d| d.as_micros() as u64),
+                    "max_us": latency.max().map(|d| d.as_micros() as u64),
+                },
+                "rtt": {
+                    "count": rtt.count(),
+                    "min_us": rtt.min().map(|d| d.as_micros() as u64),
+                    "mean_us": rtt.mean().map(|d| d.as_micros() as u64),
+                    "max_us": rtt.max().map(|d| d.as_micros() as u64),
+                },
+            }),
+        );
+    }
+}
+
+pub fn port_json(port: &MidiPort) -> Value {
+    json!({ "index": port.index, "id": port.id, "name": port.name })
+}
@@ -0,0 +1,98 @@
+use crate::output::Output;
+use passeri_tcp::tls::{ClientTls, Fingerprint, Identity, ServerTls, Trust};
+use passeri_tcp::{ReceiverConfig, SenderConfig};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// TLS options of the `send` command
+#[derive(clap::Args)]
+pub struct ServerArgs {
+    /// encrypt the connection with TLS, using a generated self-signed certificate unless `--cert` is given
+    #[arg(long)]
+    tls: bool,
+
+    /// PEM file holding the certificate chain and the private key of the sender (implies `--tls`)
+    #[arg(long, value_name = "PEM")]
+    cert: Option<PathBuf>,
+
+    /// only accept the receivers presenting a certificate with this fingerprint (mutual TLS, implies `--tls`)
+    #[arg(long, value_name = "FINGERPRINT")]
+    client_pin: Vec<Fingerprint>,
+}
+
+/// TLS options of the `receive` command
+#[derive(clap::Args)]
+pub struct ClientArgs {
+    /// encrypt the connection with TLS, trusting the sender certificate with this fingerprint
+    #[arg(long, value_name = "FINGERPRINT")]
+    pin: Vec<Fingerprint>,
+
+    /// encrypt the connection with TLS, trusting the sender certificate issued by the root certificates of this PEM file
+    #[arg(long, value_name = "PEM", conflicts_with = "pin")]
+    ca: Option<PathBuf>,
+
+    /// PEM file holding the certificate chain and the private key presented to the sender (mutual TLS)
+    #[arg(long, value_name = "PEM")]
+    cert: Option<PathBuf>,
+
+    /// name expected in the sender certificate when checked against `--ca`, its IP address by default
+    #[arg(long, value_name = "NAME", requires = "ca")]
+    server_name: Option<String>,
+}
+
+pub fn sender_config(output: &Output, args: &ServerArgs) -> Result<SenderConfig> {
+    if !args.tls && args.cert.is_none() && args.client_pin.is_empty() {
+        return Ok(SenderConfig::default());
+    }
+
+    let identity = match &args.cert {
+        Some(path) => Identity::from_pem(&std::fs::read_to_string(path)?)?,
+        None => Identity::self_signed(&["localhost"])?,
+    };
+    let client_auth = (!args.client_pin.is_empty()).then(|| Trust::Pinned(args.client_pin.clone()));
+    let tls = ServerTls::new(&identity, client_auth)?;
+    output.event(
+        "tls",
+        format!("certificate fingerprint {}", tls.fingerprint()),
+    );
+
+    Ok(SenderConfig {
+        tls: Some(tls),
+        ..Default::default()
+    })
+}
+
+pub fn receiver_config(args: &ClientArgs) -> Result<ReceiverConfig> {
+    let trust = match &args.ca {
+        Some(path) => Trust::roots_from_pem(&std::fs::read_to_string(path)?)?,
+        None if !args.pin.is_empty() => Trust::Pinned(args.pin.clone()),
+        None if args.cert.is_some() => {
+            return Err("`--cert` requires the sender to be trusted with `--pin` or `--ca`".into())
+        }
+        None => return Ok(ReceiverConfig::default()),
+    };
+
+    let identity = match &args.cert {
+        Some(path) => Some(Identity::from_pem(&std::fs::read_to_string(path)?)?),
+        None => None,
+    };
+    let mut tls = ClientTls::new(trust, identity.as_ref())?;
+    if let Some(name) = &args.server_name {
+        tls = tls.with_server_name(name)?;
+    }
+
+    Ok(ReceiverConfig {
+        tls: Some(tls),
+        ..Default::default()
+    })
+}
+
+pub fn gen_cert(output: &Output, out: &Path, names: &[String]) -> Result<()> {
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    let identity = Identity::self_signed(&names)?;
+    std::fs::write(out, identity.pem())?;
+    output.event("fingerprint", identity.fingerprint());
+    Ok(())
+}
@@ -0,0 +1,350 @@
+use passeri_api::midi::PortSelector;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Errors raised while loading a configuration file
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("unable to read {path}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("invalid TOML configuration")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("invalid YAML configuration")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("several {kind}s are named \"{name}\"")]
+    DuplicateName { kind: Kind, name: String },
+
+    #[error("{kind} \"{name}\": invalid address `{address}` ({reason})")]
+    InvalidAddress {
+        kind: Kind,
+        name: String,
+        address: String,
+        reason: String,
+    },
+
+    #[error("{kind} \"{name}\": invalid MIDI port selector")]
+    InvalidPort {
+        kind: Kind,
+        name: String,
+        #[source]
+        source: passeri_api::MidiError,
+    },
+}
+
+/// Bridges run by the daemon
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// bridges forwarding a local MIDI input to a distant receiver
+    #[serde(default, rename = "sender")]
+    pub senders: Vec<BridgeConfig>,
+    /// bridges forwarding a distant sender to a local MIDI output
+    #[serde(default, rename = "receiver")]
+    pub receivers: Vec<BridgeConfig>,
+}
+
+/// Description of a single bridge
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BridgeConfig {
+    /// unique name of the bridge among its kind, also used as MIDI client name
+    pub name: String,
+    /// network layer used by the bridge
+    #[serde(default)]
+    pub transport: Transport,
+    /// address to listen on (sender), or to connect to (receiver) which can also be the name of an advertised sender
+    pub address: String,
+    /// MIDI port selector (see [PortSelector])
+    #[serde(default = "default_midi_port")]
+    pub midi_port: String,
+    /// advertise the sender over DNS-SD under the bridge name (ignored for receivers)
+    #[serde(default)]
+    pub advertise: bool,
+    /// pre-shared key required from the receivers (sender), or presented to the sender (receiver)
+    #[serde(default)]
+    pub key: Option<String>,
+    /// receiver IP addresses or client certificate fingerprints accepted by the sender, every receiver being accepted when empty (ignored for receivers)
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// receiver IP addresses or client certificate fingerprints rejected by the sender (ignored for receivers)
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// when to restart the bridge after its net_thread ended
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    /// delay before the first restart, in milliseconds, doubled after each consecutive failure
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
+    /// upper bound of the delay between restarts, in milliseconds
+    #[serde(default = "default_max_retry_delay")]
+    pub max_retry_delay: u64,
+}
+
+/// Network layers a bridge can use
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// plain TCP stream (passeri-tcp)
+    #[default]
+    Tcp,
+}
+
+/// When a bridge is restarted by its supervisor
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// whenever the net_thread ends, including when the distant peer leaves
+    #[default]
+    Always,
+    /// only when the net_thread ends because of an error
+    OnError,
+    /// never, the bridge stays stopped until the next reload
+    Never,
+}
+
+/// Kind of a bridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Kind {
+    Sender,
+    Receiver,
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Sender => write!(f, "sender"),
+            Kind::Receiver => write!(f, "receiver"),
+        }
+    }
+}
+
+/// Identifier of a bridge in the configuration
+pub type Key = (Kind, String);
+
+fn default_midi_port() -> String {
+    "0".into()
+}
+
+fn default_retry_delay() -> u64 {
+    1_000
+}
+
+fn default_max_retry_delay() -> u64 {
+    30_000
+}
+
+impl Config {
+    /// Load and validate a configuration file, parsed as YAML for `.yaml`/`.yml` extensions and as TOML otherwise
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => Self::from_yaml(&content),
+            _ => Self::from_toml(&content),
+        }
+    }
+
+    /// Parse and validate a TOML configuration
+    pub fn from_toml(content: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parse and validate a YAML configuration
+    pub fn from_yaml(content: &str) -> Result<Self, ConfigError> {
+        let config: Config = serde_yaml::from_str(content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Iterate over every bridge with its [Key]
+    pub fn bridges(&self) -> impl Iterator<Item = (Key, &BridgeConfig)> {
+        let senders = self
+            .senders
+            .iter()
+            .map(|b| ((Kind::Sender, b.name.clone()), b));
+        let receivers = self
+            .receivers
+            .iter()
+            .map(|b| ((Kind::Receiver, b.name.clone()), b));
+        senders.chain(receivers)
+    }
+
+    /// Compare with a newly loaded configuration, returning the bridges to stop and the ones to start.
+    /// Bridges whose configuration did not change are in neither list.
+    pub fn diff<'a>(&self, new: &'a Config) -> (Vec<Key>, Vec<(Key, &'a BridgeConfig)>) {
+        let to_stop = self
+            .bridges()
+            .filter(|(key, bridge)| new.get(key) != Some(bridge))
+            .map(|(key, _)| key)
+            .collect();
+        let to_start = new
+            .bridges()
+            .filter(|(key, bridge)| self.get(key) != Some(bridge))
+            .collect();
+        (to_stop, to_start)
+    }
+
+    fn get(&self, (kind, name): &Key) -> Option<&BridgeConfig> {
+        let bridges = match kind {
+            Kind::Sender => &self.senders,
+            Kind::Receiver => &self.receivers,
+        };
+        bridges.iter().find(|bridge| &bridge.name == name)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut names = HashSet::new();
+        for ((kind, name), bridge) in self.bridges() {
+            if !names.insert((kind, name.clone())) {
+                return Err(ConfigError::DuplicateName { kind, name });
+            }
+            bridge
+                .port_selector()
+                .map_err(|source| ConfigError::InvalidPort {
+                    kind,
+                    name: name.clone(),
+                    source,
+                })?;
+            let address = match (kind, bridge.transport) {
+                // receivers may look for an advertised sender by name
+                (Kind::Receiver, _) if !bridge.address.is_empty() => Ok(()),
+                (_, Transport::Tcp) => bridge
+                    .address
+                    .parse::<<passeri_tcp::Sender as passeri_api::net::sender::Thread>::Addr>()
+                    .map(|_| ())
+                    .map_err(|err| err.to_string()),
+            };
+            address.map_err(|reason| ConfigError::InvalidAddress {
+                kind,
+                name,
+                address: bridge.address.clone(),
+                reason,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl BridgeConfig {
+    /// [PortSelector] of the bridge MIDI port
+    pub fn port_selector(&self) -> Result<PortSelector, passeri_api::MidiError> {
+        PortSelector::from_str(&self.midi_port)
+    }
+
+    /// Delay before the `attempt`-th consecutive restart
+    pub fn retry_delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .retry_delay
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_retry_delay);
+        Duration::from_millis(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+        [[sender]]
+        name = "keyboard"
+        address = "0.0.0.0:8080"
+        midi_port = "name:USB Keyboard"
+        advertise = true
+        key = "secret"
+        allow = ["192.168.1.20"]
+
+        [[receiver]]
+        name = "synth"
+        transport = "tcp"
+        address = "192.168.1.10:8080"
+        restart = "on-error"
+    "#;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::from_toml(TOML).unwrap();
+        assert_eq!(config.senders.len(), 1);
+        assert_eq!(config.receivers[0].midi_port, "0");
+        assert_eq!(config.receivers[0].restart, RestartPolicy::OnError);
+        assert_eq!(config.senders[0].restart, RestartPolicy::Always);
+        assert_eq!(config.senders[0].key.as_deref(), Some("secret"));
+        assert!(config.receivers[0].allow.is_empty());
+
+        let yaml = Config::from_yaml(
+            "
+sender:
+  - name: keyboard
+    address: 0.0.0.0:8080
+    midi_port: 'name:USB Keyboard'
+    advertise: true
+    key: secret
+    allow: [192.168.1.20]
+receiver:
+  - name: synth
+    transport: tcp
+    address: 192.168.1.10:8080
+    restart: on-error
+",
+        )
+        .unwrap();
+        assert_eq!(yaml, config);
+
+        assert!(matches!(
+            Config::from_toml("[[sender]]\nname = \"a\"\naddress = \"nowhere\""),
+            Err(ConfigError::InvalidAddress { .. })
+        ));
+        assert!(matches!(
+            Config::from_toml(
+                "[[sender]]\nname = \"a\"\naddress = \"0.0.0.0:1\"\n[[sender]]\nname = \"a\"\naddress = \"0.0.0.0:2\""
+            ),
+            Err(ConfigError::DuplicateName { .. })
+        ));
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Config::from_toml(TOML).unwrap();
+        let mut new = old.clone();
+        new.receivers[0].address = "192.168.1.11:8080".into();
+        new.senders.push(BridgeConfig {
+            name: "pads".into(),
+            ..new.senders[0].clone()
+        });
+
+        let (to_stop, to_start) = old.diff(&new);
+        assert_eq!(to_stop, vec![(Kind::Receiver, "synth".to_string())]);
+        let started: Vec<_> = to_start.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(
+            started,
+            vec![
+                (Kind::Sender, "pads".to_string()),
+                (Kind::Receiver, "synth".to_string())
+            ]
+        );
+        assert_eq!(old.diff(&old), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let bridge = &Config::from_toml(TOML).unwrap().senders[0];
+        assert_eq!(bridge.retry_delay(0), Duration::from_secs(1));
+        assert_eq!(bridge.retry_delay(2), Duration::from_secs(4));
+        assert_eq!(bridge.retry_delay(40), Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,121 @@
+//! `passerid` daemon, running the bridges described by a configuration file unattended
+//!
+//! The configuration is reloaded on `SIGHUP`: only the bridges whose description changed are restarted.
+use clap::Parser;
+use config::{Config, Key};
+use log::{error, info, warn};
+use passeri_api::discovery::Discovery;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use supervisor::Supervisor;
+
+mod config;
+mod supervisor;
+
+#[derive(Parser)]
+#[command(
+    name = "passerid",
+    version,
+    about = "Run MIDI bridges described by a configuration file"
+)]
+struct Cli {
+    /// TOML (or YAML for `.yaml`/`.yml` extensions) file describing the bridges
+    config: PathBuf,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let mut config = match Config::load(&cli.config) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{}", report(&err));
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut signals = match Signals::new([SIGHUP, SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            error!("unable to register signal handlers: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let discovery = Discovery::new()
+        .map_err(|err| warn!("DNS-SD disabled: {}", report(&err)))
+        .ok();
+    let mut supervisors: HashMap<Key, Supervisor> = config
+        .bridges()
+        .map(|(key, bridge)| {
+            let supervisor = Supervisor::spawn(key.clone(), bridge.clone(), discovery.clone());
+            (key, supervisor)
+        })
+        .collect();
+    info!("{} bridges started", supervisors.len());
+
+    for signal in signals.forever() {
+        if signal != SIGHUP {
+            break;
+        }
+        reload(&cli.config, &mut config, &mut supervisors, &discovery);
+    }
+
+    info!("stopping {} bridges", supervisors.len());
+    supervisors.values().for_each(Supervisor::stop);
+    // dropping the supervisors waits for their bridges to release the notes held and to end
+    drop(supervisors);
+    ExitCode::SUCCESS
+}
+
+/// Reload the configuration file, restarting only the bridges that changed
+fn reload(
+    path: &Path,
+    config: &mut Config,
+    supervisors: &mut HashMap<Key, Supervisor>,
+    discovery: &Option<Discovery>,
+) {
+    info!("reloading {}", path.display());
+    let new = match Config::load(path) {
+        Ok(new) => new,
+        Err(err) => {
+            error!("keeping the current configuration: {}", report(&err));
+            return;
+        }
+    };
+
+    let (to_stop, to_start) = config.diff(&new);
+    for key in &to_stop {
+        info!("stopping {} \"{}\"", key.0, key.1);
+        if let Some(supervisor) = supervisors.get(key) {
+            supervisor.stop();
+        }
+    }
+    // the bridges are stopped together, and all gone before their replacements bind the same addresses and ports
+    for key in &to_stop {
+        supervisors.remove(key);
+    }
+    for (key, bridge) in to_start {
+        info!("starting {} \"{}\"", key.0, key.1);
+        supervisors.insert(
+            key.clone(),
+            Supervisor::spawn(key, bridge.clone(), discovery.clone()),
+        );
+    }
+    *config = new;
+}
+
+/// Format an error followed by its sources
+fn report(err: &dyn Error) -> String {
+    let mut report = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        report.push_str(&format!(": {}", err));
+        source = err.source();
+    }
+    report
+}
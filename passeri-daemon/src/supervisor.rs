@@ -0,0 +1,277 @@
+use crate::config::{BridgeConfig, Key, Kind, RestartPolicy, Transport};
+use log::{error, info};
+use passeri_api::discovery::{Discovery, DEFAULT_BROWSE_TIMEOUT};
+use passeri_api::lifecycle::Event;
+use passeri_api::net::lobby::Credentials;
+use passeri_api::net::{receiver, sender};
+use passeri_api::{ReceiverBuilder, SenderBuilder};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Interval at which a running bridge is checked
+const WATCH_ITV: Duration = Duration::from_millis(100);
+
+/// How a single run of a bridge ended
+enum Outcome {
+    /// the net_thread ended normally (e.g. the distant peer left)
+    Ended(String),
+    /// the bridge could not start or its net_thread ended because of an error
+    Failed(String),
+    /// the supervisor has been asked to stop
+    Cancelled,
+}
+
+/// Run a bridge in its own thread, restarting it according to its [RestartPolicy] until stopped
+pub struct Supervisor {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Supervisor {
+    /// Start supervising the bridge described by `bridge`, `discovery` being used to advertise senders and to find the ones receivers connect to
+    pub fn spawn(key: Key, bridge: BridgeConfig, discovery: Option<Discovery>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || supervise(key, bridge, discovery, thread_stop));
+        Supervisor {
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Ask the bridge to stop without waiting for it, so several bridges can be stopped at once before being dropped
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Supervisor {
+    /// Stop the bridge and wait for it to end, its address and MIDI port being free once dropped
+    fn drop(&mut self) {
+        self.stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn supervise(key: Key, bridge: BridgeConfig, discovery: Option<Discovery>, stop: Arc<AtomicBool>) {
+    let (kind, name) = &key;
+    let mut attempt = 0;
+
+    while !stop.load(Ordering::Relaxed) {
+        let outcome = match (kind, bridge.transport) {
+            (Kind::Sender, Transport::Tcp) => {
+                run_sender::<passeri_tcp::Sender>(&bridge, discovery.as_ref(), &stop)
+            }
+            (Kind::Receiver, Transport::Tcp) => {
+                run_receiver::<passeri_tcp::Receiver>(&bridge, discovery.as_ref(), &stop)
+            }
+        };
+
+        let restart = match &outcome {
+            Outcome::Ended(reason) => {
+                info!("{} \"{}\" ended: {}", kind, name, reason);
+                attempt = 0;
+                bridge.restart == RestartPolicy::Always
+            }
+            Outcome::Failed(reason) => {
+                error!("{} \"{}\" failed: {}", kind, name, reason);
+                bridge.restart != RestartPolicy::Never
+            }
+            Outcome::Cancelled => false,
+        };
+        if !restart {
+            break;
+        }
+
+        let delay = bridge.retry_delay(attempt);
+        attempt += 1;
+        info!("{} \"{}\" restarting in {:?}", kind, name, delay);
+        sleep(delay, &stop);
+    }
+    info!("{} \"{}\" stopped", kind, name);
+}
+
+fn run_sender<T>(bridge: &BridgeConfig, discovery: Option<&Discovery>, stop: &AtomicBool) -> Outcome
+where
+    T: sender::Thread,
+    T::Addr: FromStr + Into<SocketAddr>,
+{
+    let Ok(addr) = T::Addr::from_str(&bridge.address) else {
+        return Outcome::Failed(format!("invalid address `{}`", bridge.address));
+    };
+    let port = match bridge.port_selector() {
+        Ok(port) => port,
+        Err(err) => return Outcome::Failed(err.to_string()),
+    };
+    let mut sender = match SenderBuilder::<T>::new(&[port], &bridge.name).listen(addr) {
+        Ok(sender) => sender,
+        Err(err) => return Outcome::Failed(err.to_string()),
+    };
+    info!("sender \"{}\" listening on {}", bridge.name, sender.info());
+    let outcome = serve_sender(&sender, bridge, discovery, stop);
+
+    // the net_thread is joined before the bridge restarts or the daemon exits, freeing its address and MIDI port
+    if !sender.is_finished() {
+        let _ = sender.stop();
+    }
+    match (outcome, sender.join()) {
+        (Some(outcome), _) => outcome,
+        (None, Ok(ret)) if ret.is_error() || matches!(ret, sender::ThreadReturn::JoinError) => {
+            Outcome::Failed(ret.to_string())
+        }
+        (None, Ok(ret)) => Outcome::Ended(ret.to_string()),
+        (None, Err(err)) => Outcome::Failed(err.to_string()),
+    }
+}
+
+/// Stream to one receiver at a time until the net_thread ends, returning `None`, or until stopped or failing
+fn serve_sender<T>(
+    sender: &sender::Sender<T>,
+    bridge: &BridgeConfig,
+    discovery: Option<&Discovery>,
+    stop: &AtomicBool,
+) -> Option<Outcome>
+where
+    T: sender::Thread,
+    T::Addr: Into<SocketAddr>,
+{
+    let lobby = sender.lobby();
+    lobby.set_key(bridge.key.as_deref());
+    bridge.allow.iter().for_each(|entry| lobby.allow(entry));
+    bridge.deny.iter().for_each(|entry| lobby.deny(entry));
+
+    let _advertisement = match discovery.filter(|_| bridge.advertise) {
+        Some(discovery) => match sender.advertise(discovery, &bridge.name) {
+            Ok(advertisement) => Some(advertisement),
+            Err(err) => return Some(Outcome::Failed(err.to_string())),
+        },
+        None => None,
+    };
+
+    // the sender goes back to waiting for a receiver each time one leaves
+    let events = sender.subscribe();
+    while !sender.is_finished() {
+        let client = loop {
+            if stop.load(Ordering::Relaxed) {
+                return Some(Outcome::Cancelled);
+            }
+            match sender.wait_for_client_timeout(WATCH_ITV) {
+                Ok(Some(client)) => break client,
+                Ok(None) => continue,
+                Err(err) => return Some(Outcome::Failed(err.to_string())),
+            }
+        };
+        info!("sender \"{}\" streaming to {}", bridge.name, client);
+        if let Err(err) = sender.send(client.addr.clone()) {
+            return Some(Outcome::Failed(err.to_string()));
+        }
+
+        while !sender.is_finished() {
+            if stop.load(Ordering::Relaxed) {
+                return Some(Outcome::Cancelled);
+            }
+            if events.try_iter().any(|event| event == Event::ClientLeft) {
+                info!("sender \"{}\": {} left", bridge.name, client);
+                break;
+            }
+            std::thread::sleep(WATCH_ITV);
+        }
+    }
+    None
+}
+
+fn run_receiver<T>(
+    bridge: &BridgeConfig,
+    discovery: Option<&Discovery>,
+    stop: &AtomicBool,
+) -> Outcome
+where
+    T: receiver::Thread,
+    T::Addr: FromStr + From<SocketAddr>,
+{
+    let addr = match (T::Addr::from_str(&bridge.address), discovery) {
+        (Ok(addr), _) => addr,
+        (Err(_), Some(discovery)) => {
+            match discovery.find(&bridge.address, DEFAULT_BROWSE_TIMEOUT) {
+                Ok(service) => match service.addr() {
+                    Some(addr) => addr.into(),
+                    None => {
+                        return Outcome::Failed(format!("{} advertised no address", service.name))
+                    }
+                },
+                Err(err) => return Outcome::Failed(err.to_string()),
+            }
+        }
+        (Err(_), None) => {
+            return Outcome::Failed(format!(
+                "unable to look for `{}`, DNS-SD is not available",
+                bridge.address
+            ))
+        }
+    };
+    let port = match bridge.port_selector() {
+        Ok(port) => port,
+        Err(err) => return Outcome::Failed(err.to_string()),
+    };
+    let mut credentials = Credentials::new(&bridge.name);
+    credentials.secret = bridge.key.clone();
+    let mut receiver = match ReceiverBuilder::<T>::new(&[port], &bridge.name)
+        .with_credentials(credentials)
+        .connect(addr)
+    {
+        Ok(receiver) => receiver,
+        Err(err) => return Outcome::Failed(err.to_string()),
+    };
+    let outcome = match receiver.receive() {
+        Ok(()) => {
+            info!(
+                "receiver \"{}\" receiving from {}",
+                bridge.name, bridge.address
+            );
+            loop {
+                if receiver.is_finished() {
+                    break None;
+                }
+                if stop.load(Ordering::Relaxed) {
+                    break Some(Outcome::Cancelled);
+                }
+                std::thread::sleep(WATCH_ITV);
+            }
+        }
+        Err(err) => Some(Outcome::Failed(err.to_string())),
+    };
+
+    // the net_thread is joined before the bridge restarts or the daemon exits, releasing the notes held
+    // and freeing the MIDI port
+    if !receiver.is_finished() {
+        let _ = receiver.stop();
+    }
+    match (outcome, receiver.join()) {
+        (Some(outcome), _) => outcome,
+        (None, Ok(ret)) if ret.is_error() || matches!(ret, receiver::ThreadReturn::JoinError) => {
+            Outcome::Failed(ret.to_string())
+        }
+        (None, Ok(ret)) => Outcome::Ended(ret.to_string()),
+        (None, Err(err)) => Outcome::Failed(err.to_string()),
+    }
+}
+
+/// Sleep for `delay`, waking up early if `stop` is set
+fn sleep(delay: Duration, stop: &AtomicBool) {
+    let deadline = Instant::now() + delay;
+    while !stop.load(Ordering::Relaxed) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        std::thread::sleep(left.min(WATCH_ITV));
+    }
+}
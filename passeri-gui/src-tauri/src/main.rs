@@ -5,12 +5,21 @@ use std::collections::HashMap;
 use std::fmt::format;
 use std::net::SocketAddr;
 use std::ops::DerefMut;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+
+/// Sender bridge, locked on its own so waiting for a client does not hold the [State]
+struct SenderBridge {
+    sender: Arc<Mutex<passeri_api::net::Sender<passeri_tcp::Sender>>>,
+    /// interrupts [sender_listen] when the bridge is removed
+    cancel: CancelHandle<SocketAddr>,
+}
 
 struct State {
-    sender: HashMap<Uuid, passeri_api::net::Sender<passeri_tcp::Sender>>,
+    sender: HashMap<Uuid, SenderBridge>,
     receiver: HashMap<Uuid, passeri_api::net::Receiver>,
     // receiver: <passeri_api::net::Receiver>,
+    discovery: Option<Discovery>,
+    advertisements: HashMap<Uuid, Advertisement>,
 }
 
 impl State {
@@ -19,12 +28,17 @@ impl State {
             sender: HashMap::new(),
             receiver: HashMap::new(),
             // receiver: vec![],
+            discovery: Discovery::new().ok(),
+            advertisements: HashMap::new(),
         }
     }
 }
 
 use std::str::FromStr;
 
+use passeri_api::discovery::{Advertisement, Discovery, DEFAULT_BROWSE_TIMEOUT};
+use passeri_api::midi::PortSelector;
+use passeri_api::net::sender::CancelHandle;
 use uuid::Uuid;
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
@@ -33,32 +47,76 @@ fn new_bridge(
     locked_state: tauri::State<Mutex<State>>,
     bridge_type: u8,
     addr: String,
+    midi_port: String,
     midi_port_name: String,
 ) -> Result<(String, String), String> {
-    let addr = SocketAddr::from_str(&addr).map_err(|err| format!("{}", err))?;
+    let addr = match (SocketAddr::from_str(&addr), bridge_type) {
+        (Ok(addr), _) => addr,
+        // a receiver can be given the name of an advertised sender
+        (Err(_), 1) => {
+            let discovery = locked_state
+                .lock()
+                .unwrap()
+                .discovery
+                .clone()
+                .ok_or(format!("DNS-SD is not available"))?;
+            discovery
+                .find(&addr, DEFAULT_BROWSE_TIMEOUT)
+                .map_err(|err| format!("{}", err))?
+                .addr()
+                .ok_or(format!("{} advertised no address", addr))?
+        }
+        (Err(err), _) => return Err(format!("{}", err)),
+    };
+    let midi_port = match midi_port.as_str() {
+        "" => PortSelector::Index(0),
+        selector => PortSelector::from_str(selector).map_err(|err| format!("{}", err))?,
+    };
 
     let id = Uuid::new_v4();
 
     match bridge_type {
         0 => {
             // Sender
-            let sender = passeri_api::new_sender(0, &midi_port_name, addr)
-                .map_err(|err| format!("{}", err))?;
+            let sender = passeri_api::SenderBuilder::<passeri_tcp::Sender>::new(
+                &[midi_port],
+                &midi_port_name,
+            )
+            .listen(addr)
+            .map_err(|err| format!("{}", err))?;
 
             let addr = sender.info();
 
             let mut state = locked_state.lock().unwrap();
 
             let mut_state = state.deref_mut();
-            mut_state.sender.insert(id, sender);
+            if let Some(discovery) = mut_state.discovery.as_ref() {
+                if !midi_port_name.is_empty() {
+                    let advertisement = sender
+                        .advertise(discovery, &midi_port_name)
+                        .map_err(|err| format!("{}", err))?;
+                    mut_state.advertisements.insert(id, advertisement);
+                }
+            }
+            let cancel = sender.cancel_handle();
+            mut_state.sender.insert(
+                id,
+                SenderBridge {
+                    sender: Arc::new(Mutex::new(sender)),
+                    cancel,
+                },
+            );
 
             Ok((id.to_string(), addr))
         }
         _ => {
             // Receiver
-            let receiver =
-                passeri_api::new_receiver::<passeri_tcp::Receiver>(0, &midi_port_name, addr)
-                    .map_err(|err| format!("{}", err))?;
+            let receiver = passeri_api::ReceiverBuilder::<passeri_tcp::Receiver>::new(
+                &[midi_port],
+                &midi_port_name,
+            )
+            .connect(addr)
+            .map_err(|err| format!("{}", err))?;
 
             let addr = receiver.info();
 
@@ -74,23 +132,25 @@ fn new_bridge(
 
 #[tauri::command]
 fn sender_listen(locked_state: tauri::State<Mutex<State>>, uuid: String) -> Result<(), String> {
-    let mut state = locked_state.lock().unwrap();
+    let id = Uuid::from_str(&uuid).map_err(|err| format!("{}", err))?;
 
-    let mut_state = state.deref_mut();
-
-    let sender = mut_state
+    // only the bridge stays locked while waiting, letting the other commands run
+    let sender = locked_state
+        .lock()
+        .unwrap()
         .sender
-        .get_mut(&Uuid::from_str(&uuid).map_err(|err| format!("{}", err))?)
+        .get(&id)
+        .map(|bridge| bridge.sender.clone())
         .ok_or(format!("not found"))?;
+    let sender = sender.lock().unwrap();
 
-    let addr = sender
+    let client = sender
         .wait_for_client()
         .map_err(|err| format!("err: {}", err))?;
 
-    match sender.send(addr) {
-        Ok(_) => Ok(()),
-        Err(err) => Err(format!("err: {}", err)),
-    }
+    sender
+        .send(client.addr)
+        .map_err(|err| format!("err: {}", err))
 }
 
 #[tauri::command]
@@ -112,9 +172,12 @@ fn remove_sender(locked_state: tauri::State<Mutex<State>>, uuid: String) -> Resu
     let mut state = locked_state.lock().unwrap();
 
     let mut_state = state.deref_mut();
-    mut_state
-        .sender
-        .remove(&Uuid::from_str(&uuid).map_err(|err| format!("{}", err))?);
+    let id = Uuid::from_str(&uuid).map_err(|err| format!("{}", err))?;
+    mut_state.advertisements.remove(&id);
+    if let Some(bridge) = mut_state.sender.remove(&id) {
+        // a pending sender_listen gives the sender back, dropping it
+        let _ = bridge.cancel.cancel();
+    }
     Ok(())
 }
 
@@ -129,6 +192,28 @@ fn remove_receiver(locked_state: tauri::State<Mutex<State>>, uuid: String) -> Re
     Ok(())
 }
 
+#[tauri::command]
+fn discover_senders(
+    locked_state: tauri::State<Mutex<State>>,
+) -> Result<Vec<(String, String, String)>, String> {
+    let discovery = locked_state
+        .lock()
+        .unwrap()
+        .discovery
+        .clone()
+        .ok_or(format!("DNS-SD is not available"))?;
+
+    Ok(discovery
+        .browse(DEFAULT_BROWSE_TIMEOUT)
+        .map_err(|err| format!("{}", err))?
+        .into_iter()
+        .filter_map(|service| {
+            let addr = service.addr()?;
+            Some((service.name, addr.to_string(), service.midi_port))
+        })
+        .collect())
+}
+
 fn main() {
     tauri::Builder::default()
         .manage(Mutex::new(State::new()))
@@ -137,7 +222,8 @@ fn main() {
             sender_listen,
             receiver_receive,
             remove_sender,
-            remove_receiver
+            remove_receiver,
+            discover_senders
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
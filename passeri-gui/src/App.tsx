@@ -33,8 +33,10 @@ export class Receiver {
 function App() {
   const [addr, setAddr] = useState("");
   const [name, setName] = useState("");
+  const [port, setPort] = useState("");
   const [senders, setSenders] = useState<Array<Sender>>([]);
   const [receivers, setReceivers] = useState<Array<Receiver>>([]);
+  const [discovered, setDiscovered] = useState<Array<Array<string>>>([]);
 
   //   async function greet() {
   //     // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
@@ -46,6 +48,7 @@ function App() {
     await invoke<Array<string>>("new_bridge", {
       bridgeType: type as number,
       addr,
+      midiPort: port,
       midiPortName: name,
     })
       .then((resp) => {
@@ -57,6 +60,17 @@ function App() {
         console.log(resp);
         setAddr("");
         setName("");
+        setPort("");
+      })
+      .catch((err) => {
+        console.log(err);
+      });
+  }
+
+  async function discover_senders() {
+    await invoke<Array<Array<string>>>("discover_senders")
+      .then((resp) => {
+        setDiscovered(resp);
       })
       .catch((err) => {
         console.log(err);
@@ -103,6 +117,14 @@ function App() {
     </li>
   ));
 
+  const discovered_list = discovered.map(([name, senderAddr, midiPort]) => (
+    <li key={name}>
+      <button onClick={() => setAddr(name)}>
+        {name} ({senderAddr}, {midiPort})
+      </button>
+    </li>
+  ));
+
   return (
     <div className="container">
       <h1>Welcome to Tauri!</h1>
@@ -110,15 +132,22 @@ function App() {
       <input
         value={addr}
         onChange={(e) => setAddr(e.currentTarget.value)}
-        placeholder="Enter an address"
+        placeholder="Enter an address (or a sender name)"
       />
       <input
         value={name}
         onChange={(e) => setName(e.currentTarget.value)}
         placeholder="Enter a name..."
       />
+      <input
+        value={port}
+        onChange={(e) => setPort(e.currentTarget.value)}
+        placeholder="MIDI port (index, name, id:..., re:...)"
+      />
       <button onClick={() => new_bridge(BridgeType.Sender)}>Sender</button>
       <button onClick={() => new_bridge(BridgeType.Receiver)}>Receiver</button>
+      <button onClick={() => discover_senders()}>Discover senders</button>
+      <ul>{discovered_list}</ul>
       <ul>{sender_list}</ul>
       <ul>{receiver_list}</ul>
     </div>
@@ -0,0 +1,745 @@
+#![warn(missing_docs)]
+//! Relay pairing the senders and receivers unable to reach each other, e.g. both behind NAT
+//!
+//! Both peers connect to the relay, which pairs a receiver with a sender waiting in the same room
+//! (see [RelayMessage]) then forwards the bytes of each peer to the other, within a rate limit.
+//! Peers can also enter a room as named [members](passeri_api::net::room), the relay announcing to every
+//! member the ones joining and leaving it.
+
+use log::{debug, info, warn};
+use passeri_api::net::relay::{RelayMessage, Ticket};
+use passeri_api::net::room::Member;
+use passeri_api::{Error, TransportError};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+mod limit;
+pub use limit::{ConnectionLimit, TokenBucket};
+
+/// Interval at which a connection waiting for a receiver or announcing the members of a room is checked,
+/// so the ones closed by their peer are forgotten
+const LIVENESS_ITV: Duration = Duration::from_millis(500);
+
+/// Options of the [Relay]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayConfig {
+    /// bytes forwarded per second in each direction of a pair, `0` for no limit
+    pub max_rate: u32,
+    /// connections accepted per minute from the same address, `0` for no limit
+    pub connections_per_minute: u32,
+    /// time given to a peer to introduce itself, and to a receiver to find a waiting sender
+    pub handshake_timeout: Duration,
+    /// connections served at the same time, `0` for no limit
+    pub max_connections: usize,
+    /// time after which a pair forwarding no byte in either direction is closed, [Duration::ZERO] for no limit
+    pub idle_timeout: Duration,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        RelayConfig {
+            max_rate: 64 * 1024,
+            connections_per_minute: 60,
+            handshake_timeout: Duration::from_secs(5),
+            max_connections: 1024,
+            // the heartbeat of the peers keeps their pair busy
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+//
+//	ROOMS
+//
+
+/// Connection of a receiver, sent to the sender connection it is paired with
+type Pairing = (TcpStream, SocketAddr, Slot);
+
+/// Rooms are told apart by their name and token, so no peer can claim a room or lock others out of it
+type RoomKey = (String, Option<String>);
+
+fn key(ticket: &Ticket) -> RoomKey {
+    (ticket.room.clone(), ticket.token.clone())
+}
+
+/// Sender connection waiting for a receiver
+struct Host {
+    id: u64,
+    tx: mpsc::Sender<Pairing>,
+}
+
+/// Member of a room, announced the other members joining and leaving through `tx`
+struct Attendee {
+    id: u64,
+    member: Member,
+    tx: mpsc::Sender<RelayMessage>,
+}
+
+/// Peers of a room
+#[derive(Default)]
+struct Room {
+    /// sender connections waiting for a receiver, oldest first
+    hosts: VecDeque<Host>,
+    /// pairs forwarding their bytes
+    pairs: usize,
+    /// members of the room, in the order they entered it
+    members: Vec<Attendee>,
+}
+
+impl Room {
+    /// Announce `message` to every member, the ones that just left being forgotten when they exit
+    fn announce(&self, message: &RelayMessage) {
+        for attendee in self.members.iter() {
+            let _ = attendee.tx.send(message.clone());
+        }
+    }
+}
+
+/// Rooms of a relay, living as long as one of their connections
+#[derive(Default)]
+struct Rooms {
+    rooms: Mutex<HashMap<RoomKey, Room>>,
+    /// notified whenever a sender connection waits for a receiver
+    hosted: Condvar,
+    next_id: AtomicU64,
+}
+
+impl Rooms {
+    fn lock(&self) -> MutexGuard<'_, HashMap<RoomKey, Room>> {
+        self.rooms.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Add a sender connection to its room, creating it if needed
+    fn host(&self, ticket: &Ticket) -> (u64, mpsc::Receiver<Pairing>) {
+        let mut rooms = self.lock();
+        let room = rooms.entry(key(ticket)).or_default();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel();
+        room.hosts.push_back(Host { id, tx });
+        self.hosted.notify_all();
+        (id, rx)
+    }
+
+    /// Pair a receiver with the oldest sender connection of its room, waiting up to `timeout` for one
+    fn join(&self, ticket: &Ticket, mut pairing: Pairing, timeout: Duration) -> Result<(), String> {
+        let deadline = Instant::now() + timeout;
+        let mut rooms = self.lock();
+        loop {
+            // the sender may not have hosted the room yet
+            let room = rooms.get_mut(&key(ticket));
+            let opened = room.is_some();
+            if let Some(room) = room {
+                while let Some(host) = room.hosts.pop_front() {
+                    match host.tx.send(pairing) {
+                        Ok(()) => {
+                            room.pairs += 1;
+                            return Ok(());
+                        }
+                        // the sender connection just closed
+                        Err(mpsc::SendError(unpaired)) => pairing = unpaired,
+                    }
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(match opened {
+                    true => format!("no sender waiting in {}", ticket),
+                    false => format!("no sender in {}", ticket),
+                });
+            }
+            rooms = self
+                .hosted
+                .wait_timeout(rooms, deadline - now)
+                .unwrap_or_else(|err| err.into_inner())
+                .0;
+        }
+    }
+
+    /// Add a member to its room, creating it if needed, returning the other members and the announcements to send it
+    fn enter(
+        &self,
+        ticket: &Ticket,
+        member: Member,
+    ) -> Result<(u64, Vec<Member>, mpsc::Receiver<RelayMessage>), String> {
+        let mut rooms = self.lock();
+        let room = rooms.entry(key(ticket)).or_default();
+        if room
+            .members
+            .iter()
+            .any(|attendee| attendee.member.name == member.name)
+        {
+            return Err(format!("\"{}\" is already in {}", member.name, ticket));
+        }
+        let others = room
+            .members
+            .iter()
+            .map(|attendee| attendee.member.clone())
+            .collect();
+        room.announce(&RelayMessage::Joined(member.clone()));
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel();
+        room.members.push(Attendee { id, member, tx });
+        Ok((id, others, rx))
+    }
+
+    /// Forget a member that closed its connection, announcing it to the other members,
+    /// and the room once it has no connection left
+    fn exit(&self, ticket: &Ticket, id: u64) {
+        let mut rooms = self.lock();
+        if let Some(room) = rooms.get_mut(&key(ticket)) {
+            if let Some(index) = room.members.iter().position(|attendee| attendee.id == id) {
+                let attendee = room.members.remove(index);
+                room.announce(&RelayMessage::Left(attendee.member.name));
+            }
+        }
+        self.forget_empty(&mut rooms, ticket);
+    }
+
+    /// Forget a sender connection that stopped waiting, and the room once it has no connection left
+    fn leave(&self, ticket: &Ticket, id: u64) {
+        let mut rooms = self.lock();
+        if let Some(room) = rooms.get_mut(&key(ticket)) {
+            room.hosts.retain(|host| host.id != id);
+        }
+        self.forget_empty(&mut rooms, ticket);
+    }
+
+    /// Forget a pair that stopped forwarding, and the room once it has no connection left
+    fn unpair(&self, ticket: &Ticket) {
+        let mut rooms = self.lock();
+        if let Some(room) = rooms.get_mut(&key(ticket)) {
+            room.pairs -= 1;
+        }
+        self.forget_empty(&mut rooms, ticket);
+    }
+
+    fn forget_empty(&self, rooms: &mut HashMap<RoomKey, Room>, ticket: &Ticket) {
+        let key = key(ticket);
+        if rooms
+            .get(&key)
+            .is_some_and(|room| room.hosts.is_empty() && room.pairs == 0 && room.members.is_empty())
+        {
+            debug!("{} closed", ticket);
+            rooms.remove(&key);
+        }
+    }
+}
+
+/// Place of a connection among the [RelayConfig::max_connections], given back once it is dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    /// Take a place among the `max` ones counted by `served`, returning `None` if they are all taken
+    fn take(served: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        served
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (max == 0 || count < max).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Slot(served.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Connection read until `deadline`, however slowly its peer trickles the bytes
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+//
+//	RELAY
+//
+
+/// Relay server, each connection being served by its own thread
+pub struct Relay {
+    listener: TcpListener,
+    config: RelayConfig,
+    rooms: Arc<Rooms>,
+    /// connections being served
+    served: Arc<AtomicUsize>,
+}
+
+impl Relay {
+    /// Listen for the peers on `addr`
+    pub fn bind(addr: SocketAddr, config: RelayConfig) -> Result<Self, Error> {
+        let listener = TcpListener::bind(addr).map_err(|source| TransportError::Bind {
+            addr: addr.to_string(),
+            source,
+        })?;
+        Ok(Relay {
+            listener,
+            config,
+            rooms: Arc::default(),
+            served: Arc::default(),
+        })
+    }
+
+    /// Address the relay listens on
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self
+            .listener
+            .local_addr()
+            .map_err(TransportError::Configure)?)
+    }
+
+    /// Accept the peers until the listener fails
+    pub fn run(&self) -> Result<(), Error> {
+        let mut limit = ConnectionLimit::new(self.config.connections_per_minute);
+        loop {
+            let (mut stream, addr) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(TransportError::Accept(err).into()),
+            };
+            if !limit.accept(addr.ip(), Instant::now()) {
+                warn!("too many connections from {}, refused", addr.ip());
+                let _ = RelayMessage::Refused("too many connections".into()).write_to(&mut stream);
+                continue;
+            }
+            let Some(slot) = Slot::take(&self.served, self.config.max_connections) else {
+                warn!("relay full, {} refused", addr);
+                let _ = RelayMessage::Refused("relay full".into()).write_to(&mut stream);
+                continue;
+            };
+            let config = self.config;
+            let rooms = self.rooms.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = serve(stream, addr, slot, config, &rooms) {
+                    debug!("{}: {}", addr, err);
+                }
+            });
+        }
+    }
+}
+
+/// Serve a peer until it is paired and its pair ends, or until it leaves the room it entered as a member
+fn serve(
+    stream: TcpStream,
+    addr: SocketAddr,
+    slot: Slot,
+    config: RelayConfig,
+    rooms: &Rooms,
+) -> Result<(), Error> {
+    let mut intro = Deadline {
+        stream: &stream,
+        deadline: Instant::now() + config.handshake_timeout,
+    };
+    match RelayMessage::read_from(&mut intro)? {
+        RelayMessage::Host(ticket) => {
+            let (id, rx) = rooms.host(&ticket);
+            debug!("{} waits for a receiver in {}", addr, ticket);
+            let paired = wait(&stream, &rx);
+            rooms.leave(&ticket, id);
+            let Some(receiver) = paired? else {
+                return Ok(());
+            };
+            let forwarded = forward((stream, addr, slot), receiver, &ticket, config);
+            rooms.unpair(&ticket);
+            forwarded
+        }
+        RelayMessage::Join(ticket) => {
+            // the slot is given back once the pair ends
+            match rooms.join(
+                &ticket,
+                (
+                    stream.try_clone().map_err(TransportError::Configure)?,
+                    addr,
+                    slot,
+                ),
+                config.handshake_timeout,
+            ) {
+                Ok(()) => Ok(()),
+                Err(reason) => refuse(stream, addr, reason),
+            }
+        }
+        RelayMessage::Enter(ticket, member) => {
+            match check_addr(&member, addr).and_then(|()| rooms.enter(&ticket, member.clone())) {
+                Ok((id, others, rx)) => {
+                    info!("{} entered {} as {}", addr, ticket, member);
+                    let attended = attend(stream, others, &rx);
+                    rooms.exit(&ticket, id);
+                    info!("{} left {}", member, ticket);
+                    attended
+                }
+                Err(reason) => refuse(stream, addr, reason),
+            }
+        }
+        message => refuse(stream, addr, format!("unexpected {:?}", message)),
+    }
+}
+
+/// Check that a sender member announces an address on the host of its connection,
+/// so no member can send the receivers of the room to another host
+fn check_addr(member: &Member, peer: SocketAddr) -> Result<(), String> {
+    let Some(announced) = member.addr.as_deref() else {
+        return Ok(());
+    };
+    match announced.parse::<SocketAddr>() {
+        Ok(listen) if listen.ip().to_canonical() == peer.ip().to_canonical() => Ok(()),
+        _ => Err(format!(
+            "{} announces {}, not on its host {}",
+            member,
+            announced,
+            peer.ip()
+        )),
+    }
+}
+
+fn refuse(mut stream: TcpStream, addr: SocketAddr, reason: String) -> Result<(), Error> {
+    info!("{} refused: {}", addr, reason);
+    RelayMessage::Refused(reason).write_to(&mut stream)
+}
+
+/// Wait for a receiver to be paired with a sender connection, returning `None` if the sender closes it first
+fn wait(stream: &TcpStream, rx: &mpsc::Receiver<Pairing>) -> Result<Option<Pairing>, Error> {
+    loop {
+        match rx.recv_timeout(LIVENESS_ITV) {
+            Ok(receiver) => return Ok(Some(receiver)),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(None),
+        }
+        if is_closed(stream)? {
+            return Ok(None);
+        }
+    }
+}
+
+/// Send the `others` members of the room to a member that entered it, then announce the members joining and
+/// leaving until it closes its connection
+fn attend(
+    mut stream: TcpStream,
+    others: Vec<Member>,
+    rx: &mpsc::Receiver<RelayMessage>,
+) -> Result<(), Error> {
+    RelayMessage::Members(others).write_to(&mut stream)?;
+    loop {
+        match rx.recv_timeout(LIVENESS_ITV) {
+            Ok(announcement) => announcement.write_to(&mut stream)?,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if is_closed(&stream)? {
+                    return Ok(());
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+/// Return `true` if the peer closed a connection it is not expected to talk on
+fn is_closed(stream: &TcpStream) -> Result<bool, Error> {
+    stream
+        .set_nonblocking(true)
+        .map_err(TransportError::Configure)?;
+    let peeked = stream.peek(&mut [0]);
+    stream
+        .set_nonblocking(false)
+        .map_err(TransportError::Configure)?;
+    match peeked {
+        Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+        // closed, or talking out of turn
+        Ok(_) | Err(_) => Ok(true),
+    }
+}
+
+/// Introduce the paired peers to each other, then forward their bytes until one of them closes its connection
+fn forward(
+    sender: Pairing,
+    receiver: Pairing,
+    ticket: &Ticket,
+    config: RelayConfig,
+) -> Result<(), Error> {
+    let ((mut sender, sender_addr, _sender_slot), (mut receiver, receiver_addr, _receiver_slot)) =
+        (sender, receiver);
+    info!(
+        "{} paired with {} in {}",
+        receiver_addr, sender_addr, ticket
+    );
+    RelayMessage::Paired(receiver_addr.to_string()).write_to(&mut sender)?;
+    RelayMessage::Paired(sender_addr.to_string()).write_to(&mut receiver)?;
+    let idle_timeout = (!config.idle_timeout.is_zero()).then_some(config.idle_timeout);
+    for stream in [&sender, &receiver] {
+        stream
+            .set_read_timeout(idle_timeout)
+            .map_err(TransportError::Configure)?;
+        // MIDI messages are small and latency sensitive
+        stream
+            .set_nodelay(true)
+            .map_err(TransportError::Configure)?;
+    }
+
+    let upstream = (
+        receiver.try_clone().map_err(TransportError::Configure)?,
+        sender.try_clone().map_err(TransportError::Configure)?,
+    );
+    let activity = Arc::new(Mutex::new(Instant::now()));
+    let upstream_activity = activity.clone();
+    let thread =
+        std::thread::spawn(move || pipe(upstream.0, upstream.1, config, &upstream_activity));
+    pipe(sender, receiver, config, &activity);
+    let _ = thread.join();
+    info!("{} left {}", receiver_addr, ticket);
+    Ok(())
+}
+
+/// Copy the bytes read from `from` to `to`, at most [RelayConfig::max_rate] bytes per second, then close both
+/// connections. They are also closed once `activity`, the last time a byte was forwarded in either direction
+/// of the pair, is older than the [RelayConfig::idle_timeout].
+fn pipe(mut from: TcpStream, mut to: TcpStream, config: RelayConfig, activity: &Mutex<Instant>) {
+    let max_rate = config.max_rate;
+    let mut bucket =
+        (max_rate > 0).then(|| TokenBucket::new(max_rate as f64, max_rate as f64, Instant::now()));
+    let mut buf = [0; 1024];
+    loop {
+        let len = match from.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            // only idle if the other direction is too
+            Err(err)
+                if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                    && last_activity(activity).elapsed() < config.idle_timeout =>
+            {
+                continue
+            }
+            Err(_) => break,
+        };
+        *last_activity(activity) = Instant::now();
+        if let Some(bucket) = bucket.as_mut() {
+            // the peer is slowed down by the TCP flow control while the bytes are held
+            std::thread::sleep(bucket.take(len as f64, Instant::now()));
+        }
+        if to.write_all(&buf[..len]).is_err() {
+            break;
+        }
+    }
+    // ends the copy of the other direction
+    let _ = from.shutdown(Shutdown::Both);
+    let _ = to.shutdown(Shutdown::Both);
+}
+
+fn last_activity(activity: &Mutex<Instant>) -> MutexGuard<'_, Instant> {
+    activity.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn(config: RelayConfig) -> SocketAddr {
+        let relay = Relay::bind("127.0.0.1:0".parse().unwrap(), config).unwrap();
+        let addr = relay.local_addr().unwrap();
+        std::thread::spawn(move || relay.run());
+        addr
+    }
+
+    fn connect(relay: SocketAddr, message: RelayMessage) -> TcpStream {
+        let mut stream = TcpStream::connect(relay).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        message.write_to(&mut stream).unwrap();
+        stream
+    }
+
+    fn read(stream: &mut TcpStream) -> RelayMessage {
+        RelayMessage::read_from(stream).unwrap()
+    }
+
+    #[test]
+    fn test_pair() {
+        let relay = spawn(RelayConfig::default());
+        let ticket = Ticket::new("jam").with_token("s3cr3t");
+        let mut sender = connect(relay, RelayMessage::Host(ticket.clone()));
+        let mut receiver = connect(relay, RelayMessage::Join(ticket));
+
+        let receiver_addr = receiver.local_addr().unwrap().to_string();
+        assert_eq!(read(&mut sender), RelayMessage::Paired(receiver_addr));
+        assert!(matches!(read(&mut receiver), RelayMessage::Paired(_)));
+        sender.write_all(b"hello").unwrap();
+        receiver.write_all(b"hi").unwrap();
+        let mut buf = [0; 5];
+        receiver.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        sender.read_exact(&mut buf[..2]).unwrap();
+        assert_eq!(&buf[..2], b"hi");
+
+        // the other peer is disconnected when one of them leaves
+        drop(receiver);
+        assert_eq!(sender.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_refuse() {
+        let relay = spawn(RelayConfig {
+            handshake_timeout: Duration::from_millis(200),
+            ..RelayConfig::default()
+        });
+        let ticket = Ticket::new("jam").with_token("1234");
+        let sender = connect(relay, RelayMessage::Host(ticket.clone()));
+        let mut squatters = vec![];
+        for other in [Ticket::new("jam"), Ticket::new("jam").with_token("0000")] {
+            let mut stream = connect(relay, RelayMessage::Join(other.clone()));
+            assert_eq!(
+                read(&mut stream),
+                RelayMessage::Refused("no sender in room \"jam\"".into())
+            );
+            // nor can another sender take over the room, waiting in a room of its own
+            squatters.push(connect(relay, RelayMessage::Host(other)));
+        }
+        let mut stream = connect(relay, RelayMessage::Join(Ticket::new("rehearsal")));
+        assert_eq!(
+            read(&mut stream),
+            RelayMessage::Refused("no sender in room \"rehearsal\"".into())
+        );
+
+        // the only sender connection of the room is taken
+        let mut first = connect(relay, RelayMessage::Join(ticket.clone()));
+        assert_eq!(
+            read(&mut first),
+            RelayMessage::Paired(sender.local_addr().unwrap().to_string())
+        );
+        let mut second = connect(relay, RelayMessage::Join(ticket));
+        assert_eq!(
+            read(&mut second),
+            RelayMessage::Refused("no sender waiting in room \"jam\"".into())
+        );
+    }
+
+    #[test]
+    fn test_members() {
+        let relay = spawn(RelayConfig::default());
+        let room = Ticket::new("jam").with_token("s3cr3t");
+        let alice = Member::sender("alice", None);
+        let bob = Member::receiver("bob");
+        let mut first = connect(relay, RelayMessage::Enter(room.clone(), alice.clone()));
+        assert_eq!(read(&mut first), RelayMessage::Members(vec![]));
+        let mut second = connect(relay, RelayMessage::Enter(room.clone(), bob.clone()));
+        assert_eq!(
+            read(&mut second),
+            RelayMessage::Members(vec![alice.clone()])
+        );
+        assert_eq!(read(&mut first), RelayMessage::Joined(bob));
+
+        // names are unique in a room, told apart from the ones with another token
+        let mut stream = connect(relay, RelayMessage::Enter(room.clone(), alice.clone()));
+        assert_eq!(
+            read(&mut stream),
+            RelayMessage::Refused("\"alice\" is already in room \"jam\"".into())
+        );
+        let mut stream = connect(relay, RelayMessage::Enter(Ticket::new("jam"), alice));
+        assert_eq!(read(&mut stream), RelayMessage::Members(vec![]));
+
+        // the members closing their connection are announced leaving
+        drop(second);
+        assert_eq!(read(&mut first), RelayMessage::Left("bob".into()));
+
+        // a sender can only send the receivers to its own host
+        let carol = Member::sender("carol", Some("192.0.2.1:8080"));
+        let mut stream = connect(relay, RelayMessage::Enter(room.clone(), carol));
+        assert_eq!(
+            read(&mut stream),
+            RelayMessage::Refused(
+                "sender \"carol\" announces 192.0.2.1:8080, not on its host 127.0.0.1".into()
+            )
+        );
+        let carol = Member::sender("carol", Some("127.0.0.1:8080"));
+        let mut stream = connect(relay, RelayMessage::Enter(room, carol.clone()));
+        assert!(matches!(read(&mut stream), RelayMessage::Members(_)));
+        assert_eq!(read(&mut first), RelayMessage::Joined(carol));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let relay = spawn(RelayConfig {
+            max_rate: 1000,
+            connections_per_minute: 2,
+            ..RelayConfig::default()
+        });
+        let ticket = Ticket::new("jam");
+        let mut sender = connect(relay, RelayMessage::Host(ticket.clone()));
+        let mut receiver = connect(relay, RelayMessage::Join(ticket.clone()));
+        read(&mut sender);
+        read(&mut receiver);
+        let mut third = connect(relay, RelayMessage::Join(ticket));
+        assert_eq!(
+            read(&mut third),
+            RelayMessage::Refused("too many connections".into())
+        );
+
+        // a burst of a second, then 1000 bytes per second
+        let start = Instant::now();
+        sender.write_all(&[0xF8; 1500]).unwrap();
+        receiver.read_exact(&mut [0; 1500]).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_limits() {
+        let relay = spawn(RelayConfig {
+            max_connections: 2,
+            handshake_timeout: Duration::from_millis(200),
+            idle_timeout: Duration::from_millis(300),
+            ..RelayConfig::default()
+        });
+        let ticket = Ticket::new("jam");
+        let mut sender = connect(relay, RelayMessage::Host(ticket.clone()));
+        let mut receiver = connect(relay, RelayMessage::Join(ticket.clone()));
+        read(&mut sender);
+        read(&mut receiver);
+        let mut third = connect(relay, RelayMessage::Join(ticket.clone()));
+        assert_eq!(read(&mut third), RelayMessage::Refused("relay full".into()));
+
+        // a pair forwarding nothing is closed, giving its connections back
+        assert_eq!(sender.read(&mut [0]).unwrap(), 0);
+        assert_eq!(receiver.read(&mut [0]).unwrap(), 0);
+        let refused = (0..20)
+            .map(|_| {
+                std::thread::sleep(Duration::from_millis(50));
+                read(&mut connect(relay, RelayMessage::Join(ticket.clone())))
+            })
+            .find(|refused| *refused != RelayMessage::Refused("relay full".into()));
+        assert_eq!(
+            refused,
+            Some(RelayMessage::Refused("no sender in room \"jam\"".into()))
+        );
+
+        // a peer trickling its introduction is closed once the handshake timeout is over
+        let mut intro = vec![];
+        RelayMessage::Enter(ticket, Member::receiver(&"x".repeat(100)))
+            .write_to(&mut intro)
+            .unwrap();
+        let mut slow = TcpStream::connect(relay).unwrap();
+        slow.set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        let sent = intro.iter().position(|byte| {
+            std::thread::sleep(Duration::from_millis(20));
+            let closed = match slow.read(&mut [0]) {
+                Ok(len) => len == 0,
+                Err(err) => !matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+            };
+            closed || slow.write_all(&[*byte]).is_err()
+        });
+        assert!(sent.is_some_and(|sent| sent < 50));
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Number of addresses tracked by a [ConnectionLimit] above which the idle ones are forgotten
+const MAX_TRACKED: usize = 1024;
+
+//
+//	TOKEN BUCKET
+//
+
+/// Rate limit allowing bursts of `capacity` units, refilled at `rate` units per second
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// Create a new bucket, full
+    pub fn new(capacity: f64, rate: f64, now: Instant) -> Self {
+        TokenBucket {
+            capacity,
+            rate,
+            tokens: capacity,
+            last: now,
+        }
+    }
+
+    /// Take `amount` units if available at `now`, returning `false` otherwise
+    pub fn try_take(&mut self, amount: f64, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < amount {
+            return false;
+        }
+        self.tokens -= amount;
+        true
+    }
+
+    /// Take `amount` units, returning the time to wait before using them
+    pub fn take(&mut self, amount: f64, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= amount;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    /// Return `true` if the bucket is full at `now`, the units taken so far making no difference anymore
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+}
+
+//
+//	CONNECTION LIMIT
+//
+
+/// Number of connections accepted per minute from each address
+#[derive(Debug)]
+pub struct ConnectionLimit {
+    per_minute: u32,
+    buckets: HashMap<IpAddr, TokenBucket>,
+}
+
+impl ConnectionLimit {
+    /// Create a new limit, `0` accepting any number of connections
+    pub fn new(per_minute: u32) -> Self {
+        ConnectionLimit {
+            per_minute,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Return `true` if a new connection from `addr` is accepted at `now`
+    pub fn accept(&mut self, addr: IpAddr, now: Instant) -> bool {
+        if self.per_minute == 0 {
+            return true;
+        }
+        if self.buckets.len() >= MAX_TRACKED {
+            self.buckets.retain(|_, bucket| !bucket.is_full(now));
+        }
+        let per_minute = self.per_minute as f64;
+        self.buckets
+            .entry(addr)
+            .or_insert_with(|| TokenBucket::new(per_minute, per_minute / 60.0, now))
+            .try_take(1.0, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(100.0, 50.0, now);
+        assert_eq!(bucket.take(80.0, now), Duration::ZERO);
+        assert!(!bucket.try_take(30.0, now));
+        // 50 units are owed, paid in a second
+        assert_eq!(bucket.take(70.0, now), Duration::from_secs(1));
+        assert!(bucket.try_take(25.0, now + Duration::from_millis(1500)));
+        assert!(!bucket.is_full(now + Duration::from_secs(3)));
+        assert!(bucket.is_full(now + Duration::from_secs(4)));
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let now = Instant::now();
+        let mut limit = ConnectionLimit::new(2);
+        let [host, other] = ["192.0.2.1", "192.0.2.2"].map(|ip| ip.parse().unwrap());
+        assert!(limit.accept(host, now));
+        assert!(limit.accept(host, now));
+        assert!(!limit.accept(host, now));
+        assert!(limit.accept(other, now));
+        // one connection every 30 seconds
+        assert!(limit.accept(host, now + Duration::from_secs(30)));
+        assert!(ConnectionLimit::new(0).accept(host, now));
+    }
+}
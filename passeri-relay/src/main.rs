@@ -0,0 +1,63 @@
+//! `passeri-relay` server, pairing the senders and receivers unable to reach each other
+use clap::Parser;
+use log::{error, info};
+use passeri_relay::{Relay, RelayConfig};
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(
+    name = "passeri-relay",
+    version,
+    about = "Pair Passeri senders and receivers behind NAT and forward their streams"
+)]
+struct Cli {
+    /// address to listen on
+    #[arg(default_value = "0.0.0.0:7890")]
+    addr: SocketAddr,
+
+    /// bytes forwarded per second in each direction of a pair, 0 for no limit
+    #[arg(long, default_value_t = RelayConfig::default().max_rate)]
+    max_rate: u32,
+
+    /// connections accepted per minute from the same address, 0 for no limit
+    #[arg(long, default_value_t = RelayConfig::default().connections_per_minute)]
+    connections_per_minute: u32,
+
+    /// time given to a peer to introduce itself, and to a receiver to find a waiting sender, in milliseconds
+    #[arg(long, default_value_t = 5000)]
+    handshake_timeout: u64,
+
+    /// connections served at the same time, 0 for no limit
+    #[arg(long, default_value_t = RelayConfig::default().max_connections)]
+    max_connections: usize,
+
+    /// time after which a pair forwarding nothing is closed, in seconds, 0 for no limit
+    #[arg(long, default_value_t = RelayConfig::default().idle_timeout.as_secs())]
+    idle_timeout: u64,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let config = RelayConfig {
+        max_rate: cli.max_rate,
+        connections_per_minute: cli.connections_per_minute,
+        handshake_timeout: Duration::from_millis(cli.handshake_timeout),
+        max_connections: cli.max_connections,
+        idle_timeout: Duration::from_secs(cli.idle_timeout),
+    };
+    let result = Relay::bind(cli.addr, config).and_then(|relay| {
+        info!("relay listening on {}", relay.local_addr()?);
+        relay.run()
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,48 @@
+#![warn(missing_docs)]
+//! Implementation of the Sender and Receiver traits from `passeri-api` over a serial link
+//!
+//! The link is a serial device, e.g. a USB-serial DIN-MIDI interface or a microcontroller, carrying the raw MIDI
+//! byte stream: there is no lobby handshake, heartbeat nor cable frame, the device being the only peer.
+//! The address of the bridges is the path of the device (e.g. `/dev/ttyUSB0` or `COM3`).
+
+use passeri_api::{Error, TransportError};
+use serialport::SerialPort;
+use std::time::Duration;
+
+mod serial_receiver;
+pub use serial_receiver::Receiver;
+mod serial_sender;
+pub use serial_sender::Sender;
+
+/// Speed of a DIN-MIDI link, in bits per second
+pub const DIN_MIDI_BAUD_RATE: u32 = 31250;
+
+/// Time a read of the device blocks for, after which the reader checks whether it has to stop
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Options of the serial [Sender] and [Receiver]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    /// speed of the link, in bits per second: [DIN_MIDI_BAUD_RATE] for a DIN-MIDI interface,
+    /// often 115200 for a microcontroller
+    pub baud_rate: u32,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        SerialConfig {
+            baud_rate: DIN_MIDI_BAUD_RATE,
+        }
+    }
+}
+
+/// Open the serial device at `path`, 8 data bits, no parity and 1 stop bit
+fn open(path: &str, config: SerialConfig) -> Result<Box<dyn SerialPort>, Error> {
+    Ok(serialport::new(path, config.baud_rate)
+        .timeout(READ_TIMEOUT)
+        .open()
+        .map_err(|err| TransportError::Connect {
+            addr: path.to_string(),
+            source: err.into(),
+        })?)
+}
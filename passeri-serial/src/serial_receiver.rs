@@ -0,0 +1,249 @@
+use crate::SerialConfig;
+use log::{debug, trace};
+use passeri_api::metrics::Metrics;
+use passeri_api::midi::{Control, MidiParser, Output, OutputConnection};
+use passeri_api::net::lobby::{Credentials, Features};
+use passeri_api::net::notifier::Wake;
+use passeri_api::net::receiver::{PasseriReq, Request, Responder, Response, Thread, ThreadReturn};
+use passeri_api::{Error, MidiError, TransportError};
+use serialport::SerialPort;
+use std::io::{self, ErrorKind, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, TryRecvError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Interval at which the MIDI out port is checked while receiving
+const TICK: Duration = Duration::from_millis(50);
+
+/// Reason for the event loop of the [Receiver] to wake up
+enum Wakeup {
+    /// a request was sent to it
+    Notified,
+    /// bytes were read from the device
+    Read(Vec<u8>),
+    /// the device is gone
+    Closed(io::Error),
+}
+
+/// Read the device until `stop` is set, sending the bytes read to `tx`
+fn spawn_reader(
+    mut port: Box<dyn SerialPort>,
+    tx: mpsc::Sender<Wakeup>,
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut buf = [0; 256];
+        while !stop.load(Ordering::Relaxed) {
+            let wakeup = match port.read(&mut buf) {
+                Ok(0) => Wakeup::Closed(ErrorKind::UnexpectedEof.into()),
+                Ok(len) => Wakeup::Read(buf[..len].to_vec()),
+                Err(err) if matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {
+                    continue
+                }
+                Err(err) => Wakeup::Closed(err),
+            };
+            let closed = matches!(wakeup, Wakeup::Closed(_));
+            if tx.send(wakeup).is_err() || closed {
+                return;
+            }
+        }
+    })
+}
+
+/// Implementation of the [Receiver Thread Trait](Thread) over a serial link
+///
+/// The byte stream of the device is cut into messages by a [MidiParser], the devices usually sending them
+/// a byte at a time and with running status. There is no [lobby](passeri_api::net::lobby) handshake, the
+/// credentials being ignored, and every message goes to the MIDI out port of the cable 0.
+pub struct Receiver {
+    path: String,
+    output: Output,
+    parser: MidiParser,
+    /// set once the receiver is asked to receive
+    receiving: bool,
+    wakeup_tx: mpsc::Sender<Wakeup>,
+    wakeup_rx: mpsc::Receiver<Wakeup>,
+    /// stops the thread reading the device
+    stop: Arc<AtomicBool>,
+    messenger_rx: mpsc::Receiver<PasseriReq>,
+    metrics: Metrics,
+}
+
+impl Thread for Receiver {
+    type Addr = String;
+    type Config = SerialConfig;
+    const FEATURES: Features = Features {
+        sysex: true,
+        ..Features::NONE
+    };
+
+    fn new(
+        addr: Self::Addr,
+        config: Self::Config,
+        midi_tx: Vec<OutputConnection>,
+        messenger_rx: mpsc::Receiver<PasseriReq>,
+        metrics: Metrics,
+        _credentials: Credentials,
+    ) -> Result<Self, Error> {
+        let conn = midi_tx.into_iter().next().ok_or(MidiError::CableCount(0))?;
+        let port = crate::open(&addr, config)?;
+        debug!("{} opened at {} bauds", addr, config.baud_rate);
+        let (wakeup_tx, wakeup_rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        spawn_reader(port, wakeup_tx.clone(), stop.clone());
+        Ok(Receiver {
+            path: addr,
+            output: Output::new(conn, None),
+            parser: MidiParser::new(),
+            receiving: false,
+            wakeup_tx,
+            wakeup_rx,
+            stop,
+            messenger_rx,
+            metrics,
+        })
+    }
+
+    fn run(&mut self) -> Result<(), ThreadReturn> {
+        let ret = self.serve();
+        self.stop.store(true, Ordering::Relaxed);
+        // whatever the reason, the Note Offs of the notes still held will never arrive
+        if let Err(err) = self.output.release_all() {
+            debug!("unable to release the held notes: {}", err);
+        }
+        ret
+    }
+
+    fn waker(&self) -> Wake {
+        let wakeup_tx = self.wakeup_tx.clone();
+        Arc::new(move || {
+            let _ = wakeup_tx.send(Wakeup::Notified);
+        })
+    }
+
+    fn info(&self) -> String {
+        self.path.clone()
+    }
+}
+
+impl Drop for Receiver {
+    /// Stop the thread reading the device, even if the event loop never ran
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Receiver {
+    /// Event loop of [Thread::run], returning once asked to stop or when the device is gone
+    fn serve(&mut self) -> Result<(), ThreadReturn> {
+        loop {
+            if !self.process()? {
+                return Ok(());
+            }
+            // the MIDI out port only needs to be checked while receiving
+            let wakeup = match self.receiving {
+                true => self.wakeup_rx.recv_timeout(TICK),
+                false => self
+                    .wakeup_rx
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match wakeup {
+                Ok(Wakeup::Read(bytes)) => self.read(&bytes)?,
+                Ok(Wakeup::Closed(err)) if err.kind() == ErrorKind::UnexpectedEof => {
+                    return Err(ThreadReturn::ReceiveEnd)
+                }
+                Ok(Wakeup::Closed(err)) => return Err(TransportError::Read(err).into()),
+                Ok(Wakeup::Notified) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err(ThreadReturn::Stopped),
+            }
+            if self.receiving {
+                self.output.watch_port()?;
+            }
+        }
+    }
+
+    /// Forward the messages completed by `bytes` to the MIDI out port
+    fn read(&mut self, bytes: &[u8]) -> Result<(), ThreadReturn> {
+        if !self.receiving {
+            trace!("not receiving, drop {:?}", bytes);
+            return Ok(());
+        }
+        for msg in self.parser.parse(bytes) {
+            self.output.forward(&msg, &self.metrics)?;
+        }
+        Ok(())
+    }
+
+    /// Process the pending requests, returning `false` once asked to stop
+    fn process(&mut self) -> Result<bool, ThreadReturn> {
+        loop {
+            let (req, responder) = match self.messenger_rx.try_recv() {
+                Ok(req) => req,
+                Err(TryRecvError::Empty) => return Ok(true),
+                Err(TryRecvError::Disconnected) => return Err(ThreadReturn::Recv(mpsc::RecvError)),
+            };
+            match req {
+                Request::Receive => {
+                    self.receiving = true;
+                    responder.send(Response::StartReceiving)?
+                }
+                Request::Control(control) => self.control(responder, control)?,
+                Request::Stop => {
+                    let _ = responder.send(Response::Stopped);
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    /// Apply a pause, mute, solo or panic request to the MIDI out port
+    fn control(&mut self, responder: Responder, control: Control) -> Result<(), ThreadReturn> {
+        self.output.control(control)?;
+        Ok(responder.send(Response::Applied)?)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serialport::TTYPort;
+    use std::io::Write;
+
+    #[test]
+    fn test_reader() {
+        let (mut master, slave) = TTYPort::pair().unwrap();
+        let port = crate::open(&slave.name().unwrap(), SerialConfig::default()).unwrap();
+        drop(slave);
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = spawn_reader(port, tx, stop.clone());
+
+        // a note played with running status, a byte at a time
+        let mut parser = MidiParser::new();
+        let mut messages = vec![];
+        for byte in [0x90, 0x3C, 0x40, 0x3C, 0x00] {
+            master.write_all(&[byte]).unwrap();
+        }
+        while messages.len() < 2 {
+            match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+                Wakeup::Read(bytes) => messages.extend(parser.parse(&bytes)),
+                _ => panic!("expecting bytes"),
+            }
+        }
+        assert_eq!(
+            messages,
+            vec![vec![0x90, 0x3C, 0x40], vec![0x90, 0x3C, 0x00]]
+        );
+
+        // the reader ends with the device
+        drop(master);
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Wakeup::Closed(_)
+        ));
+        reader.join().unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+use crate::SerialConfig;
+use log::{debug, trace};
+use passeri_api::lifecycle::{Event, Lifecycle};
+use passeri_api::metrics::Metrics;
+use passeri_api::midi::{ChannelFilter, Control};
+use passeri_api::net::cable::CablePayload;
+use passeri_api::net::lobby::{Auth, ClientInfo, Features, Lobby};
+use passeri_api::net::notifier::Wake;
+use passeri_api::net::sender::{PasseriReq, Request, Responder, Response, Thread, ThreadReturn};
+use passeri_api::net::PROTOCOL_VERSION;
+use passeri_api::{Error, TransportError};
+use serialport::SerialPort;
+use std::io::Write;
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
+
+type Addr = <Sender as Thread>::Addr;
+
+/// Implementation of the [Sender Thread Trait](Thread) over a serial link
+///
+/// The device is the only receiver, returned by [Request::OpenRoom] as soon as the room opens while it is not
+/// streamed to. It does not take part in the [Lobby] handshake, the admission rules being left to the host.
+/// Messages of a cable other than 0 are dropped.
+pub struct Sender {
+    path: String,
+    port: Box<dyn SerialPort>,
+    /// woken up after each request and MIDI message
+    wake_tx: mpsc::Sender<()>,
+    wake_rx: mpsc::Receiver<()>,
+    /// set once the device is accepted
+    streaming: bool,
+    /// pause, mute and solo state of the stream
+    filter: ChannelFilter,
+    midi_rx: mpsc::Receiver<CablePayload>,
+    messenger_rx: mpsc::Receiver<PasseriReq<Addr>>,
+    metrics: Metrics,
+    lifecycle: Lifecycle,
+    /// responder of the pending [Request::OpenRoom], while the room is open
+    room: Option<Responder<Addr>>,
+}
+
+impl Thread for Sender {
+    type Addr = String;
+    type Config = SerialConfig;
+    const TRANSPORT: &'static str = "serial";
+    const FEATURES: Features = Features {
+        sysex: true,
+        ..Features::NONE
+    };
+
+    fn new(
+        addr: Self::Addr,
+        config: Self::Config,
+        midi_rx: mpsc::Receiver<CablePayload>,
+        messenger_rx: mpsc::Receiver<PasseriReq<Self::Addr>>,
+        metrics: Metrics,
+        _lobby: Lobby,
+        lifecycle: Lifecycle,
+    ) -> Result<Self, Error> {
+        let port = crate::open(&addr, config)?;
+        debug!("{} opened at {} bauds", addr, config.baud_rate);
+        let (wake_tx, wake_rx) = mpsc::channel();
+        Ok(Sender {
+            path: addr,
+            port,
+            wake_tx,
+            wake_rx,
+            streaming: false,
+            filter: ChannelFilter::new(),
+            midi_rx,
+            messenger_rx,
+            metrics,
+            lifecycle,
+            room: None,
+        })
+    }
+
+    fn run(&mut self) -> Result<(), ThreadReturn<Self::Addr>> {
+        loop {
+            // the channels are drained on each wake-up
+            if !self.process()? {
+                return Ok(());
+            }
+            self.forward()?;
+            if self.wake_rx.recv().is_err() {
+                return Err(ThreadReturn::Stopped);
+            }
+        }
+    }
+
+    fn waker(&self) -> Wake {
+        let wake_tx = self.wake_tx.clone();
+        Arc::new(move || {
+            let _ = wake_tx.send(());
+        })
+    }
+
+    fn info(&self) -> Self::Addr {
+        self.path.clone()
+    }
+}
+
+impl Sender {
+    /// Process the pending requests, returning `false` once asked to stop
+    fn process(&mut self) -> Result<bool, ThreadReturn<Addr>> {
+        loop {
+            let (req, responder) = match self.messenger_rx.try_recv() {
+                Ok(req) => req,
+                Err(TryRecvError::Empty) => return Ok(true),
+                Err(TryRecvError::Disconnected) => return Err(ThreadReturn::Recv(mpsc::RecvError)),
+            };
+            // the MIDI messages played before the request are forwarded before it is applied
+            self.forward()?;
+            match req {
+                Request::OpenRoom => self.open_room(responder)?,
+                Request::CloseRoom => {
+                    if let Some(room) = self.room.take() {
+                        room.send(Response::RoomClosed)?;
+                    }
+                    responder.send(Response::RoomClosed)?
+                }
+                Request::AcceptClient(addr) if addr == self.path => {
+                    self.streaming = true;
+                    responder.send(Response::StartStream)?
+                }
+                Request::RejectClient(addr, reason) if addr == self.path => {
+                    debug!("{} rejected: {}", addr, reason);
+                    responder.send(Response::ClientRejected)?
+                }
+                Request::AcceptClient(_) | Request::RejectClient(..) => {
+                    responder.send(Response::ClientNotFound)?
+                }
+                Request::Control(control) => self.control(responder, control)?,
+                Request::Stop => {
+                    let _ = responder.send(Response::Stopped);
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    /// Open the room, answering with the device unless already streamed to
+    fn open_room(&mut self, responder: Responder<Addr>) -> Result<(), ThreadReturn<Addr>> {
+        if let Some(previous) = self.room.take() {
+            previous.send(Response::RoomClosed)?;
+        }
+        if self.streaming {
+            // no other receiver will ever show up
+            self.room = Some(responder);
+            return Ok(());
+        }
+        Ok(responder.send(Response::NewClient(ClientInfo {
+            addr: self.path.clone(),
+            name: "serial device".into(),
+            midi_port: self.path.clone(),
+            version: PROTOCOL_VERSION,
+            features: Self::FEATURES,
+            auth: Auth::None,
+        }))?)
+    }
+
+    /// Apply a pause, mute, solo or panic request to the filter, sending the messages it returns to the device
+    fn control(
+        &mut self,
+        responder: Responder<Addr>,
+        control: Control,
+    ) -> Result<(), ThreadReturn<Addr>> {
+        for msg in self.filter.apply(control) {
+            if self.streaming {
+                trace!("release {:?}", msg);
+                self.write(&msg)?;
+            }
+        }
+        Ok(responder.send(Response::Applied)?)
+    }
+
+    /// Forward the MIDI messages to the device, failing with [ThreadReturn::SendEnd] once the MIDI input is closed
+    fn forward(&mut self) -> Result<(), ThreadReturn<Addr>> {
+        loop {
+            let (cable, (_, msg)) = match self.midi_rx.try_recv() {
+                Ok(msg) => msg,
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => return Err(ThreadReturn::SendEnd),
+            };
+            if !self.streaming {
+                trace!("not streaming, drop {:?}", msg);
+                continue;
+            }
+            if cable != 0 {
+                trace!("no cable {} on a serial link, drop {:?}", cable, msg);
+                self.metrics.record_drop();
+                continue;
+            }
+            if !self.filter.forward(&msg) {
+                trace!("filtered {:?}", msg);
+                continue;
+            }
+            trace!("send {:?}", msg);
+            self.write(&msg)?;
+            self.metrics.record_message(&msg);
+        }
+    }
+
+    /// Write a message to the device, which is gone if it fails
+    fn write(&mut self, msg: &[u8]) -> Result<(), ThreadReturn<Addr>> {
+        if let Err(err) = self.port.write_all(msg) {
+            self.streaming = false;
+            self.lifecycle.emit(Event::ClientLeft);
+            return Err(TransportError::Write(err).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serialport::TTYPort;
+    use std::io::Read;
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+
+    /// Channels of a sender writing to a pseudo-terminal, waking it up after each message
+    struct Handle {
+        tx: mpsc::Sender<PasseriReq<Addr>>,
+        midi_tx: mpsc::Sender<CablePayload>,
+        wake: Wake,
+        thread: JoinHandle<Result<(), ThreadReturn<Addr>>>,
+    }
+
+    impl Handle {
+        fn request(&self, request: Request<Addr>) -> Response<Addr> {
+            let (responder, response) = oneshot::channel();
+            self.tx.send((request, responder)).unwrap();
+            (self.wake)();
+            response.recv().unwrap()
+        }
+
+        fn play(&self, cable: u8, msg: &[u8]) {
+            self.midi_tx.send((cable, (0, msg.to_vec()))).unwrap();
+            (self.wake)();
+        }
+    }
+
+    /// Spawn a sender on the slave side of a pseudo-terminal, returning its master side
+    fn spawn() -> (Handle, TTYPort) {
+        let (mut master, slave) = TTYPort::pair().unwrap();
+        master.set_timeout(Duration::from_secs(5)).unwrap();
+        let path = slave.name().unwrap();
+        let (midi_tx, midi_rx) = mpsc::channel();
+        let (tx, messenger_rx) = mpsc::channel();
+        let mut sender = Sender::new(
+            path,
+            SerialConfig::default(),
+            midi_rx,
+            messenger_rx,
+            Metrics::new(),
+            Lobby::new(),
+            Lifecycle::new(),
+        )
+        .unwrap();
+        let wake = sender.waker();
+        let thread = std::thread::spawn(move || sender.run());
+        let handle = Handle {
+            tx,
+            midi_tx,
+            wake,
+            thread,
+        };
+        (handle, master)
+    }
+
+    #[test]
+    fn test_stream() {
+        let (sender, mut master) = spawn();
+        // nothing is written until the device is accepted
+        sender.play(0, &[0x90, 0x3C, 0x40]);
+        let Response::NewClient(client) = sender.request(Request::OpenRoom) else {
+            panic!("expecting the device");
+        };
+        assert_eq!(client.auth, Auth::None);
+        assert!(matches!(
+            sender.request(Request::AcceptClient("/dev/null".into())),
+            Response::ClientNotFound
+        ));
+        assert!(matches!(
+            sender.request(Request::AcceptClient(client.addr)),
+            Response::StartStream
+        ));
+
+        sender.play(1, &[0xC0, 0x05]);
+        sender.play(0, &[0x90, 0x3E, 0x40]);
+        sender.play(0, &[0xF0, 0x43, 0x10, 0xF7]);
+        let mut buf = [0; 7];
+        master.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x90, 0x3E, 0x40, 0xF0, 0x43, 0x10, 0xF7]);
+
+        // the held note is released when pausing
+        assert!(matches!(
+            sender.request(Request::Control(Control::Pause(true))),
+            Response::Applied
+        ));
+        let mut buf = [0; 3];
+        master.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x80, 0x3E, 0x00]);
+
+        assert!(matches!(sender.request(Request::Stop), Response::Stopped));
+        assert!(sender.thread.join().unwrap().is_ok());
+    }
+}
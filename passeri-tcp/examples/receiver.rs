@@ -4,6 +4,7 @@ use std::{net::SocketAddr, process::exit};
 use std::str::FromStr;
 
 use log::{error, info};
+use passeri_api::midi::PortSelector;
 
 fn main() {
     env_logger::builder()
@@ -12,20 +13,30 @@ fn main() {
 
     let mut args: Vec<String> = env::args().collect();
     if args.len() < 3 {
-        error!("Usage:\n\treceiver <address> <midi_out_port_name>");
+        error!("Usage:\n\treceiver <address> <midi_out_port_name> [midi_out_port]");
         exit(1);
     }
 
+    let midi_port = match args.get(3) {
+        Some(selector) => PortSelector::from_str(selector).unwrap_or_else(|err| {
+            error!("Err: invalid MIDI port \"{}\" ({})", selector, err);
+            exit(1);
+        }),
+        None => PortSelector::Index(1),
+    };
+
     let addr = SocketAddr::from_str(&args[1]).expect("error while parsing address argument");
 
-    let mut receiver = passeri_api::new_receiver::<passeri_tcp::Receiver>(1, &args.remove(2), addr)
-        .unwrap_or_else(|err| {
-            error!(
-                "Err: unable to initialize Receiver on address \"{}\" ({})",
-                &args[1], err
-            );
-            exit(1);
-        });
+    let mut receiver =
+        passeri_api::ReceiverBuilder::<passeri_tcp::Receiver>::new(&[midi_port], &args.remove(2))
+            .connect(addr)
+            .unwrap_or_else(|err| {
+                error!(
+                    "Err: unable to initialize Receiver on address \"{}\" ({})",
+                    &args[1], err
+                );
+                exit(1);
+            });
 
     receiver.receive().unwrap_or_else(|err| {
         error!("error trying to receive from Sender: {}", err);
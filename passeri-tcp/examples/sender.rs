@@ -1,6 +1,7 @@
 use std::{env, net::SocketAddr, process::exit, str::FromStr};
 
 use log::{error, info};
+use passeri_api::midi::PortSelector;
 
 fn main() {
     env_logger::builder()
@@ -9,24 +10,34 @@ fn main() {
 
     let mut args: Vec<String> = env::args().collect();
     if args.len() < 3 {
-        error!("Usage:\n\treceiver <address> <midi_in_port_name>");
+        error!("Usage:\n\tsender <address> <midi_in_port_name> [midi_in_port]");
         exit(1);
     }
 
+    let midi_port = match args.get(3) {
+        Some(selector) => PortSelector::from_str(selector).unwrap_or_else(|err| {
+            error!("Err: invalid MIDI port \"{}\" ({})", selector, err);
+            exit(1);
+        }),
+        None => PortSelector::Index(0),
+    };
+
     let addr = SocketAddr::from_str(&args[1]).expect("error while parsing address argument");
 
-    let mut sender = passeri_api::new_sender::<passeri_tcp::Sender>(0, &args.remove(2), addr)
-        .unwrap_or_else(|err| {
-            error!(
-                "Err: unable to initialize Sender on address \"{}\" ({})",
-                &args[1], err
-            );
-            exit(1);
-        });
+    let mut sender =
+        passeri_api::SenderBuilder::<passeri_tcp::Sender>::new(&[midi_port], &args.remove(2))
+            .listen(addr)
+            .unwrap_or_else(|err| {
+                error!(
+                    "Err: unable to initialize Sender on address \"{}\" ({})",
+                    &args[1], err
+                );
+                exit(1);
+            });
 
-    if let Ok(addr) = sender.wait_for_client() {
-        info!("{} is now connected", addr);
-        sender.send(addr).unwrap_or_else(|err| {
+    if let Ok(client) = sender.wait_for_client() {
+        info!("{} is now connected", client);
+        sender.send(client.addr).unwrap_or_else(|err| {
             error!("error trying to receive from Sender: {}", err);
             exit(1);
         });
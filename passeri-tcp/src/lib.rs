@@ -1,11 +1,17 @@
 #![warn(missing_docs)]
 //! Implementation of the Sender and Receiver traits from `passeri-api`
 
+mod relay;
+pub mod room;
+mod stream;
 mod tcp_receiver;
-pub use tcp_receiver::Receiver;
+#[cfg(test)]
+mod testing;
+pub use tcp_receiver::{Receiver, ReceiverConfig};
 mod tcp_sender;
+pub mod tls;
 
-pub use tcp_sender::Sender;
+pub use tcp_sender::{Sender, SenderConfig};
 
 #[cfg(test)]
 mod tests {
@@ -17,6 +23,7 @@ mod tests {
     use std::{thread, vec};
 
     use log::debug;
+    use passeri_api::midi::PortSelector;
     use std::fs::File;
     use std::io::prelude::*;
     use std::sync::mpsc::RecvTimeoutError;
@@ -40,13 +47,19 @@ mod tests {
 
         let sender = thread::spawn(move || {
             // create fake Midi Source
-            let mut midi_src = passeri_api::midi::new_sender(0, "PASSERI_FAKE_SENDER").unwrap();
+            let midi_src =
+                passeri_api::midi::new_sender(&PortSelector::Index(0), "PASSERI_FAKE_SENDER")
+                    .unwrap();
             debug!("fake midi source created");
 
             // create passeri_sender
             let addr = SocketAddr::from_str("0.0.0.0:0000").unwrap();
-            let mut sender =
-                passeri_api::new_sender::<crate::Sender>(0, "PASSERI_SENDER", addr).unwrap();
+            let sender = passeri_api::SenderBuilder::<crate::Sender>::new(
+                &[PortSelector::Index(0)],
+                "PASSERI_SENDER",
+            )
+            .listen(addr)
+            .unwrap();
             debug!("passeri_sender created");
 
             // send sender address to passeri_receiver
@@ -60,13 +73,13 @@ mod tests {
             let client = sender.wait_for_client().unwrap();
 
             // start forwarding midi message
-            sender.send(client).unwrap();
+            sender.send(client.addr).unwrap();
             debug!("passeri_sender connected to passeri_receiver");
 
             // send mocking value to fake midi source
             debug!("passeri_sender start to send src vec");
             for msg in src_cpy.as_ref().chunks(32) {
-                midi_src.send(msg);
+                let _ = midi_src.send(msg);
                 std::thread::sleep(std::time::Duration::from_micros(1));
             }
             debug!("passeri_sender finished to send src vec");
@@ -74,18 +87,22 @@ mod tests {
 
         let receiver = thread::spawn(move || {
             let (sender_addr, responder) = rx.recv().expect("Unable to receive from channel");
-            let mut receiver =
-                passeri_api::new_receiver::<crate::Receiver>(1, "PASSERI_RECV", sender_addr)
-                    .unwrap();
+            let receiver = passeri_api::ReceiverBuilder::<crate::Receiver>::new(
+                &[PortSelector::Index(1)],
+                "PASSERI_RECV",
+            )
+            .connect(sender_addr)
+            .unwrap();
             debug!("passeri_receiver created");
 
             let mut res: Vec<u8> = vec![];
-            let (fake_midi_recv_conn, fake_midi_recv) =
-                passeri_api::midi::new_receiver(0, "PASSERI_FAKE_RECV").unwrap();
+            let (_fake_midi_recv_conn, fake_midi_recv) =
+                passeri_api::midi::new_receiver(&PortSelector::Index(0), "PASSERI_FAKE_RECV")
+                    .unwrap();
             debug!("fake midi receiver created");
 
             receiver.receive().unwrap();
-            responder.send(());
+            let _ = responder.send(());
 
             debug!("passeri_receiver start to receive from passeri_sender");
             loop {
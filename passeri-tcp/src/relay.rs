@@ -0,0 +1,236 @@
+use crate::stream::HANDSHAKE_TIMEOUT;
+use log::{debug, warn};
+use mio::net::TcpStream;
+use mio::{Interest, Registry, Token};
+use passeri_api::net::relay::{RelayMessage, Ticket, HEADER_LEN};
+use passeri_api::{Error, ProtocolError, TransportError};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Open a connection to `relay`, introduced with `message`
+pub(crate) fn connect(
+    relay: SocketAddr,
+    message: RelayMessage,
+) -> Result<std::net::TcpStream, Error> {
+    let mut stream =
+        std::net::TcpStream::connect(relay).map_err(|source| TransportError::Connect {
+            addr: relay.to_string(),
+            source,
+        })?;
+    stream
+        .set_nodelay(true)
+        .map_err(TransportError::Configure)?;
+    message.write_to(&mut stream)?;
+    Ok(stream)
+}
+
+/// Ask `relay` to pair the receiver with a sender waiting in the room of `ticket`,
+/// returning the connection to the sender once paired
+pub(crate) fn join(relay: SocketAddr, ticket: &Ticket) -> Result<std::net::TcpStream, Error> {
+    let mut stream = connect(relay, RelayMessage::Join(ticket.clone()))?;
+    // the relay waits up to its own handshake timeout for a sender
+    stream
+        .set_read_timeout(Some(2 * HANDSHAKE_TIMEOUT))
+        .map_err(TransportError::Configure)?;
+    match RelayMessage::read_from(&mut stream)? {
+        RelayMessage::Paired(sender) => {
+            debug!("paired with {} by {} in {}", sender, relay, ticket);
+            stream
+                .set_read_timeout(None)
+                .map_err(TransportError::Configure)?;
+            Ok(stream)
+        }
+        RelayMessage::Refused(reason) => Err(ProtocolError::RelayRefused(reason).into()),
+        message => Err(ProtocolError::InvalidMessage(format!(
+            "expecting a pairing, got {:?}",
+            message
+        ))
+        .into()),
+    }
+}
+
+/// Delay before reconnecting to a relay after its first failure, doubled after each following one
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+/// Longest delay between two attempts to reconnect to a relay
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// State of the connection of a [RelayHost]
+enum Hosting {
+    /// connecting to the relay, with the bytes of the [RelayMessage::Host] left to write
+    Connecting(TcpStream, Vec<u8>),
+    /// waiting for the relay to pair a receiver, with the bytes of its answer received so far
+    Waiting(TcpStream, Vec<u8>),
+    /// the relay is unreachable until the next attempt
+    Retrying(Instant),
+}
+
+/// Connection waiting at a relay for the next receiver of a room, standing for the listener of a [Sender](crate::Sender)
+///
+/// The connection is opened without blocking the event loop, and re-opened after an increasing delay whenever
+/// the relay is unreachable, closes it or refuses it.
+pub(crate) struct RelayHost {
+    relay: SocketAddr,
+    ticket: Ticket,
+    state: Hosting,
+    /// delay before the next attempt if the current one fails
+    backoff: Duration,
+}
+
+impl RelayHost {
+    /// Create the host of the room of `ticket`, connected to `relay` by the first call to [RelayHost::accept]
+    pub fn new(relay: SocketAddr, ticket: Ticket) -> Self {
+        RelayHost {
+            relay,
+            ticket,
+            state: Hosting::Retrying(Instant::now()),
+            backoff: MIN_BACKOFF,
+        }
+    }
+
+    /// Time left before the next attempt to reconnect to the relay, if it is unreachable
+    pub fn retry_in(&self) -> Option<Duration> {
+        match self.state {
+            Hosting::Retrying(at) => Some(at.saturating_duration_since(Instant::now())),
+            _ => None,
+        }
+    }
+
+    /// Return the connection of the receiver paired by the relay and its address, replaced by a new connection
+    /// registered with `token` for the next receiver. Fails with [ErrorKind::WouldBlock] until a receiver is paired,
+    /// the failures of the relay being retried.
+    pub fn accept(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+    ) -> io::Result<(TcpStream, SocketAddr)> {
+        match self.progress(registry, token) {
+            Ok(Some(paired)) => Ok(paired),
+            Ok(None) => Err(ErrorKind::WouldBlock.into()),
+            Err(err) => {
+                warn!(
+                    "relay {} lost: {}, retrying in {:?}",
+                    self.relay, err, self.backoff
+                );
+                if let Hosting::Connecting(conn, _) | Hosting::Waiting(conn, _) = &mut self.state {
+                    let _ = registry.deregister(conn);
+                }
+                self.state = Hosting::Retrying(Instant::now() + self.backoff);
+                self.backoff = (2 * self.backoff).min(MAX_BACKOFF);
+                Err(ErrorKind::WouldBlock.into())
+            }
+        }
+    }
+
+    /// Move the connection as far as it can go without blocking, returning the paired receiver if any
+    fn progress(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+    ) -> io::Result<Option<(TcpStream, SocketAddr)>> {
+        loop {
+            match &mut self.state {
+                Hosting::Retrying(at) if Instant::now() < *at => return Ok(None),
+                Hosting::Retrying(_) => self.state = self.host(registry, token)?,
+                Hosting::Connecting(conn, intro) => {
+                    if let Some(err) = conn.take_error()? {
+                        return Err(err);
+                    }
+                    match conn.peer_addr() {
+                        Ok(_) => {}
+                        Err(err) if err.kind() == ErrorKind::NotConnected => return Ok(None),
+                        Err(err) => return Err(err),
+                    }
+                    while !intro.is_empty() {
+                        match conn.write(intro) {
+                            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                            Ok(written) => drop(intro.drain(..written)),
+                            Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(None),
+                            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                            Err(err) => return Err(err),
+                        }
+                    }
+                    debug!(
+                        "waiting for a receiver of {} at {}",
+                        self.ticket, self.relay
+                    );
+                    registry.reregister(conn, token, Interest::READABLE)?;
+                    self.backoff = MIN_BACKOFF;
+                    let connected =
+                        std::mem::replace(&mut self.state, Hosting::Retrying(Instant::now()));
+                    if let Hosting::Connecting(conn, _) = connected {
+                        self.state = Hosting::Waiting(conn, vec![]);
+                    }
+                }
+                Hosting::Waiting(conn, answer) => {
+                    let Some(message) = read_answer(conn, answer, self.relay)? else {
+                        return Ok(None);
+                    };
+                    let addr = match message {
+                        RelayMessage::Paired(addr) => addr.parse().map_err(io::Error::other)?,
+                        RelayMessage::Refused(reason) => {
+                            return Err(io::Error::other(ProtocolError::RelayRefused(reason)))
+                        }
+                        message => {
+                            return Err(io::Error::other(format!(
+                                "unexpected relay message {:?}",
+                                message
+                            )))
+                        }
+                    };
+                    registry.deregister(conn)?;
+                    let next = self.host(registry, token)?;
+                    if let Hosting::Waiting(paired, _) = std::mem::replace(&mut self.state, next) {
+                        return Ok(Some((paired, addr)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Start connecting to the relay, registered with `token` to be notified once connected
+    fn host(&self, registry: &Registry, token: Token) -> io::Result<Hosting> {
+        let mut conn = TcpStream::connect(self.relay)?;
+        conn.set_nodelay(true)?;
+        registry.register(&mut conn, token, Interest::READABLE | Interest::WRITABLE)?;
+        let mut intro = vec![];
+        RelayMessage::Host(self.ticket.clone())
+            .write_to(&mut intro)
+            .map_err(io::Error::other)?;
+        Ok(Hosting::Connecting(conn, intro))
+    }
+}
+
+/// Read the answer of the relay into `answer`, without reading past it: the bytes following it come from the receiver.
+/// Returns `None` until it is complete.
+fn read_answer(
+    conn: &mut TcpStream,
+    answer: &mut Vec<u8>,
+    relay: SocketAddr,
+) -> io::Result<Option<RelayMessage>> {
+    loop {
+        let len = match answer.first_chunk::<HEADER_LEN>() {
+            Some(header) => RelayMessage::frame_len(header),
+            None => HEADER_LEN,
+        };
+        if answer.len() == len {
+            let answer = std::mem::take(answer);
+            return RelayMessage::read_from(&mut answer.as_slice())
+                .map(Some)
+                .map_err(io::Error::other);
+        }
+        let mut buf = vec![0; len - answer.len()];
+        match conn.read(&mut buf) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("the relay {} closed the connection", relay),
+                ))
+            }
+            Ok(read) => answer.extend_from_slice(&buf[..read]),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(None),
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
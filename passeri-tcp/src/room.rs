@@ -0,0 +1,400 @@
+//! Members of the named rooms of a relay (see [passeri_api::net::room])
+//!
+//! A sender member waits for its receivers at the relay, in the room of its [Member::ticket], and enters the
+//! room once its [Sender](crate::Sender) listens there. A [RoomReceiver] follows the senders joining and leaving
+//! the room, merging the streams of the ones it subscribed to.
+use crate::relay;
+use crate::stream::HANDSHAKE_TIMEOUT;
+use crate::{Receiver, ReceiverConfig};
+use log::{debug, warn};
+use passeri_api::midi::OutputConnection;
+use passeri_api::net::lobby::Credentials;
+use passeri_api::net::merge::{MergedReceiver, Source};
+use passeri_api::net::relay::{RelayMessage, Ticket};
+use passeri_api::net::room::{Member, RoomEvent, Roster, Subscription};
+use passeri_api::{Error, ProtocolError, TransportError};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+//
+//	PARTICIPANT
+//
+
+/// Member of a room of a relay, announced to the other members until dropped
+pub struct Participant {
+    room: Ticket,
+    member: Member,
+    roster: Arc<Mutex<Roster>>,
+    stream: TcpStream,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Participant {
+    /// Enter `room` at `relay` as `member`, `on_event` being called from another thread with the members
+    /// joining and leaving it
+    pub fn enter(
+        relay: SocketAddr,
+        room: &Ticket,
+        member: Member,
+        on_event: impl Fn(&RoomEvent) + Send + 'static,
+    ) -> Result<Self, Error> {
+        let mut stream = relay::connect(relay, RelayMessage::Enter(room.clone(), member.clone()))?;
+        stream
+            .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+            .map_err(TransportError::Configure)?;
+        let roster = match RelayMessage::read_from(&mut stream)? {
+            RelayMessage::Members(members) => Roster::new(members),
+            RelayMessage::Refused(reason) => return Err(ProtocolError::RelayRefused(reason).into()),
+            message => {
+                return Err(ProtocolError::InvalidMessage(format!(
+                    "expecting the members of {}, got {:?}",
+                    room, message
+                ))
+                .into())
+            }
+        };
+        stream
+            .set_read_timeout(None)
+            .map_err(TransportError::Configure)?;
+        debug!("entered {} as {}", room, member);
+
+        let roster = Arc::new(Mutex::new(roster));
+        let thread = {
+            let mut stream = stream.try_clone().map_err(TransportError::Configure)?;
+            let roster = roster.clone();
+            std::thread::spawn(move || loop {
+                let message = match RelayMessage::read_from(&mut stream) {
+                    Ok(message) => message,
+                    Err(err) => {
+                        debug!("left the room: {}", err);
+                        break;
+                    }
+                };
+                let event = lock(&roster).apply(message);
+                if let Some(event) = event {
+                    on_event(&event);
+                }
+            })
+        };
+        Ok(Participant {
+            room: room.clone(),
+            member,
+            roster,
+            stream,
+            thread: Some(thread),
+        })
+    }
+
+    /// Return the other members of the room, in the order they entered it
+    pub fn members(&self) -> Vec<Member> {
+        lock(&self.roster).members().to_vec()
+    }
+
+    /// Return the member entered as
+    pub fn member(&self) -> &Member {
+        &self.member
+    }
+
+    /// Return the room entered
+    pub fn room(&self) -> &Ticket {
+        &self.room
+    }
+
+    /// Return `false` once the connection to the relay is lost, the member having left the room
+    pub fn is_present(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+}
+
+impl Drop for Participant {
+    /// Leave the room, waiting for the last [RoomEvent] to be handled
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+//
+//	ROOM RECEIVER
+//
+
+/// Senders merged by a [RoomReceiver]
+struct Subscribed {
+    relay: SocketAddr,
+    room: Ticket,
+    subscription: Subscription,
+    merged: MergedReceiver,
+    /// names of the senders merged, in the order of the sources of `merged`
+    names: Vec<String>,
+    config: ReceiverConfig,
+    credentials: Credentials,
+    /// callbacks given to [RoomReceiver::on_event]
+    callbacks: Vec<EventCallback>,
+}
+
+type EventCallback = Box<dyn Fn(&RoomEvent) + Send>;
+
+impl Subscribed {
+    /// Start receiving `sender` if subscribed to and not received yet
+    fn connect(&mut self, sender: &Member) -> Result<(), Error> {
+        if !self.subscription.includes(sender) || self.names.contains(&sender.name) {
+            return Ok(());
+        }
+        let mut config = self.config.clone();
+        let addr = match sender.addr.as_deref() {
+            Some(addr) => {
+                config.relay = None;
+                addr.parse().map_err(|_| {
+                    ProtocolError::InvalidMessage(format!(
+                        "invalid address of {}: {}",
+                        sender, addr
+                    ))
+                })?
+            }
+            None => {
+                config.relay = Some(sender.ticket(&self.room));
+                self.relay
+            }
+        };
+        let index = self.merged.add_source::<Receiver>(
+            Source::new(addr),
+            config,
+            self.credentials.clone(),
+        )?;
+        if let Err(err) = self
+            .merged
+            .source(index)
+            .and_then(|source| source.receive())
+        {
+            let _ = self.merged.remove_source(index);
+            return Err(err);
+        }
+        self.names.push(sender.name.clone());
+        Ok(())
+    }
+
+    /// Stop receiving the sender `name`, if received
+    fn disconnect(&mut self, name: &str) {
+        if let Some(index) = self.names.iter().position(|known| known == name) {
+            self.names.remove(index);
+            let _ = self.merged.remove_source(index);
+        }
+    }
+
+    fn handle(&mut self, event: &RoomEvent) {
+        match event {
+            RoomEvent::Joined(member) => {
+                if let Err(err) = self.connect(member) {
+                    warn!("unable to receive {}: {}", member, err);
+                }
+            }
+            RoomEvent::Left(member) => self.disconnect(&member.name),
+        }
+        self.callbacks.iter().for_each(|callback| callback(event));
+    }
+}
+
+/// Receiver member of a room, merging into the same MIDI out ports the streams of the senders it subscribed to,
+/// as they join and leave the room
+///
+/// The senders are connected to from the thread handling the [RoomEvent]s, one at a time: a sender taking long to
+/// approve the receiver delays the following ones.
+pub struct RoomReceiver {
+    // dropped first, so no event is handled while the senders are disconnected
+    participant: Participant,
+    subscribed: Arc<Mutex<Subscribed>>,
+}
+
+impl RoomReceiver {
+    /// Enter `room` at `relay` as the receiver `name`, and start receiving the senders of `subscription`,
+    /// the messages of the cable `n` being sent to the `n`th connection of `midi_tx`
+    pub fn enter(
+        midi_tx: Vec<OutputConnection>,
+        relay: SocketAddr,
+        room: &Ticket,
+        name: &str,
+        subscription: Subscription,
+        config: ReceiverConfig,
+        credentials: Credentials,
+    ) -> Result<Self, Error> {
+        let subscribed = Arc::new(Mutex::new(Subscribed {
+            relay,
+            room: room.clone(),
+            subscription,
+            merged: MergedReceiver::with_outputs(midi_tx)?,
+            names: vec![],
+            config,
+            credentials,
+            callbacks: vec![],
+        }));
+        let participant = {
+            let subscribed = subscribed.clone();
+            Participant::enter(relay, room, Member::receiver(name), move |event| {
+                lock(&subscribed).handle(event)
+            })?
+        };
+        // the senders joining meanwhile are connected to by the event thread, and skipped here
+        for member in participant.members() {
+            lock(&subscribed).handle(&RoomEvent::Joined(member));
+        }
+        Ok(RoomReceiver {
+            participant,
+            subscribed,
+        })
+    }
+
+    /// Start receiving the sender `name`, now if in the room or as soon as it joins it
+    pub fn subscribe(&self, name: &str) -> Result<(), Error> {
+        let mut subscribed = lock(&self.subscribed);
+        subscribed.subscription.add(name);
+        match self
+            .participant
+            .members()
+            .iter()
+            .find(|member| member.name == name)
+        {
+            Some(sender) => subscribed.connect(sender),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop receiving the sender `name`, its held notes being released
+    pub fn unsubscribe(&self, name: &str) {
+        let mut subscribed = lock(&self.subscribed);
+        let members = self.participant.members();
+        subscribed.subscription.remove(name, &members);
+        subscribed.disconnect(name);
+    }
+
+    /// Return the senders received, the ones subscribed to and present in the room
+    pub fn senders(&self) -> Vec<String> {
+        lock(&self.subscribed).names.clone()
+    }
+
+    /// Run `f` on the [MergedReceiver] of the senders received, in the order of [RoomReceiver::senders]
+    pub fn with_merged<R>(&self, f: impl FnOnce(&mut MergedReceiver) -> R) -> R {
+        f(&mut lock(&self.subscribed).merged)
+    }
+
+    /// Call `callback` on the following [RoomEvent]s, once the senders joining are connected to,
+    /// from the thread handling them
+    pub fn on_event(&self, callback: impl Fn(&RoomEvent) + Send + 'static) {
+        lock(&self.subscribed).callbacks.push(Box::new(callback));
+    }
+
+    /// Return the membership of the receiver in the room
+    pub fn participant(&self) -> &Participant {
+        &self.participant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{channel_output, next, Distant};
+    use crate::SenderConfig;
+    use passeri_relay::{Relay, RelayConfig};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn spawn_relay() -> SocketAddr {
+        let relay = Relay::bind("127.0.0.1:0".parse().unwrap(), RelayConfig::default()).unwrap();
+        let addr = relay.local_addr().unwrap();
+        std::thread::spawn(move || relay.run());
+        addr
+    }
+
+    /// Enter `room` as the sender `name`, accepting every receiver at the relay
+    fn band(relay: SocketAddr, room: &Ticket, name: &str) -> (Participant, Distant) {
+        let member = Member::sender(name, None);
+        let config = SenderConfig {
+            relay: Some(member.ticket(room)),
+            ..SenderConfig::default()
+        };
+        let distant = Distant::spawn_on(relay, config);
+        distant.admit_all();
+        (
+            Participant::enter(relay, room, member, |_| {}).unwrap(),
+            distant,
+        )
+    }
+
+    #[test]
+    fn test_participant() {
+        let addr = spawn_relay();
+
+        let room = Ticket::new("jam").with_token("s3cr3t");
+        let alice = Member::sender("alice", None);
+        let (tx, rx) = mpsc::channel();
+        let first = Participant::enter(addr, &room, alice.clone(), move |event| {
+            let _ = tx.send(event.clone());
+        })
+        .unwrap();
+        assert!(first.members().is_empty());
+
+        let bob = Member::receiver("bob");
+        let second = Participant::enter(addr, &room, bob.clone(), |_| {}).unwrap();
+        assert_eq!(second.members(), vec![alice.clone()]);
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            rx.recv_timeout(timeout).unwrap(),
+            RoomEvent::Joined(bob.clone())
+        );
+        assert_eq!(first.members(), vec![bob.clone()]);
+
+        assert!(matches!(
+            Participant::enter(addr, &room, alice, |_| {}),
+            Err(Error::Protocol(ProtocolError::RelayRefused(_)))
+        ));
+        drop(second);
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), RoomEvent::Left(bob));
+        assert!(first.members().is_empty() && first.is_present());
+    }
+
+    #[test]
+    fn test_room_receiver() {
+        let relay = spawn_relay();
+        let room = Ticket::new("jam").with_token("s3cr3t");
+        let (_alice, alice) = band(relay, &room, "alice");
+        let (_bob, bob) = band(relay, &room, "bob");
+
+        let (conn, output) = channel_output(0);
+        let receiver = RoomReceiver::enter(
+            vec![conn],
+            relay,
+            &room,
+            "carol",
+            Subscription::only(["alice"]),
+            ReceiverConfig::default(),
+            Credentials::new("carol"),
+        )
+        .unwrap();
+        assert_eq!(receiver.senders(), ["alice"]);
+        bob.play_on(0, &[0x90, 0x41, 0x7F]);
+        alice.play_on(0, &[0x90, 0x40, 0x7F]);
+        assert_eq!(next(&output), [0x90, 0x40, 0x7F]);
+
+        receiver.subscribe("bob").unwrap();
+        assert_eq!(receiver.senders(), ["alice", "bob"]);
+        bob.play_on(0, &[0x90, 0x42, 0x7F]);
+        assert_eq!(next(&output), [0x90, 0x42, 0x7F]);
+
+        // the notes held by a sender are released once unsubscribed from
+        receiver.unsubscribe("alice");
+        assert_eq!(receiver.senders(), ["bob"]);
+        assert_eq!(next(&output), [0x80, 0x40, 0]);
+        alice.play_on(0, &[0x90, 0x43, 0x7F]);
+        bob.play_on(0, &[0x80, 0x42, 0x00]);
+        assert_eq!(next(&output), [0x80, 0x42, 0x00]);
+        assert!(output.try_recv().is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use crate::tls::{ClientTls, Fingerprint, ServerTls};
+use mio::net::TcpStream;
+use mio::{Interest, Registry, Token};
+use rustls::{ClientConnection, ConnectionCommon, ServerConnection, StreamOwned};
+use socket2::SockRef;
+use std::io::{self, ErrorKind, Read, Write};
+use std::time::Duration;
+
+/// Maximum time given to the distant peer to complete the TLS handshake or to answer a handshake message
+pub(crate) const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Maximum number of bytes waiting for a full non-blocking connection to drain, beyond which the distant peer is dropped
+pub(crate) const OUTGOING_LIMIT: usize = 256 * 1024;
+
+/// TCP connection to a distant peer, optionally encrypted with TLS
+///
+/// It is blocking while the peers introduce themselves, then switched to non-blocking mode
+/// to be registered in the event loop of the net_thread.
+pub(crate) enum Stream {
+    Plain(TcpStream),
+    Server(Box<StreamOwned<ServerConnection, TcpStream>>),
+    Client(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    /// Wrap a connection accepted by a sender, completing the TLS handshake when `tls` is set
+    pub fn accept(sock: TcpStream, tls: Option<&ServerTls>) -> io::Result<Self> {
+        // accepted by a non-blocking listener
+        SockRef::from(&sock).set_nonblocking(false)?;
+        let Some(tls) = tls else {
+            return Ok(Stream::Plain(sock));
+        };
+        let conn = ServerConnection::new(tls.config()).map_err(io::Error::other)?;
+        let mut stream = StreamOwned::new(conn, sock);
+        handshake(&mut stream.conn, &mut stream.sock)?;
+        Ok(Stream::Server(Box::new(stream)))
+    }
+
+    /// Wrap a connection opened by a receiver, completing the TLS handshake when `tls` is set
+    pub fn connect(sock: TcpStream, tls: Option<&ClientTls>) -> io::Result<Self> {
+        let Some(tls) = tls else {
+            return Ok(Stream::Plain(sock));
+        };
+        let server_name = tls.server_name(sock.peer_addr()?.ip());
+        let conn = ClientConnection::new(tls.config(), server_name).map_err(io::Error::other)?;
+        let mut stream = StreamOwned::new(conn, sock);
+        handshake(&mut stream.conn, &mut stream.sock)?;
+        Ok(Stream::Client(Box::new(stream)))
+    }
+
+    /// Fingerprint of the certificate the distant peer authenticated with, over mutual TLS
+    pub fn peer_fingerprint(&self) -> Option<Fingerprint> {
+        let certs = match self {
+            Stream::Plain(_) => None,
+            Stream::Server(stream) => stream.conn.peer_certificates(),
+            Stream::Client(stream) => stream.conn.peer_certificates(),
+        };
+        certs?.first().map(Fingerprint::of)
+    }
+
+    /// Underlying TCP connection
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(sock) => sock,
+            Stream::Server(stream) => &stream.sock,
+            Stream::Client(stream) => &stream.sock,
+        }
+    }
+
+    /// Underlying TCP connection, to be registered in an event loop
+    pub fn tcp_mut(&mut self) -> &mut TcpStream {
+        match self {
+            Stream::Plain(sock) => sock,
+            Stream::Server(stream) => &mut stream.sock,
+            Stream::Client(stream) => &mut stream.sock,
+        }
+    }
+
+    /// Set the timeout of the blocking reads, `None` waiting forever
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        SockRef::from(self.tcp()).set_read_timeout(timeout)
+    }
+
+    /// Switch the connection to non-blocking mode, before registering it in an event loop
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        SockRef::from(self.tcp()).set_nonblocking(nonblocking)
+    }
+}
+
+/// Bytes written to a non-blocking [Stream], kept until the connection drains so a slow distant peer never
+/// blocks the event loop
+///
+/// The connection is registered for writable events while bytes are pending (see [Outgoing::watch]),
+/// [Outgoing::flush] being called on each of them.
+#[derive(Default)]
+pub(crate) struct Outgoing {
+    buf: Vec<u8>,
+    /// bytes were written since the last flush, e.g. TLS records still buffered by the session
+    unflushed: bool,
+    /// the connection is registered for writable events
+    watching: bool,
+}
+
+impl Outgoing {
+    /// Write `buf` after the bytes already pending, keeping what the connection does not take yet.
+    /// Fails once more than [OUTGOING_LIMIT] bytes are pending.
+    pub fn send(&mut self, stream: &mut Stream, buf: &[u8]) -> io::Result<()> {
+        if self.buf.len() + buf.len() > OUTGOING_LIMIT {
+            return Err(io::Error::other(format!(
+                "more than {} bytes waiting for the distant peer",
+                OUTGOING_LIMIT
+            )));
+        }
+        self.buf.extend_from_slice(buf);
+        self.flush(stream)
+    }
+
+    /// Write and flush the pending bytes until the connection would block
+    pub fn flush(&mut self, stream: &mut Stream) -> io::Result<()> {
+        while !self.buf.is_empty() {
+            match stream.write(&self.buf) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(len) => {
+                    self.buf.drain(..len);
+                    self.unflushed = true;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        while self.unflushed {
+            match stream.flush() {
+                Ok(()) => self.unflushed = false,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Return `true` while bytes wait for the connection to drain
+    pub fn is_pending(&self) -> bool {
+        !self.buf.is_empty() || self.unflushed
+    }
+
+    /// Register the connection of `token` for writable events while bytes are pending, and only for readable ones otherwise
+    pub fn watch(
+        &mut self,
+        registry: &Registry,
+        stream: &mut Stream,
+        token: Token,
+    ) -> io::Result<()> {
+        let pending = self.is_pending();
+        if self.watching == pending {
+            return Ok(());
+        }
+        let interest = match pending {
+            true => Interest::READABLE | Interest::WRITABLE,
+            false => Interest::READABLE,
+        };
+        registry.reregister(stream.tcp_mut(), token, interest)?;
+        self.watching = pending;
+        Ok(())
+    }
+}
+
+/// Drive the TLS handshake to completion, the distant peer being given [HANDSHAKE_TIMEOUT] to answer
+fn handshake<D>(conn: &mut ConnectionCommon<D>, sock: &mut TcpStream) -> io::Result<()> {
+    let socket = SockRef::from(&*sock);
+    socket.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    socket.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    while conn.is_handshaking() {
+        conn.complete_io(sock)?;
+    }
+    // flush the last handshake messages (e.g. TLS 1.3 session tickets)
+    while conn.wants_write() {
+        conn.write_tls(sock)?;
+    }
+    let socket = SockRef::from(&*sock);
+    socket.set_read_timeout(None)?;
+    socket.set_write_timeout(None)
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(sock) => sock.read(buf),
+            Stream::Server(stream) => stream.read(buf),
+            Stream::Client(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(sock) => sock.write(buf),
+            Stream::Server(stream) => stream.write(buf),
+            Stream::Client(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(sock) => sock.flush(),
+            Stream::Server(stream) => stream.flush(),
+            Stream::Client(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Drop for Stream {
+    /// Notify the distant peer the TLS session is closed, so it can tell a normal end from a truncated connection
+    fn drop(&mut self) {
+        match self {
+            Stream::Plain(_) => (),
+            Stream::Server(stream) => close_notify(&mut stream.conn, &mut stream.sock),
+            Stream::Client(stream) => close_notify(&mut stream.conn, &mut stream.sock),
+        }
+    }
+}
+
+fn close_notify<D>(conn: &mut ConnectionCommon<D>, sock: &mut TcpStream) {
+    conn.send_close_notify();
+    while conn.wants_write() {
+        if conn.write_tls(sock).is_err() {
+            break;
+        }
+    }
+}
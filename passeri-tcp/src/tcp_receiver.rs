@@ -1,85 +1,498 @@
+use crate::relay;
+use crate::stream::{Outgoing, Stream, HANDSHAKE_TIMEOUT};
+use crate::tls::ClientTls;
 use log::{debug, trace};
-use midir::MidiOutputConnection;
-use passeri_api::midi::MidiParser;
+use mio::{Events, Interest, Poll, Token};
+use passeri_api::metrics::Metrics;
+use passeri_api::midi::{ClockConfig, Control, MidiParser, Output, OutputConnection};
+use passeri_api::net::cable::{Cable, CableSwitch};
+use passeri_api::net::heartbeat::{Beat, Heartbeat, HeartbeatConfig};
+use passeri_api::net::lobby::{Credentials, Features, Hello, Message};
+use passeri_api::net::notifier::Wake;
 use passeri_api::net::receiver::{Request, Responder, Response, Thread, ThreadReturn};
+use passeri_api::net::relay::Ticket;
+use passeri_api::{Error, ProtocolError, TransportError};
 use std::net::{SocketAddr, TcpStream};
-use std::sync::mpsc;
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
 
 type PasseriReq = (Request, Responder);
 
-use std::io::Read;
+use std::io::{ErrorKind, Read};
+use std::time::{Duration, Instant};
+
+/// Token of the connection to the sender in the event loop
+const DISTANT: Token = Token(0);
+/// Token of the [mio::Waker] called after each request
+const WAKER: Token = Token(1);
+/// Interval at which the heartbeat is checked
+const TICK: Duration = Duration::from_millis(50);
+/// Maximum number of messages held until the first [Request::Receive], the following ones being dropped
+const HELD_LIMIT: usize = 1024;
+
+/// Options of the TCP [Receiver]
+#[derive(Debug, Clone, Default)]
+pub struct ReceiverConfig {
+    /// when set, the connection to the sender is encrypted with TLS
+    pub tls: Option<ClientTls>,
+    /// heartbeat exchanged with the sender once accepted
+    pub heartbeat: HeartbeatConfig,
+    /// when set, the incoming MIDI Clock is replaced by a smoothed one generated locally
+    pub clock: Option<ClockConfig>,
+    /// when set, the address of the sender is the one of a relay, which pairs the receiver with a sender of the room
+    pub relay: Option<Ticket>,
+}
+
+/// Answer the sender [Hello] with `credentials`, then wait for the host to accept or reject the receiver,
+/// returning the features agreed for the session
+fn introduce(
+    distant: &mut Stream,
+    credentials: &Credentials,
+    midi_port: &str,
+) -> Result<Features, Error> {
+    distant
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .map_err(TransportError::Configure)?;
+    let sender = Message::expect(distant, "a hello", |message| match message {
+        Message::Hello(hello) => Some(hello),
+        _ => None,
+    })?;
+    debug!(
+        "sender \"{}\" bridging \"{}\" (protocol {}, features {})",
+        sender.name, sender.midi_port, sender.version, sender.features
+    );
+    let hello = match Hello::answer(credentials, midi_port, Receiver::FEATURES, &sender) {
+        Ok((hello, _)) => hello,
+        Err(err) => {
+            let _ = Message::Reject(err.to_string()).write_to(distant);
+            return Err(err.into());
+        }
+    };
+    Message::Hello(hello).write_to(distant)?;
+
+    // the host may take its time to approve the receiver
+    distant
+        .set_read_timeout(None)
+        .map_err(TransportError::Configure)?;
+    debug!("waiting for the sender approval");
+    match Message::read_from(distant)? {
+        Message::Accept(features) => Ok(features),
+        Message::Reject(reason) => Err(ProtocolError::Rejected(reason).into()),
+        message => Err(ProtocolError::InvalidMessage(format!(
+            "expecting a verdict, got {:?}",
+            message
+        ))
+        .into()),
+    }
+}
+
+/// Stream received from the sender, read as soon as it accepted the receiver so its pings are answered
+struct Session {
+    parser: MidiParser,
+    heartbeat: Heartbeat,
+    /// cable of the messages received
+    cables: CableSwitch,
+    /// messages received before the first [Request::Receive], played once it is processed
+    held: Option<Vec<(Cable, Vec<u8>)>>,
+}
 
 /// Implementation of the [Receiver Thread Trait](Thread) over TCP network
 pub struct Receiver {
-    midi_tx: MidiOutputConnection,
-    distant: TcpStream,
+    poll: Poll,
+    waker: Arc<mio::Waker>,
+    /// MIDI out port of each cable
+    outputs: Vec<Output>,
+    distant: Stream,
+    /// bytes waiting for the connection to drain
+    outgoing: Outgoing,
     messenger_rx: mpsc::Receiver<PasseriReq>,
+    metrics: Metrics,
+    /// taken while the connection is read
+    session: Option<Session>,
+}
+
+impl Receiver {
+    /// Start forwarding the MIDI messages carried by the connection, beginning with the ones held so far
+    fn receive(&mut self, responder: Responder) -> Result<(), ThreadReturn> {
+        let held = self
+            .session
+            .as_mut()
+            .and_then(|session| session.held.take())
+            .unwrap_or_default();
+        responder.send(Response::StartReceiving)?;
+        for (cable, msg) in held {
+            self.play(cable, &msg)?;
+        }
+        self.read()
+    }
+
+    /// Send a message to the MIDI out port of its `cable`
+    fn play(&mut self, cable: Cable, msg: &[u8]) -> Result<(), ThreadReturn> {
+        let Some(output) = self.outputs.get_mut(cable as usize) else {
+            trace!("no MIDI port for cable {}, drop {:?}", cable, msg);
+            self.metrics.record_drop();
+            return Ok(());
+        };
+        Ok(output.forward(msg, &self.metrics)?)
+    }
+
+    /// Read the connection until it would block, forwarding the MIDI messages to the port of their cable
+    fn read(&mut self) -> Result<(), ThreadReturn> {
+        let Some(mut session) = self.session.take() else {
+            return Ok(());
+        };
+        let mut buf: [u8; 1024] = [0; 1024];
+        loop {
+            let len = match self.distant.read(&mut buf) {
+                Ok(len) => len,
+                // the sender closed the connection without ending the TLS session
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => 0,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(TransportError::Read(err).into()),
+            };
+
+            if len == 0 {
+                return Err(ThreadReturn::ReceiveEnd);
+            }
+            let (midi, answers) = session.heartbeat.receive(&buf[..len]);
+            self.beat(&mut session.heartbeat, answers)?;
+            for (cable, run) in session.cables.strip(&midi) {
+                for msg in session.parser.parse(run) {
+                    match session.held.as_mut() {
+                        Some(held) if held.len() < HELD_LIMIT => held.push((cable, msg)),
+                        Some(_) => {
+                            trace!("not receiving yet, drop {:?}", msg);
+                            self.metrics.record_drop();
+                        }
+                        None => self.play(cable, &msg)?,
+                    }
+                }
+            }
+            trace!("MIDI -> {} bytes", len);
+        }
+        self.session = Some(session);
+        Ok(())
+    }
+
+    /// Write the answers to the sender heartbeat, and ping it when due
+    fn beat(&mut self, heartbeat: &mut Heartbeat, answers: Vec<Beat>) -> Result<(), ThreadReturn> {
+        for beat in answers.into_iter().chain(heartbeat.poll()?) {
+            trace!("heartbeat {:?}", beat);
+            self.outgoing
+                .send(&mut self.distant, &beat.encode())
+                .map_err(TransportError::Write)?;
+        }
+        Ok(())
+    }
+
+    /// Process the pending requests, returning `false` once asked to stop
+    fn process(&mut self) -> Result<bool, ThreadReturn> {
+        loop {
+            let (req, responder) = match self.messenger_rx.try_recv() {
+                Ok(req) => req,
+                Err(TryRecvError::Empty) => return Ok(true),
+                Err(TryRecvError::Disconnected) => return Err(ThreadReturn::Recv(mpsc::RecvError)),
+            };
+            match req {
+                Request::Receive => self.receive(responder)?,
+                Request::Control(control) => self.control(responder, control)?,
+                Request::Stop => {
+                    let _ = responder.send(Response::Stopped);
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    /// Apply a pause, mute, solo or panic request to the MIDI out port of every cable
+    fn control(&mut self, responder: Responder, control: Control) -> Result<(), ThreadReturn> {
+        for output in self.outputs.iter_mut() {
+            output.control(control)?;
+        }
+        Ok(responder.send(Response::Applied)?)
+    }
+
+    /// Time to wait for an event, the heartbeat and the generated clocks only needing to be checked while connected
+    fn timeout(&self) -> Option<Duration> {
+        self.session.as_ref()?;
+        let now = Instant::now();
+        let tick = self.outputs.iter().filter_map(Output::next_tick).min();
+        Some(tick.map_or(TICK, |at| at.saturating_duration_since(now).min(TICK)))
+    }
+
+    /// Event loop of [Thread::run], returning once asked to stop or when the stream ends
+    fn serve(&mut self) -> Result<(), ThreadReturn> {
+        let mut events = Events::with_capacity(8);
+        loop {
+            match self.poll.poll(&mut events, self.timeout()) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(TransportError::Poll(err).into()),
+            }
+            for event in events.iter().filter(|event| event.token() == DISTANT) {
+                if event.is_writable() {
+                    self.outgoing
+                        .flush(&mut self.distant)
+                        .map_err(TransportError::Write)?;
+                }
+                if event.is_readable() || event.is_read_closed() {
+                    self.read()?;
+                }
+            }
+            if !self.process()? {
+                return Ok(());
+            }
+            if let Some(mut session) = self.session.take() {
+                self.beat(&mut session.heartbeat, vec![])?;
+                self.session = Some(session);
+                self.outgoing
+                    .watch(self.poll.registry(), &mut self.distant, DISTANT)
+                    .map_err(TransportError::Poll)?;
+            }
+            for output in self.outputs.iter_mut() {
+                output.tick(&self.metrics)?;
+                output.watch_port()?;
+            }
+        }
+    }
 }
 
 impl Thread for Receiver {
     type Addr = SocketAddr;
+    type Config = ReceiverConfig;
+    const FEATURES: Features = Features {
+        sysex: true,
+        cables: true,
+        ..Features::NONE
+    };
 
     fn new(
         addr: SocketAddr,
-        midi_tx: MidiOutputConnection,
+        config: Self::Config,
+        midi_tx: Vec<OutputConnection>,
         messenger_rx: mpsc::Receiver<PasseriReq>,
-    ) -> Result<Self, String> {
+        metrics: Metrics,
+        credentials: Credentials,
+    ) -> Result<Self, Error> {
         debug!("try to connect to {}", addr);
-        let distant = match TcpStream::connect(addr) {
-            Ok(result) => result,
-            Err(err) => {
-                return Err(format!("{}", err));
-            }
+        let distant = match config.relay.as_ref() {
+            Some(ticket) => relay::join(addr, ticket)?,
+            None => TcpStream::connect(addr).map_err(|source| TransportError::Connect {
+                addr: addr.to_string(),
+                source,
+            })?,
+        };
+        let distant = mio::net::TcpStream::from_std(distant);
+        let mut distant =
+            Stream::connect(distant, config.tls.as_ref()).map_err(TransportError::Handshake)?;
+        let midi_port = midi_tx
+            .iter()
+            .map(|conn| conn.port().name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let features = introduce(&mut distant, &credentials, &midi_port)?;
+        debug!("accepted by {} with features {}", addr, features);
+
+        let poll = Poll::new().map_err(TransportError::Poll)?;
+        let waker =
+            Arc::new(mio::Waker::new(poll.registry(), WAKER).map_err(TransportError::Poll)?);
+        // the heartbeat of the sender starts as soon as it accepted the receiver
+        distant
+            .set_nonblocking(true)
+            .map_err(TransportError::Configure)?;
+        poll.registry()
+            .register(distant.tcp_mut(), DISTANT, Interest::READABLE)
+            .map_err(TransportError::Poll)?;
+        let session = Session {
+            parser: MidiParser::new(),
+            heartbeat: Heartbeat::new(config.heartbeat, metrics.clone()),
+            cables: CableSwitch::new(),
+            held: Some(vec![]),
         };
 
         Ok(Receiver {
-            midi_tx,
-            distant: distant,
+            poll,
+            waker,
+            outputs: midi_tx
+                .into_iter()
+                .map(|conn| Output::new(conn, config.clock))
+                .collect(),
+            distant,
+            outgoing: Outgoing::default(),
             messenger_rx,
+            metrics,
+            session: Some(session),
         })
     }
 
     fn run(&mut self) -> Result<(), ThreadReturn> {
-        loop {
-            let (req, responder) = self
-                .messenger_rx
-                .recv()
-                .expect("unable to read from the messenger tunnel");
-            match req {
-                Request::Receive => self.receive(responder)?,
+        let ret = self.serve();
+        // whatever the reason, the Note Offs of the notes still held will never arrive
+        for output in self.outputs.iter_mut() {
+            if let Err(err) = output.release_all() {
+                debug!("unable to release the held notes: {}", err);
             }
         }
+        ret
     }
-    /// Starting to listen over UDP socket for
-    fn receive(&mut self, responder: Responder) -> Result<(), ThreadReturn> {
-        let mut buf: [u8; 1024] = [0; 1024];
-        let mut midi_parser = MidiParser::new();
-        responder.send(Response::StartReceiving)?;
-        loop {
-            let len = self
-                .distant
-                .read(&mut buf)
-                .map_err(|err| ThreadReturn::Read(err))?;
 
-            if len == 0 {
-                return Err(ThreadReturn::ReceiveEnd);
-            }
-            for msgs in midi_parser.parse(&buf[..len]) {
-                self.midi_tx
-                    .send(&msgs)
-                    .map_err(|err| ThreadReturn::MidiSendError(err))?;
-                trace!("MIDI -> {} bytes", len);
+    fn waker(&self) -> Wake {
+        let waker = self.waker.clone();
+        Arc::new(move || {
+            if let Err(err) = waker.wake() {
+                debug!("unable to wake up the net_thread: {}", err);
             }
-            if let Some(msg) = midi_parser.flush() {
-                self.midi_tx
-                    .send(&msg)
-                    .map_err(|err| ThreadReturn::MidiSendError(err))?;
-                trace!("MIDI -> {} bytes", len);
-            }
-        }
+        })
     }
 
     fn info(&self) -> String {
-        format!("{}", self.distant.local_addr().unwrap())
+        self.distant
+            .tcp()
+            .local_addr()
+            .map_or_else(|err| err.to_string(), |addr| addr.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{channel_output, next, Distant};
+    use crate::{Sender, SenderConfig};
+    use passeri_api::midi::CLOCK;
+    use passeri_api::net::heartbeat::HEARTBEAT_STATUS;
+    use passeri_api::net::lobby::Lobby;
+    use passeri_api::net::receiver::Receiver as Bridge;
+    use passeri_api::net::sender::Thread as _;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_release_on_end() {
+        let distant = Distant::spawn();
+        let (mut bridge, outputs) = distant.bridge(1, ReceiverConfig::default());
+
+        distant.play_on(0, &[0x90, 0x40, 0x7F]);
+        assert_eq!(next(&outputs[0]), [0x90, 0x40, 0x7F]);
+
+        // the Note Off will never arrive once the sender is gone, the receiver sends it itself
+        distant.stop();
+        assert!(matches!(bridge.join().unwrap(), ThreadReturn::ReceiveEnd));
+        assert_eq!(outputs[0].iter().collect::<Vec<_>>(), [vec![0x80, 0x40, 0]]);
+    }
+
+    #[test]
+    fn test_heartbeat_timeout() {
+        // a sender vanishing without closing the connection, which never answers the heartbeat
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let distant = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            Message::Hello(Lobby::new().hello(Sender::FEATURES).unwrap())
+                .write_to(&mut stream)
+                .unwrap();
+            let Message::Hello(hello) = Message::read_from(&mut stream).unwrap() else {
+                panic!("expecting a hello");
+            };
+            Message::Accept(hello.features)
+                .write_to(&mut stream)
+                .unwrap();
+            let mut ping = [0; 1];
+            stream.read_exact(&mut ping).unwrap();
+            (stream, ping[0])
+        });
+
+        let config = ReceiverConfig {
+            heartbeat: HeartbeatConfig {
+                interval: Duration::from_millis(20),
+                timeout: Duration::from_millis(200),
+            },
+            ..ReceiverConfig::default()
+        };
+        let (conn, _output) = channel_output(0);
+        let mut bridge =
+            Bridge::new::<Receiver>(vec![conn], addr, config, Credentials::new("studio")).unwrap();
+        bridge.receive().unwrap();
+
+        let (_stream, ping) = distant.join().unwrap();
+        assert_eq!(ping, HEARTBEAT_STATUS);
+        assert!(matches!(
+            bridge.join().unwrap(),
+            ThreadReturn::Transport(TransportError::HeartbeatTimeout(_))
+        ));
+    }
+
+    #[test]
+    fn test_late_receive() {
+        let heartbeat = HeartbeatConfig {
+            interval: Duration::from_millis(20),
+            timeout: Duration::from_millis(200),
+        };
+        let sender_config = SenderConfig {
+            heartbeat,
+            ..SenderConfig::default()
+        };
+        let distant = Distant::spawn_on("127.0.0.1:0".parse().unwrap(), sender_config);
+        distant.admit_all();
+        let (conn, output) = channel_output(0);
+        let config = ReceiverConfig {
+            heartbeat,
+            ..ReceiverConfig::default()
+        };
+        let bridge =
+            Bridge::new::<Receiver>(vec![conn], distant.addr, config, Credentials::new("studio"))
+                .unwrap();
+
+        // the pings of the sender are answered before receiving, the messages played meanwhile being held
+        distant.play_on(0, &[0x90, 0x40, 0x7F]);
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(output.try_recv().is_err());
+        bridge.receive().unwrap();
+        assert_eq!(next(&output), [0x90, 0x40, 0x7F]);
+        distant.play_on(0, &[0x80, 0x40, 0]);
+        assert_eq!(next(&output), [0x80, 0x40, 0]);
+    }
+
+    #[test]
+    fn test_clock() {
+        let distant = Distant::spawn();
+        let config = ReceiverConfig {
+            clock: Some(ClockConfig {
+                smoothing: 0.5,
+                latency: Duration::from_millis(100),
+            }),
+            ..ReceiverConfig::default()
+        };
+        let (_bridge, outputs) = distant.bridge(1, config);
+
+        for _ in 0..10 {
+            distant.play_on(0, &[CLOCK]);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        distant.play_on(0, &[0x90, 0x40, 0x7F]);
+
+        // the incoming ticks are replaced by generated ones, sent ahead to compensate for the latency
+        let received: Vec<_> =
+            std::iter::from_fn(|| outputs[0].recv_timeout(Duration::from_millis(500)).ok())
+                .collect();
+        let ticks = received.iter().filter(|msg| msg[..] == [CLOCK]).count();
+        assert!(ticks > 10, "{ticks} ticks generated");
+        assert!(received.contains(&vec![0x90, 0x40, 0x7F]));
+    }
+
+    #[test]
+    fn test_cables() {
+        let distant = Distant::spawn();
+        let (bridge, outputs) = distant.bridge(2, ReceiverConfig::default());
+
+        distant.play_on(1, &[0x90, 0x40, 0x7F]);
+        assert_eq!(next(&outputs[1]), [0x90, 0x40, 0x7F]);
+
+        // the messages of a cable without port are dropped
+        distant.play_on(2, &[0x90, 0x41, 0x7F]);
+        distant.play_on(0, &[0x90, 0x42, 0x7F]);
+        assert_eq!(next(&outputs[0]), [0x90, 0x42, 0x7F]);
+        assert!(outputs[0].try_recv().is_err());
+        assert!(outputs[1].try_recv().is_err());
+        assert_eq!(bridge.stats().dropped, 1);
     }
 }
@@ -1,112 +1,1234 @@
+use crate::relay::RelayHost;
+use crate::stream::{Outgoing, Stream, HANDSHAKE_TIMEOUT};
+use crate::tls::ServerTls;
+use mio::event::Event as PollEvent;
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Registry, Token};
+use passeri_api::net::cable::{Cable, CablePayload, CableSwitch};
+use passeri_api::net::heartbeat::{Beat, Heartbeat, HeartbeatConfig};
+use passeri_api::net::lobby::{ClientInfo, Features, Lobby, Message};
+use passeri_api::net::notifier::Wake;
+use passeri_api::net::relay::Ticket;
 use passeri_api::net::sender::{PasseriReq, Request, Responder, Response, Thread, ThreadReturn};
-use std::collections::HashMap;
-use std::net::{SocketAddr, TcpListener, TcpStream};
-use std::sync::mpsc::{self};
+use passeri_api::net::RESERVED_STATUS;
+use passeri_api::{Error, ProtocolError, TransportError};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
 
-use log::{debug, trace};
-use passeri_api::midi::MidiPayload;
-use std::io::{self, Read, Write};
-use std::sync::mpsc::RecvTimeoutError;
-use std::time::Duration;
+use log::{debug, info, trace, warn};
+use passeri_api::lifecycle::{Event, Lifecycle};
+use passeri_api::metrics::Metrics;
+use passeri_api::midi::{ChannelFilter, Control, MidiState, SnapshotConfig};
+use std::io::{self, ErrorKind, Read};
+use std::time::{Duration, Instant};
 
-const CONNECTION_CHECK_ITV: Duration = Duration::from_secs(10);
+/// Token of the listener in the event loop
+const LISTENER: Token = Token(0);
+/// Token of the [mio::Waker] called after each request, MIDI message and handshake
+const WAKER: Token = Token(1);
+/// Token given to the first receiver streamed to
+const FIRST_SESSION: usize = 2;
+/// Interval at which the heartbeats are checked while streaming
+const TICK: Duration = Duration::from_millis(50);
+/// Maximum number of receivers introducing themselves at the same time, the connections beyond being dropped
+const MAX_HANDSHAKES: usize = 16;
 
 /// `passeri_api::net::Sender` trait implementation over TCP
 type Addr = <Sender as Thread>::Addr;
 
+/// Receiver that completed its handshake in the background, or the reason it failed
+type Admission = (Addr, Result<(Stream, ClientInfo<Addr>), Error>);
+
+/// Options of the TCP [Sender]
+#[derive(Debug, Clone)]
+pub struct SenderConfig {
+    /// when set, receivers must complete a TLS handshake before being offered as clients
+    pub tls: Option<ServerTls>,
+    /// heartbeat exchanged with the receiver while streaming
+    pub heartbeat: HeartbeatConfig,
+    /// state sent to each receiver accepted, before the live stream
+    pub snapshot: SnapshotConfig,
+    /// when set, the address of the sender is the one of a relay, on which it waits for the receivers of the room
+    pub relay: Option<Ticket>,
+    /// time given to the application to accept or reject a receiver admitted by the [Lobby], before it is dropped
+    pub pending_timeout: Duration,
+}
+
+impl Default for SenderConfig {
+    fn default() -> Self {
+        SenderConfig {
+            tls: None,
+            heartbeat: HeartbeatConfig::default(),
+            snapshot: SnapshotConfig::default(),
+            relay: None,
+            pending_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Source of the receivers connections
+enum Listener {
+    /// receivers connecting directly to the sender
+    Tcp(TcpListener),
+    /// receivers paired by a relay
+    Relay(RelayHost),
+}
+
+impl Listener {
+    /// Return the next receiver connection, failing with [ErrorKind::WouldBlock] if there is none
+    fn accept(&mut self, registry: &Registry) -> io::Result<(TcpStream, SocketAddr)> {
+        match self {
+            Listener::Tcp(listener) => listener.accept(),
+            Listener::Relay(relay) => relay.accept(registry, LISTENER),
+        }
+    }
+
+    /// Time left before reconnecting to an unreachable relay
+    fn retry_in(&self) -> Option<Duration> {
+        match self {
+            Listener::Tcp(_) => None,
+            Listener::Relay(relay) => relay.retry_in(),
+        }
+    }
+}
+
+/// Receiver streamed to
+struct Session {
+    addr: Addr,
+    stream: Stream,
+    /// bytes waiting for the connection to drain
+    outgoing: Outgoing,
+    features: Features,
+    heartbeat: Heartbeat,
+    /// cable of the messages sent
+    cables: CableSwitch,
+}
+
+impl Session {
+    /// Read the heartbeat frames sent by the receiver until the connection would block, answering them
+    fn read(&mut self) -> Result<(), ThreadReturn<Addr>> {
+        let mut buf = [0; 64];
+        loop {
+            let len = match self.stream.read(&mut buf) {
+                Ok(len) => len,
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => 0,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(TransportError::Read(err).into()),
+            };
+            if len == 0 {
+                return Err(ThreadReturn::RecvLeave);
+            }
+            let (_, answers) = self.heartbeat.receive(&buf[..len]);
+            self.beat(answers)?;
+        }
+    }
+
+    /// Write the answers to the receiver heartbeat, and ping it when due
+    fn beat(&mut self, answers: Vec<Beat>) -> Result<(), ThreadReturn<Addr>> {
+        for beat in answers.into_iter().chain(self.heartbeat.poll()?) {
+            trace!("heartbeat {:?} with {}", beat, self.addr);
+            self.outgoing
+                .send(&mut self.stream, &beat.encode())
+                .map_err(TransportError::Write)?;
+        }
+        Ok(())
+    }
+
+    /// Forward a MIDI message of `cable`, returning `false` if it is dropped because SysEx or cables are not agreed with the receiver
+    fn forward(&mut self, cable: Cable, msg: &[u8]) -> Result<bool, ThreadReturn<Addr>> {
+        if !self.features.sysex && msg.first() == Some(&0xF0) {
+            trace!("drop SysEx {:?}, not agreed with {}", msg, self.addr);
+            return Ok(false);
+        }
+        if !self.features.cables && cable != 0 {
+            trace!(
+                "drop {:?} of cable {}, not agreed with {}",
+                msg,
+                cable,
+                self.addr
+            );
+            return Ok(false);
+        }
+        // written at once, so the frame is never separated from its message
+        let mut buf = Vec::with_capacity(msg.len() + 2);
+        buf.extend(self.cables.select(cable).into_iter().flatten());
+        buf.extend_from_slice(msg);
+        self.outgoing
+            .send(&mut self.stream, &buf)
+            .map_err(TransportError::Write)?;
+        Ok(true)
+    }
+
+    /// Write the bytes waiting for the connection to drain
+    fn write(&mut self) -> Result<(), ThreadReturn<Addr>> {
+        self.outgoing
+            .flush(&mut self.stream)
+            .map_err(|err| TransportError::Write(err).into())
+    }
+}
+
+/// Receiver admitted by the [Lobby], waiting to be accepted or rejected
+struct Pending {
+    stream: Stream,
+    features: Features,
+    since: Instant,
+}
+
+/// Messages played on a cable
+struct Track {
+    /// pause, mute and solo state of the cable, tracking the notes held
+    filter: ChannelFilter,
+    /// programs and controllers played so far, sent to the receivers joining
+    state: MidiState,
+}
+
+impl Track {
+    fn new(filter: ChannelFilter) -> Self {
+        Track {
+            filter,
+            state: MidiState::new(),
+        }
+    }
+}
+
 /// Implementation of the [Sender Thread Trait](Thread) over TCP network
 pub struct Sender {
-    local: TcpListener,
-    distant: HashMap<Addr, TcpStream>,
-    midi_rx: mpsc::Receiver<MidiPayload>,
+    poll: Poll,
+    waker: Arc<mio::Waker>,
+    local: Listener,
+    addr: Addr,
+    tls: Option<ServerTls>,
+    heartbeat: HeartbeatConfig,
+    /// pending receivers, dropped once waiting for longer than `pending_timeout`
+    distant: HashMap<Addr, Pending>,
+    pending_timeout: Duration,
+    /// pending receivers admitted while the room was closed, returned by the next [Request::OpenRoom]
+    admitted: VecDeque<ClientInfo<Addr>>,
+    /// receivers introducing themselves in the background, up to [MAX_HANDSHAKES]
+    handshakes: usize,
+    admissions_tx: mpsc::Sender<Admission>,
+    admissions_rx: mpsc::Receiver<Admission>,
+    /// receivers streamed to
+    sessions: HashMap<Token, Session>,
+    next_token: usize,
+    /// pause, mute and solo state of the stream, given to the tracks of the cables played for the first time
+    filter: ChannelFilter,
+    /// messages played on each cable
+    tracks: BTreeMap<Cable, Track>,
+    snapshot: SnapshotConfig,
+    midi_rx: mpsc::Receiver<CablePayload>,
     messenger_rx: mpsc::Receiver<PasseriReq<Addr>>,
+    metrics: Metrics,
+    lobby: Lobby,
+    lifecycle: Lifecycle,
+    /// responder of the pending [Request::OpenRoom], while the room is open
+    room: Option<Responder<Addr>>,
 }
 
 impl Thread for Sender {
     type Addr = SocketAddr;
+    type Config = SenderConfig;
+    const TRANSPORT: &'static str = "tcp";
+    const FEATURES: Features = Features {
+        sysex: true,
+        cables: true,
+        ..Features::NONE
+    };
 
     fn new(
         addr: Self::Addr,
-        midi_rx: mpsc::Receiver<MidiPayload>,
+        config: Self::Config,
+        midi_rx: mpsc::Receiver<CablePayload>,
         messenger_rx: mpsc::Receiver<PasseriReq<Self::Addr>>,
-    ) -> Result<Self, String> {
-        let local = match TcpListener::bind(addr) {
-            Ok(result) => result,
-            Err(err) => {
-                // error!("fail to bind on {}", addr);
-                return Err(format!("{}", err));
+        metrics: Metrics,
+        lobby: Lobby,
+        lifecycle: Lifecycle,
+    ) -> Result<Self, Error> {
+        let poll = Poll::new().map_err(TransportError::Poll)?;
+        let (local, addr) = match config.relay {
+            // connected once the room is open, see Sender::poll_room
+            Some(ticket) => (Listener::Relay(RelayHost::new(addr, ticket)), addr),
+            None => {
+                let bind_error = |source| TransportError::Bind {
+                    addr: addr.to_string(),
+                    source,
+                };
+                let local = std::net::TcpListener::bind(addr).map_err(bind_error)?;
+                let addr = local.local_addr().map_err(bind_error)?;
+                // accepted while the room is open, see Sender::poll_room
+                local
+                    .set_nonblocking(true)
+                    .map_err(TransportError::Configure)?;
+                let mut local = TcpListener::from_std(local);
+                poll.registry()
+                    .register(&mut local, LISTENER, Interest::READABLE)
+                    .map_err(TransportError::Poll)?;
+                (Listener::Tcp(local), addr)
             }
         };
+        let waker =
+            Arc::new(mio::Waker::new(poll.registry(), WAKER).map_err(TransportError::Poll)?);
+        let (admissions_tx, admissions_rx) = mpsc::channel();
 
         Ok(Sender {
+            poll,
+            waker,
             local,
+            addr,
+            tls: config.tls,
+            heartbeat: config.heartbeat,
             distant: HashMap::new(),
+            pending_timeout: config.pending_timeout,
+            admitted: VecDeque::new(),
+            handshakes: 0,
+            admissions_tx,
+            admissions_rx,
+            sessions: HashMap::new(),
+            next_token: FIRST_SESSION,
+            filter: ChannelFilter::new(),
+            // the panic messages are sent on the cable 0 even if nothing was played yet
+            tracks: BTreeMap::from([(0, Track::new(ChannelFilter::new()))]),
+            snapshot: config.snapshot,
             midi_rx,
             messenger_rx,
+            metrics,
+            lobby,
+            lifecycle,
+            room: None,
         })
     }
 
     fn run(&mut self) -> Result<(), ThreadReturn<Self::Addr>> {
+        let mut events = Events::with_capacity(64);
         loop {
-            let (req, responder) = self
-                .messenger_rx
-                .recv()
-                .map_err(|err| ThreadReturn::Recv(err))?
-                .into();
+            // the heartbeats only need to be checked while streaming, and the relay while the room is open
+            let retry = self.room.as_ref().and(self.local.retry_in());
+            let timeout = (!self.sessions.is_empty())
+                .then_some(TICK)
+                .into_iter()
+                .chain(retry)
+                .chain(self.expire_in())
+                .min();
+            match self.poll.poll(&mut events, timeout) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(TransportError::Poll(err).into()),
+            }
+            for event in events.iter() {
+                match event.token() {
+                    LISTENER => self.poll_room()?,
+                    WAKER => {}
+                    token => self.poll_session(token, event),
+                }
+            }
+            if self.local.retry_in() == Some(Duration::ZERO) {
+                self.poll_room()?;
+            }
+            // the channels are not pollable, they are drained on each wake-up
+            self.admissions()?;
+            self.expire();
+            if !self.process()? {
+                return Ok(());
+            }
+            self.forward()?;
+            self.beat();
+            self.watch_writes();
+        }
+    }
+
+    fn waker(&self) -> Wake {
+        let waker = self.waker.clone();
+        Arc::new(move || {
+            if let Err(err) = waker.wake() {
+                debug!("unable to wake up the net_thread: {}", err);
+            }
+        })
+    }
+
+    fn info(&self) -> Self::Addr {
+        self.addr
+    }
+}
+
+impl Sender {
+    /// Process the pending requests, returning `false` once asked to stop
+    fn process(&mut self) -> Result<bool, ThreadReturn<Addr>> {
+        loop {
+            let (req, responder) = match self.messenger_rx.try_recv() {
+                Ok(req) => req,
+                Err(TryRecvError::Empty) => return Ok(true),
+                Err(TryRecvError::Disconnected) => return Err(ThreadReturn::Recv(mpsc::RecvError)),
+            };
+            // the MIDI messages played before the request are forwarded before it is applied
+            self.forward()?;
             match req {
                 Request::OpenRoom => self.open_room(responder)?,
-                Request::AcceptClient(addr) => self.send(addr, responder)?,
+                Request::CloseRoom => self.close_room(responder)?,
+                Request::AcceptClient(addr) => self.accept(addr, responder)?,
+                Request::RejectClient(addr, reason) => self.reject(addr, reason, responder)?,
+                Request::Control(control) => self.control(responder, control)?,
+                Request::Stop => {
+                    let _ = responder.send(Response::Stopped);
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    /// Apply a pause, mute, solo or panic request to the filter of every cable, sending the messages it returns to the receivers
+    fn control(
+        &mut self,
+        responder: Responder<Addr>,
+        control: Control,
+    ) -> Result<(), ThreadReturn<Addr>> {
+        self.filter.apply(control);
+        let mut messages = vec![];
+        for (cable, track) in self.tracks.iter_mut() {
+            messages.extend(
+                track
+                    .filter
+                    .apply(control)
+                    .into_iter()
+                    .map(|msg| (*cable, msg)),
+            );
+        }
+        for (cable, msg) in messages {
+            trace!("release {:?} on cable {}", msg, cable);
+            self.broadcast(cable, &msg);
+        }
+        Ok(responder.send(Response::Applied)?)
+    }
+
+    /// Send a MIDI message of `cable` to every receiver, returning `true` if at least one of them received it
+    fn broadcast(&mut self, cable: Cable, msg: &[u8]) -> bool {
+        let mut sent = false;
+        let mut left = vec![];
+        for (token, session) in self.sessions.iter_mut() {
+            match session.forward(cable, msg) {
+                Ok(forwarded) => sent |= forwarded,
+                Err(ret) => left.push((*token, ret)),
+            }
+        }
+        for (token, ret) in left {
+            self.leave(token, ret);
+        }
+        sent
+    }
+
+    /// Forward the MIDI messages to every receiver, failing with [ThreadReturn::SendEnd] once the MIDI input is closed
+    fn forward(&mut self) -> Result<(), ThreadReturn<Addr>> {
+        loop {
+            let (cable, msg) = match self.midi_rx.try_recv() {
+                Ok(msg) => msg,
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => return Err(ThreadReturn::SendEnd),
+            };
+            let track = self
+                .tracks
+                .entry(cable)
+                .or_insert_with(|| Track::new(self.filter.clone()));
+            // kept up to date even without receiver, for the first one to join
+            track.state.update(&msg.1);
+            if self.sessions.is_empty() {
+                // messages played while no receiver is connected are not worth sending later
+                trace!("no receiver, drop {:?}", msg);
+                continue;
+            }
+            if msg
+                .1
+                .first()
+                .is_some_and(|status| RESERVED_STATUS.contains(status))
+            {
+                trace!("reserved status, drop {:?}", msg);
+                self.metrics.record_drop();
+                continue;
+            }
+
+            if !track.filter.forward(&msg.1) {
+                trace!("filtered {:?}", msg);
+                continue;
+            }
+
+            trace!("send {:?} on cable {}", msg, cable);
+            if self.broadcast(cable, &msg.1) {
+                self.metrics.record_message(&msg.1);
+            } else {
+                self.metrics.record_drop();
             }
         }
     }
 
-    fn send(
+    /// Read the connection of a receiver or write what it is waiting for, the receiver leaving when it is closed
+    fn poll_session(&mut self, token: Token, event: &PollEvent) {
+        let Some(session) = self.sessions.get_mut(&token) else {
+            return;
+        };
+        let mut ret = Ok(());
+        if event.is_writable() {
+            ret = session.write();
+        }
+        if event.is_readable() || event.is_read_closed() {
+            ret = ret.and_then(|()| session.read());
+        }
+        if let Err(ret) = ret {
+            self.leave(token, ret);
+        }
+    }
+
+    /// Watch the connections of the receivers with bytes waiting for them to drain, so a slow receiver
+    /// does not block the others
+    fn watch_writes(&mut self) {
+        let left: Vec<_> = self
+            .sessions
+            .iter_mut()
+            .filter_map(|(token, session)| {
+                let watched =
+                    session
+                        .outgoing
+                        .watch(self.poll.registry(), &mut session.stream, *token);
+                watched
+                    .err()
+                    .map(|err| (*token, TransportError::Poll(err).into()))
+            })
+            .collect();
+        for (token, ret) in left {
+            self.leave(token, ret);
+        }
+    }
+
+    /// Ping the receivers when due, the ones silent for too long leaving
+    fn beat(&mut self) {
+        let left: Vec<_> = self
+            .sessions
+            .iter_mut()
+            .filter_map(|(token, session)| session.beat(vec![]).err().map(|ret| (*token, ret)))
+            .collect();
+        for (token, ret) in left {
+            self.leave(token, ret);
+        }
+    }
+
+    /// Forget a receiver that left, emitting [Event::ClientLeft] once there is no receiver left
+    fn leave(&mut self, token: Token, ret: ThreadReturn<Addr>) {
+        let Some(mut session) = self.sessions.remove(&token) else {
+            return;
+        };
+        info!("receiver {} left: {}", session.addr, ret);
+        if let Err(err) = self.poll.registry().deregister(session.stream.tcp_mut()) {
+            debug!("unable to deregister {}: {}", session.addr, err);
+        }
+        if self.sessions.is_empty() {
+            self.lifecycle.emit(Event::ClientLeft);
+        }
+    }
+
+    /// Start streaming to a pending receiver, along with the ones already streamed to
+    fn accept(
         &mut self,
-        distant: SocketAddr,
-        responder: Responder<Self::Addr>,
-    ) -> Result<(), ThreadReturn<Self::Addr>> {
-        if let Some(mut stream) = self.distant.remove(&distant) {
-            responder.send(Response::StartStream).unwrap();
-            let mut peek_buf = [0];
-
-            loop {
-                match self.midi_rx.recv_timeout(CONNECTION_CHECK_ITV) {
-                    Ok(msg) => {
-                        trace!("send {:?}", msg);
-                        stream
-                            .write(&msg.1)
-                            .map_err(|err| ThreadReturn::Write(err))?;
-                    }
-                    Err(RecvTimeoutError::Disconnected) => break,
-                    Err(RecvTimeoutError::Timeout) => {
-                        if stream.read(&mut peek_buf).is_ok_and(|x| x == 0) {
-                            debug!("received leaved");
-                            return Err(ThreadReturn::RecvLeave);
-                        }
-                    }
+        distant: Addr,
+        responder: Responder<Addr>,
+    ) -> Result<(), ThreadReturn<Addr>> {
+        let Some(Pending {
+            stream, features, ..
+        }) = self.distant.remove(&distant)
+        else {
+            return Ok(responder.send(Response::ClientNotFound)?);
+        };
+
+        let mut session = Session {
+            addr: distant,
+            stream,
+            outgoing: Outgoing::default(),
+            features,
+            heartbeat: Heartbeat::new(self.heartbeat, self.metrics.clone()),
+            cables: CableSwitch::new(),
+        };
+        // the verdict and the snapshot wait for the connection to drain, so a receiver not reading does not stall the loop
+        session
+            .stream
+            .set_nonblocking(true)
+            .map_err(TransportError::Configure)?;
+        let mut verdict = vec![];
+        let sent = Message::Accept(features)
+            .write_to(&mut verdict)
+            .and_then(|()| {
+                session
+                    .outgoing
+                    .send(&mut session.stream, &verdict)
+                    .map_err(|err| TransportError::Write(err).into())
+            });
+        if let Err(err) = sent {
+            debug!("{} left while pending: {}", distant, err);
+            return Ok(responder.send(Response::ClientNotFound)?);
+        }
+        // bring the receiver up to date with the programs and controllers played before it joined
+        for (cable, track) in self.tracks.iter_mut() {
+            for msg in track.state.snapshot(&self.snapshot) {
+                if !track.filter.forward(&msg) {
+                    continue;
+                }
+                trace!("snapshot {:?} on cable {} to {}", msg, cable, distant);
+                if let Err(err) = session.forward(*cable, &msg) {
+                    debug!("{} left while sending the snapshot: {}", distant, err);
+                    return Ok(responder.send(Response::ClientNotFound)?);
                 }
             }
-            Err(ThreadReturn::SendEnd)
-        } else {
-            Ok(responder.send(Response::ClientNotFound)?)
         }
+
+        let token = Token(self.next_token);
+        self.next_token += 1;
+        self.poll
+            .registry()
+            .register(session.stream.tcp_mut(), token, Interest::READABLE)
+            .map_err(TransportError::Poll)?;
+        self.sessions.insert(token, session);
+        Ok(responder.send(Response::StartStream)?)
     }
 
-    fn info(&self) -> Self::Addr {
-        self.local.local_addr().unwrap()
+    /// Open the room, answering with a receiver already admitted or the next one accepted by [Sender::poll_room]
+    fn open_room(&mut self, responder: Responder<Addr>) -> Result<(), ThreadReturn<Addr>> {
+        if let Some(previous) = self.room.replace(responder) {
+            previous.send(Response::RoomClosed)?;
+        }
+        match self.admitted.pop_front() {
+            Some(client) => self.announce(client),
+            None => self.poll_room(),
+        }
+    }
+
+    /// Close the room, answering the pending [Request::OpenRoom] if any
+    fn close_room(&mut self, responder: Responder<Addr>) -> Result<(), ThreadReturn<Addr>> {
+        if let Some(room) = self.room.take() {
+            room.send(Response::RoomClosed)?;
+        }
+        Ok(responder.send(Response::RoomClosed)?)
+    }
+
+    /// Accept the pending connections while the room is open, each one being introduced in its own thread
+    /// so a slow receiver does not stall the event loop
+    fn poll_room(&mut self) -> Result<(), ThreadReturn<Addr>> {
+        while self.room.is_some() {
+            let (distant, addr) = match self.local.accept(self.poll.registry()) {
+                Ok(accepted) => accepted,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(TransportError::Accept(err).into()),
+            };
+            if self.handshakes >= MAX_HANDSHAKES {
+                warn!(
+                    "{} receivers introducing themselves, dropping {}",
+                    MAX_HANDSHAKES, addr
+                );
+                continue;
+            }
+            self.handshakes += 1;
+            let tls = self.tls.clone();
+            let lobby = self.lobby.clone();
+            let admissions = self.admissions_tx.clone();
+            let waker = self.waker.clone();
+            std::thread::spawn(move || {
+                let admitted = Stream::accept(distant, tls.as_ref())
+                    .map_err(|err| TransportError::Handshake(err).into())
+                    .and_then(|distant| admit(distant, addr, &lobby));
+                if admissions.send((addr, admitted)).is_ok() {
+                    let _ = waker.wake();
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Collect the receivers introduced in the background, the ones failing the TLS handshake or the [Lobby] checks being dropped
+    fn admissions(&mut self) -> Result<(), ThreadReturn<Addr>> {
+        while let Ok((addr, admitted)) = self.admissions_rx.try_recv() {
+            self.handshakes -= 1;
+            match admitted {
+                Ok((stream, client)) => {
+                    let pending = Pending {
+                        stream,
+                        features: client.features,
+                        since: Instant::now(),
+                    };
+                    self.distant.insert(client.addr, pending);
+                    self.announce(client)?;
+                }
+                Err(err) => warn!("rejecting {}: {}", addr, err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Time left before the oldest pending receiver is dropped
+    fn expire_in(&self) -> Option<Duration> {
+        let since = self.distant.values().map(|pending| pending.since).min()?;
+        Some((since + self.pending_timeout).saturating_duration_since(Instant::now()))
+    }
+
+    /// Drop the pending receivers neither accepted nor rejected in time
+    fn expire(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .distant
+            .iter()
+            .filter(|(_, pending)| now.duration_since(pending.since) >= self.pending_timeout)
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in expired {
+            let Some(mut pending) = self.distant.remove(&addr) else {
+                continue;
+            };
+            self.admitted.retain(|client| client.addr != addr);
+            info!("dropping {}, neither accepted nor rejected in time", addr);
+            // best effort, the connection is closed anyway
+            let _ = pending.stream.set_nonblocking(true);
+            let _ = Message::Reject("not accepted in time".into()).write_to(&mut pending.stream);
+        }
+    }
+
+    /// Answer the [Request::OpenRoom] with an admitted receiver, keeping it for the next one if the room is closed
+    fn announce(&mut self, client: ClientInfo<Addr>) -> Result<(), ThreadReturn<Addr>> {
+        match self.room.take() {
+            Some(room) => Ok(room.send(Response::NewClient(client))?),
+            None => {
+                self.admitted.push_back(client);
+                Ok(())
+            }
+        }
+    }
+
+    /// Notify a pending receiver of its rejection and close its connection
+    fn reject(
+        &mut self,
+        distant: Addr,
+        reason: String,
+        responder: Responder<Addr>,
+    ) -> Result<(), ThreadReturn<Addr>> {
+        self.admitted.retain(|client| client.addr != distant);
+        match self.distant.remove(&distant) {
+            Some(Pending { mut stream, .. }) => {
+                if let Err(err) = Message::Reject(reason).write_to(&mut stream) {
+                    debug!("unable to notify {} of its rejection: {}", distant, err);
+                }
+                Ok(responder.send(Response::ClientRejected)?)
+            }
+            None => Ok(responder.send(Response::ClientNotFound)?),
+        }
     }
 }
 
-impl Sender {
-    /// Starting to listen over UDP socket for
-    fn open_room(&mut self, responder: Responder<Addr>) -> Result<(), ThreadReturn<Addr>> {
-        let (distant, addr) = self.local.accept()?;
-        distant
-            .set_nonblocking(true)
-            .expect("set_nonblocking call failed");
-        self.distant.insert(addr, distant);
-        responder
-            .send(Response::NewClient(addr))
-            .map_err(|err| ThreadReturn::Send(err))
+/// Introduce the sender to a new receiver and check its answer against the [Lobby] rules
+fn admit(
+    mut distant: Stream,
+    addr: Addr,
+    lobby: &Lobby,
+) -> Result<(Stream, ClientInfo<Addr>), Error> {
+    distant
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .map_err(TransportError::Configure)?;
+    let hello = lobby.hello(Sender::FEATURES)?;
+    Message::Hello(hello.clone()).write_to(&mut distant)?;
+    // an incompatible receiver rejects the sender instead of answering
+    let answer = Message::expect(&mut distant, "a hello", |message| match message {
+        Message::Hello(answer) => Some(Ok(answer)),
+        Message::Reject(reason) => Some(Err(reason)),
+        _ => None,
+    })?
+    .map_err(|reason| ProtocolError::InvalidMessage(format!("receiver left: {}", reason)))?;
+    distant
+        .set_read_timeout(None)
+        .map_err(TransportError::Configure)?;
+
+    // the receivers authenticated by their certificate are matched by its fingerprint
+    let identity = distant
+        .peer_fingerprint()
+        .map(|fingerprint| fingerprint.to_string());
+    match lobby.admit(addr, identity.as_deref(), &hello, answer) {
+        Ok(client) => Ok((distant, client)),
+        Err(reason) => {
+            let _ = Message::Reject(reason.clone()).write_to(&mut distant);
+            Err(ProtocolError::Rejected(reason).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{answer, channel_output, introduce, next, Distant};
+    use crate::{Receiver, ReceiverConfig};
+    use passeri_api::midi::{InputConnection, MidiPort};
+    use passeri_api::net::cable::CABLE_STATUS;
+    use passeri_api::net::heartbeat::HEARTBEAT_STATUS;
+    use passeri_api::net::lobby::Credentials;
+    use passeri_api::net::receiver::Receiver as Bridge;
+    use passeri_api::net::receiver::Thread as _;
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_lobby() {
+        let lobby = Lobby::new();
+        lobby.set_key(Some("secret"));
+        let sender = Distant::spawn_with(
+            "127.0.0.1:0".parse().unwrap(),
+            SenderConfig::default(),
+            lobby,
+            Lifecycle::new(),
+        );
+        let addr = sender.addr;
+
+        // receivers without the key are rejected by the lobby itself
+        let room = sender.request(Request::OpenRoom);
+        assert_eq!(
+            introduce(addr, &Credentials::new("intruder")),
+            Message::Reject("credential required".into())
+        );
+
+        // the host rejects the next one
+        let client = std::thread::spawn(move || {
+            introduce(addr, &Credentials::new("studio").with_secret("secret"))
+        });
+        let Response::NewClient(info) = room.recv().unwrap() else {
+            panic!("expecting a new client");
+        };
+        assert_eq!(info.name, "studio");
+        assert_eq!(info.midi_port, "synth");
+        assert!(info.features.sysex);
+        assert!(matches!(
+            sender
+                .request(Request::RejectClient(info.addr, "busy".into()))
+                .recv()
+                .unwrap(),
+            Response::ClientRejected
+        ));
+        assert_eq!(client.join().unwrap(), Message::Reject("busy".into()));
+
+        // closing the room answers the pending request
+        let room = sender.request(Request::OpenRoom);
+        assert!(matches!(
+            sender.request(Request::CloseRoom).recv().unwrap(),
+            Response::RoomClosed
+        ));
+        assert!(matches!(room.recv().unwrap(), Response::RoomClosed));
+    }
+
+    #[test]
+    fn test_handshake_limit() {
+        let sender = Distant::spawn();
+        let _room = sender.request(Request::OpenRoom);
+
+        // receivers never answering the hello of the sender
+        let silent: Vec<_> = (0..MAX_HANDSHAKES)
+            .map(|_| {
+                let mut stream = TcpStream::connect(sender.addr).unwrap();
+                assert!(matches!(
+                    Message::read_from(&mut stream).unwrap(),
+                    Message::Hello(_)
+                ));
+                stream
+            })
+            .collect();
+        // the next connection is dropped right away
+        let mut stream = TcpStream::connect(sender.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        assert_eq!(stream.read(&mut [0; 8]).unwrap(), 0);
+        drop(silent);
+    }
+
+    #[test]
+    fn test_pending_timeout() {
+        let config = SenderConfig {
+            pending_timeout: Duration::from_millis(200),
+            ..SenderConfig::default()
+        };
+        let sender = Distant::spawn_on("127.0.0.1:0".parse().unwrap(), config);
+        let addr = sender.addr;
+
+        // a receiver neither accepted nor rejected is dropped
+        let room = sender.request(Request::OpenRoom);
+        let client = std::thread::spawn(move || introduce(addr, &Credentials::new("studio")));
+        let Response::NewClient(info) = room.recv().unwrap() else {
+            panic!("expecting a new client");
+        };
+        assert_eq!(
+            client.join().unwrap(),
+            Message::Reject("not accepted in time".into())
+        );
+        assert!(matches!(
+            sender
+                .request(Request::AcceptClient(info.addr))
+                .recv()
+                .unwrap(),
+            Response::ClientNotFound
+        ));
+    }
+
+    #[test]
+    fn test_sessions() {
+        let port = MidiPort {
+            index: 0,
+            id: "keyboard:0".into(),
+            name: "keyboard".into(),
+        };
+        let input = InputConnection::channel(port);
+        let (notifier, events) = (input.notifier().clone(), input.lifecycle().subscribe());
+        let (midi_tx, midi_rx) = mpsc::channel();
+        let sender = passeri_api::net::Sender::<Sender>::new(
+            vec![input],
+            midi_rx,
+            "127.0.0.1:0".parse().unwrap(),
+            SenderConfig::default(),
+        )
+        .unwrap();
+        let next_event = || events.recv_timeout(Duration::from_secs(1)).unwrap();
+
+        // the sender goes back to accepting receivers once one left
+        for session in 0..2 {
+            let (output, notes) = channel_output(0);
+            let addr = sender.info();
+            let client = std::thread::spawn(move || {
+                Bridge::new::<Receiver>(
+                    vec![output],
+                    addr,
+                    ReceiverConfig::default(),
+                    Credentials::new("studio"),
+                )
+            });
+            let info = sender.wait_for_client().unwrap();
+            assert_eq!(next_event(), Event::Listening(addr.to_string()));
+            assert_eq!(next_event(), Event::ClientConnected(info.to_string()));
+            sender.send(info.addr).unwrap();
+            assert_eq!(next_event(), Event::Streaming);
+
+            let bridge = client.join().unwrap().unwrap();
+            bridge.receive().unwrap();
+            midi_tx
+                .send((0, (session, vec![0x90, 0x40, 0x7F])))
+                .unwrap();
+            notifier.notify();
+            assert_eq!(next(&notes), [0x90, 0x40, 0x7F]);
+
+            drop(bridge);
+            assert_eq!(next_event(), Event::ClientLeft);
+        }
+    }
+
+    #[test]
+    fn test_cancel() {
+        let port = MidiPort {
+            index: 0,
+            id: "keyboard:0".into(),
+            name: "keyboard".into(),
+        };
+        let (_midi_tx, midi_rx) = mpsc::channel();
+        let sender = passeri_api::net::Sender::<Sender>::new(
+            vec![InputConnection::channel(port)],
+            midi_rx,
+            "127.0.0.1:0".parse().unwrap(),
+            SenderConfig::default(),
+        )
+        .unwrap();
+        let cancel = sender.cancel_handle();
+
+        // without any wait in progress, even with the room left open by a timed out one, cancelling does nothing
+        cancel.cancel().unwrap();
+        assert!(sender
+            .wait_for_client_timeout(Duration::from_millis(50))
+            .unwrap()
+            .is_none());
+        cancel.cancel().unwrap();
+        let addr = sender.info();
+        let client = std::thread::spawn(move || introduce(addr, &Credentials::new("studio")));
+        let info = sender.wait_for_client().unwrap();
+        assert_eq!(info.name, "studio");
+        sender.reject(info.addr, "busy").unwrap();
+        assert_eq!(client.join().unwrap(), Message::Reject("busy".into()));
+
+        // the wait in progress fails, the cancel being repeated until the wait started
+        let (done_tx, done_rx) = mpsc::channel();
+        let canceller = std::thread::spawn(move || {
+            while done_rx.recv_timeout(Duration::from_millis(10)).is_err() {
+                cancel.cancel().unwrap();
+            }
+        });
+        assert!(matches!(sender.wait_for_client(), Err(Error::Cancelled)));
+        done_tx.send(()).unwrap();
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn test_requests_while_streaming() {
+        let sender = Distant::spawn();
+        let mut first = sender.stream_to(Credentials::new("studio"));
+
+        // a second receiver joins the stream
+        let mut second = sender.stream_to(Credentials::new("stage"));
+        sender.play(&[0x90, 0x40, 0x7F]);
+        for stream in [&mut first, &mut second] {
+            let mut note = [0; 3];
+            stream.read_exact(&mut note).unwrap();
+            assert_eq!(note, [0x90, 0x40, 0x7F]);
+        }
+
+        // stopping closes every connection
+        sender.stop();
+        for stream in [&mut first, &mut second] {
+            assert_eq!(stream.read(&mut [0; 8]).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn test_slow_receiver() {
+        let sender = Distant::spawn();
+        let mut fast = sender.stream_to(Credentials::new("studio"));
+        let mut slow = sender.stream_to(Credentials::new("stage"));
+
+        // far more than the connection of the receiver not reading and its outgoing buffer can hold
+        let mut dump = vec![0x7F; 4096];
+        dump[0] = 0xF0;
+        dump[4095] = 0xF7;
+        let count = 64 * crate::stream::OUTGOING_LIMIT / dump.len();
+        let (read_tx, read_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut buf = [0; 4096];
+            for _ in 0..count {
+                fast.read_exact(&mut buf).unwrap();
+                read_tx.send(buf).unwrap();
+            }
+        });
+        // the other receiver is still streamed to, at its own pace
+        for at in 0..count {
+            sender.play(&dump);
+            if at >= 16 {
+                let received = read_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+                assert_eq!(received.as_slice(), dump);
+            }
+        }
+
+        // while the slow one is dropped
+        slow.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut received = 0;
+        loop {
+            match slow.read(&mut [0; 4096]) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => received += len,
+            }
+        }
+        assert!(received < count * dump.len());
+    }
+
+    #[test]
+    fn test_pause() {
+        let sender = Distant::spawn();
+        let mut stream = sender.stream_to(Credentials::new("studio"));
+        let mut read = || {
+            let mut msg = [0; 3];
+            stream.read_exact(&mut msg).unwrap();
+            msg
+        };
+        let applied =
+            |request| matches!(sender.request(request).recv().unwrap(), Response::Applied);
+
+        sender.play(&[0x90, 0x40, 0x7F]);
+        assert_eq!(read(), [0x90, 0x40, 0x7F]);
+
+        // the held note is released when pausing, the following ones being dropped
+        assert!(applied(Request::Control(Control::Pause(true))));
+        assert_eq!(read(), [0x80, 0x40, 0]);
+        sender.play(&[0x90, 0x41, 0x7F]);
+        assert!(applied(Request::Control(Control::Pause(false))));
+        sender.play(&[0x99, 0x24, 0x40]);
+        assert_eq!(read(), [0x99, 0x24, 0x40]);
+
+        assert!(applied(Request::Control(Control::Mute(9, true))));
+        assert_eq!(read(), [0x89, 0x24, 0]);
+    }
+
+    #[test]
+    fn test_panic() {
+        let sender = Distant::spawn();
+        let mut stream = sender.stream_to(Credentials::new("studio"));
+
+        // the panic messages are sent even while paused
+        for control in [Control::Pause(true), Control::Panic(false)] {
+            assert!(matches!(
+                sender.request(Request::Control(control)).recv().unwrap(),
+                Response::Applied
+            ));
+        }
+        let mut messages = [0; 16 * 3 * 3];
+        stream.read_exact(&mut messages).unwrap();
+        assert_eq!(messages[..3], [0xB0, 120, 0]);
+        assert_eq!(messages[messages.len() - 3..], [0xBF, 121, 0]);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let sender = Distant::spawn();
+        // played before any receiver joined
+        sender.play(&[0xC0, 0x05]);
+        sender.play(&[0xB0, 0x07, 0x64]);
+        sender.play(&[0x90, 0x40, 0x7F]);
+        sender.play(&[0xE0, 0x00, 0x50]);
+
+        let mut stream = sender.stream_to(Credentials::new("studio"));
+        let mut snapshot = [0; 8];
+        stream.read_exact(&mut snapshot).unwrap();
+        assert_eq!(snapshot, [0xC0, 0x05, 0xB0, 0x07, 0x64, 0xE0, 0x00, 0x50]);
+
+        // then the live stream
+        sender.play(&[0x90, 0x41, 0x7F]);
+        let mut note = [0; 3];
+        stream.read_exact(&mut note).unwrap();
+        assert_eq!(note, [0x90, 0x41, 0x7F]);
+    }
+
+    #[test]
+    fn test_reserved_status() {
+        let sender = Distant::spawn();
+        let mut stream = sender.stream_to(Credentials::new("studio"));
+
+        // undefined real-time bytes a device may still emit, not forwarded as they start the frames
+        sender.play(&[CABLE_STATUS]);
+        sender.play(&[HEARTBEAT_STATUS]);
+        sender.play(&[0x90, 0x40, 0x7F]);
+        let mut note = [0; 3];
+        stream.read_exact(&mut note).unwrap();
+        assert_eq!(note, [0x90, 0x40, 0x7F]);
+        assert_eq!(sender.metrics.snapshot().dropped, 2);
+    }
+
+    #[test]
+    fn test_cables() {
+        let sender = Distant::spawn();
+        let mut multiplexed = sender.stream_to(Credentials::new("studio"));
+        let mut single = sender.stream_with(Credentials::new("stage"), Features::NONE);
+
+        sender.play_on(2, &[0x90, 0x40, 0x7F]);
+        sender.play_on(0, &[0x90, 0x41, 0x7F]);
+        let mut messages = [0; 10];
+        multiplexed.read_exact(&mut messages).unwrap();
+        assert_eq!(
+            messages,
+            [
+                CABLE_STATUS,
+                2,
+                0x90,
+                0x40,
+                0x7F,
+                CABLE_STATUS,
+                0,
+                0x90,
+                0x41,
+                0x7F
+            ]
+        );
+        // only the cable 0 reaches the receivers not agreeing on cables
+        let mut note = [0; 3];
+        single.read_exact(&mut note).unwrap();
+        assert_eq!(note, [0x90, 0x41, 0x7F]);
+    }
+
+    #[test]
+    fn test_relay() {
+        let relay = passeri_relay::Relay::bind(
+            "127.0.0.1:0".parse().unwrap(),
+            passeri_relay::RelayConfig {
+                handshake_timeout: Duration::from_millis(500),
+                ..passeri_relay::RelayConfig::default()
+            },
+        )
+        .unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        std::thread::spawn(move || relay.run());
+        let ticket = Ticket::new("jam").with_token("s3cr3t");
+        let config = SenderConfig {
+            relay: Some(ticket.clone()),
+            ..SenderConfig::default()
+        };
+        let sender = Distant::spawn_on(relay_addr, config);
+        assert_eq!(sender.addr, relay_addr);
+
+        // the sender waits for the next receiver once one is paired
+        for name in ["studio", "stage"] {
+            let room = sender.request(Request::OpenRoom);
+            let ticket = ticket.clone();
+            let client = std::thread::spawn(move || {
+                let stream = crate::relay::join(relay_addr, &ticket).unwrap();
+                answer(stream, &Credentials::new(name), Receiver::FEATURES)
+            });
+            let Response::NewClient(info) = room.recv().unwrap() else {
+                panic!("expecting a new client");
+            };
+            assert_eq!(info.name, name);
+            assert!(matches!(
+                sender
+                    .request(Request::AcceptClient(info.addr))
+                    .recv()
+                    .unwrap(),
+                Response::StartStream
+            ));
+            let (mut stream, verdict) = client.join().unwrap();
+            assert!(matches!(verdict, Message::Accept(_)));
+
+            sender.play(&[0x90, 0x40, 0x7F]);
+            let mut note = [0; 3];
+            stream.read_exact(&mut note).unwrap();
+            assert_eq!(note, [0x90, 0x40, 0x7F]);
+        }
+
+        // receivers without the token of the room are refused by the relay
+        assert!(matches!(
+            crate::relay::join(relay_addr, &Ticket::new("jam")),
+            Err(Error::Protocol(ProtocolError::RelayRefused(_)))
+        ));
+    }
+
+    #[test]
+    fn test_relay_retry() {
+        // the relay is not started yet
+        let relay_addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let ticket = Ticket::new("jam");
+        let config = SenderConfig {
+            relay: Some(ticket.clone()),
+            ..SenderConfig::default()
+        };
+        let sender = Distant::spawn_on(relay_addr, config);
+        let room = sender.request(Request::OpenRoom);
+        std::thread::sleep(Duration::from_millis(100));
+
+        // the sender waits at the relay once it is reachable
+        let relay =
+            passeri_relay::Relay::bind(relay_addr, passeri_relay::RelayConfig::default()).unwrap();
+        std::thread::spawn(move || relay.run());
+        let client = std::thread::spawn(move || loop {
+            // the room exists once the sender reconnected
+            match crate::relay::join(relay_addr, &ticket) {
+                Ok(stream) => {
+                    return answer(stream, &Credentials::new("studio"), Receiver::FEATURES)
+                }
+                Err(Error::Protocol(ProtocolError::RelayRefused(_))) => {
+                    std::thread::sleep(Duration::from_millis(100))
+                }
+                Err(err) => panic!("{}", err),
+            }
+        });
+        let Response::NewClient(info) = room.recv_timeout(Duration::from_secs(10)).unwrap() else {
+            panic!("expecting a new client");
+        };
+        assert_eq!(info.name, "studio");
+        sender
+            .request(Request::AcceptClient(info.addr))
+            .recv()
+            .unwrap();
+        let (_, verdict) = client.join().unwrap();
+        assert!(matches!(verdict, Message::Accept(_)));
     }
 }
@@ -0,0 +1,211 @@
+//! Peers shared by the tests of the TCP transport, running on free local ports without any MIDI backend
+use crate::{Receiver, ReceiverConfig, Sender, SenderConfig};
+use passeri_api::lifecycle::Lifecycle;
+use passeri_api::metrics::Metrics;
+use passeri_api::midi::{MidiPort, OutputConnection};
+use passeri_api::net::cable::{Cable, CablePayload};
+use passeri_api::net::lobby::{Credentials, Features, Hello, Lobby, Message};
+use passeri_api::net::notifier::Wake;
+use passeri_api::net::receiver::Receiver as Bridge;
+use passeri_api::net::receiver::Thread as _;
+use passeri_api::net::sender::{PasseriReq, Request, Response, Thread, ThreadReturn};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Connection standing for the MIDI out port `index`, and the channel receiving the messages sent to it
+pub(crate) fn channel_output(index: usize) -> (OutputConnection, mpsc::Receiver<Vec<u8>>) {
+    OutputConnection::channel(MidiPort {
+        index,
+        id: format!("synth:{index}"),
+        name: format!("synth {index}"),
+    })
+}
+
+/// Next message sent to an output of [channel_output]
+pub(crate) fn next(output: &mpsc::Receiver<Vec<u8>>) -> Vec<u8> {
+    output.recv_timeout(Duration::from_secs(1)).unwrap()
+}
+
+/// Connect to `addr` as a receiver presenting `credentials`, returning the connection and the sender verdict
+pub(crate) fn connect(addr: SocketAddr, credentials: &Credentials) -> (TcpStream, Message) {
+    connect_with(addr, credentials, Receiver::FEATURES)
+}
+
+pub(crate) fn connect_with(
+    addr: SocketAddr,
+    credentials: &Credentials,
+    features: Features,
+) -> (TcpStream, Message) {
+    answer(TcpStream::connect(addr).unwrap(), credentials, features)
+}
+
+/// Answer the sender hello on `stream`, returning the connection and the sender verdict
+pub(crate) fn answer(
+    mut stream: TcpStream,
+    credentials: &Credentials,
+    features: Features,
+) -> (TcpStream, Message) {
+    let sender = match Message::read_from(&mut stream).unwrap() {
+        Message::Hello(hello) => hello,
+        message => panic!("unexpected {:?}", message),
+    };
+    let (hello, _) = Hello::answer(credentials, "synth", features, &sender).unwrap();
+    Message::Hello(hello).write_to(&mut stream).unwrap();
+    let verdict = Message::read_from(&mut stream).unwrap();
+    (stream, verdict)
+}
+
+/// Sender verdict on a receiver presenting `credentials` to `addr`
+pub(crate) fn introduce(addr: SocketAddr, credentials: &Credentials) -> Message {
+    connect(addr, credentials).1
+}
+
+fn request(
+    tx: &mpsc::Sender<PasseriReq<SocketAddr>>,
+    wake: &Wake,
+    request: Request<SocketAddr>,
+) -> oneshot::Receiver<Response<SocketAddr>> {
+    let (responder, response) = oneshot::channel();
+    tx.send((request, responder)).unwrap();
+    wake();
+    response
+}
+
+/// Channels of a TCP sender thread, playing the messages given to it
+pub(crate) struct Distant {
+    pub addr: SocketAddr,
+    pub metrics: Metrics,
+    tx: mpsc::Sender<PasseriReq<SocketAddr>>,
+    midi_tx: mpsc::Sender<CablePayload>,
+    wake: Wake,
+    thread: JoinHandle<Result<(), ThreadReturn<SocketAddr>>>,
+}
+
+impl Distant {
+    /// Listen on a free local port
+    pub fn spawn() -> Self {
+        Self::spawn_on("127.0.0.1:0".parse().unwrap(), SenderConfig::default())
+    }
+
+    pub fn spawn_on(addr: SocketAddr, config: SenderConfig) -> Self {
+        Self::spawn_with(addr, config, Lobby::new(), Lifecycle::new())
+    }
+
+    /// Listen on `addr`, the receivers having to pass `lobby`
+    pub fn spawn_with(
+        addr: SocketAddr,
+        config: SenderConfig,
+        lobby: Lobby,
+        lifecycle: Lifecycle,
+    ) -> Self {
+        let (midi_tx, midi_rx) = mpsc::channel();
+        let (tx, messenger_rx) = mpsc::channel();
+        let metrics = Metrics::new();
+        let mut sender = Sender::new(
+            addr,
+            config,
+            midi_rx,
+            messenger_rx,
+            metrics.clone(),
+            lobby,
+            lifecycle,
+        )
+        .unwrap();
+        let addr = sender.info();
+        let wake = sender.waker();
+        let thread = std::thread::spawn(move || sender.run());
+        Distant {
+            addr,
+            metrics,
+            tx,
+            midi_tx,
+            wake,
+            thread,
+        }
+    }
+
+    pub fn request(&self, req: Request<SocketAddr>) -> oneshot::Receiver<Response<SocketAddr>> {
+        request(&self.tx, &self.wake, req)
+    }
+
+    pub fn play(&self, msg: &[u8]) {
+        self.play_on(0, msg);
+    }
+
+    pub fn play_on(&self, cable: Cable, msg: &[u8]) {
+        self.midi_tx.send((cable, (0, msg.to_vec()))).unwrap();
+        (self.wake)();
+    }
+
+    /// Open the room and accept the next receiver presenting `credentials`, returning its connection
+    pub fn stream_to(&self, credentials: Credentials) -> TcpStream {
+        self.stream_with(credentials, Receiver::FEATURES)
+    }
+
+    /// Same as [Distant::stream_to], the receiver supporting `features`
+    pub fn stream_with(&self, credentials: Credentials, features: Features) -> TcpStream {
+        let room = self.request(Request::OpenRoom);
+        let addr = self.addr;
+        let client = std::thread::spawn(move || connect_with(addr, &credentials, features));
+        let Response::NewClient(info) = room.recv().unwrap() else {
+            panic!("expecting a new client");
+        };
+        assert!(matches!(
+            self.request(Request::AcceptClient(info.addr))
+                .recv()
+                .unwrap(),
+            Response::StartStream
+        ));
+        let (stream, verdict) = client.join().unwrap();
+        assert!(matches!(verdict, Message::Accept(_)));
+        stream
+    }
+
+    /// Accept a receiver sending the messages of each cable to its own channel, and start receiving
+    pub fn bridge(
+        &self,
+        cables: usize,
+        config: ReceiverConfig,
+    ) -> (Bridge, Vec<mpsc::Receiver<Vec<u8>>>) {
+        let (conns, outputs): (Vec<_>, Vec<_>) = (0..cables).map(channel_output).unzip();
+        let room = self.request(Request::OpenRoom);
+        let addr = self.addr;
+        let client = std::thread::spawn(move || {
+            Bridge::new::<Receiver>(conns, addr, config, Credentials::new("studio"))
+        });
+        let Response::NewClient(info) = room.recv().unwrap() else {
+            panic!("expecting a new client");
+        };
+        assert!(matches!(
+            self.request(Request::AcceptClient(info.addr))
+                .recv()
+                .unwrap(),
+            Response::StartStream
+        ));
+        let bridge = client.join().unwrap().unwrap();
+        bridge.receive().unwrap();
+        (bridge, outputs)
+    }
+
+    /// Accept every receiver from another thread, until the sender stops
+    pub fn admit_all(&self) {
+        let (tx, wake) = (self.tx.clone(), self.wake.clone());
+        std::thread::spawn(move || {
+            while let Ok(Response::NewClient(info)) = request(&tx, &wake, Request::OpenRoom).recv()
+            {
+                let _ = request(&tx, &wake, Request::AcceptClient(info.addr)).recv();
+            }
+        });
+    }
+
+    /// Stop the sender, closing its connections
+    pub fn stop(self) {
+        assert!(matches!(
+            self.request(Request::Stop).recv().unwrap(),
+            Response::Stopped
+        ));
+        self.thread.join().unwrap().unwrap();
+    }
+}
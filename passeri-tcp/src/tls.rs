@@ -0,0 +1,532 @@
+//! TLS settings of the TCP [Sender](crate::Sender) and [Receiver](crate::Receiver), relying on [rustls]
+//!
+//! Peers are authenticated either by pinning the SHA-256 [Fingerprint] of their certificate,
+//! which suits self-signed certificates, or by a set of trusted root certificates.
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{
+    verify_tls12_signature, verify_tls13_signature, CryptoProvider, WebPkiSupportedAlgorithms,
+};
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, DistinguishedName, RootCertStore,
+    ServerConfig, SignatureScheme,
+};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Errors raised while building the TLS settings
+#[derive(Error, Debug)]
+pub enum TlsError {
+    /// unable to generate a self-signed certificate
+    #[error("unable to generate a self-signed certificate")]
+    Generate(#[from] rcgen::Error),
+
+    /// the PEM content can not be parsed
+    #[error("invalid PEM content")]
+    Pem(#[from] rustls::pki_types::pem::Error),
+
+    /// the PEM content does not contain any certificate
+    #[error("no certificate found in the PEM content")]
+    NoCertificate,
+
+    /// the fingerprint is not made of 32 hexadecimal bytes
+    #[error("invalid fingerprint `{0}`, expecting 32 hexadecimal bytes")]
+    InvalidFingerprint(String),
+
+    /// the server name is neither a DNS name nor an IP address
+    #[error("invalid server name `{0}`")]
+    InvalidServerName(String),
+
+    /// the certificates or settings are rejected by [rustls]
+    #[error("invalid TLS settings")]
+    Rustls(#[from] rustls::Error),
+
+    /// unable to build a verifier from the given root certificates
+    #[error("invalid root certificates ({0})")]
+    Roots(String),
+}
+
+//
+//	FINGERPRINT
+//
+
+/// SHA-256 digest of a DER encoded certificate, displayed as colon separated hexadecimal bytes
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    /// Fingerprint of a DER encoded certificate
+    pub fn of(cert: &CertificateDer) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, cert.as_ref());
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(digest.as_ref());
+        Fingerprint(bytes)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fingerprint({})", self)
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = TlsError;
+
+    /// Parse hexadecimal bytes, optionally separated by colons
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || TlsError::InvalidFingerprint(s.to_string());
+        let hex: Vec<u8> = s.bytes().filter(|c| *c != b':').collect();
+        if hex.len() != 64 {
+            return Err(invalid());
+        }
+
+        let mut bytes = [0; 32];
+        for (byte, pair) in bytes.iter_mut().zip(hex.chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+        }
+        Ok(Fingerprint(bytes))
+    }
+}
+
+//
+//	IDENTITY
+//
+
+/// Certificate chain and private key presented to the distant peer
+pub struct Identity {
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    pem: String,
+}
+
+impl Identity {
+    /// Generate a self-signed certificate valid for the given DNS names or IP addresses
+    pub fn self_signed(names: &[&str]) -> Result<Self, TlsError> {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let generated = rcgen::generate_simple_self_signed(names)?;
+        let pem = format!(
+            "{}{}",
+            generated.cert.pem(),
+            generated.signing_key.serialize_pem()
+        );
+        Self::from_pem(&pem)
+    }
+
+    /// Load a certificate chain and its private key from PEM content (e.g. the concatenation of a certificate file and a key file)
+    pub fn from_pem(pem: &str) -> Result<Self, TlsError> {
+        let certs =
+            CertificateDer::pem_slice_iter(pem.as_bytes()).collect::<Result<Vec<_>, _>>()?;
+        if certs.is_empty() {
+            return Err(TlsError::NoCertificate);
+        }
+        let key = PrivateKeyDer::from_pem_slice(pem.as_bytes())?;
+
+        Ok(Identity {
+            certs,
+            key,
+            pem: pem.to_string(),
+        })
+    }
+
+    /// Fingerprint of the certificate, to be pinned by the distant peer
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&self.certs[0])
+    }
+
+    /// PEM content of the certificate chain and its private key, loadable with [Identity::from_pem]
+    pub fn pem(&self) -> &str {
+        &self.pem
+    }
+}
+
+impl Clone for Identity {
+    fn clone(&self) -> Self {
+        Identity {
+            certs: self.certs.clone(),
+            key: self.key.clone_key(),
+            pem: self.pem.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity")
+            .field("fingerprint", &self.fingerprint())
+            .finish_non_exhaustive()
+    }
+}
+
+/// How a distant peer is authenticated
+#[derive(Debug, Clone)]
+pub enum Trust {
+    /// the peer certificate must have one of these fingerprints (suits self-signed certificates)
+    Pinned(Vec<Fingerprint>),
+    /// the peer certificate must be issued by one of these root certificates
+    Roots(Vec<CertificateDer<'static>>),
+}
+
+impl Trust {
+    /// Trust the root certificates found in PEM content
+    pub fn roots_from_pem(pem: &str) -> Result<Self, TlsError> {
+        let certs =
+            CertificateDer::pem_slice_iter(pem.as_bytes()).collect::<Result<Vec<_>, _>>()?;
+        if certs.is_empty() {
+            return Err(TlsError::NoCertificate);
+        }
+        Ok(Trust::Roots(certs))
+    }
+
+    fn root_store(certs: &[CertificateDer<'static>]) -> Result<RootCertStore, TlsError> {
+        let mut roots = RootCertStore::empty();
+        for cert in certs {
+            roots.add(cert.clone())?;
+        }
+        Ok(roots)
+    }
+}
+
+//
+//	SERVER SIDE
+//
+
+/// TLS settings of a [Sender](crate::Sender)
+#[derive(Debug, Clone)]
+pub struct ServerTls {
+    config: Arc<ServerConfig>,
+    fingerprint: Fingerprint,
+}
+
+impl ServerTls {
+    /// Build the TLS settings of a sender presenting `identity`
+    ///
+    /// # Arguments
+    /// * `identity` - certificate presented to the receivers
+    /// * `client_auth` - when set, receivers must present a certificate trusted this way (mutual TLS)
+    pub fn new(identity: &Identity, client_auth: Option<Trust>) -> Result<Self, TlsError> {
+        let builder = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?;
+        let builder = match client_auth {
+            None => builder.with_no_client_auth(),
+            Some(Trust::Pinned(fingerprints)) => {
+                builder.with_client_cert_verifier(Arc::new(PinnedVerifier::new(fingerprints)))
+            }
+            Some(Trust::Roots(certs)) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(Trust::root_store(&certs)?),
+                    provider(),
+                )
+                .build()
+                .map_err(|err| TlsError::Roots(err.to_string()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+        let config = builder.with_single_cert(identity.certs.clone(), identity.key.clone_key())?;
+
+        Ok(ServerTls {
+            config: Arc::new(config),
+            fingerprint: identity.fingerprint(),
+        })
+    }
+
+    /// Fingerprint of the certificate presented to the receivers
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+
+    pub(crate) fn config(&self) -> Arc<ServerConfig> {
+        self.config.clone()
+    }
+}
+
+//
+//	CLIENT SIDE
+//
+
+/// TLS settings of a [Receiver](crate::Receiver)
+#[derive(Debug, Clone)]
+pub struct ClientTls {
+    config: Arc<ClientConfig>,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl ClientTls {
+    /// Build the TLS settings of a receiver
+    ///
+    /// # Arguments
+    /// * `trust` - how the sender certificate is authenticated
+    /// * `identity` - certificate presented to the sender, when it requires mutual TLS
+    pub fn new(trust: Trust, identity: Option<&Identity>) -> Result<Self, TlsError> {
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?;
+        let builder = match trust {
+            Trust::Pinned(fingerprints) => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedVerifier::new(fingerprints))),
+            Trust::Roots(certs) => builder.with_root_certificates(Trust::root_store(&certs)?),
+        };
+        let config = match identity {
+            Some(identity) => {
+                builder.with_client_auth_cert(identity.certs.clone(), identity.key.clone_key())?
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        Ok(ClientTls {
+            config: Arc::new(config),
+            server_name: None,
+        })
+    }
+
+    /// Name expected in the sender certificate when it is authenticated by root certificates,
+    /// the sender IP address being used by default
+    pub fn with_server_name(mut self, name: &str) -> Result<Self, TlsError> {
+        let server_name = ServerName::try_from(name)
+            .map_err(|_| TlsError::InvalidServerName(name.to_string()))?;
+        self.server_name = Some(server_name.to_owned());
+        Ok(self)
+    }
+
+    pub(crate) fn config(&self) -> Arc<ClientConfig> {
+        self.config.clone()
+    }
+
+    pub(crate) fn server_name(&self, ip: IpAddr) -> ServerName<'static> {
+        self.server_name
+            .clone()
+            .unwrap_or_else(|| ServerName::IpAddress(ip.into()))
+    }
+}
+
+//
+//	FINGERPRINT PINNING
+//
+
+/// Certificate verifier accepting only the certificates with pinned fingerprints, whatever their issuer
+#[derive(Debug)]
+struct PinnedVerifier {
+    fingerprints: Vec<Fingerprint>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl PinnedVerifier {
+    fn new(fingerprints: Vec<Fingerprint>) -> Self {
+        PinnedVerifier {
+            fingerprints,
+            algorithms: provider().signature_verification_algorithms,
+        }
+    }
+
+    fn check(&self, cert: &CertificateDer) -> Result<(), rustls::Error> {
+        let fingerprint = Fingerprint::of(cert);
+        if self.fingerprints.contains(&fingerprint) {
+            Ok(())
+        } else {
+            log::warn!("rejecting certificate {}", fingerprint);
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.check(end_entity)?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+impl ClientCertVerifier for PinnedVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.check(end_entity)?;
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::Stream;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    /// Run a handshake over loopback, sending a byte from the server to the client. Return the fingerprint
+    /// of the client certificate seen by the server if it succeeded, and whether the client received the byte.
+    fn exchange(server: ServerTls, client: ClientTls) -> (Option<Option<Fingerprint>>, bool) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            Stream::accept(mio::net::TcpStream::from_std(sock), Some(&server))
+                .and_then(|mut stream| {
+                    stream.write_all(&[0x90])?;
+                    Ok(stream.peer_fingerprint())
+                })
+                .ok()
+        });
+        let mut buf = [0];
+        let sock = mio::net::TcpStream::from_std(TcpStream::connect(addr).unwrap());
+        let client = Stream::connect(sock, Some(&client))
+            .and_then(|mut stream| stream.read_exact(&mut buf))
+            .is_ok_and(|_| buf == [0x90]);
+
+        (server.join().unwrap(), client)
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let identity = Identity::self_signed(&["localhost"]).unwrap();
+        let fingerprint = identity.fingerprint();
+        assert_eq!(fingerprint.to_string().len(), 32 * 3 - 1);
+        assert_eq!(
+            fingerprint.to_string().parse::<Fingerprint>().unwrap(),
+            fingerprint
+        );
+        assert_eq!(
+            Identity::from_pem(identity.pem()).unwrap().fingerprint(),
+            fingerprint
+        );
+        assert!("00:11".parse::<Fingerprint>().is_err());
+    }
+
+    #[test]
+    fn test_pinned_handshake() {
+        let server = Identity::self_signed(&["localhost"]).unwrap();
+        let other = Identity::self_signed(&["localhost"]).unwrap();
+
+        let pinned = Trust::Pinned(vec![server.fingerprint()]);
+        assert_eq!(
+            exchange(
+                ServerTls::new(&server, None).unwrap(),
+                ClientTls::new(pinned, None).unwrap()
+            ),
+            (Some(None), true)
+        );
+
+        let wrong = Trust::Pinned(vec![other.fingerprint()]);
+        assert_eq!(
+            exchange(
+                ServerTls::new(&server, None).unwrap(),
+                ClientTls::new(wrong, None).unwrap()
+            ),
+            (None, false)
+        );
+    }
+
+    #[test]
+    fn test_mutual_tls() {
+        let server = Identity::self_signed(&["localhost"]).unwrap();
+        let client = Identity::self_signed(&["receiver"]).unwrap();
+        let server_tls = |fingerprint: Fingerprint| {
+            ServerTls::new(&server, Some(Trust::Pinned(vec![fingerprint]))).unwrap()
+        };
+        let trust = || Trust::Pinned(vec![server.fingerprint()]);
+
+        assert_eq!(
+            exchange(
+                server_tls(client.fingerprint()),
+                ClientTls::new(trust(), Some(&client)).unwrap()
+            ),
+            (Some(Some(client.fingerprint())), true)
+        );
+        assert_eq!(
+            exchange(
+                server_tls(client.fingerprint()),
+                ClientTls::new(trust(), None).unwrap()
+            ),
+            (None, false)
+        );
+        assert_eq!(
+            exchange(
+                server_tls(server.fingerprint()),
+                ClientTls::new(trust(), Some(&client)).unwrap()
+            ),
+            (None, false)
+        );
+    }
+}